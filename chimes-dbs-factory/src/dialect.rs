@@ -0,0 +1,969 @@
+use rbs::Value;
+
+/**
+ * 探测语句及其参数，参数使用?作为占位符
+ */
+pub type ProbeSql = (String, Vec<Value>);
+
+/**
+ * 修改字段时需要保留的字段属性，来自于数据库中探测到的字段信息
+ * 如MySQL的modify column会以新的定义整体替换原有的定义，未给出的属性会丢失
+ */
+#[derive(Debug, Clone, Default)]
+pub struct ColumnAttributes {
+    pub nullable: bool,
+    pub default: Option<String>,
+    pub auto_increment: bool,
+    pub on_update: Option<String>,
+    pub comment: Option<String>,
+}
+
+/**
+ * 数据库方言，封装不同数据库在结构探测、标识符、分页、类型转换及条件表达上的差异
+ * 缺省的实现基于INFORMATION_SCHEMA及MySQL的语法，各数据库按需覆盖
+ * 探测语句返回的列名与chimes-store-core中probe的结构保持一致
+ */
+pub trait Dialect: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /**
+     * 标识符的引用，如`name`、"name"、[name]，带有schema前缀时分别引用
+     */
+    fn quote_identifier(&self, name: &str) -> String {
+        quote_with(name, '"', '"')
+    }
+
+    /**
+     * 列出schema中的表，返回table_catalog, table_schema, table_type, table_name
+     */
+    fn load_tables_sql(&self, schema: &str) -> ProbeSql {
+        (
+            r#"SELECT table_catalog as table_catalog, table_schema as table_schema, table_type as table_type,
+    table_name as table_name
+    FROM INFORMATION_SCHEMA.TABLES WHERE table_schema = ?"#
+                .to_owned(),
+            vec![Value::String(schema.to_owned())],
+        )
+    }
+
+    fn load_one_table_sql(&self, schema: &str, table: &str) -> ProbeSql {
+        (
+            r#"SELECT table_catalog as table_catalog, table_schema as table_schema, table_type as table_type,
+    table_name as table_name
+    FROM INFORMATION_SCHEMA.TABLES WHERE table_schema = ? and table_name = ?"#
+                .to_owned(),
+            vec![Value::String(schema.to_owned()), Value::String(table.to_owned())],
+        )
+    }
+
+    /**
+     * 列出表的字段，schema为空时不限制schema，column_comment为字段的注释，不支持注释的数据库返回null
+     */
+    fn load_columns_sql(&self, schema: &str, table: &str) -> ProbeSql {
+        (
+            r#"SELECT table_schema as table_schema, table_name as table_name,  column_name as column_name,
+        column_default as column_default, data_type as orginal_type, ordinal_position as ordinal_position, character_maximum_length as character_maximum_length,
+        is_nullable as is_nullable, numeric_precision as numeric_precision, numeric_scale as numeric_scale, null as column_comment
+        FROM INFORMATION_SCHEMA.COLUMNS WHERE case when ? = '' then table_schema like '%' else table_schema = ? end and table_name = ? order by ORDINAL_POSITION ASC"#
+                .to_owned(),
+            vec![
+                Value::String(schema.to_owned()),
+                Value::String(schema.to_owned()),
+                Value::String(table.to_owned()),
+            ],
+        )
+    }
+
+    /**
+     * 列出表的主键字段，按照主键约束中的顺序排列
+     */
+    fn load_pkeys_sql(&self, schema: &str, table: &str) -> ProbeSql {
+        (
+            r#"select kcu.table_schema as table_schema, kcu.table_name as table_name, kcu.column_name as column_name, kcu.ordinal_position as ordinal_position
+    from INFORMATION_SCHEMA.table_constraints tc join INFORMATION_SCHEMA.key_column_usage kcu
+    on tc.constraint_schema = kcu.constraint_schema and tc.constraint_name = kcu.constraint_name and tc.table_name = kcu.table_name
+    where tc.constraint_type = 'PRIMARY KEY' and tc.table_schema = ? and tc.table_name = ? order by kcu.ordinal_position ASC"#
+                .to_owned(),
+            vec![Value::String(schema.to_owned()), Value::String(table.to_owned())],
+        )
+    }
+
+    /**
+     * 列出表的索引，返回index_name, column_name, non_unique, ordinal_position
+     * 返回None表示该数据库不支持索引的探测
+     */
+    fn load_indexes_sql(&self, schema: &str, table: &str) -> Option<ProbeSql> {
+        Some((
+            r#"select index_name as index_name, column_name as column_name, non_unique as non_unique, seq_in_index as ordinal_position
+    from INFORMATION_SCHEMA.statistics where table_schema = ? and table_name = ? order by index_name, seq_in_index"#
+                .to_owned(),
+            vec![Value::String(schema.to_owned()), Value::String(table.to_owned())],
+        ))
+    }
+
+    /**
+     * 列出表的外键，返回constraint_name, column_name, referenced_table_schema, referenced_table_name, referenced_column_name
+     * 返回None表示该数据库不支持外键的探测
+     */
+    fn load_foreign_keys_sql(&self, schema: &str, table: &str) -> Option<ProbeSql> {
+        Some((
+            r#"select tc.constraint_name as constraint_name, kcu.column_name as column_name, ccu.table_schema as referenced_table_schema,
+    ccu.table_name as referenced_table_name, ccu.column_name as referenced_column_name
+    from INFORMATION_SCHEMA.table_constraints tc
+    join INFORMATION_SCHEMA.key_column_usage kcu on tc.constraint_schema = kcu.constraint_schema and tc.constraint_name = kcu.constraint_name
+    join INFORMATION_SCHEMA.constraint_column_usage ccu on tc.constraint_schema = ccu.constraint_schema and tc.constraint_name = ccu.constraint_name
+    where tc.constraint_type = 'FOREIGN KEY' and tc.table_schema = ? and tc.table_name = ? order by tc.constraint_name, kcu.ordinal_position"#
+                .to_owned(),
+            vec![Value::String(schema.to_owned()), Value::String(table.to_owned())],
+        ))
+    }
+
+    /**
+     * 为查询语句追加分页，offset为0时只限制返回的条数
+     */
+    fn paginate(&self, sql: &str, limit: u64, offset: u64) -> String {
+        if offset == 0 {
+            format!("{sql} limit {limit} ")
+        } else {
+            format!("{sql} limit {limit} offset {offset} ")
+        }
+    }
+
+    /**
+     * 参数占位符，需要显式类型转换的数据库在此转换为字段的类型
+     */
+    fn cast_placeholder(&self, _field_type: &str) -> String {
+        "?".to_owned()
+    }
+
+    /**
+     * 根据数据库类型生成查询条件的SQL片段，使用?作为参数占位符
+     * values为in/not in中参数的个数，其它操作符的参数个数是固定的
+     * 返回None表示该数据库不支持该操作符
+     */
+    fn condition_present(&self, field: &str, op: &str, values: usize) -> Option<String> {
+        ansi_condition_present(field, op, values)
+    }
+
+    /**
+     * json类型字段的查询条件，默认与condition_present相同，json与数组字段语法不同的数据库需要重写
+     */
+    fn json_condition_present(&self, field: &str, op: &str, values: usize) -> Option<String> {
+        self.condition_present(field, op, values)
+    }
+
+    /**
+     * 单条语句允许的最大参数个数，用于批量插入时计算每批的行数
+     */
+    fn bulk_max_params(&self) -> usize {
+        65535
+    }
+
+    /**
+     * 生成多行插入的SQL，keys不为空时使用数据库原生的upsert语法
+     * 返回None表示该数据库不支持upsert
+     */
+    fn bulk_upsert_sql(
+        &self,
+        table: &str,
+        columns: &[String],
+        values: &[String],
+        keys: &[String],
+        _updates: &[String],
+    ) -> Option<String> {
+        if keys.is_empty() {
+            Some(bulk_insert_sql(table, columns, values))
+        } else {
+            None
+        }
+    }
+
+    fn supports_bulk_upsert(&self) -> bool {
+        false
+    }
+
+    /**
+     * 将Column中的类型（integer、string、datetime、date、time、bool、json、binary等）转为数据库的字段类型
+     * length用于字符串及二进制类型
+     */
+    fn column_type_sql(&self, col_type: &str, length: Option<i64>) -> String {
+        ansi_column_type(col_type, length)
+    }
+
+    /**
+     * 建表语句，columns为已生成的字段定义，keys为主键字段
+     */
+    fn create_table_sql(&self, table: &str, columns: &[String], keys: &[String]) -> String {
+        if keys.is_empty() {
+            format!("create table {} ({})", table, columns.join(", "))
+        } else {
+            format!(
+                "create table {} ({}, primary key ({}))",
+                table,
+                columns.join(", "),
+                keys.join(", ")
+            )
+        }
+    }
+
+    fn add_column_sql(&self, table: &str, column: &str) -> String {
+        format!("alter table {table} add column {column}")
+    }
+
+    /**
+     * 修改字段类型的语句，返回None表示该数据库不支持修改字段
+     * attrs为字段原有的属性，只修改类型时会保留其它属性的数据库（如PostgreSQL）可以忽略
+     */
+    fn modify_column_sql(&self, table: &str, column: &str, type_sql: &str, _attrs: &ColumnAttributes) -> Option<String> {
+        Some(format!("alter table {table} alter column {column} type {type_sql}"))
+    }
+
+    /**
+     * DDL是否可以在事务中执行并回滚
+     */
+    fn supports_transactional_ddl(&self) -> bool {
+        false
+    }
+}
+
+/**
+ * 标准SQL的字段类型，各方言覆盖column_type_sql时回退使用
+ */
+fn ansi_column_type(col_type: &str, length: Option<i64>) -> String {
+    let length = length.filter(|f| *f > 0);
+    match col_type.to_lowercase().as_str() {
+        "integer" | "int" | "long" => "bigint".to_owned(),
+        "bool" | "boolean" => "boolean".to_owned(),
+        "datetime" | "timestamp" => "timestamp".to_owned(),
+        "date" => "date".to_owned(),
+        "time" => "time".to_owned(),
+        "float" | "double" => "double precision".to_owned(),
+        "decimal" | "number" | "numeric" => format!("decimal({}, 6)", length.unwrap_or(20)),
+        "json" => "json".to_owned(),
+        "binary" => "blob".to_owned(),
+        "text" => "text".to_owned(),
+        _ => format!("varchar({})", length.unwrap_or(255)),
+    }
+}
+
+fn bulk_insert_sql(table: &str, columns: &[String], values: &[String]) -> String {
+    format!(
+        "insert into {} ({}) values {}",
+        table,
+        columns.join(","),
+        values.join(",")
+    )
+}
+
+/**
+ * 字符串字面量，单引号及反斜杠进行转义
+ */
+fn quote_literal(text: &str) -> String {
+    format!("'{}'", text.replace('\\', "\\\\").replace('\'', "''"))
+}
+
+/**
+ * 探测到的字段缺省值转为DDL中的表达，数字、已带引号的值以及函数表达式（如CURRENT_TIMESTAMP）原样使用，其它作为字符串
+ */
+fn default_literal(default: &str) -> String {
+    let text = default.trim();
+    let upper = text.to_uppercase();
+    if text.parse::<f64>().is_ok()
+        || text.starts_with('\'')
+        || text.starts_with('(')
+        || upper == "NULL"
+        || upper.starts_with("CURRENT_")
+        || upper.starts_with("NOW(")
+        || upper.starts_with("B'")
+    {
+        text.to_owned()
+    } else {
+        quote_literal(text)
+    }
+}
+
+/**
+ * 语句的最外层是否已有order by，子查询、字符串及带引号的标识符中的order by不计算在内
+ */
+fn has_top_level_order_by(sql: &str) -> bool {
+    let mut depth = 0;
+    let mut quote: Option<char> = None;
+    let mut word = String::new();
+    let mut after_order = false;
+    for ch in sql.chars().chain(std::iter::once(' ')) {
+        if let Some(close) = quote {
+            if ch == close {
+                quote = None;
+            }
+            continue;
+        }
+        if ch.is_alphanumeric() || ch == '_' {
+            word.push(ch);
+            continue;
+        }
+        if !word.is_empty() {
+            if depth == 0 {
+                let lower = word.to_lowercase();
+                if after_order && lower == "by" {
+                    return true;
+                }
+                after_order = lower == "order";
+            }
+            word.clear();
+        }
+        match ch {
+            '\'' | '"' => quote = Some(ch),
+            '[' => quote = Some(']'),
+            '(' => {
+                depth += 1;
+                after_order = false;
+            }
+            ')' => depth -= 1,
+            c if c.is_whitespace() => {}
+            _ => after_order = false,
+        }
+    }
+    false
+}
+
+fn quote_with(name: &str, open: char, close: char) -> String {
+    name.split('.')
+        .map(|f| {
+            format!(
+                "{}{}{}",
+                open,
+                f.replace(close, &format!("{close}{close}")),
+                close
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(".")
+}
+
+pub struct MysqlDialect;
+
+impl Dialect for MysqlDialect {
+    fn name(&self) -> &'static str {
+        "mysql"
+    }
+
+    fn quote_identifier(&self, name: &str) -> String {
+        quote_with(name, '`', '`')
+    }
+
+    fn load_columns_sql(&self, schema: &str, table: &str) -> ProbeSql {
+        (
+            r#"SELECT table_schema as table_schema, table_name as table_name,  column_name as column_name,
+        column_default as column_default, data_type as orginal_type, ordinal_position as ordinal_position, character_maximum_length as character_maximum_length,
+        is_nullable as is_nullable, numeric_precision as numeric_precision, numeric_scale as numeric_scale, column_comment as column_comment,
+        extra as column_extra
+        FROM INFORMATION_SCHEMA.COLUMNS WHERE case when ? = '' then table_schema like '%' else table_schema = ? end and table_name = ? order by ORDINAL_POSITION ASC"#
+                .to_owned(),
+            vec![
+                Value::String(schema.to_owned()),
+                Value::String(schema.to_owned()),
+                Value::String(table.to_owned()),
+            ],
+        )
+    }
+
+    fn load_pkeys_sql(&self, schema: &str, table: &str) -> ProbeSql {
+        (
+            r#"select table_schema as table_schema, table_name as table_name, column_name as column_name, ordinal_position as ordinal_position
+    from INFORMATION_SCHEMA.key_column_usage where table_schema = ? and table_name = ? and CONSTRAINT_NAME = 'PRIMARY' order by ORDINAL_POSITION ASC "#
+                .to_owned(),
+            vec![Value::String(schema.to_owned()), Value::String(table.to_owned())],
+        )
+    }
+
+    fn load_foreign_keys_sql(&self, schema: &str, table: &str) -> Option<ProbeSql> {
+        Some((
+            r#"select constraint_name as constraint_name, column_name as column_name, referenced_table_schema as referenced_table_schema,
+    referenced_table_name as referenced_table_name, referenced_column_name as referenced_column_name
+    from INFORMATION_SCHEMA.key_column_usage where table_schema = ? and table_name = ? and referenced_table_name is not null
+    order by constraint_name, ordinal_position"#
+                .to_owned(),
+            vec![Value::String(schema.to_owned()), Value::String(table.to_owned())],
+        ))
+    }
+
+    fn condition_present(&self, field: &str, op: &str, values: usize) -> Option<String> {
+        match op {
+            "overlap" => Some(format!("json_overlaps({field}, ?)")),
+            "json_contains" => Some(format!("json_contains({field}, ?)")),
+            _ => ansi_condition_present(field, op, values),
+        }
+    }
+
+    fn bulk_upsert_sql(
+        &self,
+        table: &str,
+        columns: &[String],
+        values: &[String],
+        keys: &[String],
+        updates: &[String],
+    ) -> Option<String> {
+        let insert_sql = bulk_insert_sql(table, columns, values);
+        if keys.is_empty() {
+            return Some(insert_sql);
+        }
+        let sets = if updates.is_empty() {
+            keys.iter().map(|k| format!("{k} = {k}")).collect::<Vec<String>>()
+        } else {
+            updates.iter().map(|c| format!("{c} = values({c})")).collect::<Vec<String>>()
+        };
+        Some(format!("{} on duplicate key update {}", insert_sql, sets.join(",")))
+    }
+
+    fn supports_bulk_upsert(&self) -> bool {
+        true
+    }
+
+    fn column_type_sql(&self, col_type: &str, length: Option<i64>) -> String {
+        match col_type.to_lowercase().as_str() {
+            "bool" | "boolean" => "tinyint(1)".to_owned(),
+            "datetime" | "timestamp" => "datetime".to_owned(),
+            "float" | "double" => "double".to_owned(),
+            "binary" => "longblob".to_owned(),
+            "text" => "longtext".to_owned(),
+            _ => ansi_column_type(col_type, length),
+        }
+    }
+
+    /**
+     * modify column会替换整个字段定义，因此需要带上原有的not null、default、auto_increment及comment
+     */
+    fn modify_column_sql(&self, table: &str, column: &str, type_sql: &str, attrs: &ColumnAttributes) -> Option<String> {
+        let mut def = format!("{column} {type_sql}");
+        if !attrs.nullable {
+            def.push_str(" not null");
+        }
+        if let Some(default) = attrs.default.as_ref() {
+            def.push_str(&format!(" default {}", default_literal(default)));
+        }
+        if attrs.auto_increment {
+            def.push_str(" auto_increment");
+        }
+        if let Some(on_update) = attrs.on_update.as_ref() {
+            def.push_str(&format!(" on update {on_update}"));
+        }
+        if let Some(comment) = attrs.comment.as_ref().filter(|f| !f.is_empty()) {
+            def.push_str(&format!(" comment {}", quote_literal(comment)));
+        }
+        Some(format!("alter table {table} modify column {def}"))
+    }
+}
+
+pub struct PostgresDialect;
+
+impl Dialect for PostgresDialect {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    fn load_columns_sql(&self, schema: &str, table: &str) -> ProbeSql {
+        (
+            r#"SELECT table_schema as table_schema, table_name as table_name,  column_name as column_name,
+        column_default as column_default, udt_name as orginal_type, ordinal_position as ordinal_position, character_maximum_length as character_maximum_length,
+        is_nullable as is_nullable, numeric_precision as numeric_precision, numeric_scale as numeric_scale,
+        col_description((quote_ident(table_schema) || '.' || quote_ident(table_name))::regclass::oid, ordinal_position::int) as column_comment
+        FROM INFORMATION_SCHEMA.COLUMNS WHERE case when ? = '' then table_schema like '%' else table_schema = ? end and table_name = ? order by ORDINAL_POSITION ASC"#
+                .to_owned(),
+            vec![
+                Value::String(schema.to_owned()),
+                Value::String(schema.to_owned()),
+                Value::String(table.to_owned()),
+            ],
+        )
+    }
+
+    fn load_indexes_sql(&self, schema: &str, table: &str) -> Option<ProbeSql> {
+        Some((
+            r#"select i.relname as index_name, a.attname as column_name, case when ix.indisunique then 0 else 1 end as non_unique,
+    array_position(ix.indkey::int2[], a.attnum) as ordinal_position
+    from pg_class t join pg_namespace n on n.oid = t.relnamespace
+    join pg_index ix on ix.indrelid = t.oid join pg_class i on i.oid = ix.indexrelid
+    join pg_attribute a on a.attrelid = t.oid and a.attnum = any(ix.indkey)
+    where n.nspname = ? and t.relname = ? order by i.relname, ordinal_position"#
+                .to_owned(),
+            vec![Value::String(schema.to_owned()), Value::String(table.to_owned())],
+        ))
+    }
+
+    fn cast_placeholder(&self, field_type: &str) -> String {
+        if field_type.is_empty() {
+            "?".to_owned()
+        } else {
+            format!("?::{field_type}")
+        }
+    }
+
+    fn condition_present(&self, field: &str, op: &str, values: usize) -> Option<String> {
+        match op {
+            "ilike" => Some(format!("{field} ilike ?")),
+            "overlap" => Some(format!("{field}::text[] && array(select jsonb_array_elements_text(?::jsonb))")),
+            "json_contains" => Some(format!("{field}::jsonb @> ?::jsonb")),
+            _ => ansi_condition_present(field, op, values),
+        }
+    }
+
+    /**
+     * json/jsonb字段不能转换为text[]，overlap使用jsonb_exists_any判断数组中是否包含任一字符串元素
+     * 不使用?|运算符，以免其中的?被当作参数占位符
+     */
+    fn json_condition_present(&self, field: &str, op: &str, values: usize) -> Option<String> {
+        match op {
+            "overlap" => Some(format!(
+                "jsonb_exists_any({field}::jsonb, array(select jsonb_array_elements_text(?::jsonb)))"
+            )),
+            _ => self.condition_present(field, op, values),
+        }
+    }
+
+    fn bulk_upsert_sql(
+        &self,
+        table: &str,
+        columns: &[String],
+        values: &[String],
+        keys: &[String],
+        updates: &[String],
+    ) -> Option<String> {
+        Some(on_conflict_upsert_sql(table, columns, values, keys, updates))
+    }
+
+    fn supports_bulk_upsert(&self) -> bool {
+        true
+    }
+
+    fn column_type_sql(&self, col_type: &str, length: Option<i64>) -> String {
+        match col_type.to_lowercase().as_str() {
+            "json" => "jsonb".to_owned(),
+            "binary" => "bytea".to_owned(),
+            _ => ansi_column_type(col_type, length),
+        }
+    }
+
+    fn supports_transactional_ddl(&self) -> bool {
+        true
+    }
+}
+
+pub struct SqliteDialect;
+
+impl Dialect for SqliteDialect {
+    fn name(&self) -> &'static str {
+        "sqlite"
+    }
+
+    /**
+     * SQLite没有INFORMATION_SCHEMA，使用sqlite_master及pragma表值函数探测，schema固定为main
+     */
+    fn load_tables_sql(&self, _schema: &str) -> ProbeSql {
+        (
+            r#"select 'main' as table_catalog, 'main' as table_schema, case type when 'view' then 'VIEW' else 'BASE TABLE' end as table_type,
+    name as table_name from sqlite_master where type in ('table', 'view') and name not like 'sqlite_%' order by name"#
+                .to_owned(),
+            vec![],
+        )
+    }
+
+    fn load_one_table_sql(&self, _schema: &str, table: &str) -> ProbeSql {
+        (
+            r#"select 'main' as table_catalog, 'main' as table_schema, case type when 'view' then 'VIEW' else 'BASE TABLE' end as table_type,
+    name as table_name from sqlite_master where type in ('table', 'view') and name = ?"#
+                .to_owned(),
+            vec![Value::String(table.to_owned())],
+        )
+    }
+
+    fn load_columns_sql(&self, _schema: &str, table: &str) -> ProbeSql {
+        (
+            r#"select 'main' as table_schema, ? as table_name, name as column_name, dflt_value as column_default, lower(type) as orginal_type,
+    cid + 1 as ordinal_position, null as character_maximum_length, case when "notnull" = 1 or pk > 0 then 'NO' else 'YES' end as is_nullable,
+    null as numeric_precision, null as numeric_scale, null as column_comment from pragma_table_info(?) order by cid"#
+                .to_owned(),
+            vec![Value::String(table.to_owned()), Value::String(table.to_owned())],
+        )
+    }
+
+    fn load_pkeys_sql(&self, _schema: &str, table: &str) -> ProbeSql {
+        (
+            r#"select 'main' as table_schema, ? as table_name, name as column_name, pk as ordinal_position
+    from pragma_table_info(?) where pk > 0 order by pk"#
+                .to_owned(),
+            vec![Value::String(table.to_owned()), Value::String(table.to_owned())],
+        )
+    }
+
+    fn load_indexes_sql(&self, _schema: &str, table: &str) -> Option<ProbeSql> {
+        Some((
+            r#"select il.name as index_name, ii.name as column_name, case when il."unique" = 1 then 0 else 1 end as non_unique,
+    ii.seqno + 1 as ordinal_position from pragma_index_list(?) il join pragma_index_info(il.name) ii order by il.name, ii.seqno"#
+                .to_owned(),
+            vec![Value::String(table.to_owned())],
+        ))
+    }
+
+    fn load_foreign_keys_sql(&self, _schema: &str, table: &str) -> Option<ProbeSql> {
+        Some((
+            r#"select 'fk_' || id as constraint_name, "from" as column_name, 'main' as referenced_table_schema,
+    "table" as referenced_table_name, "to" as referenced_column_name from pragma_foreign_key_list(?) order by id, seq"#
+                .to_owned(),
+            vec![Value::String(table.to_owned())],
+        ))
+    }
+
+    fn condition_present(&self, field: &str, op: &str, values: usize) -> Option<String> {
+        match op {
+            "starts_with" | "ends_with" | "contains" => Some(format!("{field} like ? escape '\\'")),
+            "overlap" => Some(format!("exists (select 1 from json_each({field}) where value in (select value from json_each(?)))")),
+            _ => ansi_condition_present(field, op, values),
+        }
+    }
+
+    fn bulk_max_params(&self) -> usize {
+        999
+    }
+
+    fn bulk_upsert_sql(
+        &self,
+        table: &str,
+        columns: &[String],
+        values: &[String],
+        keys: &[String],
+        updates: &[String],
+    ) -> Option<String> {
+        Some(on_conflict_upsert_sql(table, columns, values, keys, updates))
+    }
+
+    fn supports_bulk_upsert(&self) -> bool {
+        true
+    }
+
+    fn column_type_sql(&self, col_type: &str, length: Option<i64>) -> String {
+        match col_type.to_lowercase().as_str() {
+            "integer" | "int" | "long" => "integer".to_owned(),
+            "datetime" | "timestamp" => "datetime".to_owned(),
+            _ => ansi_column_type(col_type, length),
+        }
+    }
+
+    /**
+     * SQLite不支持修改字段的类型
+     */
+    fn modify_column_sql(&self, _table: &str, _column: &str, _type_sql: &str, _attrs: &ColumnAttributes) -> Option<String> {
+        None
+    }
+
+    fn supports_transactional_ddl(&self) -> bool {
+        true
+    }
+}
+
+pub struct MssqlDialect;
+
+impl Dialect for MssqlDialect {
+    fn name(&self) -> &'static str {
+        "mssql"
+    }
+
+    fn quote_identifier(&self, name: &str) -> String {
+        quote_with(name, '[', ']')
+    }
+
+    fn load_columns_sql(&self, schema: &str, table: &str) -> ProbeSql {
+        (
+            r#"SELECT table_schema as table_schema, table_name as table_name,  column_name as column_name,
+            column_default as column_default, data_type as orginal_type, ordinal_position as ordinal_position, character_maximum_length as character_maximum_length,
+            is_nullable as is_nullable, numeric_precision as numeric_precision, numeric_scale as numeric_scale,
+            (select cast(ep.value as nvarchar(4000)) from sys.extended_properties ep where ep.name = 'MS_Description' and ep.major_id = object_id(quote_name(table_schema) + '.' + quote_name(table_name))
+            and ep.minor_id = columnproperty(object_id(quote_name(table_schema) + '.' + quote_name(table_name)), column_name, 'ColumnId')) as column_comment
+            FROM INFORMATION_SCHEMA.COLUMNS WHERE table_schema like (case when ? = '' then '%' else CONCAT(?, '%') end) and table_name = ? order by ORDINAL_POSITION ASC"#
+                .to_owned(),
+            vec![
+                Value::String(schema.to_owned()),
+                Value::String(schema.to_owned()),
+                Value::String(table.to_owned()),
+            ],
+        )
+    }
+
+    fn load_indexes_sql(&self, schema: &str, table: &str) -> Option<ProbeSql> {
+        Some((
+            r#"select i.name as index_name, c.name as column_name, case when i.is_unique = 1 then 0 else 1 end as non_unique, ic.key_ordinal as ordinal_position
+    from sys.indexes i join sys.index_columns ic on i.object_id = ic.object_id and i.index_id = ic.index_id
+    join sys.columns c on c.object_id = ic.object_id and c.column_id = ic.column_id
+    join sys.tables t on t.object_id = i.object_id join sys.schemas s on s.schema_id = t.schema_id
+    where s.name = ? and t.name = ? and i.name is not null order by i.name, ic.key_ordinal"#
+                .to_owned(),
+            vec![Value::String(schema.to_owned()), Value::String(table.to_owned())],
+        ))
+    }
+
+    /**
+     * 复合外键在constraint_column_usage中无法与本表的字段一一对应，因此使用sys.foreign_key_columns按序号配对
+     */
+    fn load_foreign_keys_sql(&self, schema: &str, table: &str) -> Option<ProbeSql> {
+        Some((
+            r#"select fk.name as constraint_name, pc.name as column_name, rs.name as referenced_table_schema,
+    rt.name as referenced_table_name, rc.name as referenced_column_name
+    from sys.foreign_keys fk join sys.foreign_key_columns fkc on fkc.constraint_object_id = fk.object_id
+    join sys.tables t on t.object_id = fk.parent_object_id join sys.schemas s on s.schema_id = t.schema_id
+    join sys.columns pc on pc.object_id = fkc.parent_object_id and pc.column_id = fkc.parent_column_id
+    join sys.tables rt on rt.object_id = fkc.referenced_object_id join sys.schemas rs on rs.schema_id = rt.schema_id
+    join sys.columns rc on rc.object_id = fkc.referenced_object_id and rc.column_id = fkc.referenced_column_id
+    where s.name = ? and t.name = ? order by fk.name, fkc.constraint_column_id"#
+                .to_owned(),
+            vec![Value::String(schema.to_owned()), Value::String(table.to_owned())],
+        ))
+    }
+
+    /**
+     * SQL Server使用offset ... fetch next，且必须有order by
+     */
+    fn paginate(&self, sql: &str, limit: u64, offset: u64) -> String {
+        let order = if has_top_level_order_by(sql) {
+            ""
+        } else {
+            " order by (select null)"
+        };
+        format!("{sql}{order} offset {offset} rows fetch next {limit} rows only ")
+    }
+
+    fn condition_present(&self, field: &str, op: &str, values: usize) -> Option<String> {
+        match op {
+            "starts_with" | "ends_with" | "contains" => Some(format!("{field} like ? escape '\\'")),
+            _ => ansi_condition_present(field, op, values),
+        }
+    }
+
+    fn bulk_max_params(&self) -> usize {
+        2000
+    }
+
+    fn bulk_upsert_sql(
+        &self,
+        table: &str,
+        columns: &[String],
+        values: &[String],
+        keys: &[String],
+        updates: &[String],
+    ) -> Option<String> {
+        if keys.is_empty() {
+            return Some(bulk_insert_sql(table, columns, values));
+        }
+        let on = keys.iter().map(|k| format!("_tgt.{k} = _src.{k}")).collect::<Vec<String>>();
+        let matched = if updates.is_empty() {
+            String::new()
+        } else {
+            let sets = updates.iter().map(|c| format!("{c} = _src.{c}")).collect::<Vec<String>>();
+            format!(" when matched then update set {}", sets.join(","))
+        };
+        Some(format!(
+            "merge into {} as _tgt using (values {}) as _src ({}) on {}{} when not matched then insert ({}) values ({});",
+            table,
+            values.join(","),
+            columns.join(","),
+            on.join(" and "),
+            matched,
+            columns.join(","),
+            columns.iter().map(|c| format!("_src.{c}")).collect::<Vec<String>>().join(",")
+        ))
+    }
+
+    fn supports_bulk_upsert(&self) -> bool {
+        true
+    }
+
+    fn column_type_sql(&self, col_type: &str, length: Option<i64>) -> String {
+        let length = length.filter(|f| *f > 0);
+        match col_type.to_lowercase().as_str() {
+            "bool" | "boolean" => "bit".to_owned(),
+            "datetime" | "timestamp" => "datetime2".to_owned(),
+            "float" | "double" => "float".to_owned(),
+            "json" | "text" => "nvarchar(max)".to_owned(),
+            "binary" => "varbinary(max)".to_owned(),
+            "integer" | "int" | "long" | "date" | "time" | "decimal" | "number" | "numeric" => {
+                ansi_column_type(col_type, length)
+            }
+            _ => match length {
+                Some(len) if len <= 4000 => format!("nvarchar({len})"),
+                Some(_) => "nvarchar(max)".to_owned(),
+                None => "nvarchar(255)".to_owned(),
+            },
+        }
+    }
+
+    fn add_column_sql(&self, table: &str, column: &str) -> String {
+        format!("alter table {table} add {column}")
+    }
+
+    /**
+     * alter column未指定null/not null时字段会变为可为空，因此需要带上原有的可空性，default约束不受影响
+     */
+    fn modify_column_sql(&self, table: &str, column: &str, type_sql: &str, attrs: &ColumnAttributes) -> Option<String> {
+        let nullable = if attrs.nullable { "null" } else { "not null" };
+        Some(format!("alter table {table} alter column {column} {type_sql} {nullable}"))
+    }
+
+    fn supports_transactional_ddl(&self) -> bool {
+        true
+    }
+}
+
+pub struct TdengineDialect;
+
+impl Dialect for TdengineDialect {
+    fn name(&self) -> &'static str {
+        "taos"
+    }
+
+    fn quote_identifier(&self, name: &str) -> String {
+        quote_with(name, '`', '`')
+    }
+
+    /**
+     * TDengine 3.x的information_schema，超级表与普通表分别列出
+     */
+    fn load_tables_sql(&self, schema: &str) -> ProbeSql {
+        (
+            r#"select db_name as table_catalog, db_name as table_schema, type as table_type, table_name as table_name
+    from information_schema.ins_tables where db_name = ?
+    union all select db_name as table_catalog, db_name as table_schema, 'SUPER_TABLE' as table_type, stable_name as table_name
+    from information_schema.ins_stables where db_name = ?"#
+                .to_owned(),
+            vec![Value::String(schema.to_owned()), Value::String(schema.to_owned())],
+        )
+    }
+
+    fn load_one_table_sql(&self, schema: &str, table: &str) -> ProbeSql {
+        (
+            r#"select db_name as table_catalog, db_name as table_schema, type as table_type, table_name as table_name
+    from information_schema.ins_tables where db_name = ? and table_name = ?
+    union all select db_name as table_catalog, db_name as table_schema, 'SUPER_TABLE' as table_type, stable_name as table_name
+    from information_schema.ins_stables where db_name = ? and stable_name = ?"#
+                .to_owned(),
+            vec![
+                Value::String(schema.to_owned()),
+                Value::String(table.to_owned()),
+                Value::String(schema.to_owned()),
+                Value::String(table.to_owned()),
+            ],
+        )
+    }
+
+    fn load_columns_sql(&self, schema: &str, table: &str) -> ProbeSql {
+        (
+            r#"select db_name as table_schema, table_name as table_name, col_name as column_name, null as column_default,
+    lower(col_type) as orginal_type, null as ordinal_position, col_length as character_maximum_length,
+    case when col_nullable = 1 then 'YES' else 'NO' end as is_nullable, col_precision as numeric_precision, col_scale as numeric_scale, null as column_comment
+    from information_schema.ins_columns where db_name = ? and table_name = ?"#
+                .to_owned(),
+            vec![Value::String(schema.to_owned()), Value::String(table.to_owned())],
+        )
+    }
+
+    /**
+     * TDengine的主键为第一个timestamp字段
+     */
+    fn load_pkeys_sql(&self, schema: &str, table: &str) -> ProbeSql {
+        (
+            r#"select db_name as table_schema, table_name as table_name, col_name as column_name, 1 as ordinal_position
+    from information_schema.ins_columns where db_name = ? and table_name = ? and col_type = 'TIMESTAMP' limit 1"#
+                .to_owned(),
+            vec![Value::String(schema.to_owned()), Value::String(table.to_owned())],
+        )
+    }
+
+    fn load_indexes_sql(&self, _schema: &str, _table: &str) -> Option<ProbeSql> {
+        None
+    }
+
+    fn load_foreign_keys_sql(&self, _schema: &str, _table: &str) -> Option<ProbeSql> {
+        None
+    }
+
+    fn column_type_sql(&self, col_type: &str, length: Option<i64>) -> String {
+        let length = length.filter(|f| *f > 0);
+        match col_type.to_lowercase().as_str() {
+            "bool" | "boolean" => "bool".to_owned(),
+            "datetime" | "timestamp" | "date" => "timestamp".to_owned(),
+            "float" | "double" | "decimal" | "number" | "numeric" => "double".to_owned(),
+            "json" | "text" => "nchar(4096)".to_owned(),
+            "binary" => format!("varbinary({})", length.unwrap_or(1024)),
+            "integer" | "int" | "long" => "bigint".to_owned(),
+            _ => format!("nchar({})", length.unwrap_or(255)),
+        }
+    }
+
+    /**
+     * TDengine以第一个timestamp字段作为主键，不支持primary key子句
+     */
+    fn create_table_sql(&self, table: &str, columns: &[String], _keys: &[String]) -> String {
+        format!("create table {} ({})", table, columns.join(", "))
+    }
+
+    fn modify_column_sql(&self, table: &str, column: &str, type_sql: &str, _attrs: &ColumnAttributes) -> Option<String> {
+        Some(format!("alter table {table} modify column {column} {type_sql}"))
+    }
+}
+
+/**
+ * 标准SQL的条件表达，各方言覆盖condition_present时回退使用
+ */
+fn ansi_condition_present(field: &str, op: &str, values: usize) -> Option<String> {
+    match op {
+        "is null" | "is not null" => Some(format!("{field} {op}")),
+        "between" | "not between" => Some(format!("{field} {op} ? and ?")),
+        "in" | "not in" => {
+            let placeholders = vec!["?"; values.max(1)].join(",");
+            Some(format!("{field} {op} ({placeholders})"))
+        }
+        "starts_with" | "ends_with" | "contains" => Some(format!("{field} like ?")),
+        "ilike" => Some(format!("lower({field}) like lower(?)")),
+        "overlap" | "json_contains" => None,
+        _ => Some(format!("{field} {op} ?")),
+    }
+}
+
+fn on_conflict_upsert_sql(
+    table: &str,
+    columns: &[String],
+    values: &[String],
+    keys: &[String],
+    updates: &[String],
+) -> String {
+    let insert_sql = bulk_insert_sql(table, columns, values);
+    if keys.is_empty() {
+        insert_sql
+    } else if updates.is_empty() {
+        format!("{} on conflict ({}) do nothing", insert_sql, keys.join(","))
+    } else {
+        let sets = updates.iter().map(|c| format!("{c} = excluded.{c}")).collect::<Vec<String>>();
+        format!(
+            "{} on conflict ({}) do update set {}",
+            insert_sql,
+            keys.join(","),
+            sets.join(",")
+        )
+    }
+}
+
+static MYSQL: MysqlDialect = MysqlDialect;
+static POSTGRES: PostgresDialect = PostgresDialect;
+static SQLITE: SqliteDialect = SqliteDialect;
+static MSSQL: MssqlDialect = MssqlDialect;
+static TDENGINE: TdengineDialect = TdengineDialect;
+
+/**
+ * 根据驱动的名称获取方言，未知的驱动按mysql处理
+ */
+pub fn get_dialect(driver_name: &str) -> &'static dyn Dialect {
+    match driver_name.to_lowercase().as_str() {
+        "postgres" | "postgresql" | "pg" => &POSTGRES,
+        "sqlite" => &SQLITE,
+        "mssql" | "sqlserver" => &MSSQL,
+        "taos" | "tdengine" => &TDENGINE,
+        _ => &MYSQL,
+    }
+}
@@ -1,6 +1,13 @@
+use rbatis::executor::Executor;
 use rbatis::rbdc::db::Driver;
 use substring::Substring;
 
+pub mod dialect;
+pub mod pool;
+
+pub use dialect::{get_dialect, ColumnAttributes, Dialect};
+pub use pool::{create_metered_rbatis, MeteredPool, PoolOptions, PoolStats};
+
 pub fn get_sql_driver(url: &str) -> impl Driver {
     let driver: Box<dyn Driver> = match url
         .find("://")
@@ -23,65 +30,74 @@ pub fn get_driver_name(driver: &rbatis::RBatis) -> &str {
     driver.driver_type().unwrap_or("mysql")
 }
 
-pub fn get_load_column_sql(driver: &rbatis::RBatis) -> &str {
-    match get_driver_name(driver) {
-        "postgres" => {
-            "SELECT table_schema as table_schema, table_name as table_name,  column_name as column_name,
-        column_default as column_default, udt_name as orginal_type, ordinal_position as ordinal_position, character_maximum_length as character_maximum_length, 
-        is_nullable as is_nullable, numeric_precision as numeric_precision, numeric_scale as numeric_scale
-        FROM INFORMATION_SCHEMA.COLUMNS WHERE case when ? = '' then table_schema like '%' else table_schema = ? end and table_name = ? order by ORDINAL_POSITION ASC"
-        },
-        "mssql" => {
-            "SELECT table_schema as table_schema, table_name as table_name,  column_name as column_name,
-            column_default as column_default, data_type as orginal_type, ordinal_position as ordinal_position, character_maximum_length as character_maximum_length, 
-            is_nullable as is_nullable, numeric_precision as numeric_precision, numeric_scale as numeric_scale
-            FROM INFORMATION_SCHEMA.COLUMNS WHERE table_schema like (case when ? = '' then '%' else CONCAT(?, '%') end) and table_name = ? order by ORDINAL_POSITION ASC"
-        },
-        _ => {
-            "SELECT table_schema as table_schema, table_name as table_name,  column_name as column_name,
-        column_default as column_default, data_type as orginal_type, ordinal_position as ordinal_position, character_maximum_length as character_maximum_length, 
-        is_nullable as is_nullable, numeric_precision as numeric_precision, numeric_scale as numeric_scale
-        FROM INFORMATION_SCHEMA.COLUMNS WHERE case when ? = '' then table_schema like '%' else table_schema = ? end and table_name = ? order by ORDINAL_POSITION ASC" 
-        }
-    }
+pub fn get_executor_driver_name(executor: &dyn Executor) -> &str {
+    executor.driver_type().unwrap_or("mysql")
 }
 
-pub fn get_load_tables_sql(_driver: &rbatis::RBatis) -> &str {
-    r#"SELECT table_catalog as table_catalog, table_schema as table_schema, table_type as table_type, 
-    table_name as table_name
-    FROM INFORMATION_SCHEMA.TABLES WHERE table_schema = ?"#
+/**
+ * 获取RBatis所连接数据库的方言
+ */
+pub fn get_rbatis_dialect(driver: &rbatis::RBatis) -> &'static dyn Dialect {
+    get_dialect(get_driver_name(driver))
 }
 
-pub fn get_load_one_tables_sql(_driver: &rbatis::RBatis) -> &str {
-    r#"SELECT table_catalog as table_catalog, table_schema as table_schema, table_type as table_type, 
-    table_name as table_name
-    FROM INFORMATION_SCHEMA.TABLES WHERE table_schema = ? and table_name = ?"#
+/**
+ * 获取Executor所连接数据库的方言
+ */
+pub fn get_executor_dialect(executor: &dyn Executor) -> &'static dyn Dialect {
+    get_dialect(get_executor_driver_name(executor))
 }
 
+pub fn get_update_field_value_present(driver: &rbatis::RBatis, field: &str, field_type: &str) -> String {
+    format!("{field} = {}", get_rbatis_dialect(driver).cast_placeholder(field_type))
+}
 
-pub fn get_load_table_pkey_sql(_driver: &rbatis::RBatis) -> &str {
-    r#"select table_schema as table_schema, table_name as table_name, column_name as column_name, ordinal_position as ordinal_position
-    from INFORMATION_SCHEMA.key_column_usage where  table_schema = ? and table_name = ? and (CONSTRAINT_NAME = 'PRIMARY' OR CONSTRAINT_NAME like '%_pk' OR CONSTRAINT_NAME like '%_pkey') order by ORDINAL_POSITION ASC "#
+pub fn get_insert_field_value_present(driver: &rbatis::RBatis, _field: &str, field_type: &str) -> String {
+    get_rbatis_dialect(driver).cast_placeholder(field_type)
 }
 
-pub fn get_update_field_value_present(driver: &rbatis::RBatis, field: &str, field_type: &str) -> String {
-    match get_driver_name(driver) {
-        "postgres" => {
-            format!("{field} = ?::{field_type}")
-        },
-        _ => {
-            format!("{field} = ?")
-        }
-    }
+/**
+ * 根据数据库类型生成查询条件的SQL片段，使用?作为参数占位符
+ * values为in/not in中参数的个数，其它操作符的参数个数是固定的
+ * 返回None表示该数据库不支持该操作符
+ */
+pub fn get_condition_present(driver_name: &str, field: &str, op: &str, values: usize) -> Option<String> {
+    get_dialect(driver_name).condition_present(field, op, values)
 }
 
-pub fn get_insert_field_value_present(driver: &rbatis::RBatis, _field: &str, field_type: &str) -> String {
-    match get_driver_name(driver) {
-        "postgres" => {
-            format!("?::{field_type}")
-        },
-        _ => {
-            "?".to_string()
-        }
-    }
-}
\ No newline at end of file
+/**
+ * json类型字段的查询条件，参见get_condition_present
+ */
+pub fn get_json_condition_present(driver_name: &str, field: &str, op: &str, values: usize) -> Option<String> {
+    get_dialect(driver_name).json_condition_present(field, op, values)
+}
+
+/**
+ * 单条语句允许的最大参数个数，用于批量插入时计算每批的行数
+ */
+pub fn get_bulk_max_params(driver_name: &str) -> usize {
+    get_dialect(driver_name).bulk_max_params()
+}
+
+/**
+ * 生成多行插入的SQL，values为每一行的占位符，如(?,?)
+ * keys不为空时使用数据库原生的upsert语法，mysql为on duplicate key update，postgres/sqlite为on conflict，mssql为merge
+ * updates为主键冲突时需要更新的字段，返回None表示该数据库不支持upsert
+ */
+pub fn get_bulk_upsert_sql(
+    driver_name: &str,
+    table: &str,
+    columns: &[String],
+    values: &[String],
+    keys: &[String],
+    updates: &[String],
+) -> Option<String> {
+    get_dialect(driver_name).bulk_upsert_sql(table, columns, values, keys, updates)
+}
+
+/**
+ * 是否支持批量保存时使用数据库原生的upsert语法
+ */
+pub fn is_bulk_upsert_supported(driver_name: &str) -> bool {
+    get_dialect(driver_name).supports_bulk_upsert()
+}
@@ -0,0 +1,341 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+use rbatis::{
+    async_trait,
+    rbdc::{
+        db::{ConnectOptions, Connection, Driver, ExecResult, Row},
+        pool::{conn_manager::ConnManager, Pool},
+        Error,
+    },
+    DefaultPool, RBatis,
+};
+use rbs::Value;
+
+use crate::get_sql_driver;
+
+type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/**
+ * 连接池的配置，为None时使用连接池的缺省值
+ * idle_timeout为连接自上次使用起的空闲时间，max_lifetime为连接自创建起的存活时间
+ * 超过时间的连接在下次从池中取出时关闭并重新建立
+ */
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PoolOptions {
+    pub max_open: Option<u64>,
+    pub acquire_timeout: Option<Duration>,
+    pub idle_timeout: Option<Duration>,
+    pub max_lifetime: Option<Duration>,
+}
+
+/**
+ * 连接池的运行状态
+ * in_use为正在使用的连接数，waits为正在等待获取连接的请求数
+ * acquired/acquire_failed为累计获取连接成功/失败的次数，acquire_avg_micros/acquire_max_micros为获取连接的平均/最大耗时（微秒）
+ * expired为因空闲或存活超时而重建的连接数
+ */
+#[derive(Debug, Default, Clone)]
+pub struct PoolStats {
+    pub max_open: u64,
+    pub connections: u64,
+    pub in_use: u64,
+    pub idle: u64,
+    pub waits: u64,
+    pub acquired: u64,
+    pub acquire_failed: u64,
+    pub acquire_avg_micros: u64,
+    pub acquire_max_micros: u64,
+    pub expired: u64,
+}
+
+#[derive(Debug, Default)]
+struct PoolMetrics {
+    acquired: AtomicU64,
+    acquire_failed: AtomicU64,
+    acquire_micros: AtomicU64,
+    acquire_max_micros: AtomicU64,
+    expired: AtomicU64,
+}
+
+impl PoolMetrics {
+    fn record<T>(&self, start: Instant, ret: &Result<T, Error>) {
+        if ret.is_ok() {
+            let micros = start.elapsed().as_micros() as u64;
+            self.acquired.fetch_add(1, Ordering::Relaxed);
+            self.acquire_micros.fetch_add(micros, Ordering::Relaxed);
+            self.acquire_max_micros.fetch_max(micros, Ordering::Relaxed);
+        } else {
+            self.acquire_failed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/**
+ * 包装数据库驱动，使建立的连接记录创建和使用的时间
+ */
+#[derive(Debug)]
+struct LifetimeDriver {
+    inner: Arc<Box<dyn Driver>>,
+    options: Arc<RwLock<PoolOptions>>,
+    metrics: Arc<PoolMetrics>,
+}
+
+impl LifetimeDriver {
+    fn wrap(&self, conn: Box<dyn Connection>) -> Box<dyn Connection> {
+        let now = Instant::now();
+        Box::new(LifetimeConnection {
+            inner: conn,
+            created: now,
+            last_used: now,
+            options: self.options.clone(),
+            metrics: self.metrics.clone(),
+        })
+    }
+}
+
+impl Driver for LifetimeDriver {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn connect(&self, url: &str) -> BoxFuture<'_, Result<Box<dyn Connection>, Error>> {
+        let fut = self.inner.connect(url);
+        Box::pin(async move { fut.await.map(|conn| self.wrap(conn)) })
+    }
+
+    fn connect_opt<'a>(
+        &'a self,
+        opt: &'a dyn ConnectOptions,
+    ) -> BoxFuture<'a, Result<Box<dyn Connection>, Error>> {
+        let fut = self.inner.connect_opt(opt);
+        Box::pin(async move { fut.await.map(|conn| self.wrap(conn)) })
+    }
+
+    fn default_option(&self) -> Box<dyn ConnectOptions> {
+        self.inner.default_option()
+    }
+}
+
+/**
+ * 连接池从池中取出连接时会先ping，已超时的连接在ping时返回错误，由连接池关闭并重新建立连接
+ */
+struct LifetimeConnection {
+    inner: Box<dyn Connection>,
+    created: Instant,
+    last_used: Instant,
+    options: Arc<RwLock<PoolOptions>>,
+    metrics: Arc<PoolMetrics>,
+}
+
+impl LifetimeConnection {
+    fn is_expired(&self) -> bool {
+        let opts = self.options.read().unwrap();
+        opts.max_lifetime
+            .map(|f| self.created.elapsed() >= f)
+            .unwrap_or(false)
+            || opts
+                .idle_timeout
+                .map(|f| self.last_used.elapsed() >= f)
+                .unwrap_or(false)
+    }
+
+    fn touch(&mut self) -> &mut Box<dyn Connection> {
+        self.last_used = Instant::now();
+        &mut self.inner
+    }
+}
+
+impl Connection for LifetimeConnection {
+    fn get_rows(
+        &mut self,
+        sql: &str,
+        params: Vec<Value>,
+    ) -> BoxFuture<'_, Result<Vec<Box<dyn Row>>, Error>> {
+        self.touch().get_rows(sql, params)
+    }
+
+    fn get_values(
+        &mut self,
+        sql: &str,
+        params: Vec<Value>,
+    ) -> BoxFuture<'_, Result<Vec<Value>, Error>> {
+        self.touch().get_values(sql, params)
+    }
+
+    fn exec(&mut self, sql: &str, params: Vec<Value>) -> BoxFuture<'_, Result<ExecResult, Error>> {
+        self.touch().exec(sql, params)
+    }
+
+    fn ping(&mut self) -> BoxFuture<'_, Result<(), Error>> {
+        if self.is_expired() {
+            self.metrics.expired.fetch_add(1, Ordering::Relaxed);
+            return Box::pin(async { Err(Error::from("the connection was expired")) });
+        }
+        self.inner.ping()
+    }
+
+    fn close(&mut self) -> BoxFuture<'_, Result<(), Error>> {
+        self.inner.close()
+    }
+
+    fn begin(&mut self) -> BoxFuture<'_, Result<(), Error>> {
+        self.touch().begin()
+    }
+
+    fn commit(&mut self) -> BoxFuture<'_, Result<(), Error>> {
+        self.touch().commit()
+    }
+
+    fn rollback(&mut self) -> BoxFuture<'_, Result<(), Error>> {
+        self.touch().rollback()
+    }
+}
+
+#[derive(Debug)]
+struct MeteredPoolInner {
+    pool: DefaultPool,
+    options: Arc<RwLock<PoolOptions>>,
+    metrics: Arc<PoolMetrics>,
+}
+
+/**
+ * 在缺省连接池（FastPool）之上增加空闲超时、存活时间的控制以及获取连接的统计
+ * Clone得到的是同一个连接池
+ */
+#[derive(Debug, Clone)]
+pub struct MeteredPool(Arc<MeteredPoolInner>);
+
+impl MeteredPool {
+    /**
+     * 应用连接池的配置，可用于已经在使用的连接池
+     */
+    pub fn apply_options(&self, options: &PoolOptions) {
+        if let Some(max_open) = options.max_open.filter(|f| *f > 0) {
+            self.0.pool.inner.set_max_open(max_open);
+        }
+        self.0.pool.timeout.store(options.acquire_timeout);
+        *self.0.options.write().unwrap() = options.clone();
+    }
+
+    pub fn options(&self) -> PoolOptions {
+        self.0.options.read().unwrap().clone()
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        let state = self.0.pool.inner.state();
+        let metrics = &self.0.metrics;
+        let acquired = metrics.acquired.load(Ordering::Relaxed);
+        let acquire_micros = metrics.acquire_micros.load(Ordering::Relaxed);
+        PoolStats {
+            max_open: state.max_open,
+            connections: state.connections,
+            in_use: state.in_use,
+            idle: state.idle,
+            waits: state.waits,
+            acquired,
+            acquire_failed: metrics.acquire_failed.load(Ordering::Relaxed),
+            acquire_avg_micros: acquire_micros.checked_div(acquired).unwrap_or_default(),
+            acquire_max_micros: metrics.acquire_max_micros.load(Ordering::Relaxed),
+            expired: metrics.expired.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[async_trait]
+impl Pool for MeteredPool {
+    fn new(manager: ConnManager) -> Result<Self, Error>
+    where
+        Self: Sized,
+    {
+        let options = Arc::new(RwLock::new(PoolOptions::default()));
+        let metrics = Arc::new(PoolMetrics::default());
+        let driver: Box<dyn Driver> = Box::new(LifetimeDriver {
+            inner: manager.driver.clone(),
+            options: options.clone(),
+            metrics: metrics.clone(),
+        });
+        let pool = DefaultPool::new(ConnManager::new_arc(Arc::new(driver), manager.option.clone()))?;
+        Ok(Self(Arc::new(MeteredPoolInner {
+            pool,
+            options,
+            metrics,
+        })))
+    }
+
+    async fn get(&self) -> Result<Box<dyn Connection>, Error> {
+        let start = Instant::now();
+        let ret = self.0.pool.get().await;
+        self.0.metrics.record(start, &ret);
+        ret
+    }
+
+    async fn get_timeout(&self, d: Duration) -> Result<Box<dyn Connection>, Error> {
+        let start = Instant::now();
+        let ret = self.0.pool.get_timeout(d).await;
+        self.0.metrics.record(start, &ret);
+        ret
+    }
+
+    async fn set_timeout(&self, timeout: Option<Duration>) {
+        self.0.options.write().unwrap().acquire_timeout = timeout;
+        self.0.pool.set_timeout(timeout).await;
+    }
+
+    async fn set_conn_max_lifetime(&self, max_lifetime: Option<Duration>) {
+        self.0.options.write().unwrap().max_lifetime = max_lifetime;
+    }
+
+    async fn set_max_idle_conns(&self, n: u64) {
+        self.0.pool.set_max_idle_conns(n).await;
+    }
+
+    async fn set_max_open_conns(&self, n: u64) {
+        self.0.options.write().unwrap().max_open = Some(n);
+        self.0.pool.set_max_open_conns(n).await;
+    }
+
+    async fn state(&self) -> Value {
+        let mut state = self.0.pool.state().await;
+        if let Value::Map(map) = &mut state {
+            let stats = self.stats();
+            map.insert("acquired".into(), stats.acquired.into());
+            map.insert("acquire_failed".into(), stats.acquire_failed.into());
+            map.insert("acquire_avg_micros".into(), stats.acquire_avg_micros.into());
+            map.insert("acquire_max_micros".into(), stats.acquire_max_micros.into());
+            map.insert("expired".into(), stats.expired.into());
+        }
+        state
+    }
+
+    fn driver_type(&self) -> &str {
+        self.0.pool.driver_type()
+    }
+}
+
+/**
+ * 按url创建使用MeteredPool的RBatis，返回的MeteredPool用于调整配置及获取运行状态
+ */
+pub fn create_metered_rbatis(
+    url: &str,
+    options: &PoolOptions,
+) -> Result<(RBatis, MeteredPool), Error> {
+    if url.is_empty() {
+        return Err(Error::from("[rb] link url is empty!"));
+    }
+    let driver = get_sql_driver(url);
+    let mut option = driver.default_option();
+    option.set_uri(url)?;
+    let pool = MeteredPool::new(ConnManager::new_opt_box(Box::new(driver), option))?;
+    pool.apply_options(options);
+    let rb = RBatis::new();
+    rb.init_pool(pool.clone())?;
+    Ok((rb, pool))
+}
@@ -57,8 +57,47 @@ impl JwtUserClaims {
     pub fn is_anonymous(&self) -> bool {
         self.username == *"anonymous"
     }
+
+    /**
+     * 超级管理员显式声明跨租户访问后，domain为TENANT_ALL，此时不进行租户隔离
+     * 非超级管理员的domain为TENANT_ALL时仍按普通租户处理
+     */
+    pub fn is_cross_tenant(&self) -> bool {
+        self.superadmin && self.domain == TENANT_ALL
+    }
+
+    /**
+     * 返回跨租户访问的用户信息，只对超级管理员有效
+     */
+    pub fn cross_tenant(&self) -> Self {
+        let mut jwt = self.clone();
+        if jwt.superadmin {
+            jwt.domain = TENANT_ALL.to_owned();
+        }
+        jwt
+    }
+
+    /**
+     * 租户隔离时使用的租户标识，跨租户访问时返回None
+     * domain为空、为TENANT_ALL或者包含引号、反斜杠时返回错误，不允许在租户不确定的情况下访问数据
+     */
+    pub fn tenant(&self) -> Result<Option<String>> {
+        if self.is_cross_tenant() {
+            return Ok(None);
+        }
+        let domain = self.domain.trim();
+        if domain.is_empty() || domain == TENANT_ALL || domain.contains(['\'', '"', '\\']) {
+            return Err(anyhow::anyhow!("The tenant of user {} is not determined.", self.username));
+        }
+        Ok(Some(domain.to_owned()))
+    }
 }
 
+/**
+ * 表示所有租户的domain，只能由超级管理员通过请求头X-Tenant-Scope: all显式使用
+ */
+pub const TENANT_ALL: &str = "*";
+
 #[derive(Debug, Clone, Derivative, Serialize, Deserialize)]
 #[derivative(Default)]
 #[serde(default)]
@@ -4,20 +4,28 @@ use crate::service::script::ExtensionRegistry;
 use crate::service::sdk::InvokeUri;
 use crate::service::starter::MxStoreService;
 use crate::utils::global_data::i64_from_str;
+use crate::utils::redis::RedisConfig;
+use crate::utils::redis_cmd::{redis_method_kind, RedisMethodKind};
 use anyhow::anyhow;
 use auth::JwtUserClaims;
+use base64::Engine;
+use chimes_dbs_factory::{get_condition_present, get_dialect, get_json_condition_present};
+use chimes_dbs_factory::PoolOptions;
 use futures_lite::Future;
 use itertools::Itertools;
 use rbatis::Page;
+use rbatis::IPageRequest;
 use rbatis::PageRequest;
 use serde::Deserialize;
 use serde::Serialize;
+use serde_json::json;
 use serde_json::Value;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::Duration;
 
 pub mod auth;
 
@@ -239,8 +247,8 @@ pub struct Column {
     #[serde(default)]
     pub detail_only: bool,         // 该字段只在select/find_one中体现，对query/paged_query则不进行查出，主要用于text/blob字段处理。
     pub title: Option<String>,
-    pub generator: Option<String>,
-    pub validation: Option<String>, // 验证该字段数据的表达式（主要作为于Insert/Update/Upsert）
+    pub generator: Option<String>, // 值生成器，version为乐观锁的版本号字段，每次更新时自增，更新时必须提供版本号（对象的version_optional为true时可省略，省略时不检查）
+    pub validation: Option<String>, // 验证该字段数据的表达式（主要作为于Insert/Update/Upsert），多个规则以;分隔，如required;length:1,32;email
     pub desensitize: Option<String>, // 脱敏配置
     pub permitted: Option<String>,  // 赋予某角色可读写
     pub relation_object: Option<String>,
@@ -250,6 +258,20 @@ pub struct Column {
     pub relation_middle: Option<String>, // 中间表的表达式，用于实现N..N关系
 }
 
+impl Column {
+    /**
+     * 是否为json/jsonb类型的字段，按字段类型或列类型判断
+     */
+    pub fn is_json(&self) -> bool {
+        [&self.field_type, &self.col_type].iter().any(|t| {
+            matches!(
+                t.as_deref().map(|f| f.to_lowercase()).as_deref(),
+                Some("json") | Some("jsonb")
+            )
+        })
+    }
+}
+
 unsafe impl Send for Column {}
 
 unsafe impl Sync for Column {}
@@ -280,14 +302,20 @@ pub struct StoreObject {
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub delete_hooks: Vec<MethodHook>,
     #[serde(default)]
-    pub validation: bool,
+    pub validation: bool,                 // 启用字段验证，按Column.validation的规则验证写入的数据
     #[serde(default)]
-    pub parti_valid: bool,
+    pub parti_valid: bool,                // 部分验证，只验证对象中提供了的字段，适用于只更新部分字段的情况
     #[serde(default)]
     pub enable_cache: bool,
     #[serde(default)]
     #[serde(deserialize_with = "i64_from_str")]
     pub cache_time: Option<i64>,
+    #[serde(default)]
+    #[serde(deserialize_with = "i64_from_str")]
+    pub cache_jitter: Option<i64>,      // 在cache_time上随机增加不超过该值的秒数，避免同时写入的缓存同时过期
+    #[serde(default)]
+    #[serde(deserialize_with = "i64_from_str")]
+    pub cache_stale_time: Option<i64>,  // 缓存过期后仍返回旧值的秒数，期间由后台刷新缓存，为空或0时不启用
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub read_perm_roles: Vec<String>,
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
@@ -297,6 +325,20 @@ pub struct StoreObject {
     pub permission_field: Option<String>, // 用于与数据权限建立关联的字段
     pub relative_field: Option<String>,   // 用于与数据权限建立关联的字段
 
+    pub soft_delete_field: Option<String>, // 软删除的标记字段，设置后delete/delete_by只更新该字段（1/true为已删除），查询时自动过滤已删除的记录
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub restore_perm_roles: Vec<String>,   // 允许执行restore的角色，为空时按写权限处理
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub purge_perm_roles: Vec<String>,     // 允许执行purge（物理删除已软删除的记录）的角色，为空时按写权限处理
+
+    #[serde(default)]
+    pub version_optional: bool,            // 更新时允许不提供版本号，此时不进行乐观锁检查，缺省为false，没有版本号的更新会被拒绝
+    #[serde(default)]
+    pub audit: bool,                       // 启用审计，数据变更前后的内容会写入StoreServiceConfig中的audit_object
+
+    pub tenant_mode: Option<String>,       // 覆盖命名空间的tenant_mode，column为按字段隔离，none为不隔离
+    pub tenant_field: Option<String>,      // 保存租户标识的字段，缺省使用命名空间的tenant_field
+
     #[serde(default, skip_serializing)]
     pub field_map: Arc<RefCell<HashMap<String, Column>>>,
 }
@@ -349,6 +391,25 @@ impl StoreObject {
             .last()
     }
 
+    /**
+     * 将查询条件中的属性名或字段名转换为实际的字段名，不存在则返回None
+     */
+    pub fn resolve_field_name(&self, name: &str) -> Option<String> {
+        self.fields
+            .iter()
+            .find(|c| c.field_name == name || c.prop_name.as_deref() == Some(name))
+            .map(|c| c.field_name.clone())
+    }
+
+    /**
+     * 属性名或字段名对应的字段是否为json类型
+     */
+    pub fn is_json_field(&self, name: &str) -> bool {
+        self.fields
+            .iter()
+            .any(|c| (c.field_name == name || c.prop_name.as_deref() == Some(name)) && c.is_json())
+    }
+
     pub fn get_key_columns(&self) -> Vec<Column> {
         // self.fields.clone().into_iter().filter(|c| c.field_name == *field && ( !kp || (kp && c.col_type != Some("relation".to_owned())))).last()
         self.fields
@@ -358,8 +419,38 @@ impl StoreObject {
             .collect_vec()
     }
 
+    /**
+     * 获取乐观锁的版本号字段（generator为version）
+     */
+    pub fn get_version_column(&self) -> Option<Column> {
+        self.fields
+            .iter()
+            .find(|k| k.generator == Some("version".to_owned()) && k.col_type != Some("relation".to_owned()))
+            .cloned()
+    }
+
+    /**
+     * 按字段进行租户隔离时保存租户标识的字段，对象的tenant_mode优先于命名空间的设置
+     */
+    pub fn get_tenant_field(&self, conf: &StoreServiceConfig) -> Option<String> {
+        let mode = self.tenant_mode.clone().or(conf.tenant_mode.clone()).unwrap_or_default();
+        if mode.eq_ignore_ascii_case("column") {
+            self.tenant_field
+                .clone()
+                .or(conf.tenant_field.clone())
+                .filter(|f| !f.trim().is_empty())
+                .or(Some("tenant_id".to_owned()))
+        } else {
+            None
+        }
+    }
+
     pub fn has_permission(&self, uri: &InvokeUri, _jwt: &JwtUserClaims, roles: &[String]) -> bool {
-        if self.read_perm_roles.is_empty() && self.write_perm_roles.is_empty() {
+        if uri.method == *"restore" && !self.restore_perm_roles.is_empty() {
+            self.restore_perm_roles.iter().any(|f| roles.contains(f))
+        } else if uri.method == *"purge" && !self.purge_perm_roles.is_empty() {
+            self.purge_perm_roles.iter().any(|f| roles.contains(f))
+        } else if self.read_perm_roles.is_empty() && self.write_perm_roles.is_empty() {
             true
         } else if uri.is_write_method() {
             //self.write_perm_roles.contains("x")
@@ -382,6 +473,21 @@ impl StoreObject {
     }
 }
 
+/**
+ * 只读副本的配置，weight用于weighted策略，缺省为1
+ */
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReplicaConfig {
+    pub url: String,
+    #[serde(default)]
+    #[serde(deserialize_with = "i64_from_str")]
+    pub weight: Option<i64>,
+}
+
+unsafe impl Send for ReplicaConfig {}
+unsafe impl Sync for ReplicaConfig {}
+
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct PluginConfig {
@@ -402,6 +508,12 @@ pub struct StoreServiceConfig {
     pub rsa_public_key: Option<String>,
     pub rsa_private_key: Option<String>,
     pub redis_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redis: Option<RedisConfig>, // 结构化的Redis配置，支持single/cluster/sentinel，未配置urls时使用redis_url的地址
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redis_read_perm_roles: Vec<String>,  // 允许调用redis://读方法的角色，为空时不限制
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redis_write_perm_roles: Vec<String>, // 允许调用redis://写方法的角色，为空时只允许超级管理员
 
     #[serde(default)]
     pub relaxy_timezone: bool,
@@ -427,6 +539,44 @@ pub struct StoreServiceConfig {
     pub oss_bulk_id: Option<String>,
     pub oss_auth: Option<String>,
 
+    #[serde(default)]
+    pub audit: bool,                  // 对命名空间中的所有对象启用审计
+    pub audit_object: Option<String>, // 保存审计记录的对象名称
+
+    #[serde(default)]
+    #[serde(deserialize_with = "i64_from_str")]
+    pub cascade_max_depth: Option<i64>, // 关联对象级联新增/更新/删除的最大层级，缺省为5
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub read_replicas: Vec<ReplicaConfig>, // 只读副本，select/find_one/query/paged_query及QueryObject的查询使用
+    pub replica_policy: Option<String>,    // 只读副本的选择策略，round_robin（缺省）或weighted
+
+    #[serde(default)]
+    #[serde(deserialize_with = "i64_from_str")]
+    pub pool_max_open: Option<i64>, // 连接池的最大连接数，缺省为32
+    #[serde(default)]
+    #[serde(deserialize_with = "i64_from_str")]
+    pub pool_acquire_timeout: Option<i64>, // 获取连接的超时时间（秒），缺省为一直等待
+    #[serde(default)]
+    #[serde(deserialize_with = "i64_from_str")]
+    pub pool_idle_timeout: Option<i64>, // 连接的空闲超时时间（秒），超时的连接在下次使用时重建
+    #[serde(default)]
+    #[serde(deserialize_with = "i64_from_str")]
+    pub pool_max_lifetime: Option<i64>, // 连接的最长存活时间（秒），超时的连接在下次使用时重建
+
+    pub cache_backend: Option<String>, // Object/Query结果缓存的存储，memory或redis，缺省时配置了redis或redis_url则使用redis，否则使用memory
+    #[serde(default)]
+    #[serde(deserialize_with = "i64_from_str")]
+    pub cache_max_entries: Option<i64>, // memory缓存的最大条目数，缺省为10000
+    #[serde(default)]
+    #[serde(deserialize_with = "i64_from_str")]
+    pub cache_max_bytes: Option<i64>, // memory缓存的最大字节数，缺省为64M
+
+    pub tenant_mode: Option<String>,  // 按JwtUserClaims.domain进行租户隔离，column为按字段隔离，database为每个租户使用独立的数据库
+    pub tenant_field: Option<String>, // column模式下保存租户标识的字段，缺省为tenant_id
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub tenant_databases: HashMap<String, String>, // database模式下租户标识与db_url的对应关系
+
     pub namespace: String,
     pub objects: Vec<StoreObject>,
     pub querys: Vec<QueryObject>,
@@ -446,6 +596,45 @@ unsafe impl Send for StoreServiceConfig {}
 unsafe impl Sync for StoreServiceConfig {}
 
 impl StoreServiceConfig {
+    /**
+     * 连接池的配置，主库和只读副本使用相同的配置，不大于0的值视为未配置
+     */
+    pub fn pool_options(&self) -> PoolOptions {
+        let secs = |f: Option<i64>| f.filter(|t| *t > 0).map(|t| Duration::from_secs(t as u64));
+        PoolOptions {
+            max_open: self.pool_max_open.filter(|t| *t > 0).map(|t| t as u64),
+            acquire_timeout: secs(self.pool_acquire_timeout),
+            idle_timeout: secs(self.pool_idle_timeout),
+            max_lifetime: secs(self.pool_max_lifetime),
+        }
+    }
+
+    pub fn is_tenant_database(&self) -> bool {
+        self.tenant_mode
+            .as_deref()
+            .map(|f| f.eq_ignore_ascii_case("database"))
+            .unwrap_or(false)
+    }
+
+    /**
+     * 用户所属租户使用的db_url，未启用database模式或者超级管理员跨租户访问时使用db_url
+     * 租户没有配置对应的数据库时返回错误
+     */
+    pub fn get_tenant_db_url(&self, jwt: &JwtUserClaims) -> Result<String, anyhow::Error> {
+        if !self.is_tenant_database() {
+            return Ok(self.db_url.clone());
+        }
+        match jwt.tenant()? {
+            Some(tenant) => self
+                .tenant_databases
+                .get(&tenant)
+                .filter(|f| !f.is_empty())
+                .cloned()
+                .ok_or(anyhow!("No database was configured for tenant {}.", tenant)),
+            None => Ok(self.db_url.clone()),
+        }
+    }
+
     pub(crate) fn refine(&mut self) {
         let obj_map = self
             .objects
@@ -521,6 +710,56 @@ impl StoreServiceConfig {
     pub fn get_query(&self, name: &str) -> Option<&QueryObject> {
         self.query_map.get(name)
     }
+
+    /**
+     * 是否配置了Redis，redis中配置了urls或者配置了redis_url
+     */
+    pub fn has_redis(&self) -> bool {
+        self.redis.as_ref().map(|f| !f.urls.is_empty()).unwrap_or(false)
+            || self.redis_url.as_ref().map(|f| !f.trim().is_empty()).unwrap_or(false)
+    }
+
+    /**
+     * 命名空间的Redis配置，redis中配置了urls时直接使用
+     * 否则解析redis_url得到地址，redis中的其它配置（如连接池、master_name）仍然有效
+     */
+    pub fn get_redis_config(&self) -> Option<RedisConfig> {
+        let conf = self.redis.clone().unwrap_or_default();
+        if !conf.urls.is_empty() {
+            return Some(conf);
+        }
+        match self.redis_url.clone() {
+            Some(url) if !url.trim().is_empty() => match conf.with_url(&url) {
+                Ok(t) => Some(t),
+                Err(err) => {
+                    log::warn!("The redis_url of {} is invalid: {err}", self.namespace);
+                    None
+                }
+            },
+            _ => None,
+        }
+    }
+
+    /**
+     * redis://方法的权限，读写方法分别按redis_read_perm_roles/redis_write_perm_roles检查
+     * 未配置redis_write_perm_roles时写方法只允许超级管理员调用
+     * flushall/delexp只允许超级管理员调用，未知的方法不允许调用
+     */
+    pub fn has_redis_permission(&self, method: &str, jwt: &JwtUserClaims, roles: &[String]) -> bool {
+        let superadmin = jwt.superadmin || roles.contains(&"ROLE_SUPERADMIN".to_owned());
+        match redis_method_kind(method) {
+            Some(RedisMethodKind::Read) => {
+                superadmin
+                    || self.redis_read_perm_roles.is_empty()
+                    || self.redis_read_perm_roles.iter().any(|f| roles.contains(f))
+            }
+            Some(RedisMethodKind::Write) => {
+                superadmin || self.redis_write_perm_roles.iter().any(|f| roles.contains(f))
+            }
+            Some(RedisMethodKind::Admin) => superadmin,
+            None => false,
+        }
+    }
 }
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
@@ -548,6 +787,12 @@ pub struct QueryObject {
     #[serde(default)]
     #[serde(deserialize_with = "i64_from_str")]
     pub cache_time: Option<i64>,
+    #[serde(default)]
+    #[serde(deserialize_with = "i64_from_str")]
+    pub cache_jitter: Option<i64>,      // 在cache_time上随机增加不超过该值的秒数，避免同时写入的缓存同时过期
+    #[serde(default)]
+    #[serde(deserialize_with = "i64_from_str")]
+    pub cache_stale_time: Option<i64>,  // 缓存过期后仍返回旧值的秒数，期间由后台刷新缓存，为空或0时不启用
 
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub perm_roles: Vec<String>,
@@ -566,6 +811,34 @@ impl QueryObject {
             .collect()
     }
 
+    /**
+     * QueryObject的查询条件是附加在query_body之后的
+     * 如果定义了fields，则只允许使用fields中的字段，否则只允许合法的标识符（如t.name）
+     */
+    pub fn resolve_field_name(&self, name: &str) -> Option<String> {
+        if self.fields.is_empty() {
+            if is_safe_identifier(name) {
+                Some(name.to_owned())
+            } else {
+                None
+            }
+        } else {
+            self.fields
+                .iter()
+                .find(|c| c.field_name == name || c.prop_name.as_deref() == Some(name))
+                .map(|c| c.field_name.clone())
+        }
+    }
+
+    /**
+     * 属性名或字段名对应的字段是否为json类型，未定义fields时无法判断，均作为非json字段
+     */
+    pub fn is_json_field(&self, name: &str) -> bool {
+        self.fields
+            .iter()
+            .any(|c| (c.field_name == name || c.prop_name.as_deref() == Some(name)) && c.is_json())
+    }
+
     pub fn has_permission(&self, _uri: &InvokeUri, _jwt: &JwtUserClaims, roles: &[String]) -> bool {
         if self.perm_roles.is_empty() {
             true
@@ -587,6 +860,9 @@ unsafe impl Sync for QueryObject {}
 pub struct OrdianlItem {
     pub field: String,
     pub sort_asc: bool,
+
+    #[serde(skip)]
+    pub relation: Option<RelationPath>,     // 排序字段为关联对象的字段时，由refine_relation_fields解析
 }
 
 unsafe impl Send for OrdianlItem {}
@@ -602,62 +878,341 @@ pub struct ConditionItem {
     pub value2: Value,
     pub and: Vec<ConditionItem>,
     pub or: Vec<ConditionItem>,
+
+    #[serde(skip)]
+    pub relation: Option<RelationPath>,     // 条件字段为关联对象的字段时，由refine_relation_fields解析
+
+    #[serde(skip)]
+    pub json: bool,                         // 条件字段为json类型时，由mark_json_fields标记
 }
 
 unsafe impl Send for ConditionItem {}
 
 unsafe impl Sync for ConditionItem {}
+
+/**
+ * 关联属性路径（如dept.name、roles.code）解析后的结果
+ * from_sql为关联表的子查询片段，形如 dept _rel where _rel.id = _tbl.dept_id，
+ * 其中已包含与主表（别名_tbl）的关联条件，field为关联表中的字段，如_rel.name
+ * array为true表示一对多或多对多的关联，此时只能用于条件，不能用于排序
+ * args为from_sql中占位符对应的参数（如关联对象的数据权限），按出现的顺序排列
+ */
+#[derive(Debug, Default, Clone)]
+pub struct RelationPath {
+    pub from_sql: String,
+    pub field: String,
+    pub array: bool,
+    pub args: Vec<Value>,
+}
+
+unsafe impl Send for RelationPath {}
+
+unsafe impl Sync for RelationPath {}
+
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct IPaging {
     pub size: u64,
     pub current: u64,
+    pub cursor: bool,              // 启用游标（keyset）分页，此时current无效
+    pub after: Option<String>,     // 上一页返回的next_cursor，为空时从第一条记录开始
+    pub skip_count: bool,          // 游标分页时不执行count查询
 }
 
 unsafe impl Send for IPaging {}
 
 unsafe impl Sync for IPaging {}
 
-impl ConditionItem {
-    fn compose_query(&self, args: &mut Vec<Value>) -> String {
-        if self.op.trim().to_lowercase() == "between" {
-            args.push(self.value.clone());
-            args.push(self.value2.clone());
-            format!("{} {} ? and ?", self.field.clone(), self.op.clone())
-        } else if self.op.trim().to_lowercase() == "not in" {
-            let (cs, mut vals) = match self.value.clone() {
-                Value::Array(mps) => {
-                    let st = mps.iter().map(|_| "?").join(",");
-                    (st, mps.clone())
+/**
+ * 查询条件中允许使用的操作符
+ */
+pub const CONDITION_OPERATORS: &[&str] = &[
+    "=", "!=", "<>", ">", ">=", "<", "<=", "like", "not like", "ilike", "in", "not in",
+    "between", "not between", "is null", "is not null", "starts_with", "ends_with",
+    "contains", "overlap", "json_contains",
+];
+
+/**
+ * 判断是否为合法的标识符，允许使用别名前缀，如t.name
+ */
+pub fn is_safe_identifier(name: &str) -> bool {
+    !name.is_empty()
+        && name.split('.').count() <= 2
+        && name.split('.').all(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
                 }
-                _ => ("?".to_owned(), vec![self.value.clone()]),
-            };
-            args.append(&mut vals);
-            format!("{} {} ({})", self.field.clone(), self.op.clone(), cs)
-        } else if self.op.trim().to_lowercase() == "in" {
-            let (cs, mut vals) = match self.value.clone() {
+                _ => false,
+            }
+        })
+}
+
+/**
+ * 查询条件校验错误，字段或操作符不被允许时返回
+ * 通过anyhow::Error传递，调用者可以使用downcast_ref获取该结构
+ */
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConditionValidationError {
+    pub field: String,
+    pub op: Option<String>,
+    pub reason: String,
+}
+
+impl ConditionValidationError {
+    pub fn unknown_field(field: &str) -> Self {
+        Self {
+            field: field.to_owned(),
+            op: None,
+            reason: "unknown field".to_owned(),
+        }
+    }
+
+    pub fn unknown_operator(field: &str, op: &str) -> Self {
+        Self {
+            field: field.to_owned(),
+            op: Some(op.to_owned()),
+            reason: "unsupported operator".to_owned(),
+        }
+    }
+
+    pub fn invalid_cursor(reason: &str) -> Self {
+        Self {
+            field: "paging.after".to_owned(),
+            op: None,
+            reason: reason.to_owned(),
+        }
+    }
+
+    pub fn unsupported_relation_sort(field: &str) -> Self {
+        Self {
+            field: field.to_owned(),
+            op: None,
+            reason: "cannot sort on an array relation".to_owned(),
+        }
+    }
+
+    pub fn unsupported_operator(field: &str, op: &str, driver: &str) -> Self {
+        Self {
+            field: field.to_owned(),
+            op: Some(op.to_owned()),
+            reason: format!("operator is not supported by {driver}"),
+        }
+    }
+}
+
+/**
+ * 转义like中的通配符，用于starts_with/ends_with/contains
+ */
+fn escape_like_pattern(val: &Value) -> String {
+    let text = match val {
+        Value::String(s) => s.clone(),
+        _ => val.to_string(),
+    };
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+impl std::fmt::Display for ConditionValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.op {
+            Some(op) => write!(f, "Invalid condition on field '{}' with operator '{}': {}", self.field, op, self.reason),
+            None => write!(f, "Invalid condition on field '{}': {}", self.field, self.reason),
+        }
+    }
+}
+
+impl std::error::Error for ConditionValidationError {}
+
+/**
+ * 乐观锁校验失败，记录的版本号已被其它操作修改时返回
+ * 通过anyhow::Error传递，调用者可以使用downcast_ref获取该结构
+ */
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StaleRecordError {
+    pub object: String,
+    pub key: Option<String>,
+}
+
+impl StaleRecordError {
+    pub fn new(object: &str, key: Option<String>) -> Self {
+        Self {
+            object: object.to_owned(),
+            key,
+        }
+    }
+}
+
+impl std::fmt::Display for StaleRecordError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.key {
+            Some(key) => write!(f, "Stale record of '{}' ({}): it has been modified by others", self.object, key),
+            None => write!(f, "Stale record of '{}': it has been modified by others", self.object),
+        }
+    }
+}
+
+impl std::error::Error for StaleRecordError {}
+
+/**
+ * 字段数据验证失败，errors为属性名到错误描述列表的映射
+ * 通过anyhow::Error传递，调用者可以使用downcast_ref获取该结构
+ */
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FieldValidationError {
+    pub object: String,
+    pub errors: std::collections::BTreeMap<String, Vec<String>>,
+}
+
+impl FieldValidationError {
+    pub fn new(object: &str) -> Self {
+        Self {
+            object: object.to_owned(),
+            errors: std::collections::BTreeMap::new(),
+        }
+    }
+
+    pub fn add_error(&mut self, field: &str, msg: &str) {
+        self.errors.entry(field.to_owned()).or_default().push(msg.to_owned());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+impl std::fmt::Display for FieldValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let msgs = self
+            .errors
+            .iter()
+            .map(|(field, errs)| format!("{}: {}", field, errs.join(", ")))
+            .join("; ");
+        write!(f, "Validation failed for '{}': {}", self.object, msgs)
+    }
+}
+
+impl std::error::Error for FieldValidationError {}
+
+impl ConditionItem {
+    /**
+     * 规范化操作符，转为小写并去除多余的空白，不在白名单中的操作符返回错误
+     */
+    fn normalized_op(&self) -> Result<String, ConditionValidationError> {
+        let op = self.op.split_whitespace().join(" ").to_lowercase();
+        if CONDITION_OPERATORS.contains(&op.as_str()) {
+            Ok(op)
+        } else {
+            Err(ConditionValidationError::unknown_operator(&self.field, &self.op))
+        }
+    }
+
+    fn compose_query(&self, driver: &str, args: &mut Vec<Value>) -> Result<String, ConditionValidationError> {
+        if !is_safe_identifier(&self.field) {
+            return Err(ConditionValidationError::unknown_field(&self.field));
+        }
+        let op = self.normalized_op()?;
+        let mut vals = match op.as_str() {
+            "is null" | "is not null" => vec![],
+            "between" | "not between" => vec![self.value.clone(), self.value2.clone()],
+            "in" | "not in" => match self.value.clone() {
                 Value::Array(mps) => {
-                    let st = mps.iter().map(|_| "?").join(",");
-                    (st, mps.clone())
+                    if mps.is_empty() {
+                        // in ()在大多数数据库中是语法错误，直接返回恒等式
+                        return Ok(if op == "in" { "1 = 0" } else { "1 = 1" }.to_owned());
+                    }
+                    mps
                 }
-                _ => ("?".to_owned(), vec![self.value.clone()]),
-            };
-            args.append(&mut vals);
-            format!("{} {} ({})", self.field.clone(), self.op.clone(), cs)
+                _ => vec![self.value.clone()],
+            },
+            "starts_with" => vec![Value::String(format!("{}%", escape_like_pattern(&self.value)))],
+            "ends_with" => vec![Value::String(format!("%{}", escape_like_pattern(&self.value)))],
+            "contains" => vec![Value::String(format!("%{}%", escape_like_pattern(&self.value)))],
+            "overlap" | "json_contains" => {
+                vec![Value::String(serde_json::to_string(&self.value).unwrap_or_default())]
+            }
+            _ => vec![self.value.clone()],
+        };
+        let cond = if self.json {
+            get_json_condition_present(driver, &self.field, &op, vals.len())
         } else {
-            args.push(self.value.clone());
-            format!("{} {} ?", self.field.clone(), self.op.clone())
+            get_condition_present(driver, &self.field, &op, vals.len())
+        }
+        .ok_or_else(|| {
+            ConditionValidationError::unsupported_operator(&self.field, &self.op, driver)
+        })?;
+        if let Some(rel) = &self.relation {
+            args.extend(rel.args.iter().cloned());
+        }
+        args.append(&mut vals);
+        match &self.relation {
+            // 关联对象的条件使用exists子查询，避免join产生重复的记录
+            Some(rel) => Ok(format!("exists (select 1 from {} and {})", rel.from_sql, cond)),
+            None => Ok(cond),
         }
     }
 
-    pub(crate) fn to_query(&self, args: &mut Vec<Value>) -> anyhow::Result<String> {
+    /**
+     * 使用resolver将字段转换为实际的字段名，并校验操作符
+     * resolver无法解析的字段再交由relation_resolver作为关联属性路径解析
+     */
+    fn refine_fields<F, R>(&self, resolver: &F, relation_resolver: &R) -> Result<ConditionItem, ConditionValidationError>
+    where
+        F: Fn(&str) -> Option<String>,
+        R: Fn(&str) -> Option<RelationPath>,
+    {
+        let (field, relation) = match resolver(&self.field) {
+            Some(field) => (field, None),
+            None => {
+                let rel = relation_resolver(&self.field)
+                    .ok_or_else(|| ConditionValidationError::unknown_field(&self.field))?;
+                (rel.field.clone(), Some(rel))
+            }
+        };
+        let op = self.normalized_op()?;
+        Ok(ConditionItem {
+            field,
+            op,
+            value: self.value.clone(),
+            value2: self.value2.clone(),
+            and: self
+                .and
+                .iter()
+                .map(|c| c.refine_fields(resolver, relation_resolver))
+                .collect::<Result<Vec<_>, _>>()?,
+            or: self
+                .or
+                .iter()
+                .map(|c| c.refine_fields(resolver, relation_resolver))
+                .collect::<Result<Vec<_>, _>>()?,
+            relation,
+            json: self.json,
+        })
+    }
+
+    fn mark_json_fields<F>(&mut self, is_json: &F)
+    where
+        F: Fn(&str) -> bool,
+    {
+        self.json = self.relation.is_none() && is_json(&self.field);
+        for c in self.and.iter_mut().chain(self.or.iter_mut()) {
+            c.mark_json_fields(is_json);
+        }
+    }
+
+    pub(crate) fn to_query(&self, driver: &str, args: &mut Vec<Value>) -> anyhow::Result<String> {
         let mut cond_sql = String::new();
 
-        let c = self.compose_query(args);
+        let c = self.compose_query(driver, args)?;
         cond_sql.push_str(&c);
 
         for cond in self.and.iter() {
-            let q = cond.to_query(args)?;
+            let q = cond.to_query(driver, args)?;
             cond_sql.push_str(" and ");
             cond_sql.push('(');
             cond_sql.push_str(&q);
@@ -670,7 +1225,7 @@ impl ConditionItem {
             }
 
             for cond in self.or.iter() {
-                let q = cond.to_query(args)?;
+                let q = cond.to_query(driver, args)?;
                 cond_sql.push_str(" or ");
                 cond_sql.push('(');
                 cond_sql.push_str(&q);
@@ -682,6 +1237,62 @@ impl ConditionItem {
     }
 }
 
+/**
+ * 聚合查询中允许使用的聚合函数
+ */
+pub const AGGREGATE_FUNCTIONS: &[&str] = &["count", "sum", "avg", "min", "max"];
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AggregateItem {
+    pub func: String,              // count, sum, avg, min, max
+    pub field: String,             // 聚合的字段，count可以使用*
+    pub distinct: bool,            // 如count(distinct field)
+    pub alias: Option<String>,     // 结果中的键名，缺省为func_field
+}
+
+unsafe impl Send for AggregateItem {}
+
+unsafe impl Sync for AggregateItem {}
+
+impl AggregateItem {
+    pub fn output_name(&self) -> String {
+        match &self.alias {
+            Some(alias) if !alias.is_empty() => alias.clone(),
+            _ => {
+                if self.field == "*" {
+                    self.func.to_lowercase()
+                } else {
+                    format!("{}_{}", self.func.to_lowercase(), self.field.replace('.', "_"))
+                }
+            }
+        }
+    }
+
+    /**
+     * 生成聚合表达式，字段需要通过resolver的解析
+     */
+    fn to_expr<F>(&self, resolver: &F) -> Result<String, ConditionValidationError>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let func = self.func.trim().to_lowercase();
+        if !AGGREGATE_FUNCTIONS.contains(&func.as_str()) {
+            return Err(ConditionValidationError::unknown_operator(&self.field, &self.func));
+        }
+        let field = if self.field == "*" && func == "count" && !self.distinct {
+            "*".to_owned()
+        } else {
+            resolver(&self.field).ok_or_else(|| ConditionValidationError::unknown_field(&self.field))?
+        };
+        if self.distinct {
+            Ok(format!("{}(distinct {})", func, field))
+        } else {
+            Ok(format!("{}({})", func, field))
+        }
+    }
+}
+
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct QueryCondition {
@@ -690,6 +1301,19 @@ pub struct QueryCondition {
     pub sorts: Vec<OrdianlItem>,
     pub group_by: Vec<OrdianlItem>,
     pub paging: Option<IPaging>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub aggregates: Vec<AggregateItem>,      // 聚合表达式，仅用于aggregate
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub having: Vec<ConditionItem>,          // 对聚合结果的过滤条件，字段为聚合的别名或分组字段
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<String>,                 // 需要返回的字段（属性名或字段名），为空表示返回全部
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub exclude_fields: Vec<String>,         // 不需要返回的字段
+
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub include_deleted: bool,               // 包含已软删除的记录，仅对超级管理员有效
 }
 
 unsafe impl Send for QueryCondition {}
@@ -708,18 +1332,155 @@ impl QueryCondition {
         self.and.is_empty() && self.or.is_empty()
     }
 
-    pub fn to_query(&self, onlyquery: bool) -> anyhow::Result<(String, Vec<Value>)> {
+    /**
+     * 校验查询条件中的字段与操作符，返回字段名已转换为实际字段名的查询条件
+     * 条件、排序、分组以及投影中的字段都需要通过resolver的解析，否则返回ConditionValidationError
+     */
+    pub fn refine_fields<F>(&self, resolver: F) -> anyhow::Result<QueryCondition>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        self.refine_relation_fields(resolver, |_| None)
+    }
+
+    /**
+     * 与refine_fields相同，但条件及排序中的字段还可以是关联属性路径（如dept.name），由relation_resolver解析
+     * 分组与投影只能使用本对象的字段，数组关联的属性不能用于排序
+     */
+    pub fn refine_relation_fields<F, R>(&self, resolver: F, relation_resolver: R) -> anyhow::Result<QueryCondition>
+    where
+        F: Fn(&str) -> Option<String>,
+        R: Fn(&str) -> Option<RelationPath>,
+    {
+        let refine_projection = |names: &[String]| -> Result<Vec<String>, ConditionValidationError> {
+            names
+                .iter()
+                .map(|f| {
+                    resolver(f)
+                        .map(|_| f.to_owned())
+                        .ok_or_else(|| ConditionValidationError::unknown_field(f))
+                })
+                .collect()
+        };
+        let refine_ordinal = |items: &[OrdianlItem]| -> Result<Vec<OrdianlItem>, ConditionValidationError> {
+            items
+                .iter()
+                .map(|f| {
+                    resolver(&f.field)
+                        .map(|field| OrdianlItem {
+                            field,
+                            sort_asc: f.sort_asc,
+                            relation: None,
+                        })
+                        .ok_or_else(|| ConditionValidationError::unknown_field(&f.field))
+                })
+                .collect()
+        };
+        let refine_sorts = |items: &[OrdianlItem]| -> Result<Vec<OrdianlItem>, ConditionValidationError> {
+            items
+                .iter()
+                .map(|f| match resolver(&f.field) {
+                    Some(field) => Ok(OrdianlItem {
+                        field,
+                        sort_asc: f.sort_asc,
+                        relation: None,
+                    }),
+                    None => match relation_resolver(&f.field) {
+                        Some(rel) if rel.array => Err(ConditionValidationError::unsupported_relation_sort(&f.field)),
+                        Some(rel) => Ok(OrdianlItem {
+                            field: rel.field.clone(),
+                            sort_asc: f.sort_asc,
+                            relation: Some(rel),
+                        }),
+                        None => Err(ConditionValidationError::unknown_field(&f.field)),
+                    },
+                })
+                .collect()
+        };
+
+        Ok(QueryCondition {
+            and: self
+                .and
+                .iter()
+                .map(|c| c.refine_fields(&resolver, &relation_resolver))
+                .collect::<Result<Vec<_>, _>>()?,
+            or: self
+                .or
+                .iter()
+                .map(|c| c.refine_fields(&resolver, &relation_resolver))
+                .collect::<Result<Vec<_>, _>>()?,
+            sorts: refine_sorts(&self.sorts)?,
+            group_by: refine_ordinal(&self.group_by)?,
+            paging: self.paging.clone(),
+            aggregates: self.aggregates.clone(),
+            having: self.having.clone(),
+            fields: refine_projection(&self.fields)?,
+            exclude_fields: refine_projection(&self.exclude_fields)?,
+            include_deleted: self.include_deleted,
+        })
+    }
+
+    /**
+     * 标记条件中json类型的字段，is_json可以接受属性名或字段名，标记在refine_fields后仍然保留
+     * postgres中json字段与数组字段的overlap等条件需要使用不同的语法
+     */
+    pub fn mark_json_fields<F>(mut self, is_json: F) -> QueryCondition
+    where
+        F: Fn(&str) -> bool,
+    {
+        for c in self.and.iter_mut().chain(self.or.iter_mut()) {
+            c.mark_json_fields(&is_json);
+        }
+        self
+    }
+
+    pub fn has_projection(&self) -> bool {
+        !self.fields.is_empty() || !self.exclude_fields.is_empty()
+    }
+
+    /**
+     * 判断字段是否需要返回，fields与exclude_fields中可以使用属性名或字段名
+     */
+    pub fn is_projected(&self, field_name: &str, prop_name: Option<&str>) -> bool {
+        let matched = |names: &[String]| {
+            names
+                .iter()
+                .any(|n| n == field_name || prop_name == Some(n.as_str()))
+        };
+        (self.fields.is_empty() || matched(&self.fields)) && !matched(&self.exclude_fields)
+    }
+
+    /**
+     * 用于缓存键的投影描述，字段顺序不影响结果，没有投影时为空
+     */
+    pub fn projection_key(&self) -> String {
+        if !self.has_projection() {
+            return String::new();
+        }
+        let normalize = |names: &[String]| names.iter().sorted().dedup().join(",");
+        format!(
+            "fields={};exclude={}",
+            normalize(&self.fields),
+            normalize(&self.exclude_fields)
+        )
+    }
+
+    /**
+     * 生成查询条件的SQL，driver为数据库的类型，如mysql/postgres/sqlite，不同的数据库对操作符的转换不同
+     * and与or组成的条件整体以括号包围，调用者追加在租户、软删除等条件之后时，or不会绕过这些条件
+     */
+    pub fn to_query(&self, driver: &str, onlyquery: bool) -> anyhow::Result<(String, Vec<Value>)> {
         let mut cond_sql = String::new();
         let mut args = vec![];
 
         for (idx, cond) in self.and.iter().enumerate() {
             if idx == 0 {
-                let q = cond.to_query(&mut args)?;
+                let q = cond.to_query(driver, &mut args)?;
                 cond_sql.push('(');
                 cond_sql.push_str(&q);
                 cond_sql.push(')');
             } else {
-                let q = cond.to_query(&mut args)?;
+                let q = cond.to_query(driver, &mut args)?;
                 cond_sql.push_str(" and ");
                 cond_sql.push('(');
                 cond_sql.push_str(&q);
@@ -734,12 +1495,12 @@ impl QueryCondition {
 
             for (idx, cond) in self.or.iter().enumerate() {
                 if idx == 0 {
-                    let q = cond.to_query(&mut args)?;
+                    let q = cond.to_query(driver, &mut args)?;
                     cond_sql.push('(');
                     cond_sql.push_str(&q);
                     cond_sql.push(')');
                 } else {
-                    let q = cond.to_query(&mut args)?;
+                    let q = cond.to_query(driver, &mut args)?;
                     cond_sql.push_str(" or ");
                     cond_sql.push('(');
                     cond_sql.push_str(&q);
@@ -748,7 +1509,19 @@ impl QueryCondition {
             }
         }
 
+        if !cond_sql.is_empty() {
+            cond_sql = format!("({})", cond_sql);
+        }
+
         if !onlyquery {
+            if let Some(f) = self
+                .group_by
+                .iter()
+                .chain(self.sorts.iter())
+                .find(|f| !is_safe_identifier(&f.field) || f.relation.as_ref().map(|r| r.array).unwrap_or(false))
+            {
+                return Err(ConditionValidationError::unknown_field(&f.field).into());
+            }
             if !self.group_by.is_empty() {
                 cond_sql.push_str(" group by ");
                 cond_sql.push_str(&self.group_by.clone().into_iter().map(|f| f.field).join(","));
@@ -762,7 +1535,14 @@ impl QueryCondition {
                         .into_iter()
                         .map(|f| {
                             let ord = if f.sort_asc { "asc" } else { "desc" };
-                            format!("{} {}", f.field, ord)
+                            match f.relation {
+                                // 一对一的关联属性使用标量子查询排序，排序位于条件之后，其参数追加在最后
+                                Some(rel) => {
+                                    args.extend(rel.args);
+                                    format!("(select {} from {}) {}", f.field, rel.from_sql, ord)
+                                }
+                                None => format!("{} {}", f.field, ord),
+                            }
                         })
                         .join(","),
                 );
@@ -776,4 +1556,225 @@ impl QueryCondition {
             .clone()
             .map(|p| PageRequest::new(p.current, p.size))
     }
+
+    /**
+     * 是否为游标（keyset）分页
+     */
+    pub fn is_cursor_paging(&self) -> bool {
+        self.paging
+            .as_ref()
+            .map(|p| p.cursor || p.after.is_some())
+            .unwrap_or(false)
+    }
+
+    /**
+     * 游标分页需要唯一且稳定的排序，将主键追加到排序字段之后
+     * 游标分页不支持group by
+     */
+    pub fn with_cursor_keys(&self, pkeys: &[String]) -> anyhow::Result<QueryCondition> {
+        if !self.group_by.is_empty() {
+            return Err(ConditionValidationError::invalid_cursor(
+                "cursor paging does not support group_by",
+            )
+            .into());
+        }
+        if self.sorts.iter().any(|f| f.relation.is_some()) {
+            return Err(ConditionValidationError::invalid_cursor(
+                "cursor paging does not support sorts on relation fields",
+            )
+            .into());
+        }
+        let mut qs = self.clone();
+        for key in pkeys {
+            if !qs.sorts.iter().any(|f| f.field == *key) {
+                qs.sorts.push(OrdianlItem {
+                    field: key.to_owned(),
+                    sort_asc: true,
+                    relation: None,
+                });
+            }
+        }
+        if qs.sorts.is_empty() {
+            return Err(ConditionValidationError::invalid_cursor(
+                "cursor paging requires sorts or a primary key",
+            )
+            .into());
+        }
+        Ok(qs)
+    }
+
+    fn decode_cursor(&self, token: &str) -> Result<Vec<Value>, ConditionValidationError> {
+        let text = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(token)
+            .map_err(|_| ConditionValidationError::invalid_cursor("malformed cursor"))?;
+        let cursor = serde_json::from_slice::<Value>(&text)
+            .map_err(|_| ConditionValidationError::invalid_cursor("malformed cursor"))?;
+        let keys = self.sorts.iter().map(|f| Value::String(f.field.clone())).collect_vec();
+        if cursor.get("k") != Some(&Value::Array(keys)) {
+            return Err(ConditionValidationError::invalid_cursor(
+                "cursor does not match the sorts of this query",
+            ));
+        }
+        match cursor.get("v") {
+            Some(Value::Array(vals)) if vals.len() == self.sorts.len() => Ok(vals.clone()),
+            _ => Err(ConditionValidationError::invalid_cursor("malformed cursor")),
+        }
+    }
+
+    /**
+     * 根据记录中的排序字段生成下一页的游标，记录中的键可以是字段名（去除表别名）或属性名
+     */
+    pub fn make_cursor(&self, row: &Value, prop_of: impl Fn(&str) -> Option<String>) -> Option<String> {
+        let vals = self
+            .sorts
+            .iter()
+            .map(|f| {
+                let name = f.field.rsplit('.').next().unwrap_or(&f.field);
+                row.get(name)
+                    .or_else(|| prop_of(&f.field).and_then(|p| row.get(p)))
+                    .cloned()
+            })
+            .collect::<Option<Vec<Value>>>()?;
+        let keys = self.sorts.iter().map(|f| f.field.clone()).collect_vec();
+        let text = serde_json::to_vec(&json!({"k": keys, "v": vals})).ok()?;
+        Some(base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(text))
+    }
+
+    /**
+     * 生成游标分页的查询条件，包括原有的条件、游标之后的keyset条件以及排序
+     * 调用前应先使用with_cursor_keys补全排序字段，返回的条件不会为空
+     * keyset条件展开为 (a > ?) or (a = ? and b > ?) ... 的形式，以兼容混合的排序方向
+     */
+    pub fn to_cursor_query(&self, driver: &str) -> anyhow::Result<(String, Vec<Value>)> {
+        let (where_sql, mut args) = self.to_query(driver, true)?;
+        let mut parts = vec![];
+        if !where_sql.is_empty() {
+            parts.push(format!("({})", where_sql));
+        }
+
+        if let Some(token) = self.paging.as_ref().and_then(|p| p.after.clone()) {
+            let vals = self.decode_cursor(&token)?;
+            let mut keyset = vec![];
+            for (idx, f) in self.sorts.iter().enumerate() {
+                let mut segs = vec![];
+                for (eq, v) in self.sorts.iter().zip(vals.iter()).take(idx) {
+                    segs.push(format!("{} = ?", eq.field));
+                    args.push(v.clone());
+                }
+                let op = if f.sort_asc { ">" } else { "<" };
+                segs.push(format!("{} {} ?", f.field, op));
+                args.push(vals[idx].clone());
+                keyset.push(format!("({})", segs.join(" and ")));
+            }
+            parts.push(format!("({})", keyset.join(" or ")));
+        }
+
+        let mut cond_sql = if parts.is_empty() {
+            "1 = 1".to_owned()
+        } else {
+            parts.join(" and ")
+        };
+
+        let sorts = QueryCondition {
+            sorts: self.sorts.clone(),
+            ..Default::default()
+        };
+        let (order_sql, _) = sorts.to_query(driver, false)?;
+        cond_sql.push_str(&order_sql);
+        Ok((cond_sql, args))
+    }
+
+    /**
+     * 生成聚合查询的SQL，from_sql为from子句，如 from table _tbl
+     * resolver将属性名或字段名解析为(字段名, 结果中的键名)
+     * 分组及聚合在子查询中完成，having与sorts作用于子查询的结果，因此可以直接使用别名，兼容不支持在having中使用别名的数据库
+     */
+    pub fn to_aggregate_query<F>(
+        &self,
+        driver: &str,
+        from_sql: &str,
+        resolver: F,
+    ) -> anyhow::Result<(String, Vec<Value>)>
+    where
+        F: Fn(&str) -> Option<(String, String)>,
+    {
+        if self.aggregates.is_empty() {
+            return Err(ConditionValidationError {
+                field: "aggregates".to_owned(),
+                op: None,
+                reason: "at least one aggregate expression is required".to_owned(),
+            }
+            .into());
+        }
+
+        let field_of = |f: &str| resolver(f).map(|(field, _)| field);
+        let mut columns = vec![];
+        let mut groups = vec![];
+        let mut outputs = vec![];
+        for g in self.group_by.iter() {
+            let (field, output) =
+                resolver(&g.field).ok_or_else(|| ConditionValidationError::unknown_field(&g.field))?;
+            columns.push(format!("{} as {}", field, output));
+            groups.push(field);
+            outputs.push(output);
+        }
+
+        for agg in self.aggregates.iter() {
+            let output = agg.output_name();
+            if !is_safe_identifier(&output) || output.contains('.') || outputs.contains(&output) {
+                return Err(ConditionValidationError::unknown_field(&output).into());
+            }
+            columns.push(format!("{} as {}", agg.to_expr(&field_of)?, output));
+            outputs.push(output);
+        }
+
+        // having及排序只能使用结果中的键名，分组字段也可以使用其属性名或字段名
+        let output_of = |f: &str| {
+            if outputs.iter().any(|o| o == f) {
+                Some(f.to_owned())
+            } else {
+                resolver(f)
+                    .map(|(_, output)| output)
+                    .filter(|o| outputs.contains(o))
+            }
+        };
+
+        let where_cond = QueryCondition {
+            and: self.and.clone(),
+            or: self.or.clone(),
+            ..Default::default()
+        }
+        .refine_fields(field_of)?;
+        let (where_sql, mut args) = where_cond.to_query(driver, true)?;
+
+        let mut sql = format!("select {} {} where 1 = 1", columns.join(", "), from_sql);
+        if !where_cond.is_empty_condition() {
+            sql.push_str(" and ");
+            sql.push_str(&where_sql);
+        }
+        if !groups.is_empty() {
+            sql.push_str(" group by ");
+            sql.push_str(&groups.join(", "));
+        }
+
+        let outer = QueryCondition {
+            and: self.having.clone(),
+            sorts: self.sorts.clone(),
+            ..Default::default()
+        }
+        .refine_fields(output_of)?;
+        let (outer_sql, mut outer_args) = outer.to_query(driver, false)?;
+        let mut sql = format!("select * from ({}) _agg", sql);
+        if !outer.is_empty_condition() {
+            sql.push_str(" where ");
+        }
+        sql.push_str(&outer_sql);
+        args.append(&mut outer_args);
+
+        if let Some(pg) = self.to_page_request() {
+            sql = get_dialect(driver).paginate(&sql, pg.page_size(), pg.offset());
+        }
+
+        Ok((sql, args))
+    }
 }
@@ -0,0 +1,480 @@
+use chimes_dbs_factory::{get_rbatis_dialect, ColumnAttributes, Dialect};
+use chrono::Local;
+use itertools::Itertools;
+use rbatis::{executor::Executor, rbdc::Uuid, RBatis};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{Column, StoreObject};
+
+use super::probe::{ColumnInfo, KeyColumnInfo, TableInfo};
+
+/**
+ * 记录已执行的迁移的表
+ */
+pub const MIGRATION_TABLE: &str = "chimes_schema_migrations";
+
+/**
+ * 长度可以在类型后以(n)给出的字段类型
+ */
+const SIZED_TYPES: [&str; 8] = [
+    "varchar",
+    "char",
+    "nvarchar",
+    "nchar",
+    "varbinary",
+    "binary",
+    "character varying",
+    "character",
+];
+
+/**
+ * 迁移中的一条语句，action为create（建表）、add（增加字段）、modify（修改字段）
+ * destructive为true表示该语句会缩小字段的长度或精度，已有的数据可能被截断
+ */
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct MigrationStep {
+    pub object: String,
+    pub table_name: String,
+    pub action: String,
+    pub column: Option<String>,
+    pub statement: String,
+    #[serde(default)]
+    pub destructive: bool,
+}
+
+unsafe impl Send for MigrationStep {}
+unsafe impl Sync for MigrationStep {}
+
+/**
+ * 根据StoreObject的字段定义与数据库中的表结构比较后得到的迁移计划
+ * 只会建表、增加字段或修改字段的类型/长度，不会删除表或字段，主键的差异、无法执行的修改以及会截断数据的修改放入warnings
+ */
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct MigrationPlan {
+    pub namespace: String,
+    pub dialect: String,
+    pub transactional: bool,
+    pub steps: Vec<MigrationStep>,
+    pub warnings: Vec<String>,
+    pub migration_id: Option<String>,
+    pub applied: bool,
+}
+
+unsafe impl Send for MigrationPlan {}
+unsafe impl Sync for MigrationPlan {}
+
+impl MigrationPlan {
+    /**
+     * 生成迁移计划，视图及col_type为relation的字段不参与比较
+     */
+    pub async fn plan(
+        rb: &RBatis,
+        ns: &str,
+        schema: &str,
+        objects: &[StoreObject],
+    ) -> anyhow::Result<Self> {
+        let dialect = get_rbatis_dialect(rb);
+        let mut plan = MigrationPlan {
+            namespace: ns.to_owned(),
+            dialect: dialect.name().to_owned(),
+            transactional: dialect.supports_transactional_ddl(),
+            ..Default::default()
+        };
+
+        for sto in objects {
+            if sto.object_type.to_lowercase().contains("view") {
+                continue;
+            }
+            let fields = Self::table_fields(sto);
+            if fields.is_empty() {
+                plan.warnings
+                    .push(format!("{} has no column definitions, skipped.", sto.name));
+                continue;
+            }
+            let table = Self::qualified_table(dialect, schema, &sto.object_name);
+            match TableInfo::find_one_table(rb, schema, &sto.object_name).await? {
+                None => {
+                    let columns = fields
+                        .iter()
+                        .map(|f| Self::column_definition(dialect, f))
+                        .collect_vec();
+                    let keys = fields
+                        .iter()
+                        .filter(|f| f.pkey)
+                        .map(|f| dialect.quote_identifier(&f.field_name))
+                        .collect_vec();
+                    plan.steps.push(MigrationStep {
+                        object: sto.name.clone(),
+                        table_name: sto.object_name.clone(),
+                        action: "create".to_owned(),
+                        column: None,
+                        statement: dialect.create_table_sql(&table, &columns, &keys),
+                        destructive: false,
+                    });
+                }
+                Some(_) => {
+                    let exists = ColumnInfo::load_columns(rb, schema, &sto.object_name).await?;
+                    for col in fields.iter() {
+                        let probed = exists.iter().find(|c| {
+                            c.column_name
+                                .clone()
+                                .map(|n| n.eq_ignore_ascii_case(&col.field_name))
+                                .unwrap_or(false)
+                        });
+                        match probed {
+                            None => plan.steps.push(MigrationStep {
+                                object: sto.name.clone(),
+                                table_name: sto.object_name.clone(),
+                                action: "add".to_owned(),
+                                column: Some(col.field_name.clone()),
+                                statement: dialect
+                                    .add_column_sql(&table, &Self::column_definition(dialect, col)),
+                                destructive: false,
+                            }),
+                            Some(ci) if Self::is_changed(col, ci) => {
+                                let type_sql = Self::column_type(dialect, col);
+                                match dialect.modify_column_sql(
+                                    &table,
+                                    &dialect.quote_identifier(&col.field_name),
+                                    &type_sql,
+                                    &Self::column_attributes(ci),
+                                ) {
+                                    Some(stmt) => {
+                                        let shrunk = Self::shrunk_reason(col, ci);
+                                        if let Some(reason) = shrunk.as_ref() {
+                                            plan.warnings.push(format!(
+                                                "{}.{} {}, existing data may be truncated.",
+                                                sto.object_name, col.field_name, reason
+                                            ));
+                                        }
+                                        plan.steps.push(MigrationStep {
+                                            object: sto.name.clone(),
+                                            table_name: sto.object_name.clone(),
+                                            action: "modify".to_owned(),
+                                            column: Some(col.field_name.clone()),
+                                            statement: stmt,
+                                            destructive: shrunk.is_some(),
+                                        })
+                                    }
+                                    None => plan.warnings.push(format!(
+                                        "{}.{} should be changed to {}, but {} does not support modifying columns.",
+                                        sto.object_name,
+                                        col.field_name,
+                                        type_sql,
+                                        dialect.name()
+                                    )),
+                                }
+                            }
+                            Some(_) => {}
+                        }
+                    }
+
+                    let defined_keys = fields
+                        .iter()
+                        .filter(|f| f.pkey)
+                        .map(|f| f.field_name.to_lowercase())
+                        .sorted()
+                        .collect_vec();
+                    let probed_keys = KeyColumnInfo::load_table_pkeys(rb, schema, &sto.object_name)
+                        .await
+                        .unwrap_or_default()
+                        .into_iter()
+                        .filter_map(|f| f.column_name.map(|c| c.to_lowercase()))
+                        .sorted()
+                        .collect_vec();
+                    if !defined_keys.is_empty() && defined_keys != probed_keys {
+                        plan.warnings.push(format!(
+                            "The primary key of {} is ({}) in database, but ({}) is defined. Primary keys will not be changed.",
+                            sto.object_name,
+                            probed_keys.join(", "),
+                            defined_keys.join(", ")
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(plan)
+    }
+
+    /**
+     * 执行迁移计划并记录到MIGRATION_TABLE
+     * 数据库支持事务性DDL时在同一个事务中执行，任一语句失败则全部回滚
+     * 否则逐条执行，失败时已执行的语句会被记录，并返回错误
+     */
+    pub async fn apply(&mut self, rb: &RBatis, schema: &str, applied_by: &str) -> anyhow::Result<()> {
+        if self.steps.is_empty() {
+            return Ok(());
+        }
+        let dialect = get_rbatis_dialect(rb);
+        Self::ensure_migration_table(rb, schema).await?;
+        let migration_id = format!(
+            "{}-{}",
+            Local::now().format("%Y%m%d%H%M%S"),
+            Uuid::new().to_lowercase().replace('-', "").chars().take(8).collect::<String>()
+        );
+
+        if self.transactional {
+            let tx = rb.acquire_begin().await?;
+            let ret = match Self::execute(&tx, &self.steps).await {
+                (_, Some(err)) => Err(err),
+                (_, None) => self.record(&tx, dialect, schema, &migration_id, &self.steps, applied_by).await,
+            };
+            match ret {
+                Ok(_) => tx.commit().await?,
+                Err(err) => {
+                    if let Err(rerr) = tx.rollback().await {
+                        log::warn!("Could not rollback the migration {}: {}", migration_id, rerr);
+                    }
+                    return Err(err);
+                }
+            }
+        } else {
+            let conn = rb.acquire().await?;
+            let (done, err) = Self::execute(&conn, &self.steps).await;
+            if done > 0 {
+                self.record(&conn, dialect, schema, &migration_id, &self.steps[..done], applied_by)
+                    .await?;
+            }
+            if let Some(err) = err {
+                return Err(anyhow::anyhow!(
+                    "Migration {} stopped after {} of {} statement(s): {}",
+                    migration_id,
+                    done,
+                    self.steps.len(),
+                    err
+                ));
+            }
+        }
+        self.migration_id = Some(migration_id);
+        self.applied = true;
+        Ok(())
+    }
+
+    async fn execute(exec: &dyn Executor, steps: &[MigrationStep]) -> (usize, Option<anyhow::Error>) {
+        for (idx, step) in steps.iter().enumerate() {
+            log::info!("Migrate {}: {}", step.object, step.statement);
+            if let Err(err) = exec.exec(&step.statement, vec![]).await {
+                return (
+                    idx,
+                    Some(anyhow::anyhow!("{} failed: {}", step.statement, err)),
+                );
+            }
+        }
+        (steps.len(), None)
+    }
+
+    async fn record(
+        &self,
+        exec: &dyn Executor,
+        dialect: &dyn Dialect,
+        schema: &str,
+        migration_id: &str,
+        steps: &[MigrationStep],
+        applied_by: &str,
+    ) -> anyhow::Result<()> {
+        let sql = format!(
+            "insert into {} (applied_at, migration_id, namespace, object_names, statements, applied_by) values ({}, ?, ?, ?, ?, ?)",
+            Self::qualified_table(dialect, schema, MIGRATION_TABLE),
+            dialect.cast_placeholder("timestamp")
+        );
+        let args = vec![
+            rbs::Value::String(Local::now().format("%Y-%m-%d %H:%M:%S").to_string()),
+            rbs::Value::String(migration_id.to_owned()),
+            rbs::Value::String(self.namespace.clone()),
+            rbs::Value::String(steps.iter().map(|f| f.object.clone()).unique().join(",")),
+            rbs::Value::String(steps.iter().map(|f| f.statement.clone()).join(";\n")),
+            rbs::Value::String(applied_by.to_owned()),
+        ];
+        exec.exec(&sql, args).await?;
+        Ok(())
+    }
+
+    async fn ensure_migration_table(rb: &RBatis, schema: &str) -> anyhow::Result<()> {
+        if TableInfo::find_one_table(rb, schema, MIGRATION_TABLE).await?.is_some() {
+            return Ok(());
+        }
+        let dialect = get_rbatis_dialect(rb);
+        let column = |name: &str, col_type: &str, length: Option<i64>| {
+            format!(
+                "{} {}",
+                dialect.quote_identifier(name),
+                dialect.column_type_sql(col_type, length)
+            )
+        };
+        let columns = vec![
+            column("applied_at", "datetime", None),
+            format!("{} not null", column("migration_id", "string", Some(64))),
+            column("namespace", "string", Some(128)),
+            column("object_names", "string", Some(1024)),
+            column("statements", "text", None),
+            column("applied_by", "string", Some(128)),
+        ];
+        let sql = dialect.create_table_sql(
+            &Self::qualified_table(dialect, schema, MIGRATION_TABLE),
+            &columns,
+            &[dialect.quote_identifier("migration_id")],
+        );
+        rb.exec(&sql, vec![]).await?;
+        Ok(())
+    }
+
+    /**
+     * 带有schema前缀的表名，schema为空时使用连接的缺省schema
+     */
+    fn qualified_table(dialect: &dyn Dialect, schema: &str, table: &str) -> String {
+        if schema.trim().is_empty() {
+            dialect.quote_identifier(table)
+        } else {
+            dialect.quote_identifier(&format!("{}.{}", schema.trim(), table))
+        }
+    }
+
+    /**
+     * 参与迁移的字段，relation字段不是表中的字段，同名的字段只取第一个
+     */
+    fn table_fields(sto: &StoreObject) -> Vec<Column> {
+        sto.fields
+            .iter()
+            .filter(|f| {
+                !f.field_name.is_empty()
+                    && f.col_type.clone().unwrap_or_default().to_lowercase() != "relation"
+            })
+            .unique_by(|f| f.field_name.to_lowercase())
+            .cloned()
+            .collect_vec()
+    }
+
+    /**
+     * 字段的类型，field_type（数据库中的原始类型）优先，否则按col_type转换
+     */
+    fn column_type(dialect: &dyn Dialect, col: &Column) -> String {
+        let length = col.col_length.filter(|f| *f > 0);
+        match col.field_type.clone().filter(|f| !f.trim().is_empty()) {
+            Some(ft) => {
+                let ft = ft.trim().to_owned();
+                match length {
+                    Some(len)
+                        if !ft.contains('(') && SIZED_TYPES.contains(&ft.to_lowercase().as_str()) =>
+                    {
+                        format!("{ft}({len})")
+                    }
+                    _ => ft,
+                }
+            }
+            None => dialect.column_type_sql(&col.col_type.clone().unwrap_or_default(), length),
+        }
+    }
+
+    fn column_definition(dialect: &dyn Dialect, col: &Column) -> String {
+        format!(
+            "{} {}{}",
+            dialect.quote_identifier(&col.field_name),
+            Self::column_type(dialect, col),
+            if col.pkey { " not null" } else { "" }
+        )
+    }
+
+    /**
+     * 修改字段时需要保留的原有属性
+     */
+    fn column_attributes(ci: &ColumnInfo) -> ColumnAttributes {
+        let extra = ci.column_extra.clone().unwrap_or_default().to_lowercase();
+        ColumnAttributes {
+            nullable: !ci.is_nullable.clone().unwrap_or_default().eq_ignore_ascii_case("no"),
+            default: ci.column_default.clone(),
+            auto_increment: extra.contains("auto_increment"),
+            on_update: extra
+                .find("on update ")
+                .map(|idx| extra[idx + "on update ".len()..].trim().to_uppercase())
+                .filter(|f| !f.is_empty()),
+            comment: ci.column_comment.clone(),
+        }
+    }
+
+    /**
+     * 类型中以括号给出的参数，如varchar(32)为[32]，decimal(10,2)为[10, 2]
+     */
+    fn type_args(field_type: &str) -> Vec<i64> {
+        field_type
+            .split_once('(')
+            .and_then(|(_, rest)| rest.split_once(')'))
+            .map(|(args, _)| args.split(',').filter_map(|f| f.trim().parse::<i64>().ok()).collect_vec())
+            .unwrap_or_default()
+    }
+
+    /**
+     * 定义的字段长度，col_length优先，否则使用field_type中给出的长度
+     */
+    fn declared_length(col: &Column) -> Option<i64> {
+        col.col_length.filter(|f| *f > 0).or_else(|| {
+            col.field_type
+                .clone()
+                .and_then(|ft| Self::type_args(&ft).first().cloned())
+                .filter(|f| *f > 0)
+        })
+    }
+
+    /**
+     * field_type中给出的精度及小数位数，只用于decimal/numeric
+     */
+    fn declared_precision(col: &Column) -> Option<(i64, i64)> {
+        let ft = col.field_type.clone().unwrap_or_default().to_lowercase();
+        if !(ft.starts_with("decimal") || ft.starts_with("numeric")) {
+            return None;
+        }
+        match Self::type_args(&ft).as_slice() {
+            [p] => Some((*p, 0)),
+            [p, s, ..] => Some((*p, *s)),
+            _ => None,
+        }
+    }
+
+    /**
+     * 修改后长度、精度或小数位数变小时返回其说明，否则返回None
+     */
+    fn shrunk_reason(col: &Column, ci: &ColumnInfo) -> Option<String> {
+        if let (Some(len), Some(probed)) = (Self::declared_length(col), ci.character_maximum_length) {
+            if probed > 0 && len < probed && Self::declared_precision(col).is_none() {
+                return Some(format!("shrinks the length from {probed} to {len}"));
+            }
+        }
+        if let Some((p, s)) = Self::declared_precision(col) {
+            let (pp, ps) = (ci.numeric_precision.unwrap_or(0), ci.numeric_scale.unwrap_or(0));
+            if p < pp || s < ps {
+                return Some(format!("shrinks the precision from ({pp}, {ps}) to ({p}, {s})"));
+            }
+        }
+        None
+    }
+
+    /**
+     * 字段的类型、长度或精度是否与数据库中的不同，长度只比较可以指定长度的类型，精度只比较decimal/numeric
+     */
+    fn is_changed(col: &Column, ci: &ColumnInfo) -> bool {
+        let probed_type = ci.orginal_type.clone().unwrap_or_default().to_lowercase();
+        let probed_base = probed_type.split('(').next().unwrap_or_default().trim().to_owned();
+        let type_changed = match col.field_type.clone().filter(|f| !f.trim().is_empty()) {
+            Some(ft) => {
+                let ft = ft.to_lowercase();
+                ft.split('(').next().unwrap_or_default().trim() != probed_base
+            }
+            None => match col.col_type.clone().filter(|f| !f.trim().is_empty()) {
+                Some(ct) => {
+                    ColumnInfo::convert_to_type(&ct.to_lowercase())
+                        != ci.data_type.clone().unwrap_or_default()
+                }
+                None => false,
+            },
+        };
+        let length_changed = SIZED_TYPES.contains(&probed_base.as_str())
+            && matches!(
+                (Self::declared_length(col), ci.character_maximum_length),
+                (Some(a), Some(b)) if a != b
+            );
+        let precision_changed = match Self::declared_precision(col) {
+            Some((p, s)) => ci.numeric_precision.map(|pp| pp != p).unwrap_or(false) || ci.numeric_scale.unwrap_or(0) != s,
+            None => false,
+        };
+        type_changed || length_changed || precision_changed
+    }
+}
@@ -1,4 +1,4 @@
-use chimes_dbs_factory::{get_load_column_sql, get_load_one_tables_sql, get_load_table_pkey_sql, get_load_tables_sql};
+use chimes_dbs_factory::get_rbatis_dialect;
 use itertools::Itertools;
 use rbatis::RBatis;
 use serde::{Deserialize, Serialize};
@@ -19,10 +19,9 @@ unsafe impl Sync for TableInfo {}
 impl TableInfo {
     pub async fn load_tables(rb: &RBatis, table_schema: &str) -> anyhow::Result<Vec<TableInfo>> {
         // log::info!("TS: {}, TN: {}", table_schema.clone(), table_name.clone());
-        let rb_args = vec![rbs::to_value!(table_schema)];
-        let sql = get_load_tables_sql(rb);
+        let (sql, rb_args) = get_rbatis_dialect(rb).load_tables_sql(table_schema);
         match rb.query_decode::<Vec<TableInfo>>(
-            sql,
+            &sql,
             rb_args).await {
             Ok(rt) => Ok(rt),
             Err(err) => {
@@ -37,10 +36,9 @@ impl TableInfo {
         table_name: &str,
     ) -> anyhow::Result<Option<TableInfo>> {
         // log::info!("TS: {}, TN: {}", table_schema.clone(), table_name.clone());
-        let rb_args = vec![rbs::to_value!(table_schema), rbs::to_value!(table_name)];
-        let sql = get_load_one_tables_sql(rb);
+        let (sql, rb_args) = get_rbatis_dialect(rb).load_one_table_sql(table_schema, table_name);
         match rb.query_decode::<Vec<TableInfo>>(
-            sql,
+            &sql,
             rb_args).await {
             Ok(rt) => {
                 if rt.is_empty() {
@@ -70,6 +68,8 @@ pub struct ColumnInfo {
     pub is_nullable: Option<String>,
     pub numeric_precision: Option<i64>,
     pub numeric_scale: Option<i64>,
+    pub column_comment: Option<String>,
+    pub column_extra: Option<String>, // MySQL中的extra，如auto_increment、on update CURRENT_TIMESTAMP，其它数据库为空
 }
 
 unsafe impl Send for ColumnInfo {}
@@ -80,15 +80,19 @@ impl ColumnInfo {
     //        column_default, orginal_type, ordinal_position, character_maximum_length, is_nullable, numeric_precision, numeric_scale,
     //        FROM INFORMATION_SCHEMA.COLUMNS WHERE table_schema = ? and table_name = ?")]
     pub async fn load_columns(rb: &RBatis, ts: &str, tn: &str) -> anyhow::Result<Vec<Self>> {
-        let rb_args = vec![rbs::to_value!(ts), rbs::to_value!(ts), rbs::to_value!(tn)];
         // rb.update_by_wrapper(table, w, skips);
-        let sql = get_load_column_sql(rb);
+        let (sql, rb_args) = get_rbatis_dialect(rb).load_columns_sql(ts, tn);
         match rb.query_decode::<Vec<ColumnInfo>>(
-            sql,
+            &sql,
             rb_args).await {
             Ok(rs) =>{
                  Ok(rs.into_iter().map(|mut f| {
-                    f.data_type = Some(Self::convert_to_type(&f.orginal_type.clone().unwrap_or_default()));
+                    // SQLite/TDengine返回的类型中包含长度，如varchar(32)
+                    let (type_name, length) = Self::split_type_length(&f.orginal_type.clone().unwrap_or_default());
+                    if f.character_maximum_length.is_none() {
+                        f.character_maximum_length = length;
+                    }
+                    f.data_type = Some(Self::convert_to_type(&type_name));
                     f
                 }).collect_vec())
             },
@@ -98,6 +102,16 @@ impl ColumnInfo {
         }
     }
 
+    fn split_type_length(type_name: &str) -> (String, Option<i64>) {
+        match type_name.split_once('(') {
+            Some((name, rest)) => (
+                name.trim().to_lowercase(),
+                rest.trim_end_matches(')').split(',').next().and_then(|f| f.trim().parse::<i64>().ok()),
+            ),
+            None => (type_name.trim().to_lowercase(), None),
+        }
+    }
+
     pub fn convert_to_type(type_name: &str) -> String {
         match type_name {
             "int" | "int8" | "int4" | "integer" | "long" | "unsigned int" | "smallint"
@@ -115,10 +129,17 @@ impl ColumnInfo {
 
 impl From<ColumnInfo> for Column {
     fn from(val: ColumnInfo) -> Column {
+        // 有字段注释时使用注释作为标题
+        let title = val
+            .column_comment
+            .clone()
+            .map(|f| f.trim().to_owned())
+            .filter(|f| !f.is_empty())
+            .or(val.column_name.clone());
         Column {
             field_name: val.column_name.clone().unwrap_or_default(),
             prop_name: val.column_name.clone(),
-            title: val.column_name.clone(),
+            title,
             col_length: val.character_maximum_length,
             col_type: val.data_type.clone(),
             field_type: val.orginal_type.clone(),
@@ -140,11 +161,98 @@ unsafe impl Sync for KeyColumnInfo {}
 
 impl KeyColumnInfo {
     pub async fn load_table_pkeys(rb: &RBatis, ts: &str, tn: &str) -> anyhow::Result<Vec<Self>> {
-        let rb_args = vec![rbs::to_value!(ts), rbs::to_value!(tn)];
         // rb.update_by_wrapper(table, w, skips);
-        let sql = get_load_table_pkey_sql(rb);
+        let (sql, rb_args) = get_rbatis_dialect(rb).load_pkeys_sql(ts, tn);
+        match rb.query_decode(
+            &sql,
+            rb_args).await {
+            Ok(rs) => Ok(rs),
+            Err(err) => {
+                Err(anyhow::Error::new(err))
+            }
+        }
+    }
+}
+
+/**
+ * 表的索引，每个字段一条记录，复合索引按ordinal_position排列
+ * 主键索引也会被列出（MySQL中为PRIMARY）
+ */
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct IndexInfo {
+    pub index_name: Option<String>,
+    pub column_name: Option<String>,
+    pub non_unique: Option<i64>,
+    pub ordinal_position: Option<i64>,
+}
+
+unsafe impl Send for IndexInfo {}
+unsafe impl Sync for IndexInfo {}
+
+impl IndexInfo {
+    pub fn is_unique(&self) -> bool {
+        self.non_unique == Some(0)
+    }
+
+    /**
+     * 列出表的索引，数据库不支持索引探测时返回空
+     */
+    pub async fn load_indexes(rb: &RBatis, ts: &str, tn: &str) -> anyhow::Result<Vec<Self>> {
+        let (sql, rb_args) = match get_rbatis_dialect(rb).load_indexes_sql(ts, tn) {
+            Some(t) => t,
+            None => return Ok(vec![]),
+        };
+        match rb.query_decode(
+            &sql,
+            rb_args).await {
+            Ok(rs) => Ok(rs),
+            Err(err) => {
+                Err(anyhow::Error::new(err))
+            }
+        }
+    }
+
+    /**
+     * 按索引名分组，返回唯一索引的字段列表
+     */
+    pub fn unique_groups(indexes: &[Self]) -> Vec<(String, Vec<String>)> {
+        indexes
+            .iter()
+            .filter(|f| f.is_unique())
+            .sorted_by_key(|f| (f.index_name.clone().unwrap_or_default(), f.ordinal_position.unwrap_or_default()))
+            .group_by(|f| f.index_name.clone().unwrap_or_default())
+            .into_iter()
+            .map(|(name, grp)| (name, grp.filter_map(|f| f.column_name.clone()).collect_vec()))
+            .collect_vec()
+    }
+}
+
+/**
+ * 表的外键，每个字段一条记录
+ */
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ForeignKeyInfo {
+    pub constraint_name: Option<String>,
+    pub column_name: Option<String>,
+    pub referenced_table_schema: Option<String>,
+    pub referenced_table_name: Option<String>,
+    pub referenced_column_name: Option<String>,
+}
+
+unsafe impl Send for ForeignKeyInfo {}
+unsafe impl Sync for ForeignKeyInfo {}
+
+impl ForeignKeyInfo {
+    /**
+     * 列出表的外键，数据库不支持外键探测时返回空
+     */
+    pub async fn load_foreign_keys(rb: &RBatis, ts: &str, tn: &str) -> anyhow::Result<Vec<Self>> {
+        let (sql, rb_args) = match get_rbatis_dialect(rb).load_foreign_keys_sql(ts, tn) {
+            Some(t) => t,
+            None => return Ok(vec![]),
+        };
         match rb.query_decode(
-            sql,
+            &sql,
             rb_args).await {
             Ok(rs) => Ok(rs),
             Err(err) => {
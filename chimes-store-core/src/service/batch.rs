@@ -0,0 +1,160 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Error};
+use chimes_store_utils::template::json_path_get;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::{invoker::InvocationContext, starter::MxStoreService};
+
+lazy_static::lazy_static! {
+    static ref BATCH_REF_REGEX: regex::Regex = regex::Regex::new(r"\$\{([^}]+)\}").unwrap();
+}
+
+/**
+ * 批量执行中的一个步骤
+ * return为返回值的类型，option（缺省）、list或者paged，分别对应invoke_return_one/invoke_return_vec/invoke_return_page
+ * params中的字符串可以使用${jsonpath}引用之前步骤的结果，jsonpath的根为{"steps": [第0步的结果, 第1步的结果, ...]}
+ * 整个字符串为一个引用时替换为引用的值，否则替换为引用值的文本
+ */
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BatchStep {
+    pub uri: String,
+    pub params: Value,
+    #[serde(rename = "return")]
+    pub return_type: Option<String>,
+}
+
+/**
+ * 批量执行的结果，失败时failed_step为失败步骤的序号（从0开始），results为已经成功的步骤的结果
+ */
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct BatchResult {
+    pub committed: bool,
+    pub results: Vec<Value>,
+    pub failed_step: Option<usize>,
+    pub error: Option<String>,
+}
+
+fn resolve_batch_ref(path: &str, root: &Value) -> Result<Value, Error> {
+    json_path_get(root, path.trim()).ok_or(anyhow!("The reference {} was not found.", path))
+}
+
+/**
+ * 将参数中的${jsonpath}引用替换为之前步骤的结果
+ */
+pub fn resolve_batch_refs(params: &Value, root: &Value) -> Result<Value, Error> {
+    match params {
+        Value::String(text) => {
+            if let Some(cap) = BATCH_REF_REGEX.captures(text) {
+                if cap.get(0).map(|m| m.as_str().len()) == Some(text.len()) {
+                    return resolve_batch_ref(&cap[1], root);
+                }
+            } else {
+                return Ok(params.clone());
+            }
+            let mut ret = String::new();
+            let mut last = 0;
+            for cap in BATCH_REF_REGEX.captures_iter(text) {
+                let whole = cap.get(0).unwrap();
+                ret.push_str(&text[last..whole.start()]);
+                match resolve_batch_ref(&cap[1], root)? {
+                    Value::String(s) => ret.push_str(&s),
+                    val => ret.push_str(&val.to_string()),
+                }
+                last = whole.end();
+            }
+            ret.push_str(&text[last..]);
+            Ok(Value::String(ret))
+        }
+        Value::Array(list) => Ok(Value::Array(
+            list.iter()
+                .map(|f| resolve_batch_refs(f, root))
+                .collect::<Result<Vec<Value>, Error>>()?,
+        )),
+        Value::Object(mp) => {
+            let mut ret = serde_json::Map::new();
+            for (k, v) in mp.iter() {
+                ret.insert(k.clone(), resolve_batch_refs(v, root)?);
+            }
+            Ok(Value::Object(ret))
+        }
+        _ => Ok(params.clone()),
+    }
+}
+
+async fn invoke_batch_step(
+    step: &BatchStep,
+    ctx: Arc<Mutex<InvocationContext>>,
+    root: &Value,
+) -> Result<Value, Error> {
+    if step.uri.is_empty() {
+        return Err(anyhow!("No invoke URI provided"));
+    }
+    let params = match resolve_batch_refs(&step.params, root)? {
+        Value::Array(list) => list,
+        Value::Null => vec![],
+        val => vec![val],
+    };
+    match step.return_type.clone().unwrap_or_default().to_lowercase().as_str() {
+        "" | "option" => MxStoreService::invoke_return_one(step.uri.clone(), ctx, params)
+            .await
+            .map(|f| f.unwrap_or(Value::Null)),
+        "list" => MxStoreService::invoke_return_vec(step.uri.clone(), ctx, params)
+            .await
+            .map(Value::Array),
+        "paged" => {
+            let page = MxStoreService::invoke_return_page(step.uri.clone(), ctx, params).await?;
+            serde_json::to_value(page).map_err(|err| anyhow!(err))
+        }
+        tp => Err(anyhow!("Unsupported return type {}", tp)),
+    }
+}
+
+/**
+ * 在同一个InvocationContext中依次执行各步骤，所有步骤共享各命名空间的事务
+ * 全部成功时提交事务，任一步骤失败时回滚事务并停止执行后续的步骤
+ */
+pub async fn invoke_batch(ctx: InvocationContext, steps: &[BatchStep]) -> BatchResult {
+    let ctx = Arc::new(Mutex::new(ctx));
+    let mut ret = BatchResult::default();
+    for (idx, step) in steps.iter().enumerate() {
+        let root = json!({ "steps": ret.results });
+        match invoke_batch_step(step, ctx.clone(), &root).await {
+            Ok(val) => ret.results.push(val),
+            Err(err) => {
+                log::info!("Batch step {} {} failed: {}", idx, step.uri, err);
+                ret.failed_step = Some(idx);
+                ret.error = Some(err.to_string());
+                break;
+            }
+        }
+    }
+
+    let commit = ret.failed_step.is_none();
+    match Arc::try_unwrap(ctx) {
+        Ok(mtx) => {
+            let mut inner = mtx.into_inner().unwrap_or_else(|err| err.into_inner());
+            match inner.commit_or_rollback(commit).await {
+                Ok(_) => ret.committed = commit,
+                Err(err) => {
+                    if ret.error.is_none() {
+                        ret.error = Some(err.to_string());
+                    }
+                }
+            }
+        }
+        Err(ctx) => {
+            // 仍有其它地方持有InvocationContext，事务只能在其释放时提交或回滚，此时无法确认结果
+            if !commit {
+                ctx.lock().unwrap().set_failed();
+            }
+            ret.committed = false;
+            if ret.error.is_none() {
+                ret.error = Some("The transaction is still in use and will be committed or rolled back when it is released".to_owned());
+            }
+        }
+    }
+    ret
+}
@@ -8,6 +8,7 @@ use std::{
 };
 
 use crate::pin_blockon_async;
+use crate::utils::cache::cache_invalidate_tags;
 use crate::config::auth::JwtUserClaims;
 
 static INVOCATION_CTX_ID_REF: AtomicU64 = AtomicU64::new(1);
@@ -93,6 +94,26 @@ impl InvocationContext {
         ctx_inner
     }
 
+    /**
+     * 为后台任务（如刷新过期缓存）创建新的上下文，保留用户信息、角色、来源地址以及读主库、跨租户的要求
+     * 不复制事务、连接以及其它调用中产生的数据，后台任务不参与当前请求的事务
+     */
+    pub fn fork(&self) -> Self {
+        let mut ctx_inner = InvocationContext::new();
+        if let Ok(jwt) = self.obtain::<JwtUserClaims>() {
+            ctx_inner.inject(jwt.clone());
+        }
+        if let Some(addr) = self.get_string("REMOTE_ADDR") {
+            ctx_inner.insert("REMOTE_ADDR", addr);
+        }
+        if let Ok(roles) = self.get::<Vec<String>>("USER_ROLES") {
+            ctx_inner.set_user_roles(roles.clone());
+        }
+        ctx_inner.set_read_primary(self.get_bool("READ_PRIMARY"));
+        ctx_inner.set_cross_tenant(self.get_bool("CROSS_TENANT"));
+        ctx_inner
+    }
+
     pub fn new_with_id(id: u64) -> Self {
         Self {
             id,
@@ -151,6 +172,7 @@ impl InvocationContext {
     #[inline]
     pub fn obtain_jwt_user_info(&self) -> Option<JwtUserClaims> {
         match self.obtain::<JwtUserClaims>() {
+            Ok(t) if self.get_bool("CROSS_TENANT") => Some(t.cross_tenant()),
             Ok(t) => Some(t.clone()),
             Err(_) => Some(JwtUserClaims::anonymous()),
         }
@@ -308,6 +330,76 @@ impl InvocationContext {
         self.conn.get(ns).map(|f| f.to_owned())
     }
 
+    /**
+     * 要求本次调用中的查询都使用主库，不使用只读副本
+     */
+    pub fn set_read_primary(&mut self, primary: bool) {
+        self.insert("READ_PRIMARY", primary);
+    }
+
+    /**
+     * 查询是否需要使用主库，已经在该命名空间中开启了事务或者要求读主库时为true
+     */
+    pub fn is_read_primary(&self, ns: &str) -> bool {
+        self.tx.contains_key(ns) || self.get_bool("READ_PRIMARY")
+    }
+
+    /**
+     * 超级管理员显式要求跨租户访问，obtain_jwt_user_info返回的用户信息中domain为TENANT_ALL
+     */
+    pub fn set_cross_tenant(&mut self, cross: bool) {
+        self.insert("CROSS_TENANT", cross);
+    }
+
+    /**
+     * 当前用户的角色，由认证中间件解析后放在depot的_USER_ROLES中
+     */
+    pub fn set_user_roles(&mut self, roles: Vec<String>) {
+        self.insert("USER_ROLES", roles);
+    }
+
+    /**
+     * 当前用户的角色，未设置时匿名用户为ROLE_ANONYMOUS，其它用户没有角色
+     */
+    pub fn get_user_roles(&self) -> Vec<String> {
+        match self.get::<Vec<String>>("USER_ROLES") {
+            Ok(t) => t.clone(),
+            Err(_) => match self.obtain::<JwtUserClaims>() {
+                Ok(jwt) if !jwt.is_anonymous() => vec![],
+                _ => vec!["ROLE_ANONYMOUS".to_owned()],
+            },
+        }
+    }
+
+    /**
+     * 后台刷新过期缓存时使用，跳过缓存的读取，直接加载并写入缓存
+     */
+    pub fn set_cache_refresh(&mut self, refresh: bool) {
+        self.insert("CACHE_REFRESH", refresh);
+    }
+
+    pub fn is_cache_refresh(&self) -> bool {
+        self.get_bool("CACHE_REFRESH")
+    }
+
+    /**
+     * 记录写操作影响的缓存标签，在事务提交成功后才失效，回滚时丢弃
+     * 批量调用共享同一个InvocationContext，各步骤的标签在整个批次提交后一起失效
+     */
+    pub fn add_invalidate_tags(&mut self, tags: &[String]) {
+        let mut pending = self.take_invalidate_tags();
+        for tag in tags {
+            if !pending.contains(tag) {
+                pending.push(tag.to_owned());
+            }
+        }
+        self.insert("PENDING_CACHE_TAGS", pending);
+    }
+
+    fn take_invalidate_tags(&mut self) -> Vec<String> {
+        self.remove::<Vec<String>>("PENDING_CACHE_TAGS").unwrap_or_default()
+    }
+
     pub fn set_failed(&mut self) {
         self.success = false;
     }
@@ -339,7 +431,11 @@ impl InvocationContext {
         
         self.tx.clear();
 
+        let tags = self.take_invalidate_tags();
         if ret_err.is_none() {
+            if should_commit {
+                cache_invalidate_tags(&tags);
+            }
             Ok(())
         } else {
             Err(anyhow!(ret_err.unwrap()))
@@ -398,9 +494,12 @@ impl Drop for InvocationContext {
         log::info!("InvocationContext dropped.");
         let tx_ = self.tx.clone();
         let commit = self.success;
+        let tags = self.take_invalidate_tags();
         let _ = pin_blockon_async!(async move {
-            if let Err(err) = context_finalize(tx_, commit).await {
-                log::info!("error on finallize {err}");
+            match context_finalize(tx_, commit).await {
+                Ok(_) if commit => cache_invalidate_tags(&tags),
+                Ok(_) => {}
+                Err(err) => log::info!("error on finallize {err}"),
             }
             Box::new(0) as Box<dyn Any + Send + Sync>
         }).unwrap_or(0);
@@ -420,6 +519,18 @@ impl JwtFromDepot for InvocationContext {
                 ctx_inner.inject(jwtdata.claims.clone());
             }
         }
+        if let Ok(addr) = depot.get::<String>("_REMOTE_ADDR") {
+            ctx_inner.insert("REMOTE_ADDR", addr.clone());
+        }
+        if let Ok(primary) = depot.get::<bool>("_READ_PRIMARY") {
+            ctx_inner.set_read_primary(*primary);
+        }
+        if let Ok(cross) = depot.get::<bool>("_CROSS_TENANT") {
+            ctx_inner.set_cross_tenant(*cross);
+        }
+        if let Ok(roles) = depot.get::<Vec<String>>("_USER_ROLES") {
+            ctx_inner.set_user_roles(roles.clone());
+        }
         ctx_inner
     }
 }
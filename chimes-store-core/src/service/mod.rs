@@ -1,3 +1,4 @@
+pub mod batch;
 pub mod files;
 pub mod invoker;
 pub mod mx;
@@ -1,5 +1,10 @@
+use itertools::Itertools;
+
 use crate::{
-    dbs::probe::{ColumnInfo, KeyColumnInfo, TableInfo},
+    dbs::{
+        migration::MigrationPlan,
+        probe::{ColumnInfo, ForeignKeyInfo, IndexInfo, KeyColumnInfo, TableInfo},
+    },
     utils::get_multiple_rbatis,
 };
 
@@ -13,7 +18,7 @@ impl MxProbeService for MxStoreService {
         &self,
         schema: &str,
     ) -> Result<Vec<crate::dbs::probe::TableInfo>, anyhow::Error> {
-        let rb = get_multiple_rbatis(&self.0.db_url);
+        let rb = &*get_multiple_rbatis(&self.0.db_url);
         TableInfo::load_tables(rb, schema).await
     }
 
@@ -25,7 +30,7 @@ impl MxProbeService for MxStoreService {
         schema: &str,
         tbl: &str,
     ) -> Result<Option<crate::dbs::probe::TableInfo>, anyhow::Error> {
-        let rb = get_multiple_rbatis(&self.0.db_url);
+        let rb = &*get_multiple_rbatis(&self.0.db_url);
         TableInfo::find_one_table(rb, schema, tbl).await
     }
     /**
@@ -36,7 +41,7 @@ impl MxProbeService for MxStoreService {
         schema: &str,
         tbl: &str,
     ) -> Result<Vec<crate::dbs::probe::ColumnInfo>, anyhow::Error> {
-        let rb = get_multiple_rbatis(&self.0.db_url);
+        let rb = &*get_multiple_rbatis(&self.0.db_url);
         match ColumnInfo::load_columns(rb, schema, tbl).await {
             Ok(t) => Ok(t),
             Err(err) => {
@@ -51,7 +56,7 @@ impl MxProbeService for MxStoreService {
         schema: &str,
         tbl: &str,
     ) -> Result<Vec<crate::dbs::probe::KeyColumnInfo>, anyhow::Error> {
-        let rb = get_multiple_rbatis(&self.0.db_url);
+        let rb = &*get_multiple_rbatis(&self.0.db_url);
         match KeyColumnInfo::load_table_pkeys(rb, schema, tbl).await {
             Ok(t) => Ok(t),
             Err(err) => {
@@ -60,4 +65,68 @@ impl MxProbeService for MxStoreService {
             }
         }
     }
+
+    async fn probe_table_indexes(
+        &self,
+        schema: &str,
+        tbl: &str,
+    ) -> Result<Vec<crate::dbs::probe::IndexInfo>, anyhow::Error> {
+        let rb = &*get_multiple_rbatis(&self.0.db_url);
+        match IndexInfo::load_indexes(rb, schema, tbl).await {
+            Ok(t) => Ok(t),
+            Err(err) => {
+                log::info!("Error {}", err);
+                Err(err)
+            }
+        }
+    }
+
+    async fn probe_table_foreign_keys(
+        &self,
+        schema: &str,
+        tbl: &str,
+    ) -> Result<Vec<crate::dbs::probe::ForeignKeyInfo>, anyhow::Error> {
+        let rb = &*get_multiple_rbatis(&self.0.db_url);
+        match ForeignKeyInfo::load_foreign_keys(rb, schema, tbl).await {
+            Ok(t) => Ok(t),
+            Err(err) => {
+                log::info!("Error {}", err);
+                Err(err)
+            }
+        }
+    }
+}
+
+impl MxStoreService {
+    /**
+     * 比较StoreObject的定义与数据库中的表结构，生成迁移计划，names为空时包括命名空间中所有的对象
+     */
+    pub async fn plan_migration(
+        &self,
+        schema: &str,
+        names: &[String],
+    ) -> Result<MigrationPlan, anyhow::Error> {
+        let rb = &*get_multiple_rbatis(&self.0.db_url);
+        let objects = self
+            .get_objects()
+            .into_iter()
+            .filter(|f| names.is_empty() || names.contains(&f.name))
+            .collect_vec();
+        MigrationPlan::plan(rb, &self.0.namespace, schema, &objects).await
+    }
+
+    /**
+     * 生成迁移计划并执行，返回执行后的迁移计划
+     */
+    pub async fn apply_migration(
+        &self,
+        schema: &str,
+        names: &[String],
+        applied_by: &str,
+    ) -> Result<MigrationPlan, anyhow::Error> {
+        let mut plan = self.plan_migration(schema, names).await?;
+        let rb = &*get_multiple_rbatis(&self.0.db_url);
+        plan.apply(rb, schema, applied_by).await?;
+        Ok(plan)
+    }
 }
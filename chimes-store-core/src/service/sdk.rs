@@ -9,7 +9,7 @@ use substring::Substring;
 
 use crate::config::auth::JwtUserClaims;
 use crate::config::{Column, MethodHook, PluginConfig, QueryCondition};
-use crate::dbs::probe::{ColumnInfo, KeyColumnInfo, TableInfo};
+use crate::dbs::probe::{ColumnInfo, ForeignKeyInfo, IndexInfo, KeyColumnInfo, TableInfo};
 
 use super::invoker::InvocationContext;
 
@@ -73,6 +73,16 @@ pub trait MxProbeService {
         schema: &str,
         tbl: &str,
     ) -> impl Future<Output = Result<Vec<KeyColumnInfo>, Error>> + Send;
+    fn probe_table_indexes(
+        &self,
+        schema: &str,
+        tbl: &str,
+    ) -> impl Future<Output = Result<Vec<IndexInfo>, Error>> + Send;
+    fn probe_table_foreign_keys(
+        &self,
+        schema: &str,
+        tbl: &str,
+    ) -> impl Future<Output = Result<Vec<ForeignKeyInfo>, Error>> + Send;
 }
 
 pub trait RxQueryService {
@@ -142,7 +152,7 @@ impl InvokeUri {
     pub fn is_write_method(&self) -> bool {
         matches!(
             self.method.as_str(),
-            "insert" | "update" | "upsert" | "delete" | "delete_by" | "update_by"
+            "insert" | "update" | "upsert" | "delete" | "delete_by" | "update_by" | "restore" | "purge"
         )
     }
 }
@@ -1,9 +1,10 @@
 use anyhow::Error;
+use chimes_dbs_factory::PoolStats;
 use async_std::path::PathBuf;
 use base64::Engine;
 use futures_lite::Future;
 use itertools::Itertools;
-use rbatis::{Page, RBatis};
+use rbatis::{executor::Executor, Page, RBatis};
 use serde_json::Value;
 use std::{
     collections::HashMap, fs::{self, create_dir_all, remove_file, File}, io::{BufReader, Read, Write}, 
@@ -15,7 +16,7 @@ use crate::{
         auth::JwtUserClaims, HookInvoker, MethodHook, PluginConfig, QueryObject, StoreObject,
         StoreServiceConfig,
     },
-    utils::{build_path, build_path_ns, copy_to_slice, get_multiple_rbatis, global_data::{rsa_decrypt_with_private_key, rsa_encrypt_with_public_key}},
+    utils::{build_path, build_path_ns, copy_to_slice, get_multiple_rbatis, global_data::{rsa_decrypt_with_private_key, rsa_encrypt_with_public_key}, pool::{configure_pool, get_pool_stats}, replica::{mark_replica_failed, mark_replica_healthy, select_replicas, ReplicaExecutor}},
 };
 
 use super::{
@@ -437,6 +438,10 @@ impl MxStoreService {
         let ns = conf.namespace.clone();
         let mut mutconf = conf.clone();
         mutconf.refine();
+        let pool_options = mutconf.pool_options();
+        configure_pool(&mutconf.db_url, &pool_options);
+        mutconf.read_replicas.iter().for_each(|f| configure_pool(&f.url, &pool_options));
+        mutconf.tenant_databases.values().for_each(|f| configure_pool(f, &pool_options));
         Self::update_service(&ns, MxStoreService(mutconf));
     }
 
@@ -536,10 +541,63 @@ impl MxStoreService {
         FileStoreManager(self.0.clone())
     }
 
-    pub fn get_rbatis(&self) -> &RBatis {
+    pub fn get_rbatis(&self) -> Arc<RBatis> {
         get_multiple_rbatis(&self.0.db_url)
     }
 
+    /**
+     * 获取用户所属租户使用的RBatis，未启用database模式的租户隔离时与get_rbatis相同
+     */
+    pub fn get_tenant_rbatis(&self, jwt: &JwtUserClaims) -> Result<Arc<RBatis>, Error> {
+        self.0.get_tenant_db_url(jwt).map(|url| get_multiple_rbatis(&url))
+    }
+
+    /**
+     * 主库、只读副本及租户数据库连接池的运行状态，主库为primary，只读副本为replica-序号，租户数据库为tenant-租户标识
+     */
+    pub fn get_pool_stats(&self) -> Vec<(String, PoolStats)> {
+        let mut stats = vec![];
+        if let Some(st) = get_pool_stats(&self.0.db_url) {
+            stats.push(("primary".to_owned(), st));
+        }
+        for (idx, replica) in self.0.read_replicas.iter().enumerate() {
+            if let Some(st) = get_pool_stats(&replica.url) {
+                stats.push((format!("replica-{}", idx), st));
+            }
+        }
+        for (tenant, url) in self.0.tenant_databases.iter() {
+            if let Some(st) = get_pool_stats(url) {
+                stats.push((format!("tenant-{}", tenant), st));
+            }
+        }
+        stats
+    }
+
+    /**
+     * 按replica_policy选择一个可用的只读副本并获取连接
+     * 获取连接失败的副本会被标记为不可用并尝试下一个，没有配置副本或全部失败时返回None，由调用者使用主库
+     * 返回的连接在副本上执行失败时会回退到主库，参见ReplicaExecutor
+     * 只读副本属于db_url，启用database模式的租户隔离时不使用只读副本
+     */
+    pub async fn acquire_replica_connection(&self) -> Option<Arc<dyn Executor>> {
+        if self.0.is_tenant_database() {
+            return None;
+        }
+        for url in select_replicas(&self.0.namespace, &self.0.read_replicas, self.0.replica_policy.as_deref()) {
+            match get_multiple_rbatis(&url).acquire().await {
+                Ok(conn) => {
+                    mark_replica_healthy(&url);
+                    return Some(Arc::new(ReplicaExecutor::new(&url, conn, self.get_rbatis())));
+                }
+                Err(err) => {
+                    log::warn!("Could not acquire the connection of replica {}: {}", url, err);
+                    mark_replica_failed(&url);
+                }
+            }
+        }
+        None
+    }
+
     pub fn get_object(&self, name: &str) -> Option<StoreObject> {
         self.0.object_map.get(name).map(|f| f.to_owned())
     }
@@ -684,7 +742,7 @@ impl MxStoreService {
                     return mso.has_permission(uri, jwt, roles);
                 }
             } else if uri.schema == *"redis" {
-                return true;
+                return ms.get_config().has_redis_permission(&uri.method, jwt, roles);
             } else {
                 // this is a plugin
                 if let Some(pls) = MxStoreService::get_plugin_service(&uri.url_no_method()) {
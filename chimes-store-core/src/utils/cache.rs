@@ -0,0 +1,641 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use anyhow::Error;
+use rand::Rng;
+use tokio::sync::OwnedMutexGuard;
+
+use crate::config::{QueryObject, StoreObject, StoreServiceConfig};
+
+use super::get_local_timestamp;
+use super::redis::{
+    get_redis_connection, redis_del, redis_get, redis_sadd_expire, redis_set_expire, redis_smembers,
+    redis_srem,
+};
+
+const DEFAULT_CACHE_MAX_ENTRIES: u64 = 10000;
+const DEFAULT_CACHE_MAX_BYTES: u64 = 64 * 1024 * 1024;
+/**
+ * Redis缓存使用的保留前缀，缓存的条目及标签集合都保存在该前缀下，redis://的方法不允许访问
+ */
+pub const REDIS_CACHE_PREFIX: &str = "store-cache:";
+const CACHE_KEY_PREFIX: &str = "store-cache:key:";
+const CACHE_TAG_PREFIX: &str = "store-cache:tag:";
+const CACHE_STALE_PREFIX: &str = "~swr:";
+const CACHE_FLIGHT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/**
+ * 缓存的运行状态
+ * evictions为因超出容量而淘汰的条目数，expirations为因过期而移除的条目数，Redis不统计条目数及容量
+ */
+#[derive(Debug, Default, Clone)]
+pub struct CacheStats {
+    pub backend: String,
+    pub entries: u64,
+    pub bytes: u64,
+    pub max_entries: u64,
+    pub max_bytes: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub inserts: u64,
+    pub evictions: u64,
+    pub expirations: u64,
+    pub invalidations: u64,
+}
+
+#[derive(Debug, Default)]
+struct CacheCounter {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    inserts: AtomicU64,
+    evictions: AtomicU64,
+    expirations: AtomicU64,
+    invalidations: AtomicU64,
+}
+
+impl CacheCounter {
+    fn record_get(&self, ret: &Option<String>) {
+        if ret.is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn to_stats(&self, backend: &str) -> CacheStats {
+        CacheStats {
+            backend: backend.to_owned(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            inserts: self.inserts.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            expirations: self.expirations.load(Ordering::Relaxed),
+            invalidations: self.invalidations.load(Ordering::Relaxed),
+            ..Default::default()
+        }
+    }
+}
+
+/**
+ * Object及Query结果缓存的存储，每个命名空间使用一个实例
+ * expire为过期时间（秒），set_tagged写入时将键登记到其依赖的标签下，invalidate_tag删除标签下登记的所有键并返回删除的数量
+ */
+pub trait CacheBackend: Send + Sync {
+    fn get(&self, key: &str) -> Result<Option<String>, Error>;
+    fn set_tagged(&self, key: &str, value: &str, expire: u64, tags: &[String]) -> Result<(), Error>;
+    fn set(&self, key: &str, value: &str, expire: u64) -> Result<(), Error> {
+        self.set_tagged(key, value, expire, &[])
+    }
+    fn del(&self, key: &str) -> Result<(), Error>;
+    fn invalidate_tag(&self, tag: &str) -> Result<u64, Error>;
+    fn stats(&self) -> CacheStats;
+}
+
+struct MemoryEntry {
+    value: String,
+    expire_at: Instant,
+    tick: u64,
+    tags: Vec<String>,
+}
+
+#[derive(Default)]
+struct MemoryCacheInner {
+    entries: HashMap<String, MemoryEntry>,
+    order: BTreeMap<u64, String>,
+    tags: HashMap<String, HashSet<String>>,
+    tick: u64,
+    bytes: u64,
+}
+
+impl MemoryCacheInner {
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    fn remove(&mut self, key: &str) -> Option<MemoryEntry> {
+        let entry = self.entries.remove(key)?;
+        self.order.remove(&entry.tick);
+        for tag in entry.tags.iter() {
+            if let Some(keys) = self.tags.get_mut(tag) {
+                keys.remove(key);
+                if keys.is_empty() {
+                    self.tags.remove(tag);
+                }
+            }
+        }
+        self.bytes = self.bytes.saturating_sub((key.len() + entry.value.len()) as u64);
+        Some(entry)
+    }
+}
+
+/**
+ * 进程内的缓存，按LRU淘汰，条目数超过max_entries或者键值的总字节数超过max_bytes时淘汰最久未使用的条目
+ * 过期的条目在读取时移除，写入时如果需要淘汰，会先移除已经过期的条目
+ */
+pub struct MemoryCache {
+    inner: Mutex<MemoryCacheInner>,
+    max_entries: u64,
+    max_bytes: u64,
+    counter: CacheCounter,
+}
+
+impl MemoryCache {
+    pub fn new(max_entries: u64, max_bytes: u64) -> Self {
+        Self {
+            inner: Mutex::new(MemoryCacheInner::default()),
+            max_entries: max_entries.max(1),
+            max_bytes: max_bytes.max(1),
+            counter: CacheCounter::default(),
+        }
+    }
+
+    fn purge_expired(&self, inner: &mut MemoryCacheInner) {
+        let now = Instant::now();
+        let expired = inner
+            .entries
+            .iter()
+            .filter(|(_, v)| v.expire_at <= now)
+            .map(|(k, _)| k.clone())
+            .collect::<Vec<String>>();
+        for key in expired {
+            inner.remove(&key);
+            self.counter.expirations.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn is_full(&self, inner: &MemoryCacheInner, incoming: u64) -> bool {
+        inner.entries.len() as u64 >= self.max_entries || inner.bytes + incoming > self.max_bytes
+    }
+}
+
+impl CacheBackend for MemoryCache {
+    fn get(&self, key: &str) -> Result<Option<String>, Error> {
+        let mut inner = self.inner.lock().unwrap();
+        let now = Instant::now();
+        let ret = match inner.entries.get(key).map(|e| (e.expire_at > now, e.tick)) {
+            Some((true, old)) => {
+                let tick = inner.next_tick();
+                inner.order.remove(&old);
+                inner.order.insert(tick, key.to_owned());
+                inner.entries.get_mut(key).map(|entry| {
+                    entry.tick = tick;
+                    entry.value.clone()
+                })
+            }
+            Some((false, _)) => {
+                inner.remove(key);
+                self.counter.expirations.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+            None => None,
+        };
+        self.counter.record_get(&ret);
+        Ok(ret)
+    }
+
+    fn set_tagged(&self, key: &str, value: &str, expire: u64, tags: &[String]) -> Result<(), Error> {
+        let size = (key.len() + value.len()) as u64;
+        if size > self.max_bytes {
+            log::debug!("The value of {} is larger than the cache capacity.", key);
+            return Ok(());
+        }
+        let mut inner = self.inner.lock().unwrap();
+        inner.remove(key);
+        if self.is_full(&inner, size) {
+            self.purge_expired(&mut inner);
+        }
+        while self.is_full(&inner, size) {
+            let oldest = match inner.order.iter().next() {
+                Some((_, k)) => k.clone(),
+                None => break,
+            };
+            inner.remove(&oldest);
+            self.counter.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+        let tick = inner.next_tick();
+        inner.order.insert(tick, key.to_owned());
+        for tag in tags.iter() {
+            inner.tags.entry(tag.clone()).or_default().insert(key.to_owned());
+        }
+        inner.entries.insert(
+            key.to_owned(),
+            MemoryEntry {
+                value: value.to_owned(),
+                expire_at: Instant::now() + Duration::from_secs(expire),
+                tick,
+                tags: tags.to_vec(),
+            },
+        );
+        inner.bytes += size;
+        self.counter.inserts.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn del(&self, key: &str) -> Result<(), Error> {
+        self.inner.lock().unwrap().remove(key);
+        Ok(())
+    }
+
+    fn invalidate_tag(&self, tag: &str) -> Result<u64, Error> {
+        let mut inner = self.inner.lock().unwrap();
+        let keys = inner.tags.remove(tag).unwrap_or_default();
+        let mut count = 0u64;
+        for key in keys {
+            if inner.remove(&key).is_some() {
+                count += 1;
+            }
+        }
+        self.counter.invalidations.fetch_add(count, Ordering::Relaxed);
+        Ok(count)
+    }
+
+    fn stats(&self) -> CacheStats {
+        let inner = self.inner.lock().unwrap();
+        CacheStats {
+            entries: inner.entries.len() as u64,
+            bytes: inner.bytes,
+            max_entries: self.max_entries,
+            max_bytes: self.max_bytes,
+            ..self.counter.to_stats("memory")
+        }
+    }
+}
+
+/**
+ * 使用命名空间的Redis作为缓存，过期及淘汰由Redis处理
+ * 条目保存在store-cache:key:{key}，标签以集合store-cache:tag:{tag}保存其下的键，集合的过期时间不短于其中最晚过期的键，失效时只删除集合中登记的键，不扫描整个键空间
+ */
+pub struct RedisCache {
+    namespace: String,
+    counter: CacheCounter,
+}
+
+impl RedisCache {
+    pub fn new(ns: &str) -> Self {
+        Self {
+            namespace: ns.to_owned(),
+            counter: CacheCounter::default(),
+        }
+    }
+}
+
+impl CacheBackend for RedisCache {
+    fn get(&self, key: &str) -> Result<Option<String>, Error> {
+        let ret = redis_get(&self.namespace, &format!("{}{}", CACHE_KEY_PREFIX, key))?;
+        self.counter.record_get(&ret);
+        Ok(ret)
+    }
+
+    fn set_tagged(&self, key: &str, value: &str, expire: u64, tags: &[String]) -> Result<(), Error> {
+        let cache_key = format!("{}{}", CACHE_KEY_PREFIX, key);
+        redis_set_expire(&self.namespace, &cache_key, value, expire)?;
+        for tag in tags.iter() {
+            redis_sadd_expire(&self.namespace, &format!("{}{}", CACHE_TAG_PREFIX, tag), &cache_key, expire)?;
+        }
+        self.counter.inserts.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn del(&self, key: &str) -> Result<(), Error> {
+        redis_del(&self.namespace, &format!("{}{}", CACHE_KEY_PREFIX, key)).map(|_| ())
+    }
+
+    fn invalidate_tag(&self, tag: &str) -> Result<u64, Error> {
+        let tag_key = format!("{}{}", CACHE_TAG_PREFIX, tag);
+        let keys = redis_smembers(&self.namespace, &tag_key)?;
+        let mut count = 0u64;
+        for key in keys.iter() {
+            // 集群模式下各键可能位于不同的slot，逐个删除
+            redis_del(&self.namespace, key)?;
+            count += 1;
+        }
+        // 只移除已删除的键，失效期间新登记到标签下的键保持不变
+        redis_srem(&self.namespace, &tag_key, &keys)?;
+        self.counter.invalidations.fetch_add(count, Ordering::Relaxed);
+        Ok(count)
+    }
+
+    fn stats(&self) -> CacheStats {
+        self.counter.to_stats("redis")
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref NS_CACHES: RwLock<HashMap<String, Arc<dyn CacheBackend>>> = RwLock::new(HashMap::new());
+    static ref CACHE_FLIGHTS: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>> = Mutex::new(HashMap::new());
+    static ref CACHE_REFRESHING: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+/**
+ * 按命名空间的配置创建缓存，cache_backend为redis时使用Redis，为memory时使用进程内缓存
+ * 未配置cache_backend时，配置了redis或redis_url则使用Redis，否则使用进程内缓存
+ * 重新加载命名空间时，原有的缓存会被丢弃
+ */
+pub fn init_ns_cache(conf: &StoreServiceConfig) {
+    let backend = conf.cache_backend.clone().unwrap_or_default().to_lowercase();
+    let use_redis = match backend.as_str() {
+        "redis" => true,
+        "memory" => false,
+        _ => conf.has_redis(),
+    };
+    let cache: Arc<dyn CacheBackend> = if use_redis {
+        if get_redis_connection(&conf.namespace).is_none() {
+            log::warn!("The redis of {} is not available, the cache will not work.", conf.namespace);
+        }
+        Arc::new(RedisCache::new(&conf.namespace))
+    } else {
+        let max_entries = conf
+            .cache_max_entries
+            .filter(|f| *f > 0)
+            .map(|f| f as u64)
+            .unwrap_or(DEFAULT_CACHE_MAX_ENTRIES);
+        let max_bytes = conf
+            .cache_max_bytes
+            .filter(|f| *f > 0)
+            .map(|f| f as u64)
+            .unwrap_or(DEFAULT_CACHE_MAX_BYTES);
+        Arc::new(MemoryCache::new(max_entries, max_bytes))
+    };
+    NS_CACHES.write().unwrap().insert(conf.namespace.clone(), cache);
+}
+
+/**
+ * 获取命名空间的缓存，还没有初始化时使用缺省容量的进程内缓存
+ */
+pub fn get_ns_cache(ns: &str) -> Arc<dyn CacheBackend> {
+    if let Some(cache) = NS_CACHES.read().unwrap().get(ns) {
+        return cache.clone();
+    }
+    NS_CACHES
+        .write()
+        .unwrap()
+        .entry(ns.to_owned())
+        .or_insert_with(|| Arc::new(MemoryCache::new(DEFAULT_CACHE_MAX_ENTRIES, DEFAULT_CACHE_MAX_BYTES)))
+        .clone()
+}
+
+pub fn cache_get(ns: &str, key: &str) -> Result<Option<String>, Error> {
+    get_ns_cache(ns).get(key)
+}
+
+pub fn cache_set(ns: &str, key: &str, value: &str, expire: u64) -> Result<(), Error> {
+    get_ns_cache(ns).set(key, value, expire)
+}
+
+pub fn cache_del(ns: &str, key: &str) -> Result<(), Error> {
+    get_ns_cache(ns).del(key)
+}
+
+pub fn cache_set_tagged(ns: &str, key: &str, value: &str, expire: u64, tags: &[String]) -> Result<(), Error> {
+    get_ns_cache(ns).set_tagged(key, value, expire, tags)
+}
+
+/**
+ * 在所有命名空间的缓存中失效依赖于tags的条目，其它命名空间中引用了同一张表的Object或Query的缓存也一并失效
+ */
+pub fn cache_invalidate_tags(tags: &[String]) {
+    let caches = NS_CACHES
+        .read()
+        .unwrap()
+        .iter()
+        .map(|(ns, cache)| (ns.clone(), cache.clone()))
+        .collect::<Vec<(String, Arc<dyn CacheBackend>)>>();
+    for (ns, cache) in caches {
+        for tag in tags.iter() {
+            match cache.invalidate_tag(tag) {
+                Ok(count) => {
+                    if count > 0 {
+                        log::debug!("{} entries of tag {} were invalidated in {}", count, tag, ns);
+                    }
+                }
+                Err(err) => log::info!("error on invalidate tag {} in {}: {}", tag, ns, err),
+            }
+        }
+    }
+}
+
+fn to_cache_tag(table: &str) -> String {
+    table.trim().trim_matches(|c| c == '`' || c == '"').to_lowercase()
+}
+
+fn collect_object_tags(
+    conf: &StoreServiceConfig,
+    obj: &StoreObject,
+    visited: &mut HashSet<String>,
+    tags: &mut Vec<String>,
+) {
+    if !visited.insert(obj.name.clone()) {
+        return;
+    }
+    let tag = to_cache_tag(&obj.object_name);
+    if !tag.is_empty() && !tags.contains(&tag) {
+        tags.push(tag);
+    }
+    for col in obj
+        .fields
+        .iter()
+        .filter(|f| f.col_type == Some("relation".to_string()))
+    {
+        if let Some(sto) = col.relation_object.clone().and_then(|f| conf.get_object(&f)) {
+            collect_object_tags(conf, &sto, visited, tags);
+        }
+    }
+}
+
+/**
+ * Object的缓存依赖的标签，为该Object及其（逐级）关联的Object所对应的表名
+ * 写入Object时失效同样的标签，级联写入的关联对象的缓存也随之失效
+ */
+pub fn object_cache_tags(conf: &StoreServiceConfig, obj: &StoreObject) -> Vec<String> {
+    let mut tags = vec![];
+    collect_object_tags(conf, obj, &mut HashSet::new(), &mut tags);
+    tags
+}
+
+/**
+ * Query的缓存依赖的标签，为QueryObject的object_name中以,或;分隔的表名
+ * object_name为空时不登记标签，缓存只能等待过期
+ */
+pub fn query_cache_tags(query: &QueryObject) -> Vec<String> {
+    let mut tags = vec![];
+    for tag in query.object_name.split([',', ';']).map(to_cache_tag) {
+        if !tag.is_empty() && !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    tags
+}
+
+/**
+ * 各命名空间缓存的运行状态
+ */
+pub fn get_cache_stats() -> Vec<(String, CacheStats)> {
+    NS_CACHES
+        .read()
+        .unwrap()
+        .iter()
+        .map(|(ns, cache)| (ns.clone(), cache.stats()))
+        .collect()
+}
+
+/**
+ * 缓存的过期策略，由Object/Query的cache_time、cache_jitter及cache_stale_time确定
+ * 写入的缓存在ttl加上[0, jitter]之间的随机秒数后过期，stale大于0时过期后的stale秒内仍可读取旧值
+ */
+#[derive(Debug, Default, Clone)]
+pub struct CachePolicy {
+    pub ttl: u64,
+    pub jitter: u64,
+    pub stale: u64,
+}
+
+impl CachePolicy {
+    pub fn new(cache_time: Option<i64>, jitter: Option<i64>, stale: Option<i64>) -> Self {
+        Self {
+            ttl: cache_time.unwrap_or(30).max(0) as u64,
+            jitter: jitter.unwrap_or(0).max(0) as u64,
+            stale: stale.unwrap_or(0).max(0) as u64,
+        }
+    }
+
+    fn next_ttl(&self) -> u64 {
+        if self.jitter == 0 {
+            self.ttl
+        } else {
+            self.ttl + rand::thread_rng().gen_range(0..=self.jitter)
+        }
+    }
+
+    /**
+     * 条目在缓存中最长的保存时间，与分页结果一起保存的next_cursor使用该时间
+     */
+    pub fn max_expire(&self) -> u64 {
+        self.ttl + self.jitter + self.stale
+    }
+}
+
+/**
+ * 按缓存策略写入缓存，启用stale时在值前记录新鲜期的截止时间，条目保存到新鲜期之后的stale秒
+ */
+pub fn cache_set_policy(ns: &str, key: &str, value: &str, policy: &CachePolicy, tags: &[String]) -> Result<(), Error> {
+    let ttl = policy.next_ttl();
+    if policy.stale == 0 {
+        return cache_set_tagged(ns, key, value, ttl, tags);
+    }
+    let fresh_until = get_local_timestamp() + ttl * 1000;
+    let text = format!("{}{}~{}", CACHE_STALE_PREFIX, fresh_until, value);
+    cache_set_tagged(ns, key, &text, ttl + policy.stale, tags)
+}
+
+/**
+ * 拆分cache_set_policy写入的值，返回值及是否仍在新鲜期内
+ */
+fn decode_cache_value(text: String) -> (String, bool) {
+    if let Some(rest) = text.strip_prefix(CACHE_STALE_PREFIX) {
+        if let Some((ts, value)) = rest.split_once('~') {
+            if let Ok(fresh_until) = ts.parse::<u64>() {
+                return (value.to_owned(), fresh_until > get_local_timestamp());
+            }
+        }
+    }
+    (text, true)
+}
+
+/**
+ * 同一缓存键的加载权，持有期间其它请求在cache_lookup中等待，释放后它们重新读取缓存
+ */
+pub struct CacheFlight {
+    key: String,
+    guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl Drop for CacheFlight {
+    fn drop(&mut self) {
+        self.guard.take();
+        let mut flights = CACHE_FLIGHTS.lock().unwrap();
+        if flights.get(&self.key).map(|f| Arc::strong_count(f) == 1).unwrap_or(false) {
+            flights.remove(&self.key);
+        }
+    }
+}
+
+/**
+ * 过期条目的后台刷新权，每个缓存键同时只有一个后台刷新，刷新结束（无论成功与否）时释放
+ */
+pub struct CacheRefresh {
+    key: String,
+}
+
+impl Drop for CacheRefresh {
+    fn drop(&mut self) {
+        CACHE_REFRESHING.lock().unwrap().remove(&self.key);
+    }
+}
+
+/**
+ * 缓存的读取结果
+ * value为缓存的值，refresh不为空时value已过新鲜期，调用者需要在后台刷新该缓存并在刷新结束后释放refresh
+ * value为空时调用者需要加载数据，并在写入缓存后才释放本结果，以便等待中的请求读取到新写入的缓存
+ */
+#[derive(Default)]
+pub struct CacheLookup {
+    pub value: Option<String>,
+    pub refresh: Option<CacheRefresh>,
+    _flight: Option<CacheFlight>,
+}
+
+fn lookup_once(ns: &str, key: &str, flight_key: &str) -> Option<CacheLookup> {
+    let text = cache_get(ns, key).ok().flatten()?;
+    let (value, fresh) = decode_cache_value(text);
+    let refresh = if !fresh && CACHE_REFRESHING.lock().unwrap().insert(flight_key.to_owned()) {
+        Some(CacheRefresh {
+            key: flight_key.to_owned(),
+        })
+    } else {
+        None
+    };
+    Some(CacheLookup {
+        value: Some(value),
+        refresh,
+        _flight: None,
+    })
+}
+
+/**
+ * 读取缓存，并合并同一缓存键并发的未命中
+ * 未命中时只有一个请求获得加载权，其它请求等待其释放后重新读取缓存，等待超过30秒时不再等待，直接加载
+ * 已过新鲜期的条目直接返回旧值，并由第一个读到该条目的请求负责后台刷新
+ */
+pub async fn cache_lookup(ns: &str, key: &str) -> CacheLookup {
+    let flight_key = format!("{}#{}", ns, key);
+    if let Some(ret) = lookup_once(ns, key, &flight_key) {
+        return ret;
+    }
+    let lock = CACHE_FLIGHTS
+        .lock()
+        .unwrap()
+        .entry(flight_key.clone())
+        .or_default()
+        .clone();
+    let flight = match tokio::time::timeout(CACHE_FLIGHT_TIMEOUT, lock.lock_owned()).await {
+        Ok(guard) => CacheFlight {
+            key: flight_key.clone(),
+            guard: Some(guard),
+        },
+        Err(_) => {
+            log::info!("Timeout on waiting for the loading of cache {}", key);
+            return CacheLookup::default();
+        }
+    };
+    if let Some(ret) = lookup_once(ns, key, &flight_key) {
+        return ret;
+    }
+    CacheLookup {
+        value: None,
+        refresh: None,
+        _flight: Some(flight),
+    }
+}
@@ -573,6 +573,20 @@ pub fn copy_value_replaced(src_val: &Value, replace: &Value) -> Value {
     Value::Object(des_val)
 }
 
+/**
+ * 从源JSON对象中产生一个新的JSON对象，使用replace中的字段替换源值，replace中新增的字段也会被加入
+ * ** 只处理一层
+ */
+pub fn copy_value_merged(src_val: &Value, replace: &Value) -> Value {
+    let mut des_val = src_val.as_object().cloned().unwrap_or_default();
+    if let Some(opmap) = replace.as_object() {
+        for (key, val) in opmap {
+            des_val.insert(key.to_owned(), val.to_owned());
+        }
+    }
+    Value::Object(des_val)
+}
+
 /**
  * 使用replace对src_val进行替换
  * 如果具体的字段内容相同，则根据remove_equal来确定是否删除
@@ -1,5 +1,5 @@
 use anyhow::{anyhow, bail};
-use chimes_dbs_factory::get_sql_driver;
+use chimes_dbs_factory::{create_metered_rbatis, get_sql_driver};
 use chrono::{DateTime, Local};
 use rbatis::RBatis;
 use serde::{Deserialize, Serialize};
@@ -7,14 +7,18 @@ use std::collections::HashMap;
 use std::fs::create_dir_all;
 use std::mem::MaybeUninit;
 use std::path::{Path, PathBuf};
-use std::sync::{Mutex, Once};
+use std::sync::{Arc, Mutex, Once, RwLock};
 use std::time::SystemTime;
 
 pub mod algorithm;
+pub mod cache;
 pub mod crypto;
 pub mod executor;
 pub mod global_data;
+pub mod pool;
 pub mod redis;
+pub mod redis_cmd;
+pub mod replica;
 pub mod response;
 pub use response::*;
 
@@ -77,78 +81,45 @@ pub fn create_rbatis(url: &str) -> Result<RBatis, anyhow::Error> {
     }
 }
 
-#[allow(dead_code)]
-pub fn get_multiple_rbatis(url: &str) -> &'static RBatis {
-    // 使用MaybeUninit延迟初始化
-    static mut STATIC_MULTI_RB: MaybeUninit<HashMap<String, RBatis>> = MaybeUninit::uninit();
-    // Once带锁保证只进行一次初始化
-    static ONCE_HASH: Once = Once::new();
+lazy_static::lazy_static! {
+    static ref MULTI_RBATIS: RwLock<HashMap<String, Arc<RBatis>>> = RwLock::new(HashMap::new());
+}
 
-    ONCE_HASH.call_once(|| unsafe {
-        STATIC_MULTI_RB.as_mut_ptr().write(HashMap::new());
-    });
+/**
+ * 按url获取RBatis，首次使用时创建其连接池，主库、只读副本及租户数据库共用
+ * 创建在写锁内完成，多个线程同时首次访问时只会创建一个连接池
+ */
+pub fn get_multiple_rbatis(url: &str) -> Arc<RBatis> {
+    if let Some(rb) = MULTI_RBATIS.read().unwrap().get(url) {
+        return rb.clone();
+    }
 
-    unsafe {
-        if (*STATIC_MULTI_RB.as_mut_ptr()).contains_key(url) {
-            let hashmap = &*STATIC_MULTI_RB.as_mut_ptr();
-            &hashmap[url]
-        } else {
-            //async_std::task::block_on(async {
-                log::info!("Call the block on to create the sql connection.");
-                let rb = RBatis::new();
-                match rb.init(get_sql_driver(url), url) {
-                    Ok(_) => {
-                        log::info!(
-                            "Database {} was connected. Rbatis was initialized successfully.",
-                            url
-                        );
-                    }
-                    Err(err) => {
-                        log::warn!("Error: {}", err);
-                    }
-                };
-                (*STATIC_MULTI_RB.as_mut_ptr()).insert(url.to_string(), rb);
-            //});
-            let hashmap = &*STATIC_MULTI_RB.as_mut_ptr();
-            &hashmap[url]
-        }
+    let mut rbs = MULTI_RBATIS.write().unwrap();
+    if let Some(rb) = rbs.get(url) {
+        return rb.clone();
     }
+    let rb = match create_metered_rbatis(url, &pool::get_pool_options(url)) {
+        Ok((rb, mp)) => {
+            log::info!(
+                "Database {} was connected. Rbatis was initialized successfully.",
+                url
+            );
+            pool::register_pool(url, mp);
+            rb
+        }
+        Err(err) => {
+            log::warn!("Error: {}", err);
+            RBatis::new()
+        }
+    };
+    let rb = Arc::new(rb);
+    rbs.insert(url.to_string(), rb.clone());
+    rb
 }
 
 #[allow(dead_code)]
-pub async fn get_multiple_rbatis_async(url: &str) -> &'static RBatis {
-    // 使用MaybeUninit延迟初始化
-    static mut STATIC_MULTI_RB: MaybeUninit<HashMap<String, RBatis>> = MaybeUninit::uninit();
-    // Once带锁保证只进行一次初始化
-    static ONCE_HASH: Once = Once::new();
-
-    ONCE_HASH.call_once(|| unsafe {
-        STATIC_MULTI_RB.as_mut_ptr().write(HashMap::new());
-    });
-
-    unsafe {
-        if (*STATIC_MULTI_RB.as_mut_ptr()).contains_key(url) {
-            let hashmap = &*STATIC_MULTI_RB.as_mut_ptr();
-            &hashmap[url]
-        } else {
-            log::info!("Call the block on to create the sql connection.");
-            let rb = RBatis::new();
-            match rb.link(get_sql_driver(url), url).await {
-                Ok(_) => {
-                    log::info!(
-                        "Database {} was connected. Rbatis was initialized successfully.",
-                        url
-                    );
-                }
-                Err(err) => {
-                    log::warn!("Error: {}", err);
-                }
-            };
-            (*STATIC_MULTI_RB.as_mut_ptr()).insert(url.to_string(), rb);
-            let hashmap = &*STATIC_MULTI_RB.as_mut_ptr();
-            &hashmap[url]
-        }
-    }
+pub async fn get_multiple_rbatis_async(url: &str) -> Arc<RBatis> {
+    get_multiple_rbatis(url)
 }
 
 pub fn get_local_timestamp() -> u64 {
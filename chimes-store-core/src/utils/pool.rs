@@ -0,0 +1,49 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use chimes_dbs_factory::{MeteredPool, PoolOptions, PoolStats};
+
+lazy_static::lazy_static! {
+    static ref POOL_OPTIONS: Mutex<HashMap<String, PoolOptions>> = Mutex::new(HashMap::new());
+    static ref POOLS: Mutex<HashMap<String, MeteredPool>> = Mutex::new(HashMap::new());
+}
+
+/**
+ * 设置url对应连接池的配置，连接池已经创建时立即生效
+ * 连接池按url共享，多个命名空间使用相同的url时以最后加载的配置为准
+ */
+pub fn configure_pool(url: &str, options: &PoolOptions) {
+    if url.is_empty() {
+        return;
+    }
+    if let Some(pool) = POOLS.lock().unwrap().get(url) {
+        if pool.options() != *options {
+            log::info!("Apply the pool options {:?} to the existing pool.", options);
+            pool.apply_options(options);
+        }
+    }
+    POOL_OPTIONS
+        .lock()
+        .unwrap()
+        .insert(url.to_owned(), options.clone());
+}
+
+pub fn get_pool_options(url: &str) -> PoolOptions {
+    POOL_OPTIONS
+        .lock()
+        .unwrap()
+        .get(url)
+        .cloned()
+        .unwrap_or_default()
+}
+
+pub(crate) fn register_pool(url: &str, pool: MeteredPool) {
+    POOLS.lock().unwrap().insert(url.to_owned(), pool);
+}
+
+/**
+ * 获取url对应连接池的运行状态，连接池还没有创建时返回None
+ */
+pub fn get_pool_stats(url: &str) -> Option<PoolStats> {
+    POOLS.lock().unwrap().get(url).map(|f| f.stats())
+}
@@ -1,11 +1,14 @@
 use crate::config::StoreServiceConfig;
+use crate::utils::redis_cmd::is_reserved_redis_key;
 use anyhow::{anyhow, Error};
 use itertools::Itertools;
 use once_cell::sync::OnceCell;
 use r2d2::Pool;
+use redis::sentinel::{SentinelClient, SentinelNodeConnectionInfo, SentinelServerType};
 use redis::{
-    cluster::ClusterClientBuilder, from_redis_value, ConnectionLike, FromRedisValue, RedisError,
-    RedisResult,
+    cluster::ClusterClientBuilder, from_redis_value, ConnectionAddr, ConnectionInfo,
+    ConnectionLike, ErrorKind, FromRedisValue, IntoConnectionInfo, RedisConnectionInfo, RedisError,
+    RedisResult, TlsMode,
 };
 use redis::{Commands, Value};
 use serde::Deserialize;
@@ -20,48 +23,289 @@ use std::time::Duration;
 pub enum InstanceType {
     Single,
     Cluster,
+    Sentinel,
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct RedisPoolConfig {
-    pub connection_timeout: u64,
-    pub max_size: u32,
-    pub mini_idel: u32,
+    pub connection_timeout: u64, // 获取连接的超时时间（秒），缺省为30
+    pub max_size: u32,           // 连接池的最大连接数，缺省为128
+    pub mini_idel: u32,          // 连接池的最小空闲连接数，缺省为10
 }
 
-#[derive(Debug, Clone, Default)]
+/**
+ * Redis的连接配置，可以在StoreServiceConfig.redis中配置，也可以由redis_url解析得到
+ * instance_type未配置时，配置了master_name的为sentinel，多个urls的为cluster，否则为single
+ * sentinel模式下urls为sentinel节点的地址，database/username/password用于连接master
+ */
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct RedisConfig {
     pub urls: Vec<String>,
     pub database: i64,
     pub username: Option<String>,
     pub password: Option<String>,
     pub instance_type: Option<InstanceType>,
+    pub master_name: Option<String>, // sentinel模式下的master名称
+    pub sentinel_username: Option<String>, // sentinel节点的认证用户，缺省不认证
+    pub sentinel_password: Option<String>, // sentinel节点的认证密码，缺省不认证
     pub pool: RedisPoolConfig,
 }
 
 impl RedisConfig {
-    #[allow(dead_code)]
     fn instance_type_default() -> InstanceType {
         InstanceType::Single
     }
+
+    /**
+     * 解析redis_url，支持以下格式
+     * redis+sentinel://[user:password@]host1:26379,host2:26379/mymaster[/db]，sentinel模式
+     * redis+cluster://[user:password@]host1:6379,host2:6379，cluster模式
+     * 以;分隔的多个redis://地址为cluster模式，单个redis://地址为single模式
+     * 使用TLS时将redis+换成rediss+
+     */
+    pub fn from_url(full_urls: &str) -> RedisResult<Self> {
+        let full_urls = full_urls.trim();
+        if let Some((scheme, rest)) = full_urls.split_once("://") {
+            let node_scheme = match scheme {
+                "redis+sentinel" | "redis+cluster" => Some("redis"),
+                "rediss+sentinel" | "rediss+cluster" => Some("rediss"),
+                _ => None,
+            };
+            if let Some(node_scheme) = node_scheme {
+                let (auth, rest) = match rest.rsplit_once('@') {
+                    Some((auth, rest)) => (Some(auth), rest),
+                    None => (None, rest),
+                };
+                let (hosts, path) = rest.split_once('/').unwrap_or((rest, ""));
+                let urls = hosts
+                    .split(',')
+                    .map(|f| f.trim())
+                    .filter(|f| !f.is_empty())
+                    .map(|f| format!("{node_scheme}://{f}"))
+                    .collect_vec();
+                if urls.is_empty() {
+                    return Err(RedisError::from((
+                        ErrorKind::InvalidClientConfig,
+                        "No redis host was provided in the redis url.",
+                    )));
+                }
+                // 借用redis的url解析得到解码后的用户名和密码
+                let auth_info = match auth {
+                    Some(auth) => {
+                        format!(
+                            "{node_scheme}://{auth}@{}",
+                            hosts.split(',').next().unwrap_or_default()
+                        )
+                        .as_str()
+                        .into_connection_info()?
+                        .redis
+                    }
+                    None => RedisConnectionInfo::default(),
+                };
+                let paths = path.split('/').filter(|f| !f.is_empty()).collect_vec();
+                let mut conf = Self {
+                    urls,
+                    username: auth_info.username,
+                    password: auth_info.password,
+                    ..Default::default()
+                };
+                if scheme.ends_with("+sentinel") {
+                    conf.instance_type = Some(InstanceType::Sentinel);
+                    conf.master_name = paths.first().map(|f| f.to_string());
+                    conf.database = paths
+                        .get(1)
+                        .and_then(|f| f.parse().ok())
+                        .unwrap_or_default();
+                } else {
+                    conf.instance_type = Some(InstanceType::Cluster);
+                }
+                return Ok(conf);
+            }
+        }
+
+        let urls = full_urls
+            .split(';')
+            .map(|f| f.trim())
+            .filter(|f| !f.is_empty())
+            .map(|f| f.to_owned())
+            .collect_vec();
+        Ok(Self {
+            instance_type: Some(if urls.len() > 1 {
+                InstanceType::Cluster
+            } else {
+                InstanceType::Single
+            }),
+            urls,
+            ..Default::default()
+        })
+    }
+
+    /**
+     * 使用redis_url中的地址，已配置的instance_type/master_name/认证等信息保持不变
+     */
+    pub fn with_url(self, full_urls: &str) -> RedisResult<Self> {
+        let url_conf = Self::from_url(full_urls)?;
+        Ok(Self {
+            urls: url_conf.urls,
+            database: if self.database != 0 {
+                self.database
+            } else {
+                url_conf.database
+            },
+            username: self.username.or(url_conf.username),
+            password: self.password.or(url_conf.password),
+            instance_type: self.instance_type.or(url_conf.instance_type),
+            master_name: self.master_name.or(url_conf.master_name),
+            ..self
+        })
+    }
+
+    pub fn get_instance_type(&self) -> InstanceType {
+        match &self.instance_type {
+            Some(t) => t.clone(),
+            None if self.master_name.is_some() => InstanceType::Sentinel,
+            None if self.urls.len() > 1 => InstanceType::Cluster,
+            None => Self::instance_type_default(),
+        }
+    }
+
+    fn apply_redis_info(&self, info: &mut RedisConnectionInfo) {
+        if self.database != 0 {
+            info.db = self.database;
+        }
+        if self.username.is_some() {
+            info.username.clone_from(&self.username);
+        }
+        if self.password.is_some() {
+            info.password.clone_from(&self.password);
+        }
+    }
+
+    fn node_connection_infos(&self) -> RedisResult<Vec<ConnectionInfo>> {
+        self.urls
+            .iter()
+            .map(|f| {
+                let mut info = f.as_str().into_connection_info()?;
+                self.apply_redis_info(&mut info.redis);
+                Ok(info)
+            })
+            .collect()
+    }
+
+    fn sentinel_connection_infos(&self) -> RedisResult<Vec<ConnectionInfo>> {
+        self.urls
+            .iter()
+            .map(|f| {
+                let mut info = f.as_str().into_connection_info()?;
+                info.redis.db = 0;
+                if self.sentinel_username.is_some() {
+                    info.redis.username.clone_from(&self.sentinel_username);
+                }
+                if self.sentinel_password.is_some() {
+                    info.redis.password.clone_from(&self.sentinel_password);
+                }
+                Ok(info)
+            })
+            .collect()
+    }
+
+    /**
+     * 按instance_type创建RedisClient
+     * cluster模式按slot路由到对应的节点，不支持database
+     * sentinel模式每次建立连接时都向sentinel查询当前的master，故障转移后新建的连接会连接到新的master
+     */
+    pub fn to_redis_client(&self) -> RedisResult<RedisClient> {
+        if self.urls.is_empty() {
+            return Err(RedisError::from((
+                ErrorKind::InvalidClientConfig,
+                "No redis url was configured.",
+            )));
+        }
+        match self.get_instance_type() {
+            InstanceType::Single => {
+                let mut infos = self.node_connection_infos()?;
+                let cl = redis::Client::open(infos.remove(0))?;
+                Ok(RedisClient::Single(cl))
+            }
+            InstanceType::Cluster => {
+                let mut infos = self.node_connection_infos()?;
+                for info in infos.iter_mut() {
+                    info.redis.db = 0;
+                }
+                let node_info = Arc::new(infos[0].clone());
+                let cl = ClusterClientBuilder::new(infos).build()?;
+                Ok(RedisClient::Cluster(cl, node_info))
+            }
+            InstanceType::Sentinel => {
+                let master_name = match self.master_name.clone() {
+                    Some(t) if !t.is_empty() => t,
+                    _ => {
+                        return Err(RedisError::from((
+                            ErrorKind::InvalidClientConfig,
+                            "The master_name is required by redis sentinel.",
+                        )))
+                    }
+                };
+                let sentinels = self.sentinel_connection_infos()?;
+                let tls_mode = match sentinels[0].addr {
+                    ConnectionAddr::TcpTls { insecure, .. } => Some(if insecure {
+                        TlsMode::Insecure
+                    } else {
+                        TlsMode::Secure
+                    }),
+                    _ => None,
+                };
+                let mut redis_info = RedisConnectionInfo::default();
+                self.apply_redis_info(&mut redis_info);
+                let node_info = SentinelNodeConnectionInfo {
+                    tls_mode,
+                    redis_connection_info: Some(redis_info),
+                };
+                let params = SentinelParams {
+                    sentinels,
+                    master_name,
+                    node_info,
+                };
+                // 提前校验参数，建立连接时再创建SentinelClient
+                params.build_client()?;
+                Ok(RedisClient::Sentinel(Arc::new(params)))
+            }
+        }
+    }
 }
 
 pub fn to_redis_client(full_urls: &str) -> RedisResult<RedisClient> {
-    let urls = full_urls.split(';').collect_vec();
-    if urls.len() == 1 {
-        let cl = redis::Client::open(urls[0])?;
-        Ok(RedisClient::Single(cl))
-    } else {
-        let cb = ClusterClientBuilder::new(urls.clone());
-        let cl = cb.build()?;
-        Ok(RedisClient::Cluster(cl))
+    RedisConfig::from_url(full_urls)?.to_redis_client()
+}
+
+/**
+ * sentinel模式的连接参数，SentinelClient获取连接需要&mut self，且会向sentinel查询master
+ * 因此每次建立连接时创建新的SentinelClient，不共享同一个加锁的实例，以免网络请求期间阻塞其它连接的建立
+ */
+pub struct SentinelParams {
+    sentinels: Vec<ConnectionInfo>,
+    master_name: String,
+    node_info: SentinelNodeConnectionInfo,
+}
+
+impl SentinelParams {
+    fn build_client(&self) -> RedisResult<SentinelClient> {
+        SentinelClient::build(
+            self.sentinels.clone(),
+            self.master_name.clone(),
+            Some(self.node_info.clone()),
+            SentinelServerType::Master,
+        )
     }
 }
 
 #[derive(Clone)]
 pub enum RedisClient {
     Single(redis::Client),
-    Cluster(redis::cluster::ClusterClient),
+    Cluster(redis::cluster::ClusterClient, Arc<ConnectionInfo>),
+    Sentinel(Arc<SentinelParams>),
 }
 
 impl RedisClient {
@@ -71,24 +315,111 @@ impl RedisClient {
                 let conn = s.get_connection()?;
                 Ok(RedisConnection::Single(Box::new(conn)))
             }
-            RedisClient::Cluster(c) => {
+            RedisClient::Cluster(c, node_info) => {
                 let conn = c.get_connection()?;
-                Ok(RedisConnection::Cluster(Box::new(conn)))
+                Ok(RedisConnection::Cluster(Box::new(conn), node_info.clone()))
+            }
+            RedisClient::Sentinel(s) => {
+                let conn = s.build_client()?.get_connection()?;
+                Ok(RedisConnection::Single(Box::new(conn)))
             }
         }
     }
 }
 
+/**
+ * Cluster中的ConnectionInfo为初始节点的连接信息，SCAN需要按该信息（认证、TLS）直接连接各个master
+ */
 pub enum RedisConnection {
     Single(Box<redis::Connection>),
-    Cluster(Box<redis::cluster::ClusterConnection>),
+    Cluster(Box<redis::cluster::ClusterConnection>, Arc<ConnectionInfo>),
+}
+
+fn scan_once<C: ConnectionLike>(
+    conn: &mut C,
+    cursor: &str,
+    pattern: &str,
+    count: i64,
+) -> RedisResult<(String, Vec<String>)> {
+    redis::cmd("SCAN")
+        .arg(cursor)
+        .arg("MATCH")
+        .arg(pattern)
+        .arg("COUNT")
+        .arg(count)
+        .query(conn)
+}
+
+fn scan_all<C: ConnectionLike>(conn: &mut C, pattern: &str) -> RedisResult<Vec<String>> {
+    let mut keys = vec![];
+    let mut cursor = "0".to_owned();
+    loop {
+        let (next, mut part) = scan_once(conn, &cursor, pattern, 1000)?;
+        keys.append(&mut part);
+        if next == "0" {
+            return Ok(keys);
+        }
+        cursor = next;
+    }
+}
+
+/**
+ * 集群中各个master的地址，按地址排序，使分步SCAN的游标在拓扑不变时保持稳定
+ */
+fn cluster_primaries(
+    cc: &mut redis::cluster::ClusterConnection,
+) -> RedisResult<Vec<(String, u16)>> {
+    let slots = cc.req_command(redis::cmd("CLUSTER").arg("SLOTS"))?;
+    let mut nodes = vec![];
+    if let Value::Bulk(ranges) = slots {
+        for range in ranges.iter() {
+            if let Value::Bulk(items) = range {
+                if let Some(Value::Bulk(master)) = items.get(2) {
+                    if master.len() >= 2 {
+                        nodes.push((
+                            from_redis_value::<String>(&master[0])?,
+                            from_redis_value::<u16>(&master[1])?,
+                        ));
+                    }
+                }
+            }
+        }
+    }
+    nodes.sort();
+    nodes.dedup();
+    Ok(nodes)
+}
+
+fn cluster_node_connection(
+    node_info: &ConnectionInfo,
+    host: String,
+    port: u16,
+) -> RedisResult<redis::Connection> {
+    let addr = match &node_info.addr {
+        ConnectionAddr::TcpTls {
+            insecure,
+            tls_params,
+            ..
+        } => ConnectionAddr::TcpTls {
+            host,
+            port,
+            insecure: *insecure,
+            tls_params: tls_params.clone(),
+        },
+        _ => ConnectionAddr::Tcp(host, port),
+    };
+    redis::Client::open(ConnectionInfo {
+        addr,
+        redis: node_info.redis.clone(),
+    })?
+    .get_connection()
 }
 
 impl RedisConnection {
     pub fn is_open(&self) -> bool {
         match self {
             RedisConnection::Single(sc) => sc.is_open(),
-            RedisConnection::Cluster(cc) => cc.is_open(),
+            RedisConnection::Cluster(cc, _) => cc.is_open(),
         }
     }
 
@@ -98,7 +429,7 @@ impl RedisConnection {
                 Ok(val) => from_redis_value(&val),
                 Err(e) => Err(e),
             },
-            RedisConnection::Cluster(cc) => match cc.req_command(cmd) {
+            RedisConnection::Cluster(cc, _) => match cc.req_command(cmd) {
                 Ok(val) => from_redis_value(&val),
                 Err(e) => Err(e),
             },
@@ -111,7 +442,7 @@ impl RedisConnection {
                 Ok(val) => Ok(val),
                 Err(e) => Err(e),
             },
-            RedisConnection::Cluster(cc) => match cc.del(key) {
+            RedisConnection::Cluster(cc, _) => match cc.del(key) {
                 Ok(val) => Ok(val),
                 Err(e) => Err(e),
             },
@@ -134,12 +465,65 @@ impl RedisConnection {
                 }
                 Err(e) => Err(e),
             },
-            RedisConnection::Cluster(cc) => match cc.keys(key) {
+            RedisConnection::Cluster(cc, _) => match cc.keys(key) {
                 Ok(val) => from_redis_value(&val),
                 Err(e) => Err(e),
             },
         }
     }
+
+    /**
+     * 执行一次SCAN，返回下一个游标及本次扫描到的键，游标为"0"时遍历结束
+     * 集群中没有key的SCAN会被随机路由到某个节点，因此依次在每个master上执行，游标为{master序号}:{该master的游标}
+     */
+    pub fn scan_step(
+        &mut self,
+        cursor: &str,
+        pattern: &str,
+        count: i64,
+    ) -> RedisResult<(String, Vec<String>)> {
+        match self {
+            RedisConnection::Single(sc) => scan_once(sc.as_mut(), cursor, pattern, count),
+            RedisConnection::Cluster(cc, node_info) => {
+                let (index, node_cursor) = match cursor.split_once(':') {
+                    Some((i, c)) => (i.parse::<usize>().unwrap_or_default(), c),
+                    None => (0, cursor),
+                };
+                let nodes = cluster_primaries(cc)?;
+                let (host, port) = match nodes.get(index) {
+                    Some(t) => t.clone(),
+                    None => return Ok(("0".to_owned(), vec![])),
+                };
+                let mut conn = cluster_node_connection(node_info, host, port)?;
+                let (next, keys) = scan_once(&mut conn, node_cursor, pattern, count)?;
+                let next = if next != "0" {
+                    format!("{index}:{next}")
+                } else if index + 1 < nodes.len() {
+                    format!("{}:0", index + 1)
+                } else {
+                    "0".to_owned()
+                };
+                Ok((next, keys))
+            }
+        }
+    }
+
+    /**
+     * 使用SCAN遍历匹配pattern的所有键，集群模式下遍历每个master
+     */
+    pub fn scan_keys(&mut self, pattern: &str) -> RedisResult<Vec<String>> {
+        match self {
+            RedisConnection::Single(sc) => scan_all(sc.as_mut(), pattern),
+            RedisConnection::Cluster(cc, node_info) => {
+                let mut keys = vec![];
+                for (host, port) in cluster_primaries(cc)? {
+                    let mut conn = cluster_node_connection(node_info, host, port)?;
+                    keys.append(&mut scan_all(&mut conn, pattern)?);
+                }
+                Ok(keys)
+            }
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -147,7 +531,10 @@ pub struct RedisConnectionManager {
     pub redis_client: RedisClient,
 }
 
-// ToDo 实现 broken 函数
+/**
+ * 各模式的连接池使用相同的检查，取出连接时执行PING，连接断开时丢弃并重建
+ * sentinel模式以ROLE代替PING，同样只需一次往返，同时可以发现故障转移后已降级为replica的连接
+ */
 impl r2d2::ManageConnection for RedisConnectionManager {
     type Connection = RedisConnection;
     type Error = RedisError;
@@ -158,12 +545,27 @@ impl r2d2::ManageConnection for RedisConnectionManager {
     }
 
     fn is_valid(&self, conn: &mut RedisConnection) -> Result<(), Self::Error> {
+        // 故障转移后原来的master会降级为replica，需丢弃该连接，重新向sentinel获取master
+        if let RedisClient::Sentinel(_) = self.redis_client {
+            let role = conn.query::<Vec<Value>>(&redis::cmd("ROLE"))?;
+            let is_master = role
+                .first()
+                .map(|f| from_redis_value::<String>(f).unwrap_or_default() == "master")
+                .unwrap_or(false);
+            if !is_master {
+                return Err(RedisError::from((
+                    ErrorKind::ReadOnly,
+                    "The redis node is no longer the master of sentinel.",
+                )));
+            }
+            return Ok(());
+        }
         match conn {
             RedisConnection::Single(sc) => {
-                redis::cmd("PING").query(sc)?;
+                redis::cmd("PING").query::<()>(sc)?;
             }
-            RedisConnection::Cluster(cc) => {
-                redis::cmd("PING").query(cc)?;
+            RedisConnection::Cluster(cc, _) => {
+                redis::cmd("PING").query::<()>(cc)?;
             }
         }
         Ok(())
@@ -182,17 +584,42 @@ impl r2d2::ManageConnection for RedisConnectionManager {
 // }
 
 pub fn gen_redis_conn_pool(url: &str) -> Result<Pool<RedisConnectionManager>, Error> {
-    let redis_client: RedisClient = match to_redis_client(url) {
+    match RedisConfig::from_url(url) {
+        Ok(conf) => gen_redis_conn_pool_by_config(&conf),
+        Err(err) => Err(anyhow!(err.to_string())),
+    }
+}
+
+pub fn gen_redis_conn_pool_by_config(
+    conf: &RedisConfig,
+) -> Result<Pool<RedisConnectionManager>, Error> {
+    let redis_client: RedisClient = match conf.to_redis_client() {
         Ok(rc) => rc,
         Err(err) => {
             return Err(anyhow!(err.to_string()));
         }
     };
+    let max_size = if conf.pool.max_size > 0 {
+        conf.pool.max_size
+    } else {
+        128
+    };
+    let min_idle = if conf.pool.mini_idel > 0 {
+        conf.pool.mini_idel
+    } else {
+        10
+    };
+    let timeout = if conf.pool.connection_timeout > 0 {
+        conf.pool.connection_timeout
+    } else {
+        30
+    };
     let manager = RedisConnectionManager { redis_client };
     match r2d2::Pool::builder()
-        .max_size(128)
-        .min_idle(Some(10))
-        .connection_timeout(Duration::from_secs(30))
+        .max_size(max_size)
+        .min_idle(Some(min_idle.min(max_size)))
+        .connection_timeout(Duration::from_secs(timeout))
+        .test_on_check_out(true)
         .build(manager)
     {
         Ok(pool) => Ok(pool),
@@ -209,12 +636,26 @@ pub fn init_global_redis() {
 
 pub fn init_ns_scoped_redis(conf: &StoreServiceConfig) {
     if let Some(mp) = GLOBAL_REDIS_POOL.get() {
-        if let Some(url) = conf.redis_url.clone() {
-            log::info!("{}'s redis-url: {}", conf.namespace, url);
-            if let Ok(t) = gen_redis_conn_pool(&url) {
-                mp.lock()
-                    .unwrap()
-                    .insert(conf.namespace.clone(), Arc::new(t));
+        if let Some(rc) = conf.get_redis_config() {
+            log::info!(
+                "{}'s redis: {:?} {}",
+                conf.namespace,
+                rc.get_instance_type(),
+                rc.urls.join(",")
+            );
+            match gen_redis_conn_pool_by_config(&rc) {
+                Ok(t) => {
+                    mp.lock()
+                        .unwrap()
+                        .insert(conf.namespace.clone(), Arc::new(t));
+                }
+                Err(err) => {
+                    log::warn!(
+                        "The redis of {} could not be connected: {}",
+                        conf.namespace,
+                        err
+                    );
+                }
             }
         }
     }
@@ -330,49 +771,122 @@ pub fn redis_del(ns: &str, key: &str) -> Result<Option<String>, Error> {
     }
 }
 
+/**
+ * 使用SCAN遍历匹配pattern的所有键，不会像KEYS一样在键较多时阻塞Redis，集群模式下遍历每个master
+ */
+pub fn redis_scan_keys(ns: &str, pattern: &str) -> Result<Vec<String>, Error> {
+    let conn = get_redis_connection(ns);
+    match conn {
+        Some(c) => match c.get() {
+            Ok(mut tc) => {
+                let mut keys = tc
+                    .scan_keys(pattern)
+                    .map_err(|err| anyhow!(err.to_string()))?;
+                keys.sort();
+                keys.dedup();
+                Ok(keys)
+            }
+            Err(err) => Err(anyhow!(err.to_string())),
+        },
+        None => Ok(vec![]),
+    }
+}
+
+/**
+ * 分步SCAN，返回下一个游标及本次扫描到的键，游标的格式见RedisConnection::scan_step
+ */
+pub fn redis_scan(
+    ns: &str,
+    cursor: &str,
+    pattern: &str,
+    count: i64,
+) -> Result<(String, Vec<String>), Error> {
+    let conn = get_redis_connection(ns);
+    match conn {
+        Some(c) => match c.get() {
+            Ok(mut tc) => tc
+                .scan_step(cursor, pattern, count)
+                .map_err(|err| anyhow!(err.to_string())),
+            Err(err) => Err(anyhow!(err.to_string())),
+        },
+        None => Ok(("0".to_owned(), vec![])),
+    }
+}
+
 pub fn redis_delexp_cmd(ns: &str, key: &str) -> Result<Option<String>, Error> {
+    for mc in redis_scan_keys(ns, &format!("{}*", key))? {
+        if let Err(err) = redis_del(ns, &mc) {
+            log::info!("Del {} by an error {}", mc, err);
+        }
+    }
+    Ok(None)
+}
+
+/**
+ * 将member加入集合key，集合的过期时间短于expire时延长为expire，使集合不早于其中的成员过期
+ */
+pub fn redis_sadd_expire(ns: &str, key: &str, member: &str, expire: u64) -> Result<(), Error> {
     let conn = get_redis_connection(ns);
     match conn {
         Some(c) => match c.get() {
             Ok(mut tc) => {
-                match tc.query::<Vec<String>>(redis::cmd("KEYS").arg(&format!("{}*", key))) {
-                    Ok(xv) => {
-                        for mc in xv {
-                            if let Err(err) = tc.query::<String>(redis::cmd("DEL").arg(&mc)) {
-                                log::info!("Del {} by an error {}", mc, err);
-                            }
-                        }
-                        Ok(None)
-                    }
-                    Err(err) => Err(anyhow!(err.to_string())),
+                tc.query::<i64>(redis::cmd("SADD").arg(key).arg(member))
+                    .map_err(|err| anyhow!(err.to_string()))?;
+                let ttl = tc
+                    .query::<i64>(redis::cmd("TTL").arg(key))
+                    .map_err(|err| anyhow!(err.to_string()))?;
+                if ttl < expire as i64 {
+                    tc.query::<i64>(redis::cmd("EXPIRE").arg(key).arg(expire))
+                        .map_err(|err| anyhow!(err.to_string()))?;
                 }
+                Ok(())
             }
             Err(err) => Err(anyhow!(err.to_string())),
         },
-        None => Ok(None),
+        None => Ok(()),
     }
 }
 
-pub fn redis_keys(ns: &str, key: &str) -> Result<Vec<String>, Error> {
+pub fn redis_smembers(ns: &str, key: &str) -> Result<Vec<String>, Error> {
     let conn = get_redis_connection(ns);
     match conn {
         Some(c) => match c.get() {
-            Ok(mut tc) => match tc.raw_keys(&format!("{}*", key)) {
-                Ok(xv) => {
-                    log::info!("result: {xv:?}");
-                    Ok(xv)
-                }
+            Ok(mut tc) => match tc.query::<Vec<String>>(redis::cmd("SMEMBERS").arg(key)) {
+                Ok(xv) => Ok(xv),
                 Err(err) => Err(anyhow!(err.to_string())),
             },
             Err(err) => Err(anyhow!(err.to_string())),
         },
-        None => {
-            log::info!("No redis connection.");
-            Ok(vec![])
-        }
+        None => Ok(vec![]),
+    }
+}
+
+pub fn redis_srem(ns: &str, key: &str, members: &[String]) -> Result<(), Error> {
+    if members.is_empty() {
+        return Ok(());
+    }
+    let conn = get_redis_connection(ns);
+    match conn {
+        Some(c) => match c.get() {
+            Ok(mut tc) => match tc.query::<i64>(redis::cmd("SREM").arg(key).arg(members)) {
+                Ok(_) => Ok(()),
+                Err(err) => Err(anyhow!(err.to_string())),
+            },
+            Err(err) => Err(anyhow!(err.to_string())),
+        },
+        None => Ok(()),
     }
 }
 
+pub fn redis_keys(ns: &str, key: &str) -> Result<Vec<String>, Error> {
+    let xv = redis_scan_keys(ns, &format!("{}*", key))?
+        .into_iter()
+        .filter(|f| !is_reserved_redis_key(f))
+        .collect_vec();
+    log::info!("result: {xv:?}");
+    Ok(xv)
+}
+
 pub fn redis_flushall_cmd(ns: &str) -> Result<Option<String>, Error> {
     let conn = get_redis_connection(ns);
     match conn {
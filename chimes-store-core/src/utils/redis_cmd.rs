@@ -0,0 +1,373 @@
+use anyhow::{anyhow, Error};
+use serde_json::{json, Map, Value};
+
+use super::cache::REDIS_CACHE_PREFIX;
+use super::redis::{
+    get_redis_connection, redis_delexp_cmd, redis_flushall_cmd, redis_scan, redis_scan_keys,
+};
+use crate::config::auth::JwtUserClaims;
+use crate::service::invoker::InvocationContext;
+use crate::service::starter::MxStoreService;
+
+/**
+ * redis://的方法分类，用于权限检查
+ * Read/Write分别按命名空间的redis_read_perm_roles/redis_write_perm_roles检查，Admin只允许超级管理员调用
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedisMethodKind {
+    Read,
+    Write,
+    Admin,
+}
+
+/**
+ * redis://支持的方法，list/page与get相同，只是返回值按列表及分页解析
+ */
+pub const REDIS_METHODS: &[(&str, RedisMethodKind)] = &[
+    ("get", RedisMethodKind::Read),
+    ("list", RedisMethodKind::Read),
+    ("page", RedisMethodKind::Read),
+    ("exists", RedisMethodKind::Read),
+    ("ttl", RedisMethodKind::Read),
+    ("hget", RedisMethodKind::Read),
+    ("hgetall", RedisMethodKind::Read),
+    ("lrange", RedisMethodKind::Read),
+    ("llen", RedisMethodKind::Read),
+    ("smembers", RedisMethodKind::Read),
+    ("sismember", RedisMethodKind::Read),
+    ("zrange", RedisMethodKind::Read),
+    ("zrangebyscore", RedisMethodKind::Read),
+    ("zscore", RedisMethodKind::Read),
+    ("scan", RedisMethodKind::Read),
+    ("keys", RedisMethodKind::Read),
+    ("set", RedisMethodKind::Write),
+    ("del", RedisMethodKind::Write),
+    ("incr", RedisMethodKind::Write),
+    ("decr", RedisMethodKind::Write),
+    ("expire", RedisMethodKind::Write),
+    ("hset", RedisMethodKind::Write),
+    ("hdel", RedisMethodKind::Write),
+    ("lpush", RedisMethodKind::Write),
+    ("rpush", RedisMethodKind::Write),
+    ("lpop", RedisMethodKind::Write),
+    ("rpop", RedisMethodKind::Write),
+    ("sadd", RedisMethodKind::Write),
+    ("srem", RedisMethodKind::Write),
+    ("zadd", RedisMethodKind::Write),
+    ("zrem", RedisMethodKind::Write),
+    ("flushall", RedisMethodKind::Admin),
+    ("delexp", RedisMethodKind::Admin),
+];
+
+pub fn redis_method_kind(method: &str) -> Option<RedisMethodKind> {
+    REDIS_METHODS
+        .iter()
+        .find(|(name, _)| *name == method)
+        .map(|(_, kind)| *kind)
+}
+
+/**
+ * 写入Redis的文本，字符串直接写入，其它值按JSON写入
+ */
+fn to_redis_text(val: &Value) -> String {
+    match val {
+        Value::String(text) => text.clone(),
+        _ => val.to_string(),
+    }
+}
+
+/**
+ * 读取到的文本能解析为JSON时返回解析后的值，与get的处理方式一致
+ */
+fn from_redis_text(text: String) -> Value {
+    match serde_json::from_str::<Value>(&text) {
+        Ok(t) => t,
+        Err(_) => Value::String(text),
+    }
+}
+
+fn to_json_value(val: redis::Value) -> Value {
+    match val {
+        redis::Value::Nil => Value::Null,
+        redis::Value::Int(t) => json!(t),
+        redis::Value::Data(data) => from_redis_text(String::from_utf8_lossy(&data).to_string()),
+        redis::Value::Bulk(list) => Value::Array(list.into_iter().map(to_json_value).collect()),
+        redis::Value::Status(text) => Value::String(text),
+        redis::Value::Okay => Value::String("OK".to_owned()),
+    }
+}
+
+/**
+ * 将[k1, v1, k2, v2, ...]转换为对象，用于HGETALL的返回值
+ */
+fn pairs_to_object(val: Value) -> Value {
+    match val {
+        Value::Array(list) => {
+            let mut mp = Map::new();
+            let mut it = list.into_iter();
+            while let (Some(k), Some(v)) = (it.next(), it.next()) {
+                mp.insert(to_redis_text(&k), v);
+            }
+            Value::Object(mp)
+        }
+        _ => val,
+    }
+}
+
+/**
+ * 将[member1, score1, member2, score2, ...]转换为[{member, score}]，用于WITHSCORES的返回值
+ */
+fn pairs_to_scores(val: Value) -> Value {
+    match val {
+        Value::Array(list) => {
+            let mut ret = vec![];
+            let mut it = list.into_iter();
+            while let (Some(member), Some(score)) = (it.next(), it.next()) {
+                let score = to_redis_text(&score).parse::<f64>().map(|f| json!(f)).unwrap_or(score);
+                ret.push(json!({ "member": member, "score": score }));
+            }
+            Value::Array(ret)
+        }
+        _ => val,
+    }
+}
+
+fn arg_required<'a>(args: &'a [Value], idx: usize, name: &str) -> Result<&'a Value, Error> {
+    args.get(idx)
+        .filter(|f| !f.is_null())
+        .ok_or(anyhow!("The argument {} is required.", name))
+}
+
+fn arg_i64(args: &[Value], idx: usize, default: i64) -> Result<i64, Error> {
+    match args.get(idx) {
+        None | Some(Value::Null) => Ok(default),
+        Some(Value::Number(t)) => t.as_i64().ok_or(anyhow!("The argument {} should be an integer.", idx)),
+        Some(Value::String(t)) => t
+            .parse::<i64>()
+            .map_err(|_| anyhow!("The argument {} should be an integer.", idx)),
+        Some(_) => Err(anyhow!("The argument {} should be an integer.", idx)),
+    }
+}
+
+/**
+ * 多个成员的参数，可以是多个参数，也可以是一个数组参数
+ */
+fn arg_items(args: &[Value]) -> Result<Vec<String>, Error> {
+    let items = match args {
+        [Value::Array(list)] => list.iter().map(to_redis_text).collect::<Vec<String>>(),
+        _ => args.iter().map(to_redis_text).collect::<Vec<String>>(),
+    };
+    if items.is_empty() {
+        return Err(anyhow!("At least one member is required."));
+    }
+    Ok(items)
+}
+
+/**
+ * ZADD的参数，{member: score}或者[{member, score}]
+ */
+fn arg_scores(args: &[Value]) -> Result<Vec<(f64, String)>, Error> {
+    let mut ret = vec![];
+    match arg_required(args, 0, "members")? {
+        Value::Object(mp) => {
+            for (member, score) in mp.iter() {
+                let score = score.as_f64().ok_or(anyhow!("The score of {} should be a number.", member))?;
+                ret.push((score, member.clone()));
+            }
+        }
+        Value::Array(list) => {
+            for item in list.iter() {
+                let member = item
+                    .get("member")
+                    .map(to_redis_text)
+                    .ok_or(anyhow!("The member is required."))?;
+                let score = item
+                    .get("score")
+                    .and_then(|f| f.as_f64())
+                    .ok_or(anyhow!("The score of {} should be a number.", member))?;
+                ret.push((score, member));
+            }
+        }
+        _ => return Err(anyhow!("The members should be an object or an array.")),
+    }
+    if ret.is_empty() {
+        return Err(anyhow!("At least one member is required."));
+    }
+    Ok(ret)
+}
+
+/**
+ * SCAN的匹配模式，key中没有通配符时按前缀匹配
+ */
+fn scan_pattern(key: &str) -> String {
+    if key.contains(['*', '?', '[']) {
+        key.to_owned()
+    } else {
+        format!("{}*", key)
+    }
+}
+
+/**
+ * Object/Query的结果缓存保存在保留的前缀下，其中包含各个租户的数据，不允许通过redis://读写
+ */
+pub fn is_reserved_redis_key(key: &str) -> bool {
+    key.starts_with(REDIS_CACHE_PREFIX)
+}
+
+fn filter_reserved_keys(keys: Vec<String>) -> Vec<String> {
+    keys.into_iter().filter(|f| !is_reserved_redis_key(f)).collect()
+}
+
+fn redis_query_value(ns: &str, cmd: &redis::Cmd) -> Result<Value, Error> {
+    match get_redis_connection(ns) {
+        Some(c) => match c.get() {
+            Ok(mut tc) => match tc.query::<redis::Value>(cmd) {
+                Ok(xv) => Ok(to_json_value(xv)),
+                Err(err) => Err(anyhow!(err.to_string())),
+            },
+            Err(err) => Err(anyhow!(err.to_string())),
+        },
+        None => Ok(Value::Null),
+    }
+}
+
+/**
+ * 按调用上下文中的用户及角色检查redis://方法的权限
+ * REST、/api/execute及脚本中的调用都要经过该检查，不依赖于REST的中间件
+ */
+pub fn check_redis_permission(ns: &str, method: &str, ctx: &InvocationContext) -> Result<(), Error> {
+    let jwt = ctx
+        .obtain_jwt_user_info()
+        .unwrap_or_else(JwtUserClaims::anonymous);
+    let roles = ctx.get_user_roles();
+    match MxStoreService::get(ns) {
+        Some(ms) if ms.get_config().has_redis_permission(method, &jwt, &roles) => Ok(()),
+        Some(_) => Err(anyhow!("Permission denied for redis method {}.", method)),
+        None => Err(anyhow!("The namespace {} was not found.", ns)),
+    }
+}
+
+/**
+ * 执行redis://的方法，key为InvokeUri中的query，args为调用的参数
+ * set: args[0]为值，args[1]为可选的过期时间（秒）
+ * incr/decr: args[0]为可选的步长，缺省为1
+ * expire: args[0]为过期时间（秒）
+ * hget: args[0]为字段；hset: args[0]为{field: value}，或者args[0]为字段、args[1]为值；hdel: 字段列表
+ * lpush/rpush/sadd/srem/zrem: 成员列表；lrange/zrange: args[0]、args[1]为起止位置，缺省为0、-1
+ * zadd: args[0]为{member: score}或[{member, score}]；zscore/sismember: args[0]为成员
+ * zrangebyscore: args[0]、args[1]为分数的范围，可以为-inf/+inf，args[2]为可选的{withscores, offset, count}
+ * scan: key为匹配模式（没有通配符时按前缀匹配），args[0]为游标（缺省为0），args[1]为每次扫描的数量，返回{cursor, keys}，集群模式下的游标为字符串
+ */
+pub fn redis_invoke(ns: &str, method: &str, key: &str, args: &[Value]) -> Result<Value, Error> {
+    let kind = redis_method_kind(method).ok_or(anyhow!("Not implemented"))?;
+    if key.is_empty() && kind != RedisMethodKind::Admin && method != "scan" && method != "keys" {
+        return Err(anyhow!("The key of redis is required."));
+    }
+    if kind != RedisMethodKind::Admin && is_reserved_redis_key(key) {
+        return Err(anyhow!("The key {} is reserved by the cache.", key));
+    }
+
+    let mut cmd;
+    match method {
+        "get" | "list" | "page" => {
+            cmd = redis::cmd("GET");
+            cmd.arg(key);
+        }
+        "exists" | "ttl" | "hgetall" | "llen" | "smembers" | "del" | "lpop" | "rpop" => {
+            cmd = redis::cmd(&method.to_uppercase());
+            cmd.arg(key);
+        }
+        "set" => {
+            cmd = redis::cmd("SET");
+            cmd.arg(key).arg(to_redis_text(arg_required(args, 0, "value")?));
+            let expire = arg_i64(args, 1, 0)?;
+            if expire > 0 {
+                cmd.arg("EX").arg(expire);
+            }
+        }
+        "incr" | "decr" => {
+            cmd = redis::cmd(if method == "incr" { "INCRBY" } else { "DECRBY" });
+            cmd.arg(key).arg(arg_i64(args, 0, 1)?);
+        }
+        "expire" => {
+            arg_required(args, 0, "seconds")?;
+            cmd = redis::cmd("EXPIRE");
+            cmd.arg(key).arg(arg_i64(args, 0, 0)?);
+        }
+        "hget" | "zscore" | "sismember" => {
+            cmd = redis::cmd(&method.to_uppercase());
+            cmd.arg(key).arg(to_redis_text(arg_required(args, 0, "field")?));
+        }
+        "hset" => {
+            cmd = redis::cmd("HSET");
+            cmd.arg(key);
+            match arg_required(args, 0, "field")? {
+                Value::Object(mp) if args.len() == 1 => {
+                    if mp.is_empty() {
+                        return Err(anyhow!("At least one field is required."));
+                    }
+                    for (field, val) in mp.iter() {
+                        cmd.arg(field).arg(to_redis_text(val));
+                    }
+                }
+                field => {
+                    cmd.arg(to_redis_text(field)).arg(to_redis_text(arg_required(args, 1, "value")?));
+                }
+            }
+        }
+        "hdel" | "lpush" | "rpush" | "sadd" | "srem" | "zrem" => {
+            cmd = redis::cmd(&method.to_uppercase());
+            cmd.arg(key).arg(arg_items(args)?);
+        }
+        "lrange" | "zrange" => {
+            cmd = redis::cmd(&method.to_uppercase());
+            cmd.arg(key).arg(arg_i64(args, 0, 0)?).arg(arg_i64(args, 1, -1)?);
+        }
+        "zadd" => {
+            cmd = redis::cmd("ZADD");
+            cmd.arg(key);
+            for (score, member) in arg_scores(args)? {
+                cmd.arg(score).arg(member);
+            }
+        }
+        "zrangebyscore" => {
+            let opts = args.get(2).cloned().unwrap_or(Value::Null);
+            let withscores = opts.get("withscores").and_then(|f| f.as_bool()).unwrap_or(false);
+            cmd = redis::cmd("ZRANGEBYSCORE");
+            cmd.arg(key)
+                .arg(args.first().map(to_redis_text).unwrap_or("-inf".to_owned()))
+                .arg(args.get(1).map(to_redis_text).unwrap_or("+inf".to_owned()));
+            if withscores {
+                cmd.arg("WITHSCORES");
+            }
+            if let Some(count) = opts.get("count").and_then(|f| f.as_i64()) {
+                let offset = opts.get("offset").and_then(|f| f.as_i64()).unwrap_or(0);
+                cmd.arg("LIMIT").arg(offset).arg(count);
+            }
+            let ret = redis_query_value(ns, &cmd)?;
+            return Ok(if withscores { pairs_to_scores(ret) } else { ret });
+        }
+        "scan" => {
+            let cursor = to_redis_text(args.first().unwrap_or(&json!("0")));
+            let (next, keys) = redis_scan(ns, &cursor, &scan_pattern(key), arg_i64(args, 1, 100)?)?;
+            return Ok(json!({ "cursor": next, "keys": filter_reserved_keys(keys) }));
+        }
+        "keys" => {
+            return Ok(json!(filter_reserved_keys(redis_scan_keys(ns, &scan_pattern(key))?)));
+        }
+        "flushall" => {
+            return Ok(redis_flushall_cmd(ns)?.map(Value::String).unwrap_or(Value::Null));
+        }
+        "delexp" => {
+            return Ok(redis_delexp_cmd(ns, key)?.map(Value::String).unwrap_or(Value::Null));
+        }
+        _ => return Err(anyhow!("Not implemented")),
+    }
+
+    let ret = redis_query_value(ns, &cmd)?;
+    if method == "hgetall" {
+        Ok(pairs_to_object(ret))
+    } else {
+        Ok(ret)
+    }
+}
@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rbatis::executor::{Executor, RBatisConnExecutor, RBatisRef};
+use rbatis::rbdc::db::ExecResult;
+use rbatis::RBatis;
+use rbs::Value;
+
+use crate::config::ReplicaConfig;
+
+/**
+ * 只读副本获取连接失败后，在该时间内不再被选择
+ */
+const REPLICA_RETRY_SECS: u64 = 30;
+
+#[derive(Debug, Default, Clone)]
+struct ReplicaHealth {
+    failures: u64,
+    down_until: Option<Instant>,
+}
+
+lazy_static::lazy_static! {
+    static ref REPLICA_HEALTH: Mutex<HashMap<String, ReplicaHealth>> = Mutex::new(HashMap::new());
+    static ref REPLICA_COUNTER: Mutex<HashMap<String, u64>> = Mutex::new(HashMap::new());
+}
+
+/**
+ * 只读副本当前是否可用，失败后经过REPLICA_RETRY_SECS会再次被尝试
+ */
+pub fn is_replica_healthy(url: &str) -> bool {
+    match REPLICA_HEALTH.lock().unwrap().get(url).and_then(|f| f.down_until) {
+        Some(until) => Instant::now() >= until,
+        None => true,
+    }
+}
+
+pub fn mark_replica_failed(url: &str) {
+    let mut health = REPLICA_HEALTH.lock().unwrap();
+    let entry = health.entry(url.to_owned()).or_default();
+    entry.failures += 1;
+    entry.down_until = Some(Instant::now() + Duration::from_secs(REPLICA_RETRY_SECS));
+    log::warn!("Replica {} was marked as unavailable after {} failure(s).", url, entry.failures);
+}
+
+pub fn mark_replica_healthy(url: &str) {
+    let mut health = REPLICA_HEALTH.lock().unwrap();
+    if let Some(entry) = health.get_mut(url) {
+        if entry.down_until.is_some() {
+            log::info!("Replica {} is available again.", url);
+        }
+        entry.failures = 0;
+        entry.down_until = None;
+    }
+}
+
+fn next_counter(ns: &str) -> u64 {
+    let mut counter = REPLICA_COUNTER.lock().unwrap();
+    let entry = counter.entry(ns.to_owned()).or_default();
+    let current = *entry;
+    *entry = entry.wrapping_add(1);
+    current
+}
+
+/**
+ * 按策略排列可用的只读副本，调用者依次尝试，第一个为本次选中的副本
+ * round_robin按顺序轮流选择，weighted按权重轮流选择，权重不大于0的副本不会被选中
+ * 没有可用的副本时返回空
+ */
+pub fn select_replicas(ns: &str, replicas: &[ReplicaConfig], policy: Option<&str>) -> Vec<String> {
+    let healthy = replicas
+        .iter()
+        .filter(|r| !r.url.is_empty() && is_replica_healthy(&r.url))
+        .collect::<Vec<&ReplicaConfig>>();
+    if healthy.is_empty() {
+        return vec![];
+    }
+    let weighted = policy.map(|p| p.eq_ignore_ascii_case("weighted")).unwrap_or(false);
+    let counter = next_counter(ns);
+    let start = if weighted {
+        let weights = healthy
+            .iter()
+            .map(|r| r.weight.unwrap_or(1).max(0) as u64)
+            .collect::<Vec<u64>>();
+        let total: u64 = weights.iter().sum();
+        if total == 0 {
+            return vec![];
+        }
+        let mut slot = counter % total;
+        let mut picked = 0;
+        for (idx, w) in weights.iter().enumerate() {
+            if slot < *w {
+                picked = idx;
+                break;
+            }
+            slot -= w;
+        }
+        picked
+    } else {
+        (counter % healthy.len() as u64) as usize
+    };
+    healthy
+        .iter()
+        .cycle()
+        .skip(start)
+        .take(healthy.len())
+        .filter(|r| !weighted || r.weight.unwrap_or(1) > 0)
+        .map(|r| r.url.clone())
+        .collect()
+}
+
+/**
+ * 只读副本的连接，执行失败时在主库上重新执行
+ * 主库执行成功说明是副本的问题，此时将副本标记为不可用；主库也失败时（如SQL本身有误）不影响副本的状态
+ */
+pub struct ReplicaExecutor {
+    url: String,
+    conn: RBatisConnExecutor,
+    primary: Arc<RBatis>,
+}
+
+impl ReplicaExecutor {
+    pub fn new(url: &str, conn: RBatisConnExecutor, primary: Arc<RBatis>) -> Self {
+        Self {
+            url: url.to_owned(),
+            conn,
+            primary,
+        }
+    }
+
+    fn fallback_succeeded(&self, err: &rbatis::Error) {
+        log::warn!("Replica {} failed, the statement was executed on the primary: {}", self.url, err);
+        mark_replica_failed(&self.url);
+    }
+}
+
+impl RBatisRef for ReplicaExecutor {
+    fn rb_ref(&self) -> &RBatis {
+        self.conn.rb_ref()
+    }
+}
+
+impl Executor for ReplicaExecutor {
+    fn id(&self) -> i64 {
+        self.conn.id
+    }
+
+    fn exec(
+        &self,
+        sql: &str,
+        args: Vec<Value>,
+    ) -> Pin<Box<dyn Future<Output = Result<ExecResult, rbatis::Error>> + Send + '_>> {
+        let sql = sql.to_owned();
+        Box::pin(async move {
+            match Executor::exec(&self.conn, &sql, args.clone()).await {
+                Ok(rs) => Ok(rs),
+                Err(err) => {
+                    let rs = Executor::exec(self.primary.as_ref(), &sql, args).await?;
+                    self.fallback_succeeded(&err);
+                    Ok(rs)
+                }
+            }
+        })
+    }
+
+    fn query(
+        &self,
+        sql: &str,
+        args: Vec<Value>,
+    ) -> Pin<Box<dyn Future<Output = Result<Value, rbatis::Error>> + Send + '_>> {
+        let sql = sql.to_owned();
+        Box::pin(async move {
+            match Executor::query(&self.conn, &sql, args.clone()).await {
+                Ok(rs) => Ok(rs),
+                Err(err) => {
+                    let rs = Executor::query(self.primary.as_ref(), &sql, args).await?;
+                    self.fallback_succeeded(&err);
+                    Ok(rs)
+                }
+            }
+        })
+    }
+}
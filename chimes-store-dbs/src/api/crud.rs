@@ -2,6 +2,9 @@ use chimes_store_core::pin_blockon_async;
 use chimes_store_core::service::invoker::InvocationContext;
 use chimes_store_core::service::starter::MxStoreService;
 use chimes_store_core::utils::{ApiResult, ApiResult2};
+use chimes_store_core::config::ConditionValidationError;
+use chimes_store_core::config::StaleRecordError;
+use chimes_store_core::config::FieldValidationError;
 use chimes_store_core::{config::QueryCondition, service::invoker::JwtFromDepot};
 use rbatis::Page;
 use salvo::prelude::*;
@@ -9,6 +12,43 @@ use serde_json::{json, Value};
 use std::any::Any;
 use std::sync::{Arc, Mutex};
 
+/**
+ * 查询条件或字段数据校验失败属于请求错误，返回400，乐观锁冲突返回409，其它错误返回500
+ */
+pub(crate) fn error_status(err: &anyhow::Error) -> i32 {
+    if err.downcast_ref::<ConditionValidationError>().is_some()
+        || err.downcast_ref::<FieldValidationError>().is_some()
+    {
+        400
+    } else if err.downcast_ref::<StaleRecordError>().is_some() {
+        409
+    } else {
+        500
+    }
+}
+
+/**
+ * 写操作的错误返回，字段验证失败时将每个属性的错误描述作为data返回
+ */
+fn write_error_result(err: &anyhow::Error) -> ApiResult<Option<Value>> {
+    let mut ret = ApiResult::error(error_status(err), format!("{}", err).as_str());
+    if let Some(verr) = err.downcast_ref::<FieldValidationError>() {
+        ret.data = Some(Some(json!(verr.errors)));
+    }
+    ret
+}
+
+/**
+ * 游标分页时，将InvocationContext中的NEXT_CURSOR作为next_cursor附加到分页结果中
+ */
+fn with_next_cursor(page: Page<Value>, ctx: &Arc<Mutex<InvocationContext>>) -> Value {
+    let mut ret = json!(page);
+    if let Some(cursor) = ctx.lock().unwrap().get_string("NEXT_CURSOR") {
+        ret["next_cursor"] = json!(cursor);
+    }
+    ret
+}
+
 #[handler]
 pub async fn select(depot: &mut Depot, req: &mut Request) -> Json<ApiResult<Option<Value>>> {
     // let params = req.parse_params::<Value>().expect("unexpect format");
@@ -26,7 +66,7 @@ pub async fn select(depot: &mut Depot, req: &mut Request) -> Json<ApiResult<Opti
     .await
     {
         Ok(rs) => Json(ApiResult::ok(rs)),
-        Err(err) => Json(ApiResult::error(500, format!("{}", err).as_str())),
+        Err(err) => Json(ApiResult::error(error_status(&err), format!("{}", err).as_str())),
     }
 }
 
@@ -47,7 +87,7 @@ pub async fn find_one(depot: &mut Depot, req: &mut Request) -> Json<ApiResult<Op
     .await
     {
         Ok(rs) => Json(ApiResult::ok(rs)),
-        Err(err) => Json(ApiResult::error(500, format!("{}", err).as_str())),
+        Err(err) => Json(ApiResult::error(error_status(&err), format!("{}", err).as_str())),
     }
 
 }
@@ -69,7 +109,7 @@ pub async fn insert(depot: &mut Depot, req: &mut Request) -> Json<ApiResult<Opti
     .await
     {
         Ok(rs) => Json(ApiResult::ok(rs)),
-        Err(err) => Json(ApiResult::error(500, format!("{}", err).as_str())),
+        Err(err) => Json(write_error_result(&err)),
     }
 }
 
@@ -90,7 +130,7 @@ pub async fn update(depot: &mut Depot, req: &mut Request) -> Json<ApiResult<Opti
     .await
     {
         Ok(rs) => Json(ApiResult::ok(rs)),
-        Err(err) => Json(ApiResult::error(500, format!("{}", err).as_str())),
+        Err(err) => Json(write_error_result(&err)),
     }
 }
 
@@ -111,7 +151,7 @@ pub async fn save_batch(depot: &mut Depot, req: &mut Request) -> Json<ApiResult<
     .await
     {
         Ok(rs) => Json(ApiResult::ok(rs)),
-        Err(err) => Json(ApiResult::error(500, format!("{}", err).as_str())),
+        Err(err) => Json(write_error_result(&err)),
     }
 }
 
@@ -149,7 +189,7 @@ pub async fn upsert(depot: &mut Depot, req: &mut Request) -> Json<ApiResult<Opti
     .await
     {
         Ok(rs) => Json(ApiResult::ok(rs)),
-        Err(err) => Json(ApiResult::error(500, format!("{}", err).as_str())),
+        Err(err) => Json(write_error_result(&err)),
     }
 }
 
@@ -170,11 +210,51 @@ pub async fn delete(depot: &mut Depot, req: &mut Request) -> Json<ApiResult<Opti
     .await
     {
         Ok(rs) => Json(ApiResult::ok(rs)),
-        Err(err) => Json(ApiResult::error(500, format!("{}", err).as_str())),
+        Err(err) => Json(ApiResult::error(error_status(&err), format!("{}", err).as_str())),
     }
 
 }
 
+#[handler]
+pub async fn restore(depot: &mut Depot, req: &mut Request) -> Json<ApiResult<Option<Value>>> {
+    let ns = req.param::<String>("ns").unwrap();
+    let name = req.param::<String>("name").unwrap();
+    let cond = req.parse_body::<Value>().await.unwrap();
+
+    let ctx = Arc::new(Mutex::new(InvocationContext::from_depot(depot)));
+
+    match MxStoreService::invoke_return_one(
+        format!("object://{}/{}#restore", ns, name),
+        ctx,
+        vec![json!(cond)],
+    )
+    .await
+    {
+        Ok(rs) => Json(ApiResult::ok(rs)),
+        Err(err) => Json(ApiResult::error(error_status(&err), format!("{}", err).as_str())),
+    }
+}
+
+#[handler]
+pub async fn purge(depot: &mut Depot, req: &mut Request) -> Json<ApiResult<Option<Value>>> {
+    let ns = req.param::<String>("ns").unwrap();
+    let name = req.param::<String>("name").unwrap();
+    let cond = req.parse_body::<Value>().await.unwrap();
+
+    let ctx = Arc::new(Mutex::new(InvocationContext::from_depot(depot)));
+
+    match MxStoreService::invoke_return_one(
+        format!("object://{}/{}#purge", ns, name),
+        ctx,
+        vec![json!(cond)],
+    )
+    .await
+    {
+        Ok(rs) => Json(ApiResult::ok(rs)),
+        Err(err) => Json(ApiResult::error(error_status(&err), format!("{}", err).as_str())),
+    }
+}
+
 #[handler]
 pub async fn delete_by(depot: &mut Depot, req: &mut Request) -> Json<ApiResult<Option<Value>>> {
     // let params = req.parse_params::<Value>().expect("unexpect format");
@@ -206,7 +286,7 @@ pub async fn delete_by(depot: &mut Depot, req: &mut Request) -> Json<ApiResult<O
     .await
     {
         Ok(rs) => Json(ApiResult::ok(rs)),
-        Err(err) => Json(ApiResult::error(500, format!("{}", err).as_str())),
+        Err(err) => Json(ApiResult::error(error_status(&err), format!("{}", err).as_str())),
     }
 }
 
@@ -231,7 +311,7 @@ pub async fn update_by(depot: &mut Depot, req: &mut Request) -> Json<ApiResult<O
 
     match MxStoreService::invoke_return_one(format!("object://{}/{}#update_by", ns, name), ctx, vec![cond, json!(condition)]).await {
         Ok(rs) => Json(ApiResult::ok(rs)),
-        Err(err) => Json(ApiResult::error(500, format!("{}", err).as_str())),
+        Err(err) => Json(write_error_result(&err)),
     }
 }
 
@@ -252,12 +332,12 @@ pub async fn query(depot: &mut Depot, req: &mut Request) -> Json<ApiResult<Vec<V
     .await
     {
         Ok(rs) => Json(ApiResult::ok(rs)),
-        Err(err) => Json(ApiResult::error(500, format!("{}", err).as_str())),
+        Err(err) => Json(ApiResult::error(error_status(&err), format!("{}", err).as_str())),
     }
 }
 
 #[handler]
-pub async fn paged_query(depot: &mut Depot, req: &mut Request) -> Json<ApiResult2<Page<Value>>> {
+pub async fn paged_query(depot: &mut Depot, req: &mut Request) -> Json<ApiResult2<Value>> {
     // let params = req.parse_params::<Value>().expect("unexpect format");
     let ns = req.param::<String>("ns").unwrap();
     let name = req.param::<String>("name").unwrap();
@@ -267,15 +347,55 @@ pub async fn paged_query(depot: &mut Depot, req: &mut Request) -> Json<ApiResult
 
     match MxStoreService::invoke_return_page(
         format!("object://{}/{}#paged_query", ns, name),
+        ctx.clone(),
+        vec![json!(cond)],
+    )
+    .await
+    {
+        Ok(rs) => Json(ApiResult2::ok(with_next_cursor(rs, &ctx))),
+        Err(err) => Json(ApiResult2::error(error_status(&err), format!("{}", err).as_str())),
+    }
+
+}
+
+#[handler]
+pub async fn aggregate(depot: &mut Depot, req: &mut Request) -> Json<ApiResult<Vec<Value>>> {
+    let ns = req.param::<String>("ns").unwrap();
+    let name = req.param::<String>("name").unwrap();
+    let cond = req.parse_body::<QueryCondition>().await.unwrap();
+
+    let ctx = Arc::new(Mutex::new(InvocationContext::from_depot(depot)));
+
+    match MxStoreService::invoke_return_vec(
+        format!("object://{}/{}#aggregate", ns, name),
         ctx,
         vec![json!(cond)],
     )
     .await
     {
-        Ok(rs) => Json(ApiResult2::ok(rs)),
-        Err(err) => Json(ApiResult2::error(500, format!("{}", err).as_str())),
+        Ok(rs) => Json(ApiResult::ok(rs)),
+        Err(err) => Json(ApiResult::error(error_status(&err), format!("{}", err).as_str())),
     }
+}
+
+#[handler]
+pub async fn history(depot: &mut Depot, req: &mut Request) -> Json<ApiResult<Vec<Value>>> {
+    let ns = req.param::<String>("ns").unwrap();
+    let name = req.param::<String>("name").unwrap();
+    let cond = req.parse_body::<Value>().await.unwrap();
 
+    let ctx = Arc::new(Mutex::new(InvocationContext::from_depot(depot)));
+
+    match MxStoreService::invoke_return_vec(
+        format!("object://{}/{}#history", ns, name),
+        ctx,
+        vec![cond],
+    )
+    .await
+    {
+        Ok(rs) => Json(ApiResult::ok(rs)),
+        Err(err) => Json(ApiResult::error(error_status(&err), format!("{}", err).as_str())),
+    }
 }
 
 #[handler]
@@ -305,7 +425,7 @@ pub async fn query_search(depot: &mut Depot, req: &mut Request) -> Json<ApiResul
     .await
     {
         Ok(rs) => Json(ApiResult::ok(rs)),
-        Err(err) => Json(ApiResult::error(500, format!("{}", err).as_str())),
+        Err(err) => Json(ApiResult::error(error_status(&err), format!("{}", err).as_str())),
     }
 
 }
@@ -314,7 +434,7 @@ pub async fn query_search(depot: &mut Depot, req: &mut Request) -> Json<ApiResul
 pub async fn query_paged_search(
     depot: &mut Depot,
     req: &mut Request,
-) -> Json<ApiResult2<Page<Value>>> {
+) -> Json<ApiResult2<Value>> {
     // let params = req.parse_params::<Value>().expect("unexpect format");
     let ns = req.param::<String>("ns").unwrap();
     let name = req.param::<String>("name").unwrap();
@@ -334,13 +454,13 @@ pub async fn query_paged_search(
 
     match MxStoreService::invoke_return_page(
         format!("query://{}/{}#paged_search", ns, name),
-        ctx,
+        ctx.clone(),
         vec![cond, json!(condition)],
     )
     .await
     {
-        Ok(rs) => Json(ApiResult2::ok(rs)),
-        Err(err) => Json(ApiResult2::error(500, format!("{}", err).as_str())),
+        Ok(rs) => Json(ApiResult2::ok(with_next_cursor(rs, &ctx))),
+        Err(err) => Json(ApiResult2::error(error_status(&err), format!("{}", err).as_str())),
     }
 
 }
@@ -364,7 +484,7 @@ pub async fn test(depot: &mut Depot, req: &mut Request) -> Json<ApiResult<Option
         .await
         {
             Ok(rs) => Json(ApiResult::ok(rs)),
-            Err(err) => Json(ApiResult::error(500, format!("{}", err).as_str())),
+            Err(err) => Json(ApiResult::error(error_status(&err), format!("{}", err).as_str())),
         };
         Box::new(ret) as Box<dyn Any + Send + Sync>
     }).unwrap_or(Json(ApiResult::error(500, "Unknonw error".to_string().as_str())))
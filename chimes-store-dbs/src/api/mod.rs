@@ -13,9 +13,13 @@ pub fn get_salvo_service_router() -> Vec<Router> {
         Router::with_path("object/<ns>/<name>/save_batch").post(crud::save_batch),
         Router::with_path("object/<ns>/<name>/delete").post(crud::delete),
         Router::with_path("object/<ns>/<name>/delete_by").post(crud::delete_by),
+        Router::with_path("object/<ns>/<name>/restore").post(crud::restore),
+        Router::with_path("object/<ns>/<name>/purge").post(crud::purge),
         Router::with_path("object/<ns>/<name>/update_by").post(crud::update_by),
         Router::with_path("object/<ns>/<name>/query").post(crud::query),
         Router::with_path("object/<ns>/<name>/paged_query").post(crud::paged_query),
+        Router::with_path("object/<ns>/<name>/aggregate").post(crud::aggregate),
+        Router::with_path("object/<ns>/<name>/history").post(crud::history),
         Router::with_path("query/<ns>/<name>/search").post(crud::query_search),
         Router::with_path("query/<ns>/<name>/paged_search").post(crud::query_paged_search),
         Router::with_path("redis/<ns>/redis/get").get(redis::redis_get_object),
@@ -26,6 +30,9 @@ pub fn get_salvo_service_router() -> Vec<Router> {
         Router::with_path("redis/<ns>/redis/keys").get(redis::redis_keys_vec),
         Router::with_path("redis/<ns>/redis/flushall").post(redis::redis_flushall),
         Router::with_path("redis/<ns>/redis/delexp").post(redis::redis_delexp),
+        Router::with_path("redis/<ns>/redis/<method>")
+            .get(redis::redis_invoke_method)
+            .post(redis::redis_invoke_method),
         Router::with_path("object/<ns>/<name>/test").post(crud::test),
     ]
 }
@@ -40,5 +47,8 @@ pub fn get_management_redis_service_routers() -> Vec<Router> {
         Router::with_path("/<ns>/redis/keys").get(redis::redis_keys_vec),
         Router::with_path("/<ns>/redis/flushall").post(redis::redis_flushall),
         Router::with_path("/<ns>/redis/delexp").post(redis::redis_delexp),
+        Router::with_path("/<ns>/redis/<method>")
+            .get(redis::redis_invoke_method)
+            .post(redis::redis_invoke_method),
     ]
 }
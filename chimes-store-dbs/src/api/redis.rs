@@ -165,3 +165,38 @@ pub async fn redis_delexp(
         Err(err) => Json(ApiResult::error(500, format!("{}", err).as_str())),
     }
 }
+
+/**
+ * 调用redis://的任意方法，key通过query参数传入，POST的body为参数，数组作为参数列表，其它值作为第一个参数
+ */
+#[handler]
+pub async fn redis_invoke_method(
+    depot: &mut Depot,
+    req: &mut Request,
+) -> Json<ApiResult<Option<Value>>> {
+    let ns = req.param::<String>("ns").unwrap();
+    let method = req.param::<String>("method").unwrap_or_default();
+    let query = req.query::<String>("key").unwrap_or_default();
+    let args = if req.method() == salvo::http::Method::POST {
+        match req.parse_body::<Value>().await {
+            Ok(Value::Array(list)) => list,
+            Ok(Value::Null) | Err(_) => vec![],
+            Ok(val) => vec![val],
+        }
+    } else {
+        vec![]
+    };
+
+    let ctx = Arc::new(Mutex::new(InvocationContext::from_depot(depot)));
+
+    match MxStoreService::invoke_return_one(
+        format!("redis://{}/redis?{}#{}", ns, query, method),
+        ctx,
+        args,
+    )
+    .await
+    {
+        Ok(rs) => Json(ApiResult::ok(rs)),
+        Err(err) => Json(ApiResult::error(500, format!("{}", err).as_str())),
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Error;
+use chimes_store_core::config::auth::JwtUserClaims;
+use chimes_store_core::config::{ConditionItem, OrdianlItem, QueryCondition};
+use chimes_store_core::service::invoker::InvocationContext;
+use rbatis::executor::{Executor, RBatisTxExecutor};
+use serde_json::{json, Map, Value};
+
+use super::crud::{DbCrud, DbStoreObject};
+use super::desensitize_process;
+
+/**
+ * 审计记录中来自请求的信息，在进入事务前从InvocationContext中获取
+ * 审计对象需要定义以下属性：object, record_key, action, before_image, after_image, diff,
+ * user_id, username, domain, remote_addr, invoke_uri, audit_time，未定义的属性不会被保存
+ */
+#[derive(Debug, Default, Clone)]
+pub(crate) struct AuditInfo {
+    pub action: String,
+    pub invoke_uri: String,
+    pub remote_addr: Option<String>,
+}
+
+impl AuditInfo {
+    pub(crate) fn from_context(ctx: &Arc<Mutex<InvocationContext>>, action: &str, invoke_uri: &str) -> Self {
+        Self {
+            action: action.to_owned(),
+            invoke_uri: invoke_uri.to_owned(),
+            remote_addr: ctx.lock().unwrap().get_string("REMOTE_ADDR"),
+        }
+    }
+}
+
+/**
+ * 比较变更前后的数据，返回发生变化的属性，格式为 {prop: {before, after}}
+ */
+pub(crate) fn audit_diff(before: &Value, after: &Value) -> Value {
+    let empty = Map::new();
+    let bmp = before.as_object().unwrap_or(&empty);
+    let amp = after.as_object().unwrap_or(&empty);
+    let mut diff = Map::new();
+    for key in bmp.keys().chain(amp.keys().filter(|k| !bmp.contains_key(*k))) {
+        let bv = bmp.get(key).cloned().unwrap_or(Value::Null);
+        let av = amp.get(key).cloned().unwrap_or(Value::Null);
+        if bv != av {
+            diff.insert(key.clone(), json!({"before": bv, "after": av}));
+        }
+    }
+    Value::Object(diff)
+}
+
+impl DbStoreObject {
+    /**
+     * 获取保存审计记录的对象，当前对象或者整个命名空间开启了审计，且配置了audit_object时返回
+     */
+    pub(crate) fn get_audit_object(&self) -> Option<DbStoreObject> {
+        if !(self.0.audit || self.1.audit) {
+            return None;
+        }
+        let audit_name = self.1.audit_object.clone().filter(|f| !f.is_empty())?;
+        if audit_name == self.0.name {
+            return None;
+        }
+        match self.1.get_object(&audit_name) {
+            Some(audit) => Some(DbStoreObject(audit, self.1.clone(), self.2.clone())),
+            None => {
+                log::warn!("The audit object {audit_name} was not found in namespace {}.", self.1.namespace);
+                None
+            }
+        }
+    }
+
+    /**
+     * 在事务中按主键读取记录当前的原始内容，作为审计的前后镜像，未开启审计时返回空
+     * 镜像不进行脱敏，以免脱敏字段的变更无法被记录，查询审计记录时再进行脱敏
+     */
+    pub(crate) async fn audit_images(
+        &self,
+        executor: Arc<RBatisTxExecutor>,
+        jwt: &JwtUserClaims,
+        rows: &[Value],
+    ) -> Result<Vec<Value>, Error> {
+        let mut images = vec![];
+        if self.get_audit_object().is_none() {
+            return Ok(images);
+        }
+        let exec: Arc<dyn Executor> = executor;
+        for row in rows {
+            if let Some(image) = self.select_raw(exec.clone(), jwt, row).await? {
+                images.push(image);
+            }
+        }
+        Ok(images)
+    }
+
+    /**
+     * 在事务中按条件读取记录当前的内容，用于update_by/delete_by的审计，未开启审计时返回空
+     */
+    pub(crate) async fn audit_query_images(
+        &self,
+        executor: Arc<RBatisTxExecutor>,
+        jwt: &JwtUserClaims,
+        qs: &QueryCondition,
+    ) -> Result<Vec<Value>, Error> {
+        if self.get_audit_object().is_none() {
+            return Ok(vec![]);
+        }
+        let exec: Arc<dyn Executor> = executor.clone();
+        let rows = self.query(exec, jwt, qs).await?;
+        self.audit_images(executor, jwt, &rows).await
+    }
+
+    /**
+     * 按当前对象的脱敏配置处理字段的值，非字符串的值保持不变
+     */
+    fn desensitize_prop(&self, prop: &str, value: &mut Value) {
+        let col = self.0.fields.iter().find(|c| {
+            c.desensitize.is_some() && c.prop_name.clone().unwrap_or(c.field_name.clone()) == prop
+        });
+        if let (Some(col), Value::String(text)) = (col, &value) {
+            *value = Value::String(desensitize_process(
+                text.clone(),
+                &self.1.namespace,
+                &col.desensitize,
+                col.crypto_store,
+            ));
+        }
+    }
+
+    /**
+     * 审计记录中保存的是原始内容，返回前对before_image/after_image/diff中的字段进行脱敏
+     */
+    fn desensitize_audit_record(&self, rec: &mut Value) {
+        let mp = match rec.as_object_mut() {
+            Some(mp) => mp,
+            None => return,
+        };
+        for name in ["before_image", "after_image"] {
+            if let Some(Value::String(text)) = mp.get(name) {
+                if let Ok(Value::Object(mut image)) = serde_json::from_str::<Value>(text) {
+                    for (prop, value) in image.iter_mut() {
+                        self.desensitize_prop(prop, value);
+                    }
+                    mp.insert(name.to_owned(), json!(Value::Object(image).to_string()));
+                }
+            }
+        }
+        if let Some(Value::String(text)) = mp.get("diff") {
+            if let Ok(Value::Object(mut diff)) = serde_json::from_str::<Value>(text) {
+                for (prop, change) in diff.iter_mut() {
+                    for side in ["before", "after"] {
+                        if let Some(value) = change.get_mut(side) {
+                            self.desensitize_prop(prop, value);
+                        }
+                    }
+                }
+                mp.insert("diff".to_owned(), json!(Value::Object(diff).to_string()));
+            }
+        }
+    }
+
+    /**
+     * 重新读取keys所对应记录的内容作为变更后的镜像，与before比较后写入审计对象
+     * 与业务操作使用同一个事务，审计写入失败时整个操作失败
+     */
+    pub(crate) async fn write_audit(
+        &self,
+        executor: Arc<RBatisTxExecutor>,
+        jwt: &JwtUserClaims,
+        info: &AuditInfo,
+        before: Vec<Value>,
+        keys: &[Value],
+    ) -> Result<(), Error> {
+        let audit = match self.get_audit_object() {
+            Some(audit) => audit,
+            None => return Ok(()),
+        };
+        let after = self.audit_images(executor.clone(), jwt, keys).await?;
+
+        let mut records: Vec<(String, Value, Value)> = vec![];
+        let mut index: HashMap<String, usize> = HashMap::new();
+        for (image, is_after) in before.into_iter().map(|v| (v, false)).chain(after.into_iter().map(|v| (v, true))) {
+            let key = self.get_pkey_value_present(&image).unwrap_or_default();
+            let pos = *index.entry(key.clone()).or_insert_with(|| {
+                records.push((key, Value::Null, Value::Null));
+                records.len() - 1
+            });
+            if is_after {
+                records[pos].2 = image;
+            } else {
+                records[pos].1 = image;
+            }
+        }
+
+        let audit_time = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+        for (key, before, after) in records {
+            let diff = audit_diff(&before, &after);
+            if !before.is_null() && !after.is_null() && diff.as_object().map(|d| d.is_empty()).unwrap_or(true) {
+                continue;
+            }
+            let rec = json!({
+                "object": format!("{}/{}", self.1.namespace, self.0.name),
+                "record_key": key,
+                "action": info.action,
+                "before_image": if before.is_null() { Value::Null } else { json!(before.to_string()) },
+                "after_image": if after.is_null() { Value::Null } else { json!(after.to_string()) },
+                "diff": diff.to_string(),
+                "user_id": jwt.userid,
+                "username": jwt.username,
+                "domain": jwt.domain,
+                "remote_addr": info.remote_addr,
+                "invoke_uri": info.invoke_uri,
+                "audit_time": audit_time,
+            });
+            audit.insert(executor.clone(), jwt, &rec).await?;
+        }
+        Ok(())
+    }
+
+    /**
+     * 查询记录的变更历史，t中需要包含主键，按审计时间倒序返回
+     */
+    pub(crate) async fn audit_history(
+        &self,
+        rb: Arc<dyn Executor>,
+        jwt: &JwtUserClaims,
+        t: &Value,
+    ) -> Result<Vec<Value>, Error> {
+        let audit = match self.get_audit_object() {
+            Some(audit) => audit,
+            None => return Err(anyhow::anyhow!("Audit is not enabled for {}", self.0.name)),
+        };
+        let key = match self.get_pkey_value_present(t) {
+            Some(key) => key,
+            None => return Err(anyhow::anyhow!("No primary key defined for {}", self.0.name)),
+        };
+        let field = |prop: &str| audit.0.resolve_field_name(prop).unwrap_or(prop.to_owned());
+        let qs = QueryCondition {
+            and: vec![
+                ConditionItem {
+                    field: field("object"),
+                    op: "=".to_owned(),
+                    value: json!(format!("{}/{}", self.1.namespace, self.0.name)),
+                    ..Default::default()
+                },
+                ConditionItem {
+                    field: field("record_key"),
+                    op: "=".to_owned(),
+                    value: json!(key),
+                    ..Default::default()
+                },
+            ],
+            sorts: vec![OrdianlItem {
+                field: field("audit_time"),
+                sort_asc: false,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let mut records = audit.query(rb, jwt, &qs).await?;
+        for rec in records.iter_mut() {
+            self.desensitize_audit_record(rec);
+        }
+        Ok(records)
+    }
+}
@@ -1,15 +1,28 @@
+use std::collections::{HashMap, HashSet};
+use std::pin::Pin;
 use std::sync::Arc;
 use anyhow::{anyhow, Error};
+use chimes_dbs_factory::get_bulk_max_params;
+use chimes_dbs_factory::get_bulk_upsert_sql;
+use chimes_dbs_factory::get_dialect;
+use chimes_dbs_factory::get_executor_dialect;
+use chimes_dbs_factory::get_executor_driver_name;
 use chimes_dbs_factory::get_insert_field_value_present;
+use chimes_dbs_factory::is_bulk_upsert_supported;
 use chimes_dbs_factory::get_update_field_value_present;
 use chimes_store_core::config::auth::AuthorizationConfig;
 use chimes_store_core::config::auth::JwtUserClaims;
+use chimes_store_core::config::Column;
 use chimes_store_core::config::ConditionItem;
 use chimes_store_core::config::StoreObject;
 use chimes_store_core::config::StoreServiceConfig;
+use chimes_store_core::config::StaleRecordError;
+use chimes_store_core::config::FieldValidationError;
 use chimes_store_core::utils::global_data::copy_value_compared_replaced;
 use chimes_store_core::utils::global_data::copy_value_excluded;
+use chimes_store_core::utils::global_data::copy_value_merged;
 use chimes_store_core::utils::global_data::copy_value_replaced;
+use futures_lite::Future;
 use itertools::Itertools;
 use rbatis::executor::Executor;
 use rbatis::rbdc::Uuid;
@@ -18,12 +31,17 @@ use serde_json::json;
 use serde_json::Value;
 
 use chimes_store_core::config::QueryCondition;
+use chimes_store_core::config::RelationPath;
+use chimes_store_core::config::is_safe_identifier;
 
 use crate::dbs::decode_vec_custom_fields_list;
+use crate::dbs::decode_vec_raw_fields_list;
+use crate::dbs::{decode_count_result, split_cursor_page};
 
 use super::crypto_desenstize_process;
 use super::is_desensitize_with_crypto_store;
 use super::refine_column_value;
+use super::validator::validate_fields;
 use super::refine_column_value_option;
 
 pub(crate) trait DbCrud<T: Sized + Send + Sync> {
@@ -51,6 +69,18 @@ pub(crate) trait DbCrud<T: Sized + Send + Sync> {
         jwt: &JwtUserClaims,
         qs: &QueryCondition,
     ) -> Result<Value, Error>;
+    async fn restore(
+        &self,
+        executor: Arc<RBatisTxExecutor>,
+        jwt: &JwtUserClaims,
+        t: &T,
+    ) -> Result<T, Error>;
+    async fn purge(
+        &self,
+        executor: Arc<RBatisTxExecutor>,
+        jwt: &JwtUserClaims,
+        t: &T,
+    ) -> Result<T, Error>;
     async fn upsert(
         &self,
         executor: Arc<RBatisTxExecutor>,
@@ -59,7 +89,6 @@ pub(crate) trait DbCrud<T: Sized + Send + Sync> {
         qs: Option<QueryCondition>,        
     ) -> Result<Value, Error>;
 
-    #[allow(dead_code)]
     async fn save_batch(
         &self,
         executor: Arc<RBatisTxExecutor>,
@@ -92,7 +121,19 @@ pub(crate) trait DbCrud<T: Sized + Send + Sync> {
         rb: Arc<dyn Executor>,
         jwt: &JwtUserClaims,
         qs: &QueryCondition,
-    ) -> Result<Page<T>, Error>;    
+    ) -> Result<Page<T>, Error>;
+    async fn cursor_query(
+        &self,
+        rb: Arc<dyn Executor>,
+        jwt: &JwtUserClaims,
+        qs: &QueryCondition,
+    ) -> Result<(Page<T>, Option<String>), Error>;
+    async fn aggregate(
+        &self,
+        rb: Arc<dyn Executor>,
+        jwt: &JwtUserClaims,
+        qs: &QueryCondition,
+    ) -> Result<Vec<T>, Error>;
 }
 
 pub fn validate_object(val: &Value, st: &StoreObject, pkcheck: bool) -> Result<(), anyhow::Error> {
@@ -121,6 +162,68 @@ pub fn validate_object(val: &Value, st: &StoreObject, pkcheck: bool) -> Result<(
     }
 }
 
+/**
+ * 批量保存时单行数据的准备结果
+ * fields为插入的字段，updates为主键冲突时需要更新的字段，key为主键值（自增主键未赋值时为None，只能插入）
+ * existed表示按主键判断该记录已经存在，已存在的记录使用原生的upsert语法，其它记录只插入
+ */
+struct BulkRow {
+    index: usize,
+    existed: bool,
+    row: Value,
+    fields: Vec<String>,
+    presents: Vec<String>,
+    updates: Vec<String>,
+    args: Vec<rbs::Value>,
+    key: Option<(String, Vec<rbs::Value>)>,
+}
+
+/**
+ * 批量保存的结果，inserted与updated为按主键区分的新增及更新的记录，failed为未保存的记录及其原因，均按输入的顺序排列
+ */
+#[derive(Debug, Default, Clone)]
+pub struct BatchSaveResult {
+    pub inserted: Vec<Value>,
+    pub updated: Vec<Value>,
+    pub failed: Vec<Value>,
+}
+
+impl BatchSaveResult {
+    pub fn to_value(&self) -> Value {
+        json!({
+            "affect_rows": self.inserted.len() + self.updated.len(),
+            "inserted": self.inserted,
+            "updated": self.updated,
+            "failed": self.failed,
+        })
+    }
+}
+
+/**
+ * 批量保存中失败的记录，字段验证失败时附加每个属性的错误描述
+ */
+fn bulk_failed_row(index: usize, row: &Value, err: &Error) -> Value {
+    let mut failed = json!({"index": index, "row": row, "reason": err.to_string()});
+    if let Some(verr) = err.downcast_ref::<FieldValidationError>() {
+        failed["code"] = json!("invalid");
+        failed["errors"] = json!(verr.errors);
+    } else if err.downcast_ref::<StaleRecordError>().is_some() {
+        failed["code"] = json!("stale");
+    }
+    failed
+}
+
+/**
+ * 将主键值转换为可比较的文本，用于判断批量保存中的记录是否已经存在
+ */
+fn bulk_key_text(v: &rbs::Value) -> String {
+    match v {
+        rbs::Value::String(s) => s.clone(),
+        rbs::Value::Ext(_, b) => bulk_key_text(b),
+        other => other.to_string(),
+    }
+}
+
 //
 // 对于Column中定义了Relation且RelationArray为True的列
 // 则是按照主表中的主键进行查询，因为这是一种1..N的关系，在主表中，是没有保存附属表的ID的，通常不会在主表中保存附属表的ID数组。
@@ -129,13 +232,14 @@ pub fn validate_object(val: &Value, st: &StoreObject, pkcheck: bool) -> Result<(
 // 一个StoreObject中可以允许定义多个附属表的关联对象
 // 而对于对于Column中定义了Relation且RelationArray为False的列
 // 则对应的该字段所保存的是附属表的主键ID，则直接已该字段的值对Relation Object进行查询即可
-// 级联更新/新增，只能支持一个层级
+// 级联更新/新增/删除支持多个层级，所有层级都在同一个事务中执行
 // json!{
 //    val: json!{
 //         val2: json!{ ... }
 //    }
 // }
-// 上面的JSON中，val2将不被支持了
+// 上面的JSON中，val2也会被级联处理，层级不能超过命名空间中配置的cascade_max_depth，
+// 级联路径中同一个对象出现两次时视为循环引用，返回错误
 //
 pub struct DbStoreObject(
     pub StoreObject,
@@ -143,156 +247,47 @@ pub struct DbStoreObject(
     pub AuthorizationConfig,
 );
 
-async fn insert_casc_2(
-    _sto: &StoreObject,
-    _stc: &StoreServiceConfig,
-    _auth: &AuthorizationConfig,
-    _executor: Arc<RBatisTxExecutor>,
-    _jwt: &JwtUserClaims,
-    _t: &Value,
-    _isarray: bool,
-) -> Result<Value, anyhow::Error> {
-    Ok(Value::Null)
+type CascadeFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, Error>> + Send + 'a>>;
+
+/**
+ * 级联操作所经过的对象路径，用于检测循环引用以及限制级联的层级
+ */
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CascadeTrail {
+    path: Vec<String>,
+    max_depth: usize,
 }
 
-async fn insert_casc_1(
-    sto: &StoreObject,
-    stc: &StoreServiceConfig,
-    auth: &AuthorizationConfig,
-    executor: Arc<RBatisTxExecutor>,
-    jwt: &JwtUserClaims,
-    t: &Value,
-    isarray: bool,
-) -> Result<Value, anyhow::Error> {
-    let mut update_map = serde_json::Map::new();
-    for col in sto
-        .fields
-        .iter()
-        .filter(|p| p.col_type == Some("relation".to_string()) && isarray == p.relation_array)
-        .cloned()
-    {
-        if let Some(vt) = t.get(col.prop_name.clone().unwrap_or(col.field_name.clone())) {
-            if let Some(sto) = stc.get_object(&col.relation_object.clone().unwrap_or_default()) {
-                let dbx = Box::new(DbStoreObject(sto.to_owned(), stc.clone(), auth.clone()));
-                match vt {
-                    Value::Object(ts) => {
-                        // if this is a json object, it should be a object to be update
-                        log::info!("TS: {:?}", ts);
-                        if let Some(mt) =
-                            ts.get(&col.relation_field.clone().unwrap_or("id".to_owned()))
-                        {
-                            if mt.is_null() {
-                                // insert, the new id should be update to major table
-                                match dbx.insert_(executor.clone(), jwt, vt).await {
-                                    Ok(tret) => {
-                                        log::info!("insert_: {:?}", tret);
-                                        update_map.insert(
-                                            col.prop_name.clone().unwrap_or(col.field_name.clone()),
-                                            tret,
-                                        );
-                                    }
-                                    Err(err) => {
-                                        return Err(err);
-                                    }
-                                }
-                            } else {
-                                // do update
-                                match dbx.update_(executor.clone(), jwt, vt).await {
-                                    Ok(_) => {
-                                        // update_map.insert("k", tret.get("last_insert_id").map(|f| f.to_owned()).unwrap_or(Value::Null));
-                                    }
-                                    Err(err) => {
-                                        return Err(err);
-                                    }
-                                }
-                            }
-                        } else {
-                            // insert, new id should be update to major table
-                            match dbx.insert_(executor.clone(), jwt, vt).await {
-                                Ok(tret) => {
-                                    log::info!("insert_: {:?}", tret);
-                                    update_map.insert(
-                                        col.prop_name.clone().unwrap_or(col.field_name.clone()),
-                                        tret,
-                                    );
-                                }
-                                Err(err) => {
-                                    return Err(err);
-                                }
-                            }
-                        }
-                    }
-                    Value::Array(list) => {
-                        log::info!("Array List: {:?}", list);
-                        // If there is a list belone to relationship, we should choose delete first or update only
-                        // So that, we will check this
-                        if let Some(stm) =
-                            stc.get_object(&col.relation_object.clone().unwrap_or_default())
-                        {
-                            let dbx =
-                                Box::new(DbStoreObject(stm.to_owned(), stc.clone(), auth.clone()));
-                            if col.relation_array {
-                                if let Some(tvl) = t.get(col.field_name.clone()) {
-                                    let mut del_qs = QueryCondition::default();
-                                    del_qs.and.push(ConditionItem {
-                                        field: col.relation_field.clone().unwrap_or_default(),
-                                        op: "=".to_owned(),
-                                        value: tvl.clone(),
-                                        ..Default::default()
-                                    });
-                                    let pkey = dbx.get_primary_key_field();
-                                    if pkey.is_some() {
-                                        let pkey_str = pkey.unwrap();
-                                        let mut mst = vec![];
-                                        for vl in list {
-                                            if let Some(kv) = vl.get(&pkey_str) {
-                                                mst.push(kv.clone());
-                                            }
-                                        }
+impl CascadeTrail {
+    pub(crate) fn new(dbs: &DbStoreObject) -> Self {
+        Self {
+            path: vec![dbs.0.name.clone()],
+            max_depth: dbs.1.cascade_max_depth.filter(|f| *f > 0).unwrap_or(5) as usize,
+        }
+    }
 
-                                        if !mst.is_empty() {
-                                            del_qs.and.push(ConditionItem {
-                                                field: dbx
-                                                    .get_primary_key_field()
-                                                    .unwrap_or_default(),
-                                                op: " NOT IN ".to_owned(),
-                                                value: Value::Array(mst),
-                                                ..Default::default()
-                                            });
-                                        }
-                                    }
-                                    let _ = dbx.delete_by(executor.clone(), jwt, &del_qs).await?;
-                                }
-                            }
-                            for val in list {
-                                // replace the val of to
-                                let mut mutval = val.clone();
-                                if let Value::Object(mp) = &mut mutval {
-                                    // 备注：如果是1..N关联，则需要在表数据定义中产生一个该字段field_name与prop_name保持一致的Column，不然，会找不到？
-                                    if let Some(tvl) = t.get(col.field_name.clone()) {
-                                        mp.insert(
-                                            col.relation_field.clone().unwrap_or_default(),
-                                            tvl.clone(),
-                                        );
-                                    }
-                                };
-                                match dbx.upsert_(executor.clone(), jwt, &mutval, None).await {
-                                    Ok(_) => {}
-                                    Err(err) => {
-                                        return Err(err);
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    _ => {
-                        // do nothing
-                    }
-                }
-            }
+    /**
+     * 进入下一级的关联对象
+     */
+    fn enter(&self, name: &str) -> Result<Self, Error> {
+        if self.path.iter().any(|f| f == name) {
+            return Err(anyhow!("Cascade cycle detected: {} -> {}", self.path.join(" -> "), name));
+        }
+        if self.path.len() > self.max_depth {
+            return Err(anyhow!(
+                "Cascade depth exceeds the max depth {}: {} -> {}",
+                self.max_depth,
+                self.path.join(" -> "),
+                name
+            ));
         }
+        let mut path = self.path.clone();
+        path.push(name.to_owned());
+        Ok(Self {
+            path,
+            max_depth: self.max_depth,
+        })
     }
-    Ok(Value::Object(update_map))
 }
 
 impl DbStoreObject {
@@ -377,6 +372,7 @@ impl DbStoreObject {
             "mod_datetime" => rbs::to_value!(rbatis::rbdc::DateTime::now()),
             "mod_date" => rbs::to_value!(rbatis::rbdc::DateTime::now()),
             "mod_time" => rbs::to_value!(rbatis::rbdc::DateTime::now()),
+            "version" => rbs::to_value!(1i64),
             _ => rbs::to_value!(Value::Null)
         }
     }
@@ -448,6 +444,19 @@ impl DbStoreObject {
             }
         }
 
+        // 按字段隔离租户时，租户字段总是使用当前用户的租户标识
+        if let Ok(Some((field, tenant))) = self.tenant_scope(jwt) {
+            match insert_fields.iter().position(|f| *f == field) {
+                Some(idx) => answers[idx] = rbs::to_value!(tenant),
+                None => {
+                    let field_type = self.0.get_column(&field, true).and_then(|c| c.field_type).unwrap_or_default();
+                    insert_values.push(get_insert_field_value_present(rb, &field, &field_type));
+                    insert_fields.push(field);
+                    answers.push(rbs::to_value!(tenant));
+                }
+            }
+        }
+
         (answers, insert_fields.join(","), insert_values.join(","))
     }
 
@@ -463,10 +472,19 @@ impl DbStoreObject {
         let mut update_fields = vec![];
         let mut key_fields = vec![];
         let ns = self.1.namespace.clone();
+        let tenant_field = self.tenant_scope(jwt).ok().flatten().map(|(f, _)| f);
         // let mut pkeys = vec![];
 
         for col in self.0.fields.iter() {
             let prop = col.prop_name.clone().unwrap_or(col.field_name.clone());
+            if col.generator == Some("version".to_owned()) {
+                // 版本号字段不直接更新，由数据库自增，旧值作为乐观锁的条件
+                continue;
+            }
+            if !col.pkey && tenant_field.as_ref() == Some(&col.field_name) {
+                // 租户字段不允许被修改
+                continue;
+            }
             if let Some(v) = t.get(prop) {
                 if col.pkey {
                     answers_keys.push(rbs::to_value!(v.to_owned()));                    
@@ -518,28 +536,100 @@ impl DbStoreObject {
             }
         }
 
+        if !update_fields.is_empty() {
+            if let Some(ver) = self.0.get_version_column() {
+                update_fields.push(format!("{} = {} + 1", ver.field_name, ver.field_name));
+            }
+        }
+
         if with_key {
             answers.append(&mut answers_keys);
         }
         (answers, update_fields.join(","), key_fields.join(" and "))
     }
 
-    fn to_select_sql(&self, with_key: bool, with_blob: bool, perm_sql: Option<String>) -> String {
+    /**
+     * 乐观锁的条件，对象定义了版本号字段时，返回附加的where条件及其参数
+     * t中没有提供版本号时返回FieldValidationError，以免覆盖他人的修改；对象设置了version_optional时不检查
+     */
+    pub(crate) fn to_version_condition(&self, t: &Value) -> Result<Option<(String, rbs::Value)>, Error> {
+        let ver = match self.0.get_version_column() {
+            Some(ver) => ver,
+            None => return Ok(None),
+        };
+        let prop = ver.prop_name.clone().unwrap_or(ver.field_name.clone());
+        match t.get(&prop) {
+            Some(v) if !v.is_null() => Ok(Some((format!(" and {} = ? ", ver.field_name), rbs::to_value!(v.to_owned())))),
+            _ if self.0.version_optional => Ok(None),
+            _ => {
+                let mut err = FieldValidationError::new(&self.0.name);
+                err.add_error(&prop, "version is required for the update");
+                Err(anyhow!(err))
+            }
+        }
+    }
+
+    /**
+     * 更新成功后，返回值中的版本号与数据库保持一致
+     */
+    fn bump_version(&self, t: &Value) -> Value {
+        let mut ret = t.clone();
+        if let Some(ver) = self.0.get_version_column() {
+            let prop = ver.prop_name.clone().unwrap_or(ver.field_name.clone());
+            let next = match t.get(&prop) {
+                Some(Value::Number(n)) => n.as_i64().map(|v| json!(v + 1)),
+                Some(Value::String(n)) => n.parse::<i64>().ok().map(|v| json!(v + 1)),
+                _ => None,
+            };
+            if let (Some(next), Some(mp)) = (next, ret.as_object_mut()) {
+                mp.insert(prop, next);
+            }
+        }
+        ret
+    }
+
+    pub fn has_version(&self) -> bool {
+        self.0.get_version_column().is_some()
+    }
+
+    /**
+     * 生成select语句，projection中指定了fields/exclude_fields时只查询需要返回的字段
+     * 主键及排序字段总是会被查询，以便于关联对象的加载以及游标的生成
+     * 占位符依次为数据权限、主键（keycond为true时）及租户（参见tenant_args），调用者追加的条件在其后
+     */
+    fn to_select_sql(
+        &self,
+        jwt: &JwtUserClaims,
+        with_key: bool,
+        with_blob: bool,
+        perm_sql: Option<String>,
+        projection: Option<&QueryCondition>,
+    ) -> String {
         let sql = if self.0.select_sql.is_empty() {
             let mut text = String::from("select ");
             let mut field_str = vec![];
 
+            let projected = |fl: &Column| match projection {
+                Some(qs) if qs.has_projection() => {
+                    // 只有在fields中明确指定时，才查询detail_only的字段
+                    let visible = !fl.detail_only || with_blob || !qs.fields.is_empty();
+                    fl.pkey
+                        || qs.sorts.iter().any(|f| f.field == fl.field_name)
+                        || (visible && qs.is_projected(&fl.field_name, fl.prop_name.as_deref()))
+                }
+                _ => !fl.detail_only || with_blob,
+            };
+
             for fl in self
                 .0
                 .fields
                 .iter()
+                .filter(|fl| projected(fl))
                 .sorted_by(|a, b| Ord::cmp(&a.field_name, &b.field_name))
                 .dedup_by(|x, y| x.field_name == y.field_name)
                 .cloned()
             {
-                if !fl.detail_only || with_blob {
-                    field_str.push(format!("_tbl.{}", fl.field_name.clone()));
-                }
+                field_str.push(format!("_tbl.{}", fl.field_name.clone()));
             }
 
             text.push_str(field_str.join(",").as_str());
@@ -556,24 +646,317 @@ impl DbStoreObject {
 
             text.push_str(
                 format!(
-                    " from {} _tbl {} where {}",
+                    " from {} _tbl {} where {}{}{}",
                     self.0.object_name.clone(),
                     perm_sql.unwrap_or_default(),
-                    keycond
+                    keycond,
+                    self.soft_delete_condition("_tbl.", projection),
+                    self.tenant_condition("_tbl.", jwt)
                 )
                 .as_str(),
             );
             text
         } else {
-            self.0.select_sql.clone()
+            // 自定义的select_sql同样需要过滤已软删除的记录，以及限定租户
+            format!(
+                "{}{}{}",
+                self.0.select_sql,
+                self.soft_delete_condition("", projection),
+                self.tenant_condition("", jwt)
+            )
         };
         sql
     }
 
+    /**
+     * 按主键读取记录的原始内容，包括已软删除的记录，不进行脱敏，不加载关联对象，用于审计的前后镜像
+     */
+    pub(crate) async fn select_raw(
+        &self,
+        rb: Arc<dyn Executor>,
+        jwt: &JwtUserClaims,
+        t: &Value,
+    ) -> Result<Option<Value>, Error> {
+        let raw_qs = QueryCondition {
+            include_deleted: true,
+            ..Default::default()
+        };
+        let sql = self.to_select_sql(jwt, true, true, None, Some(&raw_qs));
+        let mut args = self.get_keys_values(t);
+        args.extend(self.tenant_args(jwt));
+        let rs = rb.query(&sql, args).await?;
+        let mut rows =
+            decode_vec_raw_fields_list(rb, jwt, &self.1, rs, &self.0.fields, &self.1.namespace).await?;
+        Ok(if rows.is_empty() { None } else { Some(rows.remove(0)) })
+    }
+
+    /**
+     * 按照查询条件中的投影过滤需要返回的字段，未请求的relation字段不会被加载
+     */
+    pub fn projected_columns(&self, qs: &QueryCondition) -> Vec<Column> {
+        if qs.has_projection() {
+            self.0
+                .fields
+                .iter()
+                .filter(|c| qs.is_projected(&c.field_name, c.prop_name.as_deref()))
+                .cloned()
+                .collect_vec()
+        } else {
+            self.0.fields.clone()
+        }
+    }
+
     pub fn to_condition(&self, qs: &Value) -> Result<QueryCondition, Error> {
         serde_json::from_value::<QueryCondition>(qs.to_owned()).map_err(|err| anyhow!(err))
     }
 
+    /**
+     * 校验查询条件中的字段及操作符，并将属性名转换为字段名
+     */
+    pub fn refine_condition(&self, qs: &QueryCondition) -> Result<QueryCondition, Error> {
+        Ok(qs
+            .refine_fields(|f| self.0.resolve_field_name(f))?
+            .mark_json_fields(|f| self.0.is_json_field(f)))
+    }
+
+    /**
+     * 解析关联属性路径，如dept.name、roles.code
+     * 前缀为relation列的属性名，后缀为关联对象中的字段（属性名或字段名），只支持一级关联
+     * 生成的子查询片段通过relation列的字段与主表（别名_tbl）关联，relation_middle存在时经由中间表关联
+     * 关联对象的数据权限同样生效；脱敏、加密存储的字段，以及限定了角色（permitted）的字段不能作为关联属性路径使用
+     */
+    pub fn resolve_relation_path(&self, jwt: &JwtUserClaims, path: &str) -> Option<RelationPath> {
+        let (prefix, name) = path.split_once('.')?;
+        let col = self.0.fields.iter().find(|c| {
+            c.col_type.clone().unwrap_or_default().to_lowercase() == "relation"
+                && c.prop_name.clone().unwrap_or(c.field_name.clone()) == prefix
+        })?;
+        let sto = col.relation_object.clone().and_then(|f| self.1.get_object(&f))?;
+        let target = sto
+            .fields
+            .iter()
+            .find(|c| c.field_name == name || c.prop_name.as_deref() == Some(name))?;
+        let restricted = target.desensitize.clone().map(|f| !f.is_empty()).unwrap_or(false)
+            || target.crypto_store
+            || (target.permitted.clone().map(|f| !f.is_empty()).unwrap_or(false) && !jwt.superadmin);
+        if restricted || target.col_type.clone().unwrap_or_default().to_lowercase() == "relation" {
+            return None;
+        }
+        let field = target.field_name.clone();
+        let key = col.relation_field.clone().and_then(|f| sto.resolve_field_name(&f))?;
+        if ![sto.object_name.as_str(), &col.field_name, &field, &key]
+            .iter()
+            .all(|f| is_safe_identifier(f))
+        {
+            return None;
+        }
+
+        let middle = col.relation_middle.clone().filter(|f| !f.trim().is_empty());
+        let array = col.relation_array || middle.is_some();
+        let join = match middle {
+            Some(mid) => {
+                let (table, left, right) = super::parse_relation_middle(&mid)?;
+                if ![&table, &left, &right].iter().all(|f| is_safe_identifier(f)) {
+                    return None;
+                }
+                format!(
+                    "_rel.{} in (select {} from {} where {} = _tbl.{})",
+                    key, right, table, left, col.field_name
+                )
+            }
+            None => format!("_rel.{} = _tbl.{}", key, col.field_name),
+        };
+        let rel = DbStoreObject(sto.clone(), self.1.clone(), self.2.clone());
+        let perm_sql = rel.permission_join_sql("_rel", "__rp");
+        let mut args = if perm_sql.is_some() {
+            vec![Value::String(jwt.userid.clone())]
+        } else {
+            vec![]
+        };
+        if let Ok(Some((_, tenant))) = rel.tenant_scope(jwt) {
+            args.push(Value::String(tenant));
+        }
+        Some(RelationPath {
+            from_sql: format!(
+                "{} _rel {} where {}{}{}",
+                sto.object_name,
+                perm_sql.unwrap_or_default(),
+                join,
+                rel.soft_delete_condition("_rel.", None),
+                rel.tenant_condition("_rel.", jwt)
+            ),
+            field: format!("_rel.{}", field),
+            array,
+            args,
+        })
+    }
+
+    /**
+     * 查询时使用的条件校验，include_deleted只对超级管理员有效
+     * 查询的条件及排序中可以使用关联属性路径，参见resolve_relation_path
+     */
+    pub fn refine_query_condition(&self, jwt: &JwtUserClaims, qs: &QueryCondition) -> Result<QueryCondition, Error> {
+        let mut qs = qs
+            .refine_relation_fields(
+                |f| self.0.resolve_field_name(f),
+                |f| self.resolve_relation_path(jwt, f),
+            )?
+            .mark_json_fields(|f| self.0.is_json_field(f));
+        qs.include_deleted = qs.include_deleted && jwt.superadmin;
+        Ok(qs)
+    }
+
+    /**
+     * 软删除的标记字段及其已删除、未删除的值，标记字段为bool类型时使用true/false，否则使用1/0
+     */
+    fn soft_delete_flag(&self) -> Option<(String, &'static str, &'static str)> {
+        let flag = self.0.soft_delete_field.clone().filter(|f| !f.is_empty())?;
+        let boolean = self
+            .0
+            .get_column(&flag, true)
+            .map(|c| c.field_type.unwrap_or_default().to_lowercase().starts_with("bool"))
+            .unwrap_or(false);
+        if boolean {
+            Some((flag, "true", "false"))
+        } else {
+            Some((flag, "1", "0"))
+        }
+    }
+
+    /**
+     * 过滤已软删除记录的条件（以and开头），未启用软删除或者qs中include_deleted为true时返回空串
+     * alias为表别名的前缀，如_tbl.
+     */
+    pub(crate) fn soft_delete_condition(&self, alias: &str, qs: Option<&QueryCondition>) -> String {
+        match self.soft_delete_flag() {
+            Some((flag, _, normal)) if !qs.map(|q| q.include_deleted).unwrap_or(false) => {
+                format!(" and ({alias}{flag} = {normal} or {alias}{flag} is null) ")
+            }
+            _ => String::new(),
+        }
+    }
+
+    /**
+     * 按字段进行租户隔离时的租户字段及当前用户的租户标识，未启用或者超级管理员跨租户访问时返回None
+     * 启用后无法确定当前用户的租户时返回错误
+     */
+    pub(crate) fn tenant_scope(&self, jwt: &JwtUserClaims) -> Result<Option<(String, String)>, Error> {
+        let field = match self.0.get_tenant_field(&self.1) {
+            Some(field) => field,
+            None => return Ok(None),
+        };
+        if !is_safe_identifier(&field) {
+            return Err(anyhow!("Invalid tenant field {field} of {}", self.0.name));
+        }
+        Ok(jwt.tenant()?.map(|tenant| (field, tenant)))
+    }
+
+    /**
+     * 租户隔离的条件（以and开头），alias为表别名的前缀，无法确定租户时返回不匹配任何记录的条件
+     * 租户标识使用占位符，其参数由tenant_args提供，调用者需要按占位符在语句中的位置放入参数
+     */
+    pub(crate) fn tenant_condition(&self, alias: &str, jwt: &JwtUserClaims) -> String {
+        match self.tenant_scope(jwt) {
+            Ok(Some((field, _))) => format!(" and {alias}{field} = ? "),
+            Ok(None) => String::new(),
+            Err(err) => {
+                log::warn!("{err}");
+                " and 1 = 0 ".to_owned()
+            }
+        }
+    }
+
+    /**
+     * tenant_condition中占位符对应的参数，没有租户条件时为空
+     */
+    pub(crate) fn tenant_args(&self, jwt: &JwtUserClaims) -> Vec<rbs::Value> {
+        match self.tenant_scope(jwt) {
+            Ok(Some((_, tenant))) => vec![rbs::to_value!(tenant)],
+            _ => vec![],
+        }
+    }
+
+    /**
+     * 插入前检查租户，无法确定当前用户的租户时返回错误
+     * 超级管理员跨租户访问时不会自动填充租户字段，此时要求数据中显式给出租户标识，以免写入不属于任何租户的记录
+     */
+    pub(crate) fn check_insert_tenant(&self, jwt: &JwtUserClaims, t: &Value) -> Result<(), Error> {
+        if self.tenant_scope(jwt)?.is_some() {
+            return Ok(());
+        }
+        let field = match self.0.get_tenant_field(&self.1) {
+            Some(field) => field,
+            None => return Ok(()),
+        };
+        let prop = self
+            .0
+            .get_column(&field, true)
+            .and_then(|c| c.prop_name)
+            .unwrap_or(field.clone());
+        let present = match t.get(&prop) {
+            Some(Value::Null) | None => false,
+            Some(Value::String(s)) => !s.trim().is_empty(),
+            Some(_) => true,
+        };
+        if present {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "The tenant field {} of {} is required when inserting across tenants.",
+                prop,
+                self.0.name
+            ))
+        }
+    }
+
+    /**
+     * 生成删除语句中where之前的部分，启用软删除时为设置删除标记的update语句，
+     * 同时会更新generator为deleted_at/deleted_by的字段，返回语句及其参数
+     */
+    fn to_delete_sql(&self, rb: &rbatis::RBatis, jwt: &JwtUserClaims) -> (String, Vec<rbs::Value>) {
+        match self.soft_delete_flag() {
+            Some((flag, deleted, _)) => {
+                let mut sets = vec![format!("{flag} = {deleted}")];
+                let mut args = vec![];
+                for col in self.0.fields.iter().filter(|c| c.col_type != Some("relation".to_owned())) {
+                    let value = match col.generator.clone().unwrap_or_default().as_str() {
+                        "deleted_at" => rbs::to_value!(rbatis::rbdc::DateTime::now()),
+                        "deleted_by" => rbs::to_value!(jwt.userid.clone()),
+                        _ => continue,
+                    };
+                    sets.push(get_update_field_value_present(rb, &col.field_name, &col.field_type.clone().unwrap_or_default()));
+                    args.push(value);
+                }
+                (
+                    format!(
+                        "update {} set {} where 1 = 1 {}{} and ",
+                        self.0.object_name.clone(),
+                        sets.join(","),
+                        self.soft_delete_condition("", None),
+                        self.tenant_condition("", jwt)
+                    ),
+                    args.into_iter().chain(self.tenant_args(jwt)).collect_vec(),
+                )
+            }
+            None => (
+                format!(
+                    "delete from {} where 1 = 1 {} and ",
+                    self.0.object_name.clone(),
+                    self.tenant_condition("", jwt)
+                ),
+                self.tenant_args(jwt),
+            ),
+        }
+    }
+
+    fn to_key_condition(&self) -> String {
+        self.0
+            .get_key_columns()
+            .into_iter()
+            .map(|f| format!(" {} = ? ", f.field_name))
+            .join(" AND ")
+    }
+
     pub fn has_relationship(&self) -> bool {
         self.0
             .fields
@@ -595,9 +978,16 @@ impl DbStoreObject {
     }
 
     pub fn generate_permission_sql(&self) -> Option<String> {
+        self.permission_join_sql("_tbl", "__p")
+    }
+
+    /**
+     * 数据权限的join片段，table为当前对象的表别名，perm为权限表的别名，占位符对应当前用户的userid
+     */
+    fn permission_join_sql(&self, table: &str, perm: &str) -> Option<String> {
         if self.0.data_permission && self.2.data_permission {
             let permit_sql = format!(
-                " INNER JOIN {} __p ON __p.{} = _tbl.{} AND __p.{} = ? ",
+                " INNER JOIN {} {perm} ON {perm}.{} = {table}.{} AND {perm}.{} = ? ",
                 self.2.relative_table.clone().unwrap_or_default(),
                 self.0
                     .relative_field
@@ -630,88 +1020,236 @@ impl DbStoreObject {
         }
     }
 
-    async fn delete_casc(
-        &self,
+    /**
+     * 级联删除ro所关联的附属表数据，附属表还有关联对象时，先读取附属表的记录逐级删除
+     */
+    pub(crate) fn delete_casc<'a>(
+        &'a self,
         executor: Arc<RBatisTxExecutor>,
-        jwt: &JwtUserClaims,
+        jwt: &'a JwtUserClaims,
         ro: Value,
-    ) -> Result<(), anyhow::Error> {
-        for col in self
-            .0
-            .clone()
-            .fields
-            .iter()
-            .filter(|f| f.col_type == Some("relation".to_string()))
-        {
-            if let Some(pkval) = ro.get(col.prop_name.clone().unwrap_or(col.field_name.clone())) {
-                if let Some(relation_object) = col.relation_object.clone() {
-                    if let Some(relation) = self.1.get_object(&relation_object) {
-                        let dbx = DbStoreObject(relation.clone(), self.1.clone(), self.2.clone());
-                        if col.relation_array {
-                            let valkey = if pkval.is_array() || pkval.is_object() {
-                                ro.get(col.field_name.clone())
-                                    .map(|f| f.to_owned())
-                                    .unwrap_or(Value::Null)
-                            } else {
-                                pkval.clone()
-                            };
-                            let ci = ConditionItem {
-                                field: col.relation_field.clone().unwrap_or_default(),
-                                op: "=".to_owned(),
-                                value: valkey,
-                                ..Default::default()
-                            };
-                            let qs = QueryCondition {
-                                and: vec![ci],
-                                ..Default::default()
-                            };
-                            let _ = dbx.delete_by_(executor.clone(), jwt, &qs).await?;
-                        } else {
-                            let pkey = dbx.get_primary_key_field().unwrap_or("id".to_owned());
-                            if let Some(keyval) = pkval.get(&pkey) {
-                                let ci = ConditionItem {
-                                    field: pkey.clone(),
-                                    op: "=".to_owned(),
-                                    value: keyval.to_owned(),
-                                    ..Default::default()
-                                };
-                                let qs = QueryCondition {
-                                    and: vec![ci],
-                                    ..Default::default()
-                                };
-                                let _ = dbx.delete_by_(executor.clone(), jwt, &qs).await?;
-                            }
+        trail: &'a CascadeTrail,
+    ) -> CascadeFuture<'a, ()> {
+        Box::pin(async move {
+            for col in self
+                .0
+                .fields
+                .iter()
+                .filter(|f| f.col_type == Some("relation".to_string()))
+            {
+                let pkval = match ro.get(col.prop_name.clone().unwrap_or(col.field_name.clone())) {
+                    Some(pkval) => pkval,
+                    None => continue,
+                };
+                let relation = match col.relation_object.clone().and_then(|f| self.1.get_object(&f)) {
+                    Some(relation) => relation,
+                    None => continue,
+                };
+                let dbx = DbStoreObject(relation.clone(), self.1.clone(), self.2.clone());
+                let qs = if col.relation_array {
+                    let valkey = if pkval.is_array() || pkval.is_object() {
+                        ro.get(col.field_name.clone())
+                            .map(|f| f.to_owned())
+                            .unwrap_or(Value::Null)
+                    } else {
+                        pkval.clone()
+                    };
+                    QueryCondition {
+                        and: vec![ConditionItem {
+                            field: col.relation_field.clone().unwrap_or_default(),
+                            op: "=".to_owned(),
+                            value: valkey,
+                            ..Default::default()
+                        }],
+                        ..Default::default()
+                    }
+                } else {
+                    // 已加载的关联对象按其主键删除，未加载时（如select_raw的结果）按关联字段的原始值删除
+                    let (keyfield, keyval) = if pkval.is_object() {
+                        let pkey = dbx.get_primary_key_field().unwrap_or("id".to_owned());
+                        match pkval.get(&pkey) {
+                            Some(keyval) => (pkey, keyval.to_owned()),
+                            None => continue,
+                        }
+                    } else if !pkval.is_null() {
+                        match col.relation_field.clone().or(dbx.get_primary_key_field()) {
+                            Some(field) => (field, pkval.to_owned()),
+                            None => continue,
                         }
+                    } else {
+                        continue;
+                    };
+                    QueryCondition {
+                        and: vec![ConditionItem {
+                            field: keyfield,
+                            op: "=".to_owned(),
+                            value: keyval,
+                            ..Default::default()
+                        }],
+                        ..Default::default()
                     }
-                }
+                };
+                dbx.delete_by_casc(executor.clone(), jwt, &qs, trail).await?;
             }
-        }
-        Ok(())
+            Ok(())
+        })
     }
 
-    async fn insert_(
+    /**
+     * 按条件删除当前对象（作为附属表）的数据，当前对象还有关联对象时继续级联删除
+     * 当前对象启用软删除时只标记删除，其下的关联数据保持不变，以便restore时完整恢复
+     */
+    async fn delete_by_casc(
         &self,
         executor: Arc<RBatisTxExecutor>,
         jwt: &JwtUserClaims,
-        t: &Value,
+        qs: &QueryCondition,
+        trail: &CascadeTrail,
     ) -> Result<Value, Error> {
-        let mt = if self.has_relationship() {
-            match insert_casc_2(
-                &self.0.clone(),
-                &self.1.clone(),
-                &self.2.clone(),
-                executor.clone(),
-                jwt,
-                t,
-                false,
-            )
-            .await
+        let child_trail = trail.enter(&self.0.name)?;
+        if self.has_relationship() && self.soft_delete_flag().is_none() {
+            let exec: Arc<dyn Executor> = executor.clone();
+            for row in self.query(exec, jwt, qs).await? {
+                self.delete_casc(executor.clone(), jwt, row, &child_trail).await?;
+            }
+        }
+        self.delete_by_(executor, jwt, qs).await
+    }
+
+    /**
+     * 级联新增/更新关联对象
+     * isarray为false时处理1..1的关联，在主表保存前执行，返回附属表保存后的对象，用于回填主表的关联字段
+     * isarray为true时处理1..N的关联，在主表保存后执行，未在列表中出现的附属表数据将被删除，返回保存后的附属表列表
+     * 附属表自身的关联对象通过insert_/update_/upsert_继续级联处理
+     */
+    fn insert_casc<'a>(
+        &'a self,
+        executor: Arc<RBatisTxExecutor>,
+        jwt: &'a JwtUserClaims,
+        t: &'a Value,
+        isarray: bool,
+        trail: &'a CascadeTrail,
+    ) -> CascadeFuture<'a, Value> {
+        Box::pin(async move {
+            let mut update_map = serde_json::Map::new();
+            for col in self
+                .0
+                .fields
+                .iter()
+                .filter(|p| p.col_type == Some("relation".to_string()) && isarray == p.relation_array)
             {
-                Ok(val) => copy_value_replaced(t, &val),
-                Err(err) => {
-                    return Err(err);
+                let prop = col.prop_name.clone().unwrap_or(col.field_name.clone());
+                let vt = match t.get(&prop) {
+                    Some(vt) => vt,
+                    None => continue,
+                };
+                let sto = match self.1.get_object(&col.relation_object.clone().unwrap_or_default()) {
+                    Some(sto) => sto,
+                    None => continue,
+                };
+                let dbx = DbStoreObject(sto.to_owned(), self.1.clone(), self.2.clone());
+                match vt {
+                    Value::Object(ts) => {
+                        let child_trail = trail.enter(&dbx.0.name)?;
+                        let relval = ts.get(&col.relation_field.clone().unwrap_or("id".to_owned()));
+                        let tret = if relval.map(|f| f.is_null()).unwrap_or(true) {
+                            // insert, the new id should be update to major table
+                            dbx.insert_(executor.clone(), jwt, vt, &child_trail).await?
+                        } else {
+                            dbx.update_(executor.clone(), jwt, vt, &child_trail).await?
+                        };
+                        update_map.insert(prop, tret);
+                    }
+                    Value::Array(list) => {
+                        let child_trail = trail.enter(&dbx.0.name)?;
+                        if col.relation_array {
+                            if let Some(tvl) = t.get(col.field_name.clone()) {
+                                // 删除未出现在列表中的附属表数据
+                                let mut del_qs = QueryCondition::default();
+                                del_qs.and.push(ConditionItem {
+                                    field: col.relation_field.clone().unwrap_or_default(),
+                                    op: "=".to_owned(),
+                                    value: tvl.clone(),
+                                    ..Default::default()
+                                });
+                                if let Some(pkey_str) = dbx.get_primary_key_field() {
+                                    let mst = list.iter().filter_map(|vl| vl.get(&pkey_str).cloned()).collect_vec();
+                                    if !mst.is_empty() {
+                                        del_qs.and.push(ConditionItem {
+                                            field: pkey_str,
+                                            op: " NOT IN ".to_owned(),
+                                            value: Value::Array(mst),
+                                            ..Default::default()
+                                        });
+                                    }
+                                }
+                                dbx.delete_by_casc(executor.clone(), jwt, &del_qs, trail).await?;
+                            }
+                        }
+                        let mut saved = vec![];
+                        for val in list {
+                            // replace the val of to
+                            let mut mutval = val.clone();
+                            if let Value::Object(mp) = &mut mutval {
+                                // 备注：如果是1..N关联，则需要在表数据定义中产生一个该字段field_name与prop_name保持一致的Column，不然，会找不到？
+                                if let Some(tvl) = t.get(col.field_name.clone()) {
+                                    mp.insert(col.relation_field.clone().unwrap_or_default(), tvl.clone());
+                                }
+                            };
+                            saved.push(dbx.upsert_(executor.clone(), jwt, &mutval, None, &child_trail).await?);
+                        }
+                        update_map.insert(prop, Value::Array(saved));
+                    }
+                    _ => {
+                        // do nothing
+                    }
                 }
             }
+            Ok(Value::Object(update_map))
+        })
+    }
+
+    /**
+     * 插入后回填生成的值，自增主键使用last_insert_id，其它生成器使用插入时产生的值
+     */
+    fn fill_generated_values(
+        &self,
+        mt: &Value,
+        fields: &str,
+        args: &[rbs::Value],
+        last_insert_id: &rbs::Value,
+    ) -> Value {
+        let mut smt = mt.clone();
+        let inserted: HashMap<&str, &rbs::Value> = fields.split(',').zip(args.iter()).collect();
+        if let Some(mxt) = smt.as_object_mut() {
+            for col in self.0.fields.iter().filter(|c| c.col_type != Some("relation".to_owned())) {
+                let prop = col.prop_name.clone().unwrap_or(col.field_name.clone());
+                let generator = col.generator.clone().unwrap_or_default();
+                let absent = mxt.get(&prop).map(|v| v.is_null()).unwrap_or(true);
+                if col.pkey && (generator == *"autoincrement" || (generator.is_empty() && absent)) {
+                    mxt.insert(prop, json!(last_insert_id));
+                } else if !generator.is_empty() && absent {
+                    if let Some(v) = inserted.get(col.field_name.as_str()) {
+                        mxt.insert(prop, rbatis::decode::<Value>((*v).clone()).unwrap_or(Value::Null));
+                    }
+                }
+            }
+        }
+        smt
+    }
+
+    async fn insert_(
+        &self,
+        executor: Arc<RBatisTxExecutor>,
+        jwt: &JwtUserClaims,
+        t: &Value,
+        trail: &CascadeTrail,
+    ) -> Result<Value, Error> {
+        validate_fields(t, &self.0)?;
+        self.check_insert_tenant(jwt, t)?;
+        let mt = if self.has_relationship() {
+            let val = self.insert_casc(executor.clone(), jwt, t, false, trail).await?;
+            copy_value_replaced(t, &val)
         } else {
             t.clone()
         };
@@ -724,43 +1262,15 @@ impl DbStoreObject {
             insert_values
         );
 
-        match executor.exec(&sql, args).await {
+        match executor.exec(&sql, args.clone()).await {
             Ok(ts) => {
-                let mut smt = mt.clone();
-                self.0
-                    .fields
-                    .clone()
-                    .into_iter()
-                    .filter(|p| p.pkey)
-                    .for_each(|f| {
-                        if let Some(mxt) = smt.as_object_mut() {
-                            log::info!("show mxt: {:?}", mxt);
-                            mxt.insert(
-                                f.prop_name.unwrap_or(f.field_name),
-                                json!(ts.last_insert_id),
-                            );
-                        }
-                    });
-                log::info!("show mxt inserted: {:?}", smt);
+                let smt = self.fill_generated_values(&mt, &insert_fields, &args, &ts.last_insert_id);
                 if self.has_relationship() {
-                    match insert_casc_2(
-                        &self.0.clone(),
-                        &self.1.clone(),
-                        &self.2.clone(),
-                        executor,
-                        jwt,
-                        t,
-                        true,
-                    )
-                    .await
-                    {
-                        Ok(_) => {}
-                        Err(err) => {
-                            return Err(err);
-                        }
-                    }
+                    let val = self.insert_casc(executor, jwt, &smt, true, trail).await?;
+                    Ok(copy_value_replaced(&smt, &val))
+                } else {
+                    Ok(smt)
                 }
-                Ok(smt)
             }
             Err(err) => Err(anyhow::Error::new(err)),
         }
@@ -771,66 +1281,55 @@ impl DbStoreObject {
         executor: Arc<RBatisTxExecutor>,
         jwt: &JwtUserClaims,
         t: &Value,
+        trail: &CascadeTrail,
     ) -> Result<Value, Error> {
+        validate_fields(t, &self.0)?;
         let perm_sql = self.generate_permission_update_sql();
 
-        let mt = if self.has_relationship() {
-            match insert_casc_2(
-                &self.0.clone(),
-                &self.1.clone(),
-                &self.2.clone(),
-                executor.clone(),
-                jwt,
-                t,
-                false,
-            )
-            .await
-            {
-                Ok(val) => copy_value_replaced(t, &val),
-                Err(err) => {
-                    return Err(err);
-                }
-            }
+        let (mt, pre) = if self.has_relationship() {
+            let val = self.insert_casc(executor.clone(), jwt, t, false, trail).await?;
+            (copy_value_replaced(t, &val), val)
         } else {
-            t.clone()
+            (t.clone(), json!({}))
         };
 
         let (mut args, update_fields, update_keys) = self.to_update_rbs_value_vec(&executor.rb, jwt,&mt, true);
-        let sql = format!(
-            "update {} set {} where {} {}",
-            self.0.object_name.clone(),
-            update_fields,
-            update_keys,
-            perm_sql.clone().unwrap_or_default()
-        );
+        if !update_fields.is_empty() {
+            let version = self.to_version_condition(t)?;
+            let sql = format!(
+                "update {} set {} where {}{} {}{}",
+                self.0.object_name.clone(),
+                update_fields,
+                update_keys,
+                version.clone().map(|(c, _)| c).unwrap_or_default(),
+                perm_sql.clone().unwrap_or_default(),
+                self.tenant_condition("", jwt)
+            );
 
-        if perm_sql.is_some() {
-            args.push(rbs::to_value!(jwt.userid.clone()));
-        }
+            if let Some((_, v)) = version.clone() {
+                args.push(v);
+            }
 
-        match executor.exec(&sql, args).await {
-            Ok(_) => {
-                if self.has_relationship() {
-                    match insert_casc_2(
-                        &self.0.clone(),
-                        &self.1.clone(),
-                        &self.2.clone(),
-                        executor,
-                        jwt,
-                        t,
-                        true,
-                    )
-                    .await
-                    {
-                        Ok(_) => {}
-                        Err(err) => {
-                            return Err(err);
-                        }
-                    }
+            if perm_sql.is_some() {
+                args.push(rbs::to_value!(jwt.userid.clone()));
+            }
+            args.extend(self.tenant_args(jwt));
+
+            match executor.exec(&sql, args).await {
+                Ok(rr) if rr.rows_affected == 0 && version.is_some() => {
+                    return Err(anyhow!(StaleRecordError::new(&self.0.name, self.get_pkey_value_present(t))));
                 }
-                Ok(t.clone())
+                Ok(_) => {}
+                Err(err) => return Err(anyhow::Error::new(err)),
             }
-            Err(err) => Err(anyhow::Error::new(err)),
+        }
+
+        let ret = copy_value_replaced(&self.bump_version(t), &pre);
+        if self.has_relationship() {
+            let val = self.insert_casc(executor, jwt, t, true, trail).await?;
+            Ok(copy_value_replaced(&ret, &val))
+        } else {
+            Ok(ret)
         }
     }
 
@@ -843,6 +1342,7 @@ impl DbStoreObject {
         jwt: &JwtUserClaims,
         val: &Value,
         qs: Option<QueryCondition>,
+        trail: &CascadeTrail,
     ) -> Result<Value, Error> {
         let qx = qs.unwrap_or_else(|| {
             let mut m = QueryCondition::default();
@@ -857,10 +1357,11 @@ impl DbStoreObject {
             }
             m
         });
+        let qx = self.refine_condition(&qx)?;
 
         // let perm_sql = self.generate_permission_sql();
-        let mut sql = self.to_select_sql(false, false, None);
-        let (cond_sql, cond_args) = qx.to_query(true)?;
+        let mut sql = self.to_select_sql(jwt, false, false, None, None);
+        let (cond_sql, cond_args) = qx.to_query(get_executor_driver_name(executor.as_ref()), true)?;
 
         if qx.is_empty_condition() {
             sql.push_str(&cond_sql);
@@ -869,21 +1370,23 @@ impl DbStoreObject {
             sql.push_str(&cond_sql);
         }
 
-        let args = cond_args
+        let args = self
+            .tenant_args(jwt)
             .into_iter()
-            .map(|v| rbs::to_value!(v))
+            .chain(cond_args.into_iter().map(|v| rbs::to_value!(v)))
             .collect_vec();
 
         match executor.query_decode::<Vec<Value>>(&sql, args).await {
             Ok(vrs) => {
                 if vrs.is_empty() {
                     // do insert.
-                    self.insert_(executor, jwt, val).await
+                    self.insert_(executor, jwt, val, trail).await
                 } else if vrs.len() == 1 {
-                    // do update
+                    // do update，合并原记录前检查调用者是否提供了版本号
+                    self.to_version_condition(val)?;
                     let new_val = copy_value_excluded(val, &self.0.get_key_columns().iter().map(|f| f.field_name.clone()).collect_vec());
-                    let upd_val = copy_value_replaced(&vrs[0], &new_val);
-                    self.update_(executor, jwt, &upd_val).await
+                    let upd_val = copy_value_merged(&vrs[0], &new_val);
+                    self.update_(executor, jwt, &upd_val, trail).await
                 } else {
                     // report an error
                     Err(anyhow!("Upsert could not be executed when there are many records by this condition."))
@@ -893,6 +1396,371 @@ impl DbStoreObject {
         }
     }
 
+    /**
+     * 批量保存，可以批量处理的记录按字段分组后使用多行插入及数据库原生的upsert语法，
+     * 带有_cond条件或者级联关系的记录，以及需要与原值比较的脱敏记录、带有版本号的已存在记录，仍然逐条进行upsert
+     * 按字段隔离租户时所有记录都逐条进行upsert
+     * 校验失败或者版本号冲突的记录不会保存，在failed中返回其序号及原因，数据库执行出错时整个批次失败
+     */
+    pub(crate) async fn save_batch_detail(
+        &self,
+        executor: Arc<RBatisTxExecutor>,
+        jwt: &JwtUserClaims,
+        val: Vec<Value>,
+    ) -> Result<BatchSaveResult, Error> {
+        let driver = get_executor_driver_name(executor.as_ref()).to_owned();
+        // 按字段隔离租户时，数据库原生的upsert无法限定租户，因此逐条进行upsert
+        // 跨租户访问时同样逐条处理，以便检查插入的记录是否给出了租户标识
+        self.tenant_scope(jwt)?;
+        let tenant_field = self.0.get_tenant_field(&self.1);
+        if tenant_field.is_some() && is_bulk_upsert_supported(&driver) && val.len() > 1 {
+            log::info!("The bulk save is disabled for {} because it is isolated by the tenant column, {} rows are saved one by one.", self.0.name, val.len());
+        }
+        let bulkable = is_bulk_upsert_supported(&driver) && !self.has_relationship() && tenant_field.is_none();
+        let mut saved = vec![];
+        let mut failed = vec![];
+        let mut singles = vec![];
+        let mut bulks = vec![];
+
+        for (index, row) in val.iter().enumerate() {
+            if !bulkable || row.get("_cond").is_some() {
+                singles.push(index);
+                continue;
+            }
+            match self.to_bulk_row(&executor.rb, jwt, index, row) {
+                Ok(br) => bulks.push(br),
+                Err(err) => failed.push(bulk_failed_row(index, row, &err)),
+            }
+        }
+
+        let keyed = bulks.iter().filter(|r| r.key.is_some()).collect_vec();
+        let mut existed = self.bulk_existing_keys(executor.clone(), &keyed).await?;
+
+        if self.has_desensitize() || self.has_version() {
+            // 已经存在的记录更新时需要与原值比较，避免将脱敏后的值写回，或者需要校验版本号
+            let (olds, news): (Vec<BulkRow>, Vec<BulkRow>) = bulks
+                .into_iter()
+                .partition(|r| r.key.as_ref().map(|(k, _)| existed.contains(k)).unwrap_or(false));
+            singles.extend(olds.into_iter().map(|r| r.index));
+            bulks = news;
+        }
+
+        self.bulk_upsert_rows(executor.clone(), bulks, &mut existed, &mut saved).await?;
+
+        singles.sort();
+        for index in singles {
+            let row = &val[index];
+            if let Err(err) = validate_object(row, &self.0, false) {
+                failed.push(bulk_failed_row(index, row, &err));
+                continue;
+            }
+            let qst = row
+                .get("_cond")
+                .and_then(|qsval| serde_json::from_value::<QueryCondition>(qsval.to_owned()).ok());
+            match self.upsert_detail(executor.clone(), jwt, row, qst).await {
+                Ok((v, inserted)) => saved.push((index, v, inserted)),
+                Err(err)
+                    if err.downcast_ref::<StaleRecordError>().is_some()
+                        || err.downcast_ref::<FieldValidationError>().is_some() =>
+                {
+                    failed.push(bulk_failed_row(index, row, &err));
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        saved.sort_by_key(|(index, _, _)| *index);
+        failed.sort_by_key(|f| f["index"].as_u64().unwrap_or_default());
+        let (inserted, updated): (Vec<_>, Vec<_>) = saved.into_iter().partition(|(_, _, inserted)| *inserted);
+        Ok(BatchSaveResult {
+            inserted: inserted.into_iter().map(|(_, v, _)| v).collect(),
+            updated: updated.into_iter().map(|(_, v, _)| v).collect(),
+            failed,
+        })
+    }
+
+    /**
+     * 按照条件进行新增或更新，返回保存后的数据以及是否为新增
+     */
+    pub(crate) async fn upsert_detail(
+        &self,
+        executor: Arc<RBatisTxExecutor>,
+        jwt: &JwtUserClaims,
+        val: &Value,
+        qs: Option<QueryCondition>,
+    ) -> Result<(Value, bool), Error> {
+        validate_object(val, &self.0, false)?;
+        validate_fields(val, &self.0)?;
+        let qx = qs.unwrap_or_else(|| {
+            let mut m = QueryCondition::default();
+            for k in self.0.get_key_columns().iter().map(|f| f.field_name.clone()).collect_vec() {
+                let ci = ConditionItem {
+                    field: k.clone(),
+                    op: "=".to_owned(),
+                    value: self.get_field_value(&k, val),
+                    ..Default::default()
+                };
+                m.and.push(ci);
+            }
+            m
+        });
+        let qx = self.refine_condition(&qx)?;
+
+        log::info!("QS: {:?}", qx);
+
+        // let perm_sql = self.generate_permission_sql();
+        let mut sql = self.to_select_sql(jwt, false, false, None, None);
+        let (cond_sql, cond_args) = qx.to_query(get_executor_driver_name(executor.as_ref()), true)?;
+        if qx.is_empty_condition() {
+            sql.push_str(&cond_sql);
+        } else {
+            sql.push_str(" AND ");
+            sql.push_str(&cond_sql);
+        }
+
+
+        let args = self
+            .tenant_args(jwt)
+            .into_iter()
+            .chain(cond_args.into_iter().map(|v| rbs::to_value!(v)))
+            .collect_vec();
+
+        let rs = match executor.query(&sql, args).await {
+            Ok(rs) => rs,
+            Err(err) => {
+                log::info!("test error : {:?}", err);
+                return Err(anyhow::Error::new(err));
+            }
+        };
+
+        match decode_vec_custom_fields_list(
+            executor.clone(),
+            jwt,
+            &self.1,
+            rs,
+            &self.0.fields,
+            &self.1.namespace,
+        )
+        .await
+        {
+            Ok(vrs) => {
+                if vrs.is_empty() {
+                    // do insert.
+                    self.insert(executor, jwt, val).await.map(|v| (v, true))
+                } else if vrs.len() == 1 {
+                    // do update，合并原记录前检查调用者是否提供了版本号
+                    self.to_version_condition(val)?;
+                    let new_val = copy_value_excluded(val, &self.0.get_key_columns().iter().map(|f| f.field_name.clone()).collect_vec());
+                    let upd_val = copy_value_merged(&vrs[0], &new_val);
+                    self.update(executor, jwt, &upd_val).await.map(|v| (v, false))
+                } else {
+                    // report an error
+                    Err(anyhow!("Upsert could not be executed when there are many records by this condition."))
+                }
+            }
+            Err(err) => Err(anyhow!(err)),
+        }
+    }
+
+
+    /**
+     * 准备批量保存的单行数据，生成器产生的值会回填到返回的行中
+     * 未提供的字段不参与插入，由数据库的默认值处理；非mod_开头的生成字段在主键冲突时不更新
+     */
+    fn to_bulk_row(&self, rb: &rbatis::RBatis, jwt: &JwtUserClaims, index: usize, t: &Value) -> Result<BulkRow, Error> {
+        validate_object(t, &self.0, false)?;
+        validate_fields(t, &self.0)?;
+        let ns = self.1.namespace.clone();
+        let mut bulk = BulkRow {
+            index,
+            existed: false,
+            row: t.clone(),
+            fields: vec![],
+            presents: vec![],
+            updates: vec![],
+            args: vec![],
+            key: None,
+        };
+        let mut keyed = true;
+        let mut key_texts = vec![];
+        let mut key_args = vec![];
+
+        for col in self.0.fields.iter().filter(|c| c.col_type != Some("relation".to_owned())) {
+            let prop = col.prop_name.clone().unwrap_or(col.field_name.clone());
+            let generator = col.generator.clone().unwrap_or_default();
+            let (value, generated) = match t.get(&prop) {
+                Some(tv) if !tv.is_null() => {
+                    if is_desensitize_with_crypto_store(&col.desensitize, col.crypto_store) {
+                        let text = tv.as_str().unwrap_or_default();
+                        (rbs::to_value!(crypto_desenstize_process(text.to_owned(), &ns, &col.desensitize)), false)
+                    } else {
+                        (rbs::to_value!(tv), false)
+                    }
+                }
+                tv => {
+                    if generator == *"autoincrement" {
+                        keyed = keyed && !col.pkey;
+                        continue;
+                    } else if !generator.is_empty() {
+                        let gv = self.get_generated_value(&generator, jwt);
+                        if let Some(mp) = bulk.row.as_object_mut() {
+                            mp.insert(prop.clone(), rbatis::decode::<Value>(gv.clone()).unwrap_or(Value::Null));
+                        }
+                        (gv, true)
+                    } else if col.pkey {
+                        return Err(anyhow!("No primary key Property {prop} present"));
+                    } else if tv.is_none() {
+                        continue;
+                    } else {
+                        (rbs::to_value!(Value::Null), false)
+                    }
+                }
+            };
+
+            if col.pkey {
+                key_texts.push(bulk_key_text(&value));
+                key_args.push(value.clone());
+            } else if !generated || generator.starts_with("mod_") {
+                bulk.updates.push(col.field_name.clone());
+            }
+            bulk.fields.push(col.field_name.clone());
+            bulk.presents.push(get_insert_field_value_present(rb, &col.field_name, &col.field_type.clone().unwrap_or_default()));
+            bulk.args.push(value);
+        }
+
+        if bulk.fields.is_empty() {
+            return Err(anyhow!("Object does not contains any fields."));
+        }
+        if keyed && !key_args.is_empty() {
+            bulk.key = Some((key_texts.join("&"), key_args));
+        }
+        Ok(bulk)
+    }
+
+    /**
+     * 查询批量保存的记录中，主键已经存在的记录
+     */
+    async fn bulk_existing_keys(
+        &self,
+        executor: Arc<RBatisTxExecutor>,
+        rows: &[&BulkRow],
+    ) -> Result<HashSet<String>, Error> {
+        let mut existed = HashSet::new();
+        let keys = self.0.get_key_columns();
+        if keys.is_empty() || rows.is_empty() {
+            return Ok(existed);
+        }
+        let driver = get_executor_driver_name(executor.as_ref()).to_owned();
+        let keycond = keys
+            .iter()
+            .map(|k| format!("{} = {}", k.field_name, get_insert_field_value_present(&executor.rb, &k.field_name, &k.field_type.clone().unwrap_or_default())))
+            .join(" and ");
+        let size = (get_bulk_max_params(&driver) / keys.len()).clamp(1, 1000);
+
+        for part in rows.chunks(size) {
+            let mut conds = vec![];
+            let mut args = vec![];
+            for (_, kv) in part.iter().filter_map(|r| r.key.as_ref()) {
+                conds.push(format!("({keycond})"));
+                args.extend(kv.iter().cloned());
+            }
+            let sql = format!(
+                "select {} from {} where {}",
+                keys.iter().map(|k| k.field_name.clone()).join(","),
+                self.0.object_name.clone(),
+                conds.join(" or ")
+            );
+            if let rbs::Value::Array(list) = executor.query(&sql, args).await? {
+                for item in list {
+                    existed.insert(keys.iter().map(|k| bulk_key_text(&item[k.field_name.as_str()])).join("&"));
+                }
+            }
+        }
+        Ok(existed)
+    }
+
+    /**
+     * 将字段相同的记录分组，使用多行插入及数据库原生的upsert语法保存
+     * existed为已经存在的主键，按主键区分新增和更新，新插入的主键也会加入其中，批次中重复的主键后续作为更新
+     * 新增的记录只使用insert，不附加upsert子句，避免mysql的on duplicate key update在唯一索引冲突时更新了其它记录
+     */
+    async fn bulk_upsert_rows(
+        &self,
+        executor: Arc<RBatisTxExecutor>,
+        rows: Vec<BulkRow>,
+        existed: &mut HashSet<String>,
+        saved: &mut Vec<(usize, Value, bool)>,
+    ) -> Result<(), Error> {
+        let driver = get_executor_driver_name(executor.as_ref()).to_owned();
+        let mut groups: Vec<Vec<BulkRow>> = vec![];
+        for mut row in rows {
+            if let Some((k, _)) = row.key.as_ref() {
+                row.existed = !existed.insert(k.clone());
+            }
+            match groups.iter_mut().find(|g| {
+                g[0].fields == row.fields && g[0].updates == row.updates && g[0].existed == row.existed
+            }) {
+                Some(g) => g.push(row),
+                None => groups.push(vec![row]),
+            }
+        }
+        // 先插入新增的记录，批次中重复的主键才能在随后被更新
+        groups.sort_by_key(|g| g[0].existed);
+
+        for group in groups {
+            let size = (get_bulk_max_params(&driver) / group[0].fields.len()).clamp(1, 1000);
+            let mut chunk: Vec<BulkRow> = vec![];
+            let mut chunk_keys = HashSet::new();
+            for row in group {
+                // 同一条语句中不能出现重复的主键，否则postgres等数据库会报错
+                let dup = row.key.as_ref().map(|(k, _)| chunk_keys.contains(k)).unwrap_or(false);
+                if chunk.len() >= size || dup {
+                    self.bulk_exec_chunk(executor.clone(), &driver, std::mem::take(&mut chunk), saved).await?;
+                    chunk_keys.clear();
+                }
+                if let Some((k, _)) = row.key.as_ref() {
+                    chunk_keys.insert(k.clone());
+                }
+                chunk.push(row);
+            }
+            if !chunk.is_empty() {
+                self.bulk_exec_chunk(executor.clone(), &driver, chunk, saved).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn bulk_exec_chunk(
+        &self,
+        executor: Arc<RBatisTxExecutor>,
+        driver: &str,
+        chunk: Vec<BulkRow>,
+        saved: &mut Vec<(usize, Value, bool)>,
+    ) -> Result<(), Error> {
+        let keys = if chunk[0].existed {
+            self.get_keys()
+        } else {
+            vec![]
+        };
+        let presents = chunk.iter().map(|r| format!("({})", r.presents.join(","))).collect_vec();
+        let sql = match get_bulk_upsert_sql(
+            driver,
+            &self.0.object_name,
+            &chunk[0].fields,
+            &presents,
+            &keys,
+            &chunk[0].updates,
+        ) {
+            Some(sql) => sql,
+            None => return Err(anyhow!("Bulk upsert is not supported by {driver}")),
+        };
+        let args = chunk.iter().flat_map(|r| r.args.iter().cloned()).collect_vec();
+        executor.exec(&sql, args).await?;
+
+        saved.extend(chunk.into_iter().map(|r| (r.index, r.row, !r.existed)));
+        Ok(())
+    }
+
     /**
      * Delete操作
      * 如果该StoreObject定义了级联删除，则会执行级联删除操作
@@ -904,24 +1772,20 @@ impl DbStoreObject {
         jwt: &JwtUserClaims,
         qs: &QueryCondition,
     ) -> Result<Value, Error> {
+        let qs = &self.refine_condition(qs)?;
         let perm_sql = self.generate_permission_update_sql();
-        let (cond, cond_args) = qs.to_query(true)?;
-        let mut sql = format!(
-            "delete from {} where 1 = 1 ",
-            self.0.object_name
-        );
+        let (cond, cond_args) = qs.to_query(get_executor_driver_name(executor.as_ref()), true)?;
+        let (del_prefix, mut args) = self.to_delete_sql(&executor.rb, jwt);
+        let mut sql = format!("{} 1 = 1 ", del_prefix);
 
-        if qs.is_empty_condition() {
+        if !qs.is_empty_condition() {
             sql.push_str(" AND ");
             sql.push_str(&cond);
         }
 
         sql.push_str(&perm_sql.clone().unwrap_or_default());
 
-        let mut args = cond_args
-            .into_iter()
-            .map(|v| rbs::to_value!(v))
-            .collect_vec();
+        args.extend(cond_args.into_iter().map(|v| rbs::to_value!(v)));
 
         if perm_sql.is_some() {
             args.push(rbs::to_value!(jwt.userid.clone()));
@@ -935,6 +1799,9 @@ impl DbStoreObject {
 }
 
 impl DbCrud<Value> for DbStoreObject {
+    /**
+     * 新增记录，关联对象会被级联新增/更新，返回的对象中包含各层级所产生的主键及生成的值
+     */
     async fn insert(
         &self,
         executor: Arc<RBatisTxExecutor>,
@@ -942,27 +1809,14 @@ impl DbCrud<Value> for DbStoreObject {
         t: &Value,
     ) -> Result<Value, Error> {
         validate_object(t, &self.0, false)?;
+        validate_fields(t, &self.0)?;
+        self.check_insert_tenant(jwt, t)?;
 
+        let trail = CascadeTrail::new(self);
         let mt = if self.has_relationship() {
-            match insert_casc_1(
-                &self.0.clone(),
-                &self.1.clone(),
-                &self.2.clone(),
-                executor.clone(),
-                jwt,
-                t,
-                false,
-            )
-            .await
-            {
-                Ok(val) => {
-                    log::info!("Insert_casc_1: {:?}", val);
-                    copy_value_replaced(t, &val)
-                }
-                Err(err) => {
-                    return Err(err);
-                }
-            }
+            let val = self.insert_casc(executor.clone(), jwt, t, false, &trail).await?;
+            log::info!("Insert_casc: {:?}", val);
+            copy_value_replaced(t, &val)
         } else {
             t.clone()
         };
@@ -977,46 +1831,23 @@ impl DbCrud<Value> for DbStoreObject {
             insert_values
         );
 
-        match executor.exec(&sql, args).await {
+        match executor.exec(&sql, args.clone()).await {
             Ok(ts) => {
-                let mut smt = mt.clone();
-                self.0
-                    .fields
-                    .clone()
-                    .into_iter()
-                    .filter(|p| p.pkey)
-                    .for_each(|f| {
-                        if let Some(mxt) = smt.as_object_mut() {
-                            mxt.insert(
-                                f.prop_name.unwrap_or(f.field_name),
-                                json!(ts.last_insert_id),
-                            );
-                        }
-                    });
+                let smt = self.fill_generated_values(&mt, &insert_fields, &args, &ts.last_insert_id);
                 if self.has_relationship() {
-                    match insert_casc_1(
-                        &self.0.clone(),
-                        &self.1.clone(),
-                        &self.2.clone(),
-                        executor,
-                        jwt,
-                        &smt,
-                        true,
-                    )
-                    .await
-                    {
-                        Ok(_) => {}
-                        Err(err) => {
-                            return Err(err);
-                        }
-                    }
+                    let val = self.insert_casc(executor, jwt, &smt, true, &trail).await?;
+                    Ok(copy_value_replaced(&smt, &val))
+                } else {
+                    Ok(smt)
                 }
-                Ok(smt)
             }
             Err(err) => Err(anyhow::Error::new(err)),
         }
     }
 
+    /**
+     * 更新记录，关联对象会被级联新增/更新，主表内容没有变化时仍然会处理1..N的关联对象
+     */
     async fn update(
         &self,
         executor: Arc<RBatisTxExecutor>,
@@ -1025,27 +1856,15 @@ impl DbCrud<Value> for DbStoreObject {
     ) -> Result<Value, Error> {
         let perm_sql = self.generate_permission_update_sql();
 
-        validate_object(t, &self.0, true)?;
-
-        let mt = if self.has_relationship() {
-            match insert_casc_1(
-                &self.0.clone(),
-                &self.1.clone(),
-                &self.2.clone(),
-                executor.clone(),
-                jwt,
-                t,
-                false,
-            )
-            .await
-            {
-                Ok(val) => copy_value_replaced(t, &val),
-                Err(err) => {
-                    return Err(err);
-                }
-            }
+        validate_object(t, &self.0, true)?;
+        validate_fields(t, &self.0)?;
+
+        let trail = CascadeTrail::new(self);
+        let (mt, pre) = if self.has_relationship() {
+            let val = self.insert_casc(executor.clone(), jwt, t, false, &trail).await?;
+            (copy_value_replaced(t, &val), val)
         } else {
-            t.clone()
+            (t.clone(), json!({}))
         };
 
         let mt = if self.has_desensitize() {
@@ -1071,42 +1890,45 @@ impl DbCrud<Value> for DbStoreObject {
 
         let (mut args, update_fields, update_keys) = self.to_update_rbs_value_vec(&executor.rb, jwt,&mt, true);
         if update_fields.is_empty() {
+            if self.has_relationship() {
+                let val = self.insert_casc(executor, jwt, t, true, &trail).await?;
+                return Ok(copy_value_replaced(&copy_value_replaced(t, &pre), &val));
+            }
             return Ok(mt);
         }
+        // 脱敏比较后mt中可能不再包含版本号，因此使用原始的t作为乐观锁的条件
+        let version = self.to_version_condition(t)?;
         let sql = format!(
-            "update {} set {} where {} {}",
+            "update {} set {} where {}{} {}{}",
             self.0.object_name.clone(),
             update_fields,
             update_keys,
-            perm_sql.clone().unwrap_or_default()
+            version.clone().map(|(c, _)| c).unwrap_or_default(),
+            perm_sql.clone().unwrap_or_default(),
+            self.tenant_condition("", jwt)
         );
 
+        if let Some((_, v)) = version.clone() {
+            args.push(v);
+        }
+
         if perm_sql.is_some() {
             args.push(rbs::to_value!(jwt.userid.clone()));
         }
+        args.extend(self.tenant_args(jwt));
 
         match executor.exec(&sql, args).await {
             Ok(rr) => {
                 if rr.rows_affected > 0 {
+                    let ret = copy_value_replaced(&self.bump_version(t), &pre);
                     if self.has_relationship() {
-                        match insert_casc_1(
-                            &self.0.clone(),
-                            &self.1.clone(),
-                            &self.2.clone(),
-                            executor,
-                            jwt,
-                            t,
-                            true,
-                        )
-                        .await
-                        {
-                            Ok(_) => {}
-                            Err(err) => {
-                                return Err(err);
-                            }
-                        }
+                        let val = self.insert_casc(executor, jwt, t, true, &trail).await?;
+                        Ok(copy_value_replaced(&ret, &val))
+                    } else {
+                        Ok(ret)
                     }
-                    Ok(t.clone())
+                } else if version.is_some() {
+                    Err(anyhow!(StaleRecordError::new(&self.0.name, self.get_pkey_value_present(t))))
                 } else {
                     Err(anyhow!("Nothing to updated"))
                 }
@@ -1125,99 +1947,19 @@ impl DbCrud<Value> for DbStoreObject {
         val: &Value,
         qs: Option<QueryCondition>,
     ) -> Result<Value, Error> {
-        validate_object(val, &self.0, false)?;
-        let qx = qs.unwrap_or_else(|| {
-            let mut m = QueryCondition::default();
-            for k in self.0.get_key_columns().iter().map(|f| f.field_name.clone()).collect_vec() {
-                let ci = ConditionItem {
-                    field: k.clone(),
-                    op: "=".to_owned(),
-                    value: self.get_field_value(&k, val),
-                    ..Default::default()
-                };
-                m.and.push(ci);
-            }
-            m
-        });
-
-        log::info!("QS: {:?}", qx);
-
-        // let perm_sql = self.generate_permission_sql();
-        let mut sql = self.to_select_sql(false, false, None);
-        let (cond_sql, cond_args) = qx.to_query(true)?;
-        if qx.is_empty_condition() {
-            sql.push_str(&cond_sql);
-        } else {
-            sql.push_str(" AND ");
-            sql.push_str(&cond_sql);
-        }
-
-
-        let args = cond_args
-            .into_iter()
-            .map(|v| rbs::to_value!(v))
-            .collect_vec();
-
-        let rs = match executor.query(&sql, args).await {
-            Ok(rs) => rs,
-            Err(err) => {
-                log::info!("test error : {:?}", err);
-                return Err(anyhow::Error::new(err));
-            }
-        };
-
-        match decode_vec_custom_fields_list(
-            executor.clone(),
-            jwt,
-            &self.1,
-            rs,
-            &self.0.fields,
-            &self.1.namespace,
-        )
-        .await
-        {
-            Ok(vrs) => {
-                if vrs.is_empty() {
-                    // do insert.
-                    self.insert(executor, jwt, val).await
-                } else if vrs.len() == 1 {
-                    // do update
-                    let new_val = copy_value_excluded(val, &self.0.get_key_columns().iter().map(|f| f.field_name.clone()).collect_vec());
-                    let upd_val = copy_value_replaced(&vrs[0], &new_val);
-                    self.update(executor, jwt, &upd_val).await
-                } else {
-                    // report an error
-                    Err(anyhow!("Upsert could not be executed when there are many records by this condition."))
-                }
-            }
-            Err(err) => Err(anyhow!(err)),
-        }
+        self.upsert_detail(executor, jwt, val, qs).await.map(|(v, _)| v)
     }
 
-
+    /**
+     * 批量保存，返回新增、更新以及失败的记录，参见save_batch_detail
+     */
     async fn save_batch(
         &self,
         executor: Arc<RBatisTxExecutor>,
         jwt: &JwtUserClaims,
         val: Vec<Value>
     ) -> Result<Value, Error> {
-        let mut it: i64 = 0;
-        for ic in val {
-            let qst = if let Some(qsval) = ic.get("_cond") {
-                if let Ok(qs) = serde_json::from_value::<QueryCondition>(qsval.to_owned()) {
-                    Some(qs)
-                } else {
-                    None
-                }
-            } else {
-                None
-            };
-
-            self.upsert(executor.clone(), jwt, &ic, qst).await?;
-            it += 1;
-        }
-
-        Ok(json!({"affect_rows": it}))
+        Ok(self.save_batch_detail(executor, jwt, val).await?.to_value())
     }
 
     async fn delete(
@@ -1229,28 +1971,24 @@ impl DbCrud<Value> for DbStoreObject {
         validate_object(t, &self.0, true)?;
 
         let perm_sql = self.generate_permission_update_sql();
-        let keycond = self
-            .0
-            .get_key_columns()
-            .clone()
-            .into_iter()
-            .map(|f| format!(" {} = ? ", f.field_name))
-            .join(" AND ");
+        let (del_prefix, mut args) = self.to_delete_sql(&executor.rb, jwt);
         let del_sql = format!(
-            "delete from {} where {} {}",
-            self.0.object_name.clone(),
-            keycond,
+            "{}{} {}",
+            del_prefix,
+            self.to_key_condition(),
             perm_sql.clone().unwrap_or_default()
         );
 
-        if self.has_relationship() {
-            let exec: Arc<dyn Executor> = Arc::new(executor.rb.clone());
+        // 软删除时不级联删除关联对象，以便restore时完整恢复，purge时再级联删除
+        if self.has_relationship() && self.soft_delete_flag().is_none() {
+            // 在同一个事务中读取并级联删除各层级的关联对象
+            let exec: Arc<dyn Executor> = executor.clone();
             if let Ok(Some(tv)) = self.select(exec, jwt, t).await {
-                self.delete_casc(executor.clone(), jwt, tv).await?;
+                self.delete_casc(executor.clone(), jwt, tv, &CascadeTrail::new(self)).await?;
             }
         }
 
-        let mut args = self.get_keys_values(t);
+        args.append(&mut self.get_keys_values(t));
 
         if perm_sql.is_some() {
             args.push(rbs::to_value!(jwt.userid.clone())); // should guess the field type of userid
@@ -1262,6 +2000,113 @@ impl DbCrud<Value> for DbStoreObject {
         }
     }
 
+    /**
+     * 恢复已软删除的记录，同时清空deleted_at/deleted_by字段
+     */
+    async fn restore(
+        &self,
+        executor: Arc<RBatisTxExecutor>,
+        jwt: &JwtUserClaims,
+        t: &Value,
+    ) -> Result<Value, Error> {
+        validate_object(t, &self.0, true)?;
+        let (flag, deleted, normal) = match self.soft_delete_flag() {
+            Some(f) => f,
+            None => return Err(anyhow!("Soft delete is not enabled for {}", self.0.name)),
+        };
+
+        let perm_sql = self.generate_permission_update_sql();
+        let mut sets = vec![format!("{flag} = {normal}")];
+        for col in self.0.fields.iter().filter(|c| {
+            matches!(c.generator.as_deref(), Some("deleted_at") | Some("deleted_by"))
+        }) {
+            sets.push(format!("{} = null", col.field_name));
+        }
+        let sql = format!(
+            "update {} set {} where {} and {} = {} {}{}",
+            self.0.object_name.clone(),
+            sets.join(","),
+            self.to_key_condition(),
+            flag,
+            deleted,
+            perm_sql.clone().unwrap_or_default(),
+            self.tenant_condition("", jwt)
+        );
+
+        let mut args = self.get_keys_values(t);
+        if perm_sql.is_some() {
+            args.push(rbs::to_value!(jwt.userid.clone()));
+        }
+        args.extend(self.tenant_args(jwt));
+
+        match executor.exec(&sql, args).await {
+            Ok(rr) if rr.rows_affected == 0 => Err(anyhow!("Nothing to restore")),
+            Ok(_) => Ok(t.clone()),
+            Err(err) => Err(anyhow::Error::new(err)),
+        }
+    }
+
+    /**
+     * 物理删除已软删除的记录，未被软删除的记录不会被删除
+     * 软删除时保留的关联对象在此时级联删除，附属表启用软删除时按其自身的配置标记删除
+     */
+    async fn purge(
+        &self,
+        executor: Arc<RBatisTxExecutor>,
+        jwt: &JwtUserClaims,
+        t: &Value,
+    ) -> Result<Value, Error> {
+        validate_object(t, &self.0, true)?;
+        let (flag, deleted, _) = match self.soft_delete_flag() {
+            Some(f) => f,
+            None => return Err(anyhow!("Soft delete is not enabled for {}", self.0.name)),
+        };
+
+        let perm_sql = self.generate_permission_update_sql();
+        let sql = format!(
+            "delete from {} where {} and {} = {} {}{}",
+            self.0.object_name.clone(),
+            self.to_key_condition(),
+            flag,
+            deleted,
+            perm_sql.clone().unwrap_or_default(),
+            self.tenant_condition("", jwt)
+        );
+
+        let mut args = self.get_keys_values(t);
+        if perm_sql.is_some() {
+            args.push(rbs::to_value!(jwt.userid.clone()));
+        }
+        args.extend(self.tenant_args(jwt));
+
+        if self.has_relationship() {
+            // 只有记录已被软删除时才级联删除其关联对象
+            let check_sql = format!(
+                "select count(1) from {} where {} and {} = {} {}",
+                self.0.object_name.clone(),
+                self.to_key_condition(),
+                flag,
+                deleted,
+                self.tenant_condition("", jwt)
+            );
+            let mut check_args = self.get_keys_values(t);
+            check_args.extend(self.tenant_args(jwt));
+            let deleted_count = decode_count_result(executor.query(&check_sql, check_args).await?);
+            let exec: Arc<dyn Executor> = executor.clone();
+            if deleted_count > 0 {
+                if let Some(row) = self.select_raw(exec, jwt, t).await? {
+                    self.delete_casc(executor.clone(), jwt, row, &CascadeTrail::new(self)).await?;
+                }
+            }
+        }
+
+        match executor.exec(&sql, args).await {
+            Ok(rr) if rr.rows_affected == 0 => Err(anyhow!("Nothing to purge")),
+            Ok(_) => Ok(t.clone()),
+            Err(err) => Err(anyhow::Error::new(err)),
+        }
+    }
+
     async fn select(
         &self,
         rb: Arc<dyn Executor>,
@@ -1270,7 +2115,8 @@ impl DbCrud<Value> for DbStoreObject {
     ) -> Result<Option<Value>, Error> {
         let perm_sql = self.generate_permission_sql();
         let mut args = self.get_keys_values(t);
-        let sql = self.to_select_sql(true, true, perm_sql.clone());
+        args.extend(self.tenant_args(jwt));
+        let sql = self.to_select_sql(jwt, true, true, perm_sql.clone(), None);
         log::info!("Select Query: {}", sql.clone());
 
         if let Some(t) = perm_sql {
@@ -1314,9 +2160,10 @@ impl DbCrud<Value> for DbStoreObject {
         jwt: &JwtUserClaims,
         qs: &QueryCondition,
     ) -> Result<Option<Value>, Error> {
+        let qs = &self.refine_query_condition(jwt, qs)?;
         let perm_sql = self.generate_permission_sql();
-        let mut sql = self.to_select_sql(false, true, perm_sql.clone());
-        let (cond_sql, cond_args) = qs.to_query(false)?;
+        let mut sql = self.to_select_sql(jwt, false, true, perm_sql.clone(), Some(qs));
+        let (cond_sql, cond_args) = qs.to_query(get_executor_driver_name(rb.as_ref()), false)?;
 
         if qs.is_empty_condition() {
             sql.push_str(&cond_sql);
@@ -1325,9 +2172,10 @@ impl DbCrud<Value> for DbStoreObject {
             sql.push_str(&cond_sql);
         }
 
-        let mut args = cond_args
+        let mut args = self
+            .tenant_args(jwt)
             .into_iter()
-            .map(|v| rbs::to_value!(v))
+            .chain(cond_args.into_iter().map(|v| rbs::to_value!(v)))
             .collect_vec();
 
         if let Some(t) = perm_sql {
@@ -1344,7 +2192,7 @@ impl DbCrud<Value> for DbStoreObject {
                     jwt,
                     &self.1,
                     rs,
-                    &self.0.fields,
+                    &self.projected_columns(qs),
                     &self.1.namespace,
                 )
                 .await
@@ -1372,9 +2220,10 @@ impl DbCrud<Value> for DbStoreObject {
         jwt: &JwtUserClaims,
         qs: &QueryCondition,
     ) -> Result<Vec<Value>, Error> {
+        let qs = &self.refine_query_condition(jwt, qs)?;
         let perm_sql = self.generate_permission_sql();
-        let mut sql = self.to_select_sql(false, false, perm_sql.clone());
-        let (cond_sql, cond_args) = qs.to_query(false)?;
+        let mut sql = self.to_select_sql(jwt, false, false, perm_sql.clone(), Some(qs));
+        let (cond_sql, cond_args) = qs.to_query(get_executor_driver_name(rb.as_ref()), false)?;
 
         if qs.is_empty_condition() {
             sql.push_str(&cond_sql);
@@ -1383,9 +2232,10 @@ impl DbCrud<Value> for DbStoreObject {
             sql.push_str(&cond_sql);
         }        
 
-        let mut args = cond_args
+        let mut args = self
+            .tenant_args(jwt)
             .into_iter()
-            .map(|v| rbs::to_value!(v))
+            .chain(cond_args.into_iter().map(|v| rbs::to_value!(v)))
             .collect_vec();
 
         if let Some(t) = perm_sql {
@@ -1402,7 +2252,7 @@ impl DbCrud<Value> for DbStoreObject {
                     jwt,
                     &self.1,
                     rs,
-                    &self.0.fields,
+                    &self.projected_columns(qs),
                     &self.1.namespace,
                 )
                 .await
@@ -1424,8 +2274,9 @@ impl DbCrud<Value> for DbStoreObject {
         jwt: &JwtUserClaims,
         qs: &QueryCondition,
     ) -> Result<Page<Value>, Error> {
+        let qs = &self.refine_query_condition(jwt, qs)?;
         let perm_sql = self.generate_permission_sql();
-        let mut sql = self.to_select_sql(false, false, perm_sql.clone());
+        let mut sql = self.to_select_sql(jwt, false, false, perm_sql.clone(), Some(qs));
         let pagereq = match qs.to_page_request() {
             Some(p) => p,
             None => {
@@ -1434,11 +2285,13 @@ impl DbCrud<Value> for DbStoreObject {
         };
 
         let mut count_sql = format!(
-            "select count(1) from {} where 1 = 1 ",
-            self.0.object_name.clone()
+            "select count(1) from {} _tbl where 1 = 1 {}{}",
+            self.0.object_name.clone(),
+            self.soft_delete_condition("_tbl.", Some(qs)),
+            self.tenant_condition("_tbl.", jwt)
         );
 
-        let (cond_sql, cond_args) = qs.to_query(false)?;
+        let (cond_sql, cond_args) = qs.to_query(get_executor_driver_name(rb.as_ref()), false)?;
 
 
         if qs.is_empty_condition() {
@@ -1448,14 +2301,7 @@ impl DbCrud<Value> for DbStoreObject {
             sql.push_str(&cond_sql);
         }
 
-        sql.push_str(
-            format!(
-                " limit {} offset {} ",
-                pagereq.page_size(),
-                pagereq.offset()
-            )
-            .as_str(),
-        );
+        let sql = get_executor_dialect(rb.as_ref()).paginate(&sql, pagereq.page_size(), pagereq.offset());
 
         if qs.is_empty_condition() {
             count_sql.push_str(&cond_sql);
@@ -1464,10 +2310,13 @@ impl DbCrud<Value> for DbStoreObject {
             count_sql.push_str(&cond_sql);
         }
 
-        let mut args = cond_args
+        let mut args = self
+            .tenant_args(jwt)
             .into_iter()
-            .map(|v| rbs::to_value!(v))
+            .chain(cond_args.into_iter().map(|v| rbs::to_value!(v)))
             .collect_vec();
+        // 统计总数的语句中没有数据权限的join，不需要其参数
+        let count_args = args.clone();
 
         if let Some(t) = perm_sql {
             if !t.is_empty() {
@@ -1477,7 +2326,7 @@ impl DbCrud<Value> for DbStoreObject {
 
         log::info!("Query: {}", sql.clone());
 
-        let total = match rb.query(&count_sql, args.clone()).await {
+        let total = match rb.query(&count_sql, count_args).await {
             Ok(rs) => {
                 match rbatis::decode::<Value>(rs) {
                     Ok(rts) => {
@@ -1519,7 +2368,7 @@ impl DbCrud<Value> for DbStoreObject {
                     jwt,
                     &self.1,
                     rs,
-                    &self.0.fields,
+                    &self.projected_columns(qs),
                     &self.1.namespace,
                 )
                 .await
@@ -1538,6 +2387,179 @@ impl DbCrud<Value> for DbStoreObject {
         }
     }
 
+    /**
+     * 游标（keyset）分页查询
+     * 使用排序字段及主键作为游标，不使用offset，返回的游标用于查询下一页，没有下一页时为None
+     */
+    async fn cursor_query(
+        &self,
+        rb: Arc<dyn Executor>,
+        jwt: &JwtUserClaims,
+        qs: &QueryCondition,
+    ) -> Result<(Page<Value>, Option<String>), Error> {
+        let pkeys = self
+            .0
+            .get_key_columns()
+            .into_iter()
+            .map(|f| f.field_name)
+            .collect_vec();
+        let qs = &self.refine_query_condition(jwt, qs)?.with_cursor_keys(&pkeys)?;
+        let paging = qs.paging.clone().unwrap_or_default();
+        let size = paging.size.max(1);
+        let driver = get_executor_driver_name(rb.as_ref());
+        let perm_sql = self.generate_permission_sql();
+        let mut sql = self.to_select_sql(jwt, false, false, perm_sql.clone(), Some(qs));
+
+        let (cond_sql, cond_args) = qs.to_cursor_query(driver)?;
+        sql.push_str(" AND ");
+        sql.push_str(&cond_sql);
+        let sql = get_dialect(driver).paginate(&sql, size + 1, 0);
+
+        let mut args = self
+            .tenant_args(jwt)
+            .into_iter()
+            .chain(cond_args.into_iter().map(|v| rbs::to_value!(v)))
+            .collect_vec();
+
+        if let Some(t) = perm_sql {
+            if !t.is_empty() {
+                args.insert(0, rbs::to_value!(jwt.userid.clone()));
+            }
+        }
+
+        let total = if paging.skip_count {
+            0u64
+        } else {
+            let mut count_sql = format!(
+                "select count(1) from {} _tbl where 1 = 1 {}{}",
+                self.0.object_name.clone(),
+                self.soft_delete_condition("_tbl.", Some(qs)),
+                self.tenant_condition("_tbl.", jwt)
+            );
+            let (count_cond, count_args) = qs.to_query(driver, true)?;
+            if !qs.is_empty_condition() {
+                count_sql.push_str(" AND ");
+                count_sql.push_str(&count_cond);
+            }
+            let count_args = self
+                .tenant_args(jwt)
+                .into_iter()
+                .chain(count_args.into_iter().map(|v| rbs::to_value!(v)))
+                .collect_vec();
+            match rb.query(&count_sql, count_args).await {
+                Ok(rs) => decode_count_result(rs),
+                Err(err) => {
+                    log::info!("Error : {}", err);
+                    0u64
+                }
+            }
+        };
+
+        log::info!("Query: {}", sql.clone());
+
+        match rb.query(&sql, args).await {
+            Ok(rs) => {
+                let (rs, next_cursor) = split_cursor_page(rs, size, qs, |f| {
+                    self.0
+                        .fields
+                        .iter()
+                        .find(|c| c.field_name == f)
+                        .and_then(|c| c.prop_name.clone())
+                });
+                let mp = decode_vec_custom_fields_list(
+                    rb,
+                    jwt,
+                    &self.1,
+                    rs,
+                    &self.projected_columns(qs),
+                    &self.1.namespace,
+                )
+                .await?;
+                let mut page = Page::new_total(1, size, total).set_records(mp);
+                page.do_count = !paging.skip_count;
+                Ok((page, next_cursor))
+            }
+            Err(err) => {
+                log::info!("test error : {:?}", err);
+                Err(anyhow::Error::new(err))
+            }
+        }
+    }
+
+    /**
+     * 聚合查询，按group_by分组，返回以分组字段的属性名及聚合别名为键的记录
+     * 只允许对StoreObject中定义的字段（非relation）进行聚合
+     */
+    async fn aggregate(
+        &self,
+        rb: Arc<dyn Executor>,
+        jwt: &JwtUserClaims,
+        qs: &QueryCondition,
+    ) -> Result<Vec<Value>, Error> {
+        let perm_sql = self.generate_permission_sql();
+        // 条件、having及排序由to_aggregate_query统一校验，这里只处理include_deleted
+        let qs = &QueryCondition {
+            include_deleted: qs.include_deleted && jwt.superadmin,
+            ..qs.clone()
+        }
+        .mark_json_fields(|f| self.0.is_json_field(f));
+        let soft_delete = format!("{}{}", self.soft_delete_condition("", Some(qs)), self.tenant_condition("", jwt));
+        let from_sql = if soft_delete.is_empty() {
+            format!(
+                "from {} _tbl {}",
+                self.0.object_name.clone(),
+                perm_sql.clone().unwrap_or_default()
+            )
+        } else {
+            format!(
+                "from (select * from {} where 1 = 1 {}) _tbl {}",
+                self.0.object_name.clone(),
+                soft_delete,
+                perm_sql.clone().unwrap_or_default()
+            )
+        };
+        let (sql, cond_args) = qs.to_aggregate_query(
+            get_executor_driver_name(rb.as_ref()),
+            &from_sql,
+            |f| {
+                self.0
+                    .fields
+                    .iter()
+                    .filter(|c| c.col_type != Some("relation".to_owned()))
+                    .find(|c| c.field_name == f || c.prop_name.as_deref() == Some(f))
+                    .map(|c| {
+                        (
+                            c.field_name.clone(),
+                            c.prop_name.clone().unwrap_or(c.field_name.clone()),
+                        )
+                    })
+            },
+        )?;
+
+        let mut args = cond_args
+            .into_iter()
+            .map(|v| rbs::to_value!(v))
+            .collect_vec();
+
+        if let Some(t) = perm_sql {
+            if !t.is_empty() {
+                args.insert(0, rbs::to_value!(jwt.userid.clone()));
+            }
+        }
+        // 租户条件位于数据权限的join之前的子查询中
+        args.splice(0..0, self.tenant_args(jwt));
+
+        log::info!("Aggregate: {}", sql.clone());
+
+        match rb.query(&sql, args).await {
+            Ok(rs) => rbatis::decode::<Vec<Value>>(rs).map_err(|err| anyhow!(err)),
+            Err(err) => {
+                log::info!("test error : {:?}", err);
+                Err(anyhow::Error::new(err))
+            }
+        }
+    }
+
     /**
      * Delete操作
      * 如果该StoreObject定义了级联删除，则会执行级联删除操作
@@ -1549,12 +2571,11 @@ impl DbCrud<Value> for DbStoreObject {
         jwt: &JwtUserClaims,
         qs: &QueryCondition,
     ) -> Result<Value, Error> {
+        let qs = &self.refine_condition(qs)?;
         let perm_sql = self.generate_permission_update_sql();
-        let (cond, cond_args) = qs.to_query(true)?;
-        let mut sql = format!(
-            "delete from {} where 1 = 1 ",
-            self.0.object_name
-        );
+        let (cond, cond_args) = qs.to_query(get_executor_driver_name(executor.as_ref()), true)?;
+        let (del_prefix, mut args) = self.to_delete_sql(&executor.rb, jwt);
+        let mut sql = format!("{} 1 = 1 ", del_prefix);
 
         if !qs.is_empty_condition() {
             sql.push_str(" AND ");
@@ -1563,22 +2584,20 @@ impl DbCrud<Value> for DbStoreObject {
 
         sql.push_str(&perm_sql.clone().unwrap_or_default());
 
-        let mut args = cond_args
-            .into_iter()
-            .map(|v| rbs::to_value!(v))
-            .collect_vec();
+        args.extend(cond_args.into_iter().map(|v| rbs::to_value!(v)));
 
         if perm_sql.is_some() {
             args.push(rbs::to_value!(jwt.userid.clone()));
         }
 
-        if self.has_relationship() {
-            let rb = executor.rb.clone();
-            let arcxe: Arc<dyn Executor> = Arc::new(rb);
+        // 软删除时不级联删除关联对象，以便restore时完整恢复，purge时再级联删除
+        if self.has_relationship() && self.soft_delete_flag().is_none() {
+            let trail = CascadeTrail::new(self);
+            let arcxe: Arc<dyn Executor> = executor.clone();
             match self.query(arcxe, jwt, qs).await {
                 Ok(rs) => {
                     for ro in rs {
-                        self.delete_casc(executor.clone(), jwt, ro).await?
+                        self.delete_casc(executor.clone(), jwt, ro, &trail).await?
                     }
                 }
                 Err(err) => {
@@ -1603,7 +2622,9 @@ impl DbCrud<Value> for DbStoreObject {
         val: &Value,
         qs: &QueryCondition,
     ) -> Result<Value, Error> {
+        let qs = &self.refine_condition(qs)?;
         validate_object(val, &self.0, false)?;
+        validate_fields(val, &self.0)?;
 
         let perm_sql = self.generate_permission_update_sql();
 
@@ -1611,16 +2632,19 @@ impl DbCrud<Value> for DbStoreObject {
             return Err(anyhow!("No condition provided."));
         }
 
-        let (cond, cond_args) = qs.to_query(true)?;
+        let (cond, cond_args) = qs.to_query(get_executor_driver_name(executor.as_ref()), true)?;
 
 
         let (mut args, update_fields, _) = self.to_update_rbs_value_vec(&executor.rb, jwt, val, false);
+        let version = self.to_version_condition(val)?;
         let sql = format!(
-            "update {} set {} where {} {}",
+            "update {} set {} where {}{} {}{}",
             self.0.object_name.clone(),
             update_fields,
             cond,
-            perm_sql.clone().unwrap_or_default()
+            version.clone().map(|(c, _)| c).unwrap_or_default(),
+            perm_sql.clone().unwrap_or_default(),
+            self.tenant_condition("", jwt)
         );
 
         let mut c_args = cond_args
@@ -1630,11 +2654,19 @@ impl DbCrud<Value> for DbStoreObject {
 
         args.append(&mut c_args);
 
+        if let Some((_, v)) = version.clone() {
+            args.push(v);
+        }
+
         if perm_sql.is_some() {
             args.push(rbs::to_value!(jwt.userid.clone()));
         }
+        args.extend(self.tenant_args(jwt));
 
         match executor.exec(&sql, args).await {
+            Ok(rs) if rs.rows_affected == 0 && version.is_some() => {
+                Err(anyhow!(StaleRecordError::new(&self.0.name, None)))
+            }
             Ok(rs) => Ok(json!({"rows_affected": rs.rows_affected})),
             Err(err) => Err(anyhow::Error::new(err)),
         }
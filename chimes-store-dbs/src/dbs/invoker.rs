@@ -2,14 +2,19 @@ use anyhow::{anyhow, Error};
 use chimes_store_core::config::auth::{AuthorizationConfig, JwtUserClaims};
 use chimes_store_core::config::{QueryCondition, QueryObject};
 use chimes_store_core::service::starter::MxStoreService;
-use chimes_store_core::utils::redis::{redis_del, redis_delexp_cmd, redis_get, redis_set_expire};
+use chimes_store_core::utils::cache::{
+    cache_lookup, cache_set_policy,
+    object_cache_tags, query_cache_tags, CacheLookup, CachePolicy, CacheRefresh,
+};
 use rbatis::executor::Executor;
 use core::future::Future;
 use rbatis::Page;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::pin::Pin;
 use std::sync::{Arc, Mutex};
 
+use super::audit::AuditInfo;
 use super::crud::DbCrud;
 use super::crud::DbStoreObject;
 use super::query::DbQueryObject;
@@ -21,11 +26,14 @@ pub struct DbStoreServiceInvocation();
 
 impl DbStoreServiceInvocation {
     fn convert_to_cache_id(&self, uri: &InvokeUri, jwt: &JwtUserClaims, args: &[Value]) -> String {
+        // 跨租户访问与本租户内访问的结果不同，不能共享缓存
         let id_body = format!(
-            "{}#{}#{}",
+            "{}#{}#{}#{}#{}",
             uri.url_no_method(),
+            jwt.domain,
             jwt.username,
-            serde_json::to_string(args).unwrap_or_default()
+            jwt.is_cross_tenant(),
+            cache_args_body(args)
         );
         let hash = md5::compute(id_body);
         let hex_hash = format!("{:x}", hash);
@@ -39,38 +47,233 @@ impl DbStoreServiceInvocation {
         jwt: &JwtUserClaims,
         keyid: &str,
     ) -> String {
-        let id_body = format!("{}#{}#{}", uri.url_no_method(), jwt.username, keyid);
+        let id_body = format!(
+            "{}#{}#{}#{}#{}",
+            uri.url_no_method(),
+            jwt.domain,
+            jwt.username,
+            jwt.is_cross_tenant(),
+            keyid
+        );
         let hash = md5::compute(id_body);
         // let hash = sha2::Sha256::digest(id_body.as_bytes());
         let hex_hash = format!("{:x}", hash);
         format!("{}-{}-{}", uri.object.clone(), method, hex_hash)
     }
+}
 
-    fn get_cache_id_prefix(
-        &self,
-        uri: &InvokeUri,
-        _jwt: &JwtUserClaims,
-        _args: &[Value],
-    ) -> Vec<String> {
-        vec![
-            format!("{}-query-", uri.object.clone()),
-            format!("{}-paged_query-", uri.object.clone()),
-        ]
+/**
+ * 生成缓存键中的参数部分，fields/exclude_fields转换为规范化的投影描述
+ * 使得投影相同但字段顺序不同的请求共享缓存，而不同投影的请求不会相互命中
+ */
+fn cache_args_body(args: &[Value]) -> String {
+    args.iter()
+        .map(|arg| match arg {
+            Value::Object(mp) if mp.contains_key("fields") || mp.contains_key("exclude_fields") => {
+                match serde_json::from_value::<QueryCondition>(arg.clone()) {
+                    Ok(qs) => {
+                        let mut rest = mp.clone();
+                        rest.remove("fields");
+                        rest.remove("exclude_fields");
+                        format!(
+                            "{}#{}",
+                            serde_json::to_string(&rest).unwrap_or_default(),
+                            qs.projection_key()
+                        )
+                    }
+                    Err(_) => serde_json::to_string(arg).unwrap_or_default(),
+                }
+            }
+            _ => serde_json::to_string(arg).unwrap_or_default(),
+        })
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/**
+ * 根据查询条件选择offset分页或游标分页，游标分页返回的next_cursor保存在InvocationContext中
+ */
+async fn paged_or_cursor_query(
+    dbs: &DbStoreObject,
+    conn: Arc<dyn Executor>,
+    jwt: &JwtUserClaims,
+    qs: &QueryCondition,
+    ctx: &Arc<Mutex<InvocationContext>>,
+) -> Result<Page<Value>, Error> {
+    if qs.is_cursor_paging() {
+        let (page, next_cursor) = dbs.cursor_query(conn, jwt, qs).await?;
+        set_next_cursor(ctx, next_cursor);
+        Ok(page)
+    } else {
+        dbs.paged_query(conn, jwt, qs).await
     }
+}
 
-    fn cache_remove(&self, ns: &str, prefix: &[String], ids: &[String]) {
-        for pref in prefix {
-            if let Err(err) = redis_delexp_cmd(ns, pref) {
-                log::debug!("error for del {} in redis {}", pref, err);
-            }
+/**
+ * query与aggregate共用相同的hooks、缓存以及返回值的处理
+ */
+async fn query_or_aggregate(
+    dbs: &DbStoreObject,
+    conn: Arc<dyn Executor>,
+    jwt: &JwtUserClaims,
+    qs: &QueryCondition,
+    aggregate: bool,
+) -> Result<Vec<Value>, Error> {
+    if aggregate {
+        dbs.aggregate(conn, jwt, qs).await
+    } else {
+        dbs.query(conn, jwt, qs).await
+    }
+}
+
+/**
+ * 游标分页的下一页游标，以NEXT_CURSOR保存在InvocationContext中，没有下一页时移除
+ */
+fn set_next_cursor(ctx: &Arc<Mutex<InvocationContext>>, next_cursor: Option<String>) {
+    let mut ctx = ctx.lock().unwrap();
+    match next_cursor {
+        Some(cursor) => {
+            ctx.insert("NEXT_CURSOR", cursor);
+        }
+        None => {
+            ctx.delete("NEXT_CURSOR");
         }
+    }
+}
 
-        for pref in ids {
-            if let Err(err) = redis_del(ns, pref) {
-                log::debug!("error for del {} in redis {}", pref, err);
-            }
+/**
+ * 游标分页的缓存内容，next_cursor与分页结果放在同一个缓存项中，两者同时过期或失效
+ */
+#[derive(Serialize, Deserialize)]
+struct CursorPageCache {
+    page: Page<Value>,
+    next_cursor: Option<String>,
+}
+
+/**
+ * 分页结果转换为缓存的文本，游标分页时连同next_cursor一起缓存
+ */
+fn encode_page_cache(page: &Page<Value>, cursor_mode: bool, next_cursor: Option<String>) -> serde_json::Result<String> {
+    if cursor_mode {
+        serde_json::to_string(&CursorPageCache {
+            page: page.clone(),
+            next_cursor,
+        })
+    } else {
+        serde_json::to_string(page)
+    }
+}
+
+/**
+ * 从缓存的文本中还原分页结果，游标分页时同时将next_cursor恢复到InvocationContext中
+ */
+fn decode_page_cache(text: &str, cursor_mode: bool, ctx: &Arc<Mutex<InvocationContext>>) -> Option<Page<Value>> {
+    if cursor_mode {
+        let cached = serde_json::from_str::<CursorPageCache>(text).ok()?;
+        set_next_cursor(ctx, cached.next_cursor);
+        Some(cached.page)
+    } else {
+        serde_json::from_str::<Page<Value>>(text).ok()
+    }
+}
+
+enum CacheRefreshKind {
+    One,
+    Vec,
+    Page,
+}
+
+/**
+ * 是否读写缓存，该命名空间已经开启事务（如/api/execute/batch）时不使用缓存
+ * 事务中读取到的数据尚未提交，可能随后被回滚，不能放入所有用户共享的缓存
+ */
+fn cache_enabled(enable: bool, ns: &str, ctx: &Arc<Mutex<InvocationContext>>) -> bool {
+    enable && ctx.lock().unwrap().get_tx_executor_sync(ns).is_none()
+}
+
+/**
+ * 读取缓存，未启用缓存、本次调用为后台刷新或者要求读主库时不读取
+ * 要求读主库的调用需要读到刚写入的数据，缓存中可能是写入前的旧值，因此直接查询，查询结果仍然写入缓存
+ */
+async fn lookup_cache(
+    enable: bool,
+    ns: &str,
+    cache_id: &str,
+    ctx: &Arc<Mutex<InvocationContext>>,
+) -> CacheLookup {
+    let (refreshing, primary) = {
+        let ctx = ctx.lock().unwrap();
+        (ctx.is_cache_refresh(), ctx.get_bool("READ_PRIMARY"))
+    };
+    if !enable || refreshing || primary {
+        return CacheLookup::default();
+    }
+    cache_lookup(ns, cache_id).await
+}
+
+/**
+ * 缓存已过新鲜期时，在后台以相同的用户及参数重新调用，重新加载后写入缓存
+ * 后台调用使用由当前InvocationContext派生的新上下文，保留角色、跨租户等要求，但不参与当前请求的事务
+ */
+fn spawn_cache_refresh(
+    uri: String,
+    ctx: &Arc<Mutex<InvocationContext>>,
+    args: Vec<Value>,
+    kind: CacheRefreshKind,
+    refresh: CacheRefresh,
+) {
+    let mut ctx = ctx.lock().unwrap().fork();
+    tokio::spawn(async move {
+        ctx.set_cache_refresh(true);
+        let ctx = Arc::new(Mutex::new(ctx));
+        let ret = match kind {
+            CacheRefreshKind::One => MxStoreService::invoke_return_one(uri.clone(), ctx, args)
+                .await
+                .map(|_| ()),
+            CacheRefreshKind::Vec => MxStoreService::invoke_return_vec(uri.clone(), ctx, args)
+                .await
+                .map(|_| ()),
+            CacheRefreshKind::Page => MxStoreService::invoke_return_page(uri.clone(), ctx, args)
+                .await
+                .map(|_| ()),
+        };
+        if let Err(err) = ret {
+            log::info!("Error for refresh the cache of {}: {}", uri, err);
         }
+        drop(refresh);
+    });
+}
+
+/**
+ * 获取只读操作使用的连接，该命名空间已经开启事务时使用事务的连接，以便读取到事务中尚未提交的数据
+ * InvocationContext中已有连接时直接使用
+ * 没有开启事务且不要求读主库时，优先使用只读副本，副本的连接不放入InvocationContext，以免后续的操作使用到副本
+ * 启用database模式的租户隔离时使用用户所属租户的数据库
+ */
+async fn acquire_read_connection(
+    mss: &'static MxStoreService,
+    ctx: &Arc<Mutex<InvocationContext>>,
+    jwt: &JwtUserClaims,
+    ns: &str,
+    conn_opt: Option<Arc<dyn Executor>>,
+) -> Result<Arc<dyn Executor>, Error> {
+    let tx_opt = ctx.lock().unwrap().get_tx_executor_sync(ns);
+    if let Some(tx) = tx_opt {
+        return Ok(tx);
     }
+    if let Some(conn) = conn_opt {
+        return Ok(conn);
+    }
+    let primary = ctx.lock().unwrap().is_read_primary(ns);
+    if !primary {
+        if let Some(conn) = mss.acquire_replica_connection().await {
+            return Ok(conn);
+        }
+    }
+    let con = mss.get_tenant_rbatis(jwt)?.acquire().await?;
+    let xcon: Arc<dyn Executor> = Arc::new(con);
+    ctx.lock().unwrap().set_rbatis_connection(ns, xcon.clone());
+    Ok(xcon)
 }
 
 /**
@@ -102,8 +305,6 @@ impl Invocation for DbStoreServiceInvocation {
 
         let full_uri = uri.url();
 
-        
-
         let method = uri.method.clone();
         let local_args = args.to_owned();
         // let mut ctx_ = ctx;
@@ -125,20 +326,19 @@ impl Invocation for DbStoreServiceInvocation {
             || method == *"save_batch"
             || method == *"update_by"
             || method == *"delete_by"
+            || method == *"restore"
+            || method == *"purge"
         {
-            if dbs.0.enable_cache {
-                let cache_ids = self.get_cache_id_prefix(uri, &jwt, args);
-                for id in cache_ids {
-                    if let Err(err) = redis_delexp_cmd(&uri.namespace, &id) {
-                        log::info!("error on redis delete {:?}", err);
-                    }
-                }
-            }
-            let rb_ = mss.get_rbatis();
+            // 其它命名空间可能缓存了依赖于该表的结果，无论本对象是否启用缓存都需要失效
+            // 写操作成功后只记录在InvocationContext中，事务提交后才失效，以免失效后又读到未提交前的数据
+            let tags = object_cache_tags(&dbs.1, &dbs.0);
+            let rb_ = match mss.get_tenant_rbatis(&jwt) {
+                Ok(rb) => rb,
+                Err(err) => return Box::pin(async move { Err(err) }),
+            };
             
             match method_str {
                 "insert" => {
-                    let prefixes = self.get_cache_id_prefix(uri, &jwt, args);
                     Box::pin(async move {
                         if let Ok(conn) = rb_.acquire().await {
                             ctx.lock().unwrap().set_rbatis_connection(&ns, Arc::new(conn));
@@ -162,9 +362,18 @@ impl Invocation for DbStoreServiceInvocation {
                         .await?;
 
                         // ctx.lock().unwrap().insert("key", Arc::clone(&tx_));
-                        match dbs.insert(tx, &jwt, &pass_args[0]).await {
+                        match dbs.insert(tx.clone(), &jwt, &pass_args[0]).await {
                             Ok(v) => {
+                                if let Err(err) = dbs
+                                    .write_audit(tx.clone(), &jwt, &AuditInfo::from_context(&ctx, "insert", &full_uri), vec![], std::slice::from_ref(&v))
+                                    .await
+                                {
+                                    ctx.lock().unwrap().set_failed();
+                                    ctx.lock().unwrap().insert("EXCEPTION", err.to_string());
+                                    return Err(err);
+                                }
                                 ctx.lock().unwrap().insert("RETURN_VALUE", Some(v.clone()));
+                                ctx.lock().unwrap().add_invalidate_tags(&tags);
                                 let _ = MxStoreService::invoke_post_hook_(
                                     full_uri,
                                     dbs.0.insert_hooks.clone(),
@@ -172,9 +381,6 @@ impl Invocation for DbStoreServiceInvocation {
                                     pass_args.clone(),
                                 )
                                 .await?;
-                                if dbs.0.enable_cache {
-                                    self.cache_remove(&ns, &prefixes, &[]);
-                                }
                                 match ctx.lock().unwrap().get::<Option<Value>>("RETURN_VALUE") {
                                     Ok(ts) => Ok(ts.to_owned()),
                                     Err(_) => Ok(Some(v)),
@@ -197,22 +403,7 @@ impl Invocation for DbStoreServiceInvocation {
                 }
                 "update" => {
                     // the args[0] will be hooked update to another value, that may be error
-                    let prefixes = if dbs.0.enable_cache {
-                        self.get_cache_id_prefix(uri, &jwt, args)
-                    } else {
-                        vec![]
-                    };
 
-                    let cache_id = if dbs.0.enable_cache {
-                        self.get_single_to_cache_id(
-                            uri,
-                            "select",
-                            &jwt,
-                            &dbs.get_pkey_value_present(&args[0]).unwrap_or_default(),
-                        )
-                    } else {
-                        "-----------------------------------------".to_owned()
-                    };
 
                     Box::pin(async move {
                         let tx = if let Some(conn) = tx_opt {
@@ -232,9 +423,19 @@ impl Invocation for DbStoreServiceInvocation {
                         )
                         .await?;
 
-                        match dbs.update(tx, &jwt, &pass_args[0]).await {
+                        let before = dbs.audit_images(tx.clone(), &jwt, &pass_args[..1]).await?;
+                        match dbs.update(tx.clone(), &jwt, &pass_args[0]).await {
                             Ok(v) => {
+                                if let Err(err) = dbs
+                                    .write_audit(tx.clone(), &jwt, &AuditInfo::from_context(&ctx, "update", &full_uri), before, std::slice::from_ref(&v))
+                                    .await
+                                {
+                                    ctx.lock().unwrap().set_failed();
+                                    ctx.lock().unwrap().insert("EXCEPTION", err.to_string());
+                                    return Err(err);
+                                }
                                 ctx.lock().unwrap().insert("RETURN_VALUE", Some(v.clone()));
+                                ctx.lock().unwrap().add_invalidate_tags(&tags);
                                 let _ = MxStoreService::invoke_post_hook_(
                                     full_uri,
                                     dbs.0.update_hooks.clone(),
@@ -242,9 +443,6 @@ impl Invocation for DbStoreServiceInvocation {
                                     pass_args.clone(),
                                 )
                                 .await?;
-                                if dbs.0.enable_cache {
-                                    self.cache_remove(&ns, &prefixes, &[cache_id]);
-                                }
                                 Ok(Some(v))
                             }
                             Err(err) => {
@@ -263,22 +461,7 @@ impl Invocation for DbStoreServiceInvocation {
                     })
                 }
                 "upsert" => {
-                    let prefixes = if dbs.0.enable_cache {
-                        self.get_cache_id_prefix(uri, &jwt, args)
-                    } else {
-                        vec![]
-                    };
 
-                    let cache_id = if dbs.0.enable_cache {
-                        self.get_single_to_cache_id(
-                            uri,
-                            "select",
-                            &jwt,
-                            &dbs.get_pkey_value_present(&args[0]).unwrap_or_default(),
-                        )
-                    } else {
-                        "-------------------------------------------".to_owned()
-                    };
                     Box::pin(async move {
                         let pass_args = MxStoreService::invoke_pre_hook_(
                             full_uri.clone(),
@@ -317,9 +500,7 @@ impl Invocation for DbStoreServiceInvocation {
                                 )
                                 .await?;
 
-                                if dbs.0.enable_cache {
-                                    self.cache_remove(&ns, &prefixes, &[cache_id]);
-                                }
+                                ctx.lock().unwrap().add_invalidate_tags(&tags);
                                 match ctx.lock().unwrap().get::<Option<Value>>("RETURN_VALUE") {
                                     Ok(ts) => Ok(ts.to_owned()),
                                     Err(_) => Ok(Some(v)),
@@ -341,22 +522,7 @@ impl Invocation for DbStoreServiceInvocation {
                     })
                 },
                 "save_batch" => {
-                    let prefixes = if dbs.0.enable_cache {
-                        self.get_cache_id_prefix(uri, &jwt, args)
-                    } else {
-                        vec![]
-                    };
 
-                    let cache_id = if dbs.0.enable_cache {
-                        self.get_single_to_cache_id(
-                            uri,
-                            "select",
-                            &jwt,
-                            &dbs.get_pkey_value_present(&args[0]).unwrap_or_default(),
-                        )
-                    } else {
-                        "-------------------------------------------".to_owned()
-                    };
                     Box::pin(async move {
                         let _ = MxStoreService::invoke_pre_hook_(
                             full_uri.clone(),
@@ -382,79 +548,65 @@ impl Invocation for DbStoreServiceInvocation {
                             ctx.lock().unwrap().set_tx_executor_sync(&ns, txc.clone());
                             txc
                         };
-                        let mut ctret_vals = vec![];
-                        for ctval in pass_args.clone() {
-                            let qst = if let Some(qsval) = ctval.get("_cond") {
-                                if let Ok(qs) = serde_json::from_value::<QueryCondition>(qsval.to_owned()) {
-                                    Some(qs)
-                                } else {
-                                    None
-                                }
-                            } else {
-                                None
-                            };
-                            let ctret = match dbs.upsert(tx.clone(), &jwt, &ctval, qst).await {
-                                Ok(v) => {
-                                    ctx.lock().unwrap().insert("RETURN_VALUE", Some(v.clone()));
-                                    let _ = MxStoreService::invoke_post_hook_(
-                                        full_uri.clone(),
-                                        dbs.0.upsert_hooks.clone(),
-                                        ctx.clone(),
-                                        pass_args.clone(),
-                                    )
-                                    .await?;
 
-                                    if dbs.0.enable_cache {
-                                        self.cache_remove(&ns, &prefixes, &[cache_id.clone()]);
-                                    }
-                                    match ctx.lock().unwrap().get::<Option<Value>>("RETURN_VALUE") {
-                                        Ok(ts) => Ok(ts.to_owned()),
-                                        Err(_) => Ok(Some(v)),
+                        match dbs.save_batch_detail(tx.clone(), &jwt, pass_args.clone()).await {
+                            Ok(mut saved) => {
+                                if !dbs.0.upsert_hooks.is_empty() {
+                                    // 每一条保存成功的记录，仍然执行upsert的后置钩子，钩子可以修改返回的记录
+                                    for rows in [&mut saved.inserted, &mut saved.updated] {
+                                        let mut ctret_vals = vec![];
+                                        for row in rows.drain(..) {
+                                            ctx.lock().unwrap().insert("RETURN_VALUE", Some(row.clone()));
+                                            let _ = MxStoreService::invoke_post_hook_(
+                                                full_uri.clone(),
+                                                dbs.0.upsert_hooks.clone(),
+                                                ctx.clone(),
+                                                pass_args.clone(),
+                                            )
+                                            .await?;
+                                            match ctx.lock().unwrap().get::<Option<Value>>("RETURN_VALUE") {
+                                                Ok(Some(tt)) => ctret_vals.push(tt.to_owned()),
+                                                Ok(None) => {}
+                                                Err(_) => ctret_vals.push(row),
+                                            }
+                                        }
+                                        *rows = ctret_vals;
                                     }
                                 }
-                                Err(err) => {
-                                    ctx.lock().unwrap().set_failed();
-                                    ctx.lock().unwrap().insert("EXCEPTION", err.to_string());
-                                    let _ = MxStoreService::invoke_post_hook_(
-                                        full_uri.clone(),
-                                        dbs.0.upsert_hooks.clone(),
-                                        ctx.clone(),
-                                        pass_args.clone(),
-                                    )
-                                    .await?;
-                                    Err(err)
-                                }
-                            };
-                            
-                            if ctret.is_err() {
-                                return ctret;
-                            } else if let Ok(Some(tt)) = ctret {
-                                ctret_vals.push(tt);
-                            }
-                        }
+                                let v = saved.to_value();
 
+                                ctx.lock().unwrap().add_invalidate_tags(&tags);
 
-                        
-                        ctx.lock().unwrap().insert("RETURN_VALUE", Some(ctret_vals.clone()));
-                        let _ = MxStoreService::invoke_post_hook_(
-                            full_uri.clone(),
-                            dbs.0.upsert_hooks.clone(),
-                            ctx.clone(),
-                            pass_args.clone(),
-                        )
-                        .await?;
+                                ctx.lock().unwrap().insert("RETURN_VALUE", Some(v.clone()));
+                                let _ = MxStoreService::invoke_post_hook_(
+                                    full_uri.clone(),
+                                    dbs.0.savebatch_hooks.clone(),
+                                    ctx.clone(),
+                                    pass_args.clone(),
+                                )
+                                .await?;
 
-                        let okret = Some(Value::Array(ctret_vals));
-                        Ok(okret)
+                                match ctx.lock().unwrap().get::<Option<Value>>("RETURN_VALUE") {
+                                    Ok(ts) => Ok(ts.to_owned()),
+                                    Err(_) => Ok(Some(v)),
+                                }
+                            }
+                            Err(err) => {
+                                ctx.lock().unwrap().set_failed();
+                                ctx.lock().unwrap().insert("EXCEPTION", err.to_string());
+                                let _ = MxStoreService::invoke_post_hook_(
+                                    full_uri.clone(),
+                                    dbs.0.savebatch_hooks.clone(),
+                                    ctx.clone(),
+                                    pass_args.clone(),
+                                )
+                                .await?;
+                                Err(err)
+                            }
+                        }
                     })
                 },
                 "update_by" => {
-                    let uri_cp = uri.clone();
-                    let prefixes = if dbs.0.enable_cache {
-                        self.get_cache_id_prefix(uri, &jwt, args)
-                    } else {
-                        vec![]
-                    };
 
                     Box::pin(async move {
                         let pass_args = MxStoreService::invoke_pre_hook_(
@@ -504,7 +656,7 @@ impl Invocation for DbStoreServiceInvocation {
                             txc
                         };
 
-                        let update_vec = match dbs.query(Arc::new(rb_.clone()), &jwt, &qs).await {
+                        let update_vec = match dbs.query(rb_.clone(), &jwt, &qs).await {
                             Ok(ts) => ts,
                             Err(err) => {
                                 ctx.lock().unwrap().insert("EXCEPTION", err.to_string());
@@ -519,13 +671,22 @@ impl Invocation for DbStoreServiceInvocation {
                             }
                         };
 
-                        match dbs.update_by(tx, &jwt, &pass_args[0], &qs).await {
+                        let before = dbs.audit_query_images(tx.clone(), &jwt, &qs).await?;
+                        match dbs.update_by(tx.clone(), &jwt, &pass_args[0], &qs).await {
                             Ok(v) => {
+                                let keys = before.clone();
+                                if let Err(err) = dbs
+                                    .write_audit(tx.clone(), &jwt, &AuditInfo::from_context(&ctx, "update_by", &full_uri), before, &keys)
+                                    .await
+                                {
+                                    ctx.lock().unwrap().set_failed();
+                                    ctx.lock().unwrap().insert("EXCEPTION", err.to_string());
+                                    return Err(err);
+                                }
                                 ctx.clone()
                                     .lock()
                                     .unwrap()
                                     .insert("rows_affected", Some(v.clone()));
-                                let mut cached_ids = vec![];
                                 for tc in update_vec {
                                     ctx.clone()
                                         .lock()
@@ -538,20 +699,9 @@ impl Invocation for DbStoreServiceInvocation {
                                         pass_args.clone(),
                                     )
                                     .await?;
-                                    if dbs.0.enable_cache {
-                                        let cid = self.get_single_to_cache_id(
-                                            &uri_cp,
-                                            "select",
-                                            &jwt,
-                                            &dbs.get_pkey_value_present(&tc).unwrap_or_default(),
-                                        );
-                                        cached_ids.push(cid);
-                                    }
                                 }
 
-                                if dbs.0.enable_cache {
-                                    self.cache_remove(&ns, &prefixes, &cached_ids);
-                                }
+                                ctx.lock().unwrap().add_invalidate_tags(&tags);
                                 Ok(Some(v))
                             }
                             Err(err) => {
@@ -570,24 +720,6 @@ impl Invocation for DbStoreServiceInvocation {
                     })
                 }
                 "delete" => {
-                    let uri_cp = uri.clone();
-                    let prefixes = if dbs.0.enable_cache {
-                        self.get_cache_id_prefix(uri, &jwt, args)
-                    } else {
-                        vec![]
-                    };
-
-                    let cid = if dbs.0.enable_cache {
-                        self.get_single_to_cache_id(
-                            &uri_cp,
-                            "select",
-                            &jwt,
-                            &dbs.get_pkey_value_present(&args[0]).unwrap_or_default(),
-                        )
-                    } else {
-                        "------------------------------------".to_owned()
-                    };
-
                     Box::pin(async move {
                         let pass_args = MxStoreService::invoke_pre_hook_(
                             full_uri.clone(),
@@ -607,13 +739,36 @@ impl Invocation for DbStoreServiceInvocation {
                         };
 
                     
-                        match dbs.delete(tx, &jwt, &pass_args[0]).await {
+                        let before = dbs.audit_images(tx.clone(), &jwt, &pass_args[..1]).await?;
+                        match dbs.delete(tx.clone(), &jwt, &pass_args[0]).await {
                             Ok(v) => {
+                                let keys = before.clone();
+                                if let Err(err) = dbs
+                                    .write_audit(tx.clone(), &jwt, &AuditInfo::from_context(&ctx, "delete", &full_uri), before, &keys)
+                                    .await
+                                {
+                                    ctx.lock().unwrap().set_failed();
+                                    ctx.lock().unwrap().insert("EXCEPTION", err.to_string());
+                                    return Err(err);
+                                }
                                 ctx.clone()
                                     .lock()
                                     .unwrap()
                                     .insert("rows_affected", Some(v.clone()));
                                 ctx.lock().unwrap().insert("RETURN_VALUE", Some(v.clone()));
+                                ctx.lock().unwrap().add_invalidate_tags(&tags);
+                                let _ = MxStoreService::invoke_post_hook_(
+                                    full_uri,
+                                    dbs.0.delete_hooks.clone(),
+                                    ctx,
+                                    pass_args.clone(),
+                                )
+                                .await?;
+                                Ok(Some(v))
+                            }
+                            Err(err) => {
+                                ctx.lock().unwrap().set_failed();
+                                ctx.lock().unwrap().insert("EXCEPTION", err.to_string());
                                 let _ = MxStoreService::invoke_post_hook_(
                                     full_uri,
                                     dbs.0.delete_hooks.clone(),
@@ -621,9 +776,64 @@ impl Invocation for DbStoreServiceInvocation {
                                     pass_args.clone(),
                                 )
                                 .await?;
-                                if dbs.0.enable_cache {
-                                    self.cache_remove(&ns, &prefixes, &[cid]);
+                                Err(err)
+                            }
+                        }
+                    })
+                }
+                "restore" | "purge" => {
+                    // 与delete使用相同的Hook及审计，Hook中可以通过InvokeUri的method区分
+                    let restore = method_str == "restore";
+                    let action = method_str.to_owned();
+                    Box::pin(async move {
+                        let pass_args = MxStoreService::invoke_pre_hook_(
+                            full_uri.clone(),
+                            dbs.0.delete_hooks.clone(),
+                            ctx.clone(),
+                            local_args.to_vec(),
+                        )
+                        .await?;
+
+                        let tx = if let Some(conn) = tx_opt {
+                            conn
+                        } else {
+                            let con = rb_.acquire_begin().await?;
+                            let txc = Arc::new(con);
+                            ctx.lock().unwrap().set_tx_executor_sync(&ns, txc.clone());
+                            txc
+                        };
+
+                        let before = dbs.audit_images(tx.clone(), &jwt, &pass_args[..1]).await?;
+                        let ret = if restore {
+                            dbs.restore(tx.clone(), &jwt, &pass_args[0]).await
+                        } else {
+                            dbs.purge(tx.clone(), &jwt, &pass_args[0]).await
+                        };
+
+                        match ret {
+                            Ok(v) => {
+                                let keys = before.clone();
+                                if let Err(err) = dbs
+                                    .write_audit(tx.clone(), &jwt, &AuditInfo::from_context(&ctx, &action, &full_uri), before, &keys)
+                                    .await
+                                {
+                                    ctx.lock().unwrap().set_failed();
+                                    ctx.lock().unwrap().insert("EXCEPTION", err.to_string());
+                                    return Err(err);
                                 }
+                                ctx.clone()
+                                    .lock()
+                                    .unwrap()
+                                    .insert("rows_affected", Some(v.clone()));
+                                ctx.lock().unwrap().insert("RETURN_VALUE", Some(v.clone()));
+                                ctx.lock().unwrap().add_invalidate_tags(&tags);
+                                let _ = MxStoreService::invoke_post_hook_(
+                                    full_uri,
+                                    dbs.0.delete_hooks.clone(),
+                                    ctx,
+                                    pass_args.clone(),
+                                )
+                                .await?;
                                 Ok(Some(v))
                             }
                             Err(err) => {
@@ -642,12 +852,6 @@ impl Invocation for DbStoreServiceInvocation {
                     })
                 }
                 "delete_by" => {
-                    let uri_cp = uri.clone();
-                    let prefixes = if dbs.0.enable_cache {
-                        self.get_cache_id_prefix(uri, &jwt, args)
-                    } else {
-                        vec![]
-                    };
 
                     Box::pin(async move {
                         let pass_args = MxStoreService::invoke_pre_hook_(
@@ -688,7 +892,7 @@ impl Invocation for DbStoreServiceInvocation {
                             }
                         };
                   
-                        let update_vec = match dbs.query(Arc::new(rb_.clone()), &jwt, &qs).await {
+                        let update_vec = match dbs.query(rb_.clone(), &jwt, &qs).await {
                             Ok(ts) => ts,
                             Err(err) => {
                                 ctx.lock().unwrap().insert("EXCEPTION", err.to_string());
@@ -712,14 +916,23 @@ impl Invocation for DbStoreServiceInvocation {
                             txc
                         };
 
-                        match dbs.delete_by(tx, &jwt, &qs).await {
+                        let before = dbs.audit_query_images(tx.clone(), &jwt, &qs).await?;
+                        match dbs.delete_by(tx.clone(), &jwt, &qs).await {
                             Ok(v) => {
+                                let keys = before.clone();
+                                if let Err(err) = dbs
+                                    .write_audit(tx.clone(), &jwt, &AuditInfo::from_context(&ctx, "delete_by", &full_uri), before, &keys)
+                                    .await
+                                {
+                                    ctx.lock().unwrap().set_failed();
+                                    ctx.lock().unwrap().insert("EXCEPTION", err.to_string());
+                                    return Err(err);
+                                }
                                 // v.get("rows_affected")
                                 ctx.clone()
                                     .lock()
                                     .unwrap()
                                     .insert("rows_affected", Some(v.clone()));
-                                let mut cached_ids = vec![];
                                 for tc in update_vec {
                                     ctx.clone()
                                         .lock()
@@ -732,20 +945,9 @@ impl Invocation for DbStoreServiceInvocation {
                                         pass_args.clone(),
                                     )
                                     .await?;
-                                    if dbs.0.enable_cache {
-                                        let cid = self.get_single_to_cache_id(
-                                            &uri_cp,
-                                            "select",
-                                            &jwt,
-                                            &dbs.get_pkey_value_present(&tc).unwrap_or_default(),
-                                        );
-                                        cached_ids.push(cid);
-                                    }
                                 }
 
-                                if dbs.0.enable_cache {
-                                    self.cache_remove(&ns, &prefixes, &cached_ids);
-                                }
+                                ctx.lock().unwrap().add_invalidate_tags(&tags);
                                 Ok(Some(v))
                             }
                             Err(err) => {
@@ -773,30 +975,33 @@ impl Invocation for DbStoreServiceInvocation {
                 &jwt,
                 &key_id.unwrap_or_default(),
             );
+            let tags = object_cache_tags(&dbs.1, &dbs.0);
+            let policy = CachePolicy::new(dbs.0.cache_time, dbs.0.cache_jitter, dbs.0.cache_stale_time);
+            let enable_cache = cache_enabled(dbs.0.enable_cache, &ns, &ctx);
 
-            let rb_ = mss.get_rbatis();
             let conn_opt = ctx.lock().unwrap().get_rbatis_connection(&ns);
             
             match method_str {
                 "select" => Box::pin(async move {
-                    if dbs.0.enable_cache {
-                        if let Ok(Some(cache_ret)) = redis_get(&ns, &cache_id) {
-                            if let Ok(ret) = serde_json::from_str::<Value>(&cache_ret) {
-                                if !ret.is_null() {
-                                    return Ok(Some(ret));
-                                }
+                    let lookup = lookup_cache(enable_cache, &ns, &cache_id, &ctx).await;
+                    if let Some(refresh) = lookup.refresh {
+                        spawn_cache_refresh(
+                            full_uri.clone(),
+                            &ctx,
+                            local_args.clone(),
+                            CacheRefreshKind::One,
+                            refresh,
+                        );
+                    }
+                    if let Some(cache_ret) = lookup.value.as_ref() {
+                        if let Ok(ret) = serde_json::from_str::<Value>(cache_ret) {
+                            if !ret.is_null() {
+                                return Ok(Some(ret));
                             }
                         }
                     }
 
-                    let conn = if let Some(conn) = conn_opt {
-                        conn
-                    } else {
-                        let con = rb_.acquire().await?;
-                        let xcon: Arc<dyn Executor> = Arc::new(con);
-                        ctx.lock().unwrap().set_rbatis_connection(&ns, xcon.clone());
-                        xcon
-                    };
+                    let conn = acquire_read_connection(mss, &ctx, &jwt, &ns, conn_opt).await?;
 
                     let pass_args = MxStoreService::invoke_pre_hook_(
                         full_uri.clone(),
@@ -824,15 +1029,16 @@ impl Invocation for DbStoreServiceInvocation {
                             .await?;
                             match ctx.lock().unwrap().get::<Option<Value>>("RETURN_VALUE") {
                                 Ok(ts) => {
-                                    if dbs.0.enable_cache && ts.is_some() {
+                                    if enable_cache && ts.is_some() {
                                         if let Ok(text) =
                                             serde_json::to_string(&ts.clone().unwrap())
                                         {
-                                            if let Err(err) = redis_set_expire(
+                                            if let Err(err) = cache_set_policy(
                                                 &ns,
                                                 &cache_id,
                                                 &text,
-                                                dbs.0.cache_time.unwrap_or(30) as u64,
+                                                &policy,
+                                                &tags,
                                             ) {
                                                 log::info!("error for cache {}", err);
                                             }
@@ -858,24 +1064,25 @@ impl Invocation for DbStoreServiceInvocation {
                 }),
                 "find_one" => {
                     Box::pin(async move {
-                        if dbs.0.enable_cache {
-                            if let Ok(Some(cache_ret)) = redis_get(&ns, &cache_id) {
-                                if let Ok(ret) = serde_json::from_str::<Value>(&cache_ret) {
-                                    if !ret.is_null() {
-                                        return Ok(Some(ret));
-                                    }
+                        let lookup = lookup_cache(enable_cache, &ns, &cache_id, &ctx).await;
+                        if let Some(refresh) = lookup.refresh {
+                            spawn_cache_refresh(
+                                full_uri.clone(),
+                                &ctx,
+                                local_args.clone(),
+                                CacheRefreshKind::One,
+                                refresh,
+                            );
+                        }
+                        if let Some(cache_ret) = lookup.value.as_ref() {
+                            if let Ok(ret) = serde_json::from_str::<Value>(cache_ret) {
+                                if !ret.is_null() {
+                                    return Ok(Some(ret));
                                 }
                             }
                         }
                         
-                        let conn = if let Some(conn) = conn_opt {
-                            conn
-                        } else {
-                            let con = rb_.acquire().await?;
-                            let xcon: Arc<dyn Executor> = Arc::new(con);
-                            ctx.lock().unwrap().set_rbatis_connection(&ns, xcon.clone());
-                            xcon
-                        };
+                        let conn = acquire_read_connection(mss, &ctx, &jwt, &ns, conn_opt).await?;
 
                         let pass_args = MxStoreService::invoke_pre_hook_(
                             full_uri.clone(),
@@ -904,15 +1111,16 @@ impl Invocation for DbStoreServiceInvocation {
                                     .await?;
                                     match ctx.lock().unwrap().get::<Option<Value>>("RETURN_VALUE") {
                                         Ok(ts) => {
-                                            if dbs.0.enable_cache && ts.is_some() {
+                                            if enable_cache && ts.is_some() {
                                                 if let Ok(text) =
                                                     serde_json::to_string(&ts.clone().unwrap())
                                                 {
-                                                    if let Err(err) = redis_set_expire(
+                                                    if let Err(err) = cache_set_policy(
                                                         &ns,
                                                         &cache_id,
                                                         &text,
-                                                        dbs.0.cache_time.unwrap_or(30) as u64,
+                                                        &policy,
+                                                        &tags,
                                                     ) {
                                                         log::info!("error for cache {}", err);
                                                     }
@@ -977,8 +1185,6 @@ impl Invocation for DbStoreServiceInvocation {
         // let mut ctx_ = ctx;
         let method_str = method.as_str();
 
-        let rb_ = mss.get_rbatis();
-
         let full_uri = uri.url();
         let ns = uri.namespace.clone();
 
@@ -990,32 +1196,42 @@ impl Invocation for DbStoreServiceInvocation {
             .obtain_jwt_user_info()
             .unwrap_or(JwtUserClaims::anonymous());
 
+        let rb_ = match mss.get_tenant_rbatis(&jwt) {
+            Ok(rb) => rb,
+            Err(err) => return Box::pin(async move { Err(err) }),
+        };
+
         let conn_opt = ctx.lock().unwrap().get_rbatis_connection(&ns);
 
         match method_str {
-            "query" => {
+            "query" | "aggregate" => {
+                let aggregate = method_str == "aggregate";
                 let cache_id = self.convert_to_cache_id(uri, &jwt, args);
+                let tags = object_cache_tags(&dbs.1, &dbs.0);
+                let policy = CachePolicy::new(dbs.0.cache_time, dbs.0.cache_jitter, dbs.0.cache_stale_time);
+                let enable_cache = cache_enabled(dbs.0.enable_cache, &ns, &ctx);
                 Box::pin(async move {
-                    if dbs.0.enable_cache {
-                        if let Ok(Some(cache_ret)) = redis_get(&ns, &cache_id) {
-                            if let Ok(ret) = serde_json::from_str::<Value>(&cache_ret) {
-                                if !ret.is_null() && ret.is_array() {
-                                    if let Some(tx) = ret.as_array() {
-                                        return Ok(tx.to_owned());
-                                    }
+                    let lookup = lookup_cache(enable_cache, &ns, &cache_id, &ctx).await;
+                    if let Some(refresh) = lookup.refresh {
+                        spawn_cache_refresh(
+                            full_uri.clone(),
+                            &ctx,
+                            local_args.clone(),
+                            CacheRefreshKind::Vec,
+                            refresh,
+                        );
+                    }
+                    if let Some(cache_ret) = lookup.value.as_ref() {
+                        if let Ok(ret) = serde_json::from_str::<Value>(cache_ret) {
+                            if !ret.is_null() && ret.is_array() {
+                                if let Some(tx) = ret.as_array() {
+                                    return Ok(tx.to_owned());
                                 }
                             }
                         }
                     }
                     
-                    let conn = if let Some(conn) = conn_opt {
-                        conn
-                    } else {
-                        let con = rb_.acquire().await?;
-                        let xcon: Arc<dyn Executor> = Arc::new(con);
-                        ctx.lock().unwrap().set_rbatis_connection(&ns, xcon.clone());
-                        xcon
-                    };
+                    let conn = acquire_read_connection(mss, &ctx, &jwt, &ns, conn_opt).await?;
 
                     let pass_args = MxStoreService::invoke_pre_hook_(
                         full_uri.clone(),
@@ -1031,7 +1247,7 @@ impl Invocation for DbStoreServiceInvocation {
                         local_args[0].to_owned()
                     };
                     match dbs.to_condition(&fix_arg) {
-                        Ok(qs) => match dbs.query(conn, &jwt, &qs).await {
+                        Ok(qs) => match query_or_aggregate(&dbs, conn, &jwt, &qs, aggregate).await {
                             Ok(ts) => {
                                 ctx.lock().unwrap().insert("RETURN_VALUE", ts.clone());
                                 MxStoreService::invoke_post_hook_(
@@ -1043,16 +1259,17 @@ impl Invocation for DbStoreServiceInvocation {
                                 .await?;
                                 match ctx.lock().unwrap().get::<Vec<Value>>("RETURN_VALUE") {
                                     Ok(ts) => {
-                                        if dbs.0.enable_cache {
+                                        if enable_cache {
                                             let tc = ts.clone();
                                             if let Ok(text) = serde_json::to_string(&tc) {
-                                                if let Err(err) = redis_set_expire(
+                                                if let Err(err) = cache_set_policy(
                                                     &ns,
                                                     &cache_id,
                                                     &text,
-                                                    dbs.0.cache_time.unwrap_or(30) as u64,
+                                                    &policy,
+                                                    &tags,
                                                 ) {
-                                                    log::info!("Error for redis set {}", err);
+                                                    log::info!("Error for cache set {}", err);
                                                 }
                                             }
                                         }
@@ -1087,6 +1304,20 @@ impl Invocation for DbStoreServiceInvocation {
                     }
                 })
             }
+            "history" => Box::pin(async move {
+                let conn = if let Some(conn) = conn_opt {
+                    conn
+                } else {
+                    let con = rb_.acquire().await?;
+                    let xcon: Arc<dyn Executor> = Arc::new(con);
+                    ctx.lock().unwrap().set_rbatis_connection(&ns, xcon.clone());
+                    xcon
+                };
+                match local_args.first() {
+                    Some(t) => dbs.audit_history(conn, &jwt, t).await,
+                    None => Err(anyhow!("No record provided for history")),
+                }
+            }),
             _ => Box::pin(async move { Err(anyhow!("Not implemented")) }),
         }
     }
@@ -1115,7 +1346,6 @@ impl Invocation for DbStoreServiceInvocation {
         // let mut ctx_ = ctx;
         let method_str = method.as_str();
 
-        let rb_ = mss.get_rbatis();
 
         let full_uri = uri.url();
         let ns = uri.namespace.clone();
@@ -1132,23 +1362,32 @@ impl Invocation for DbStoreServiceInvocation {
         match method_str {
             "paged_query" => {
                 let cache_id = self.convert_to_cache_id(uri, &jwt, args);
+                let tags = object_cache_tags(&dbs.1, &dbs.0);
+                let policy = CachePolicy::new(dbs.0.cache_time, dbs.0.cache_jitter, dbs.0.cache_stale_time);
+                let enable_cache = cache_enabled(dbs.0.enable_cache, &ns, &ctx);
+                let cursor_mode = args
+                    .first()
+                    .and_then(|f| dbs.to_condition(f).ok())
+                    .map(|qs| qs.is_cursor_paging())
+                    .unwrap_or(false);
                 Box::pin(async move {
-                    if dbs.0.enable_cache {
-                        if let Ok(Some(cache_ret)) = redis_get(&ns, &cache_id) {
-                            if let Ok(ret) = serde_json::from_str::<Page<Value>>(&cache_ret) {
-                                return Ok(ret);
-                            }
+                    let lookup = lookup_cache(enable_cache, &ns, &cache_id, &ctx).await;
+                    if let Some(refresh) = lookup.refresh {
+                        spawn_cache_refresh(
+                            full_uri.clone(),
+                            &ctx,
+                            local_args.clone(),
+                            CacheRefreshKind::Page,
+                            refresh,
+                        );
+                    }
+                    if let Some(cache_ret) = lookup.value.as_ref() {
+                        if let Some(ret) = decode_page_cache(cache_ret, cursor_mode, &ctx) {
+                            return Ok(ret);
                         }
                     }
 
-                    let conn = if let Some(conn) = conn_opt {
-                        conn
-                    } else {
-                        let con = rb_.acquire().await?;
-                        let xcon: Arc<dyn Executor> = Arc::new(con);
-                        ctx.lock().unwrap().set_rbatis_connection(&ns, xcon.clone());
-                        xcon
-                    };
+                    let conn = acquire_read_connection(mss, &ctx, &jwt, &ns, conn_opt).await?;
 
                     let pass_args = MxStoreService::invoke_pre_hook_(
                         full_uri.clone(),
@@ -1164,7 +1403,7 @@ impl Invocation for DbStoreServiceInvocation {
                         local_args[0].to_owned()
                     };
                     match dbs.to_condition(&fix_arg) {
-                        Ok(qs) => match dbs.paged_query(conn, &jwt, &qs).await {
+                        Ok(qs) => match paged_or_cursor_query(&dbs, conn, &jwt, &qs, &ctx).await {
                             Ok(ts) => {
                                 ctx.lock().unwrap().insert("RETURN_VALUE", ts.clone());
                                 MxStoreService::invoke_post_hook_(
@@ -1174,18 +1413,19 @@ impl Invocation for DbStoreServiceInvocation {
                                     pass_args,
                                 )
                                 .await?;
+                                let next_cursor = ctx.lock().unwrap().get_string("NEXT_CURSOR");
                                 match ctx.lock().unwrap().get::<Page<Value>>("RETURN_VALUE") {
                                     Ok(ts) => {
-                                        if dbs.0.enable_cache {
-                                            let tc = ts.clone();
-                                            if let Ok(text) = serde_json::to_string(&tc) {
-                                                if let Err(err) = redis_set_expire(
+                                        if enable_cache {
+                                            if let Ok(text) = encode_page_cache(ts, cursor_mode, next_cursor.clone()) {
+                                                if let Err(err) = cache_set_policy(
                                                     &ns,
                                                     &cache_id,
                                                     &text,
-                                                    dbs.0.cache_time.unwrap_or(30) as u64,
+                                                    &policy,
+                                                    &tags,
                                                 ) {
-                                                    log::info!("Error for redis set {}", err);
+                                                    log::info!("Error for cache set {}", err);
                                                 }
                                             }
                                         }
@@ -1229,11 +1469,14 @@ pub struct DbQueryServiceInvocation();
 
 impl DbQueryServiceInvocation {
     fn convert_to_cache_id(&self, uri: &InvokeUri, jwt: &JwtUserClaims, args: &[Value]) -> String {
+        // 跨租户访问与本租户内访问的结果不同，不能共享缓存
         let id_body = format!(
-            "{}#{}#{}",
+            "{}#{}#{}#{}#{}",
             uri.url_no_method(),
+            jwt.domain,
             jwt.username,
-            serde_json::to_string(args).unwrap_or_default()
+            jwt.is_cross_tenant(),
+            cache_args_body(args)
         );
         let hash = md5::compute(id_body);
         let hex_hash = format!("{:x}", hash);
@@ -1282,7 +1525,6 @@ impl Invocation for DbQueryServiceInvocation {
         //     mss.get_rbatis().to_owned()
         // };
 
-        let rb_ = mss.get_rbatis(); // .to_owned();
 
         let jwt = ctx
             .lock()
@@ -1297,24 +1539,28 @@ impl Invocation for DbQueryServiceInvocation {
                 let full_url = uri.url();
                 let pass_args = args.to_vec();
                 let cache_id = self.convert_to_cache_id(uri, &jwt, args);
+                let tags = query_cache_tags(&dbs.0);
+                let policy = CachePolicy::new(dbs.0.cache_time, dbs.0.cache_jitter, dbs.0.cache_stale_time);
+                let enable_cache = cache_enabled(dbs.0.enable_cache, &ns, &ctx);
                 log::info!("search {}", cache_id);
                 Box::pin(async move {
-                    if dbs.0.enable_cache {
-                        if let Ok(Some(cache_ret)) = redis_get(&ns, &cache_id) {
-                            if let Ok(ret) = serde_json::from_str::<Vec<Value>>(&cache_ret) {
-                                return Ok(ret);
-                            }
+                    let lookup = lookup_cache(enable_cache, &ns, &cache_id, &ctx).await;
+                    if let Some(refresh) = lookup.refresh {
+                        spawn_cache_refresh(
+                            full_url.clone(),
+                            &ctx,
+                            pass_args.clone(),
+                            CacheRefreshKind::Vec,
+                            refresh,
+                        );
+                    }
+                    if let Some(cache_ret) = lookup.value.as_ref() {
+                        if let Ok(ret) = serde_json::from_str::<Vec<Value>>(cache_ret) {
+                            return Ok(ret);
                         }
                     }
 
-                    let conn = if let Some(conn) = conn_opt {
-                        conn
-                    } else {
-                        let con = rb_.acquire().await?;
-                        let xcon: Arc<dyn Executor> = Arc::new(con);
-                        ctx.lock().unwrap().set_rbatis_connection(&ns, xcon.clone());
-                        xcon
-                    };
+                    let conn = acquire_read_connection(mss, &ctx, &jwt, &ns, conn_opt).await?;
 
                     let mix_args = MxStoreService::invoke_pre_hook_(
                         full_url.clone(),
@@ -1344,17 +1590,18 @@ impl Invocation for DbQueryServiceInvocation {
                                     .await?;
                                     match ctx.lock().unwrap().get::<Vec<Value>>("RETURN_VALUE") {
                                         Ok(retval) => {
-                                            if dbs.0.enable_cache {
+                                            if enable_cache {
                                                 let tc = retval.clone();
                                                 if let Ok(text) = serde_json::to_string(&tc) {
-                                                    // log::info!("set redis {} = {}", cache_id, text);
-                                                    if let Err(err) = redis_set_expire(
+                                                    // log::info!("set cache {} = {}", cache_id, text);
+                                                    if let Err(err) = cache_set_policy(
                                                         &ns,
                                                         &cache_id,
                                                         &text,
-                                                        dbs.0.cache_time.unwrap_or(30) as u64,
+                                                        &policy,
+                                                        &tags,
                                                     ) {
-                                                        log::info!("Error for redis set {}", err);
+                                                        log::info!("Error for cache set {}", err);
                                                     }
                                                 }
                                             }
@@ -1365,17 +1612,18 @@ impl Invocation for DbQueryServiceInvocation {
                                         }
                                     }
                                 } else {
-                                    if dbs.0.enable_cache {
+                                    if enable_cache {
                                         let tc = rs.clone();
                                         if let Ok(text) = serde_json::to_string(&tc) {
-                                            // log::info!("set redis {} = {}", cache_id, text);
-                                            if let Err(err) = redis_set_expire(
+                                            // log::info!("set cache {} = {}", cache_id, text);
+                                            if let Err(err) = cache_set_policy(
                                                 &ns,
                                                 &cache_id,
                                                 &text,
-                                                dbs.0.cache_time.unwrap_or(30) as u64,
+                                                &policy,
+                                                &tags,
                                             ) {
-                                                log::info!("Error for redis set {}", err);
+                                                log::info!("Error for cache set {}", err);
                                             }
                                         }
                                     }
@@ -1420,7 +1668,6 @@ impl Invocation for DbQueryServiceInvocation {
         // let mut ctx_ = ctx;
         let method_str = method.as_str();
 
-        let rb_ = mss.get_rbatis().to_owned();
 
         let conn_opt = ctx.lock().unwrap().get_rbatis_connection(&ns);
 
@@ -1435,23 +1682,31 @@ impl Invocation for DbQueryServiceInvocation {
                 let pass_args = args.to_vec();
                 let full_uri = uri.url();
                 let cache_id = self.convert_to_cache_id(uri, &jwt, args);
+                let tags = query_cache_tags(&dbs.0);
+                let policy = CachePolicy::new(dbs.0.cache_time, dbs.0.cache_jitter, dbs.0.cache_stale_time);
+                let enable_cache = cache_enabled(dbs.0.enable_cache, &ns, &ctx);
+                let cursor_mode = dbs
+                    .to_condition(args)
+                    .map(|qs| qs.is_cursor_paging())
+                    .unwrap_or(false);
                 Box::pin(async move {
-                    if dbs.0.enable_cache {
-                        if let Ok(Some(cache_ret)) = redis_get(&ns, &cache_id) {
-                            if let Ok(ret) = serde_json::from_str::<Page<Value>>(&cache_ret) {
-                                return Ok(ret);
-                            }
+                    let lookup = lookup_cache(enable_cache, &ns, &cache_id, &ctx).await;
+                    if let Some(refresh) = lookup.refresh {
+                        spawn_cache_refresh(
+                            full_uri.clone(),
+                            &ctx,
+                            pass_args.clone(),
+                            CacheRefreshKind::Page,
+                            refresh,
+                        );
+                    }
+                    if let Some(cache_ret) = lookup.value.as_ref() {
+                        if let Some(ret) = decode_page_cache(cache_ret, cursor_mode, &ctx) {
+                            return Ok(ret);
                         }
                     }
 
-                    let conn = if let Some(conn) = conn_opt {
-                        conn
-                    } else {
-                        let con = rb_.acquire().await?;
-                        let xcon: Arc<dyn Executor> = Arc::new(con);
-                        ctx.lock().unwrap().set_rbatis_connection(&ns, xcon.clone());
-                        xcon
-                    };
+                    let conn = acquire_read_connection(mss, &ctx, &jwt, &ns, conn_opt).await?;
 
                     let mix_args = MxStoreService::invoke_pre_hook_(
                         full_uri.clone(),
@@ -1462,7 +1717,14 @@ impl Invocation for DbQueryServiceInvocation {
                     .await?;
                     let fix_arg = mix_args[0].to_owned();
                     if let Ok(qs) = dbs.to_condition(&mix_args) {
-                        let ret = dbs.paged_query(conn, &jwt, &fix_arg, &qs).await?;
+                        let ret = if qs.is_cursor_paging() {
+                            let (page, next_cursor) = dbs.cursor_query(conn, &jwt, &fix_arg, &qs).await?;
+                            set_next_cursor(&ctx, next_cursor);
+                            page
+                        } else {
+                            dbs.paged_query(conn, &jwt, &fix_arg, &qs).await?
+                        };
+                        let next_cursor = ctx.lock().unwrap().get_string("NEXT_CURSOR");
                         if !dbs.0.hooks.is_empty() {
                             ctx.lock().unwrap().insert("RETURN_VALUE", ret.clone());
                             MxStoreService::invoke_post_hook_(
@@ -1474,16 +1736,16 @@ impl Invocation for DbQueryServiceInvocation {
                             .await?;
                             match ctx.lock().unwrap().get::<Page<Value>>("RETURN_VALUE") {
                                 Ok(retval) => {
-                                    if dbs.0.enable_cache {
-                                        let tc = retval.clone();
-                                        if let Ok(text) = serde_json::to_string(&tc) {
-                                            if let Err(err) = redis_set_expire(
+                                    if enable_cache {
+                                        if let Ok(text) = encode_page_cache(retval, cursor_mode, next_cursor.clone()) {
+                                            if let Err(err) = cache_set_policy(
                                                 &ns,
                                                 &cache_id,
                                                 &text,
-                                                dbs.0.cache_time.unwrap_or(30) as u64,
+                                                &policy,
+                                                &tags,
                                             ) {
-                                                log::info!("Error for redis set {}", err);
+                                                log::info!("Error for cache set {}", err);
                                             }
                                         }
                                     }
@@ -1492,17 +1754,17 @@ impl Invocation for DbQueryServiceInvocation {
                                 Err(_) => Err(anyhow!("Could not GET/CAST the RETURN_VALUE")),
                             }
                         } else {
-                            if dbs.0.enable_cache {
-                                let tc = ret.clone();
-                                if let Ok(text) = serde_json::to_string(&tc) {
-                                    // log::info!("set redis {} = {}", cache_id, text);
-                                    if let Err(err) = redis_set_expire(
+                            if enable_cache {
+                                if let Ok(text) = encode_page_cache(&ret, cursor_mode, next_cursor.clone()) {
+                                    // log::info!("set cache {} = {}", cache_id, text);
+                                    if let Err(err) = cache_set_policy(
                                         &ns,
                                         &cache_id,
                                         &text,
-                                        dbs.0.cache_time.unwrap_or(30) as u64,
+                                        &policy,
+                                        &tags,
                                     ) {
-                                        log::info!("Error for redis set {}", err);
+                                        log::info!("Error for cache set {}", err);
                                     }
                                 }
                             }
@@ -1531,7 +1793,15 @@ impl Invocation for DbQueryServiceInvocation {
     
             let dbs = DbQueryObject(QueryObject::default(), mss.get_config(), AuthorizationConfig::get());
     
-            let rb_ = mss.get_rbatis(); // .to_owned();
+            let jwt = ctx
+                .lock()
+                .unwrap()
+                .obtain_jwt_user_info()
+                .unwrap_or(JwtUserClaims::anonymous());
+            let rb_ = match mss.get_tenant_rbatis(&jwt) {
+                Ok(rb) => rb,
+                Err(err) => return Box::pin(async move { Err(err) }),
+            };
             let conn_opt = ctx.lock().unwrap().get_rbatis_connection(&namespace);
             let ns_ = namespace.clone();
             Box::pin(async move {
@@ -16,11 +16,20 @@ use rbatis::{executor::Executor, rbatis_codegen::ops::AsProxy};
 use serde_json::{json, Map, Number, Value};
 use std::{collections::HashMap, pin::Pin, sync::Arc};
 use substring::Substring;
+use chimes_dbs_factory::get_executor_driver_name;
+use chimes_store_core::config::is_safe_identifier;
 
+/**
+ * 批量加载关联对象时，每个IN查询最多包含的值的个数
+ */
+const RELATION_BATCH_SIZE: usize = 500;
+
+pub mod audit;
 pub mod crud;
 pub mod invoker;
 pub mod query;
 pub mod redis;
+pub mod validator;
 
 fn should_return_plain_text(des: &Option<String>, cs: bool) -> bool {
     if des.is_none() {
@@ -140,6 +149,7 @@ pub async fn decode_relation_async(
                         value2: Value::Null,
                         and: vec![],
                         or: vec![],
+                        ..Default::default()
                     });
                     if col.relation_array {
                         match dso.query(rb, jwt, &qs).await {
@@ -177,6 +187,224 @@ pub async fn decode_relation_async(
     }
 }
 
+/**
+ * 将关联字段的值转换为可比较的文本，用于批量加载后将关联对象与记录对应
+ */
+fn relation_key_text(v: &Value) -> String {
+    match v {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/**
+ * 解析relation_middle，格式为：中间表(当前对象关联字段,关联对象关联字段)，如user_role(user_id,role_id)
+ */
+fn parse_relation_middle(middle: &str) -> Option<(String, String, String)> {
+    let (table, rest) = middle.trim().split_once('(')?;
+    let (left, right) = rest.trim_end().strip_suffix(')')?.split_once(',')?;
+    let (table, left, right) = (table.trim(), left.trim(), right.trim());
+    if table.is_empty() || left.is_empty() || right.is_empty() {
+        None
+    } else {
+        Some((table.to_owned(), left.to_owned(), right.to_owned()))
+    }
+}
+
+/**
+ * 使用IN查询批量加载关联对象，每批最多RELATION_BATCH_SIZE个值，返回的记录以relation_field的值分组
+ * 加载通过关联对象的query执行，因此脱敏以及数据权限的处理与单独查询时一致
+ */
+async fn load_relation_objects(
+    rb: Arc<dyn Executor>,
+    jwt: &JwtUserClaims,
+    dso: &DbStoreObject,
+    field: &str,
+    keys: &[Value],
+) -> Result<HashMap<String, Vec<Value>>, anyhow::Error> {
+    let prop = dso
+        .0
+        .fields
+        .iter()
+        .find(|c| c.field_name == field && c.col_type != Some("relation".to_owned()))
+        .map(|c| c.prop_name.clone().unwrap_or(c.field_name.clone()))
+        .unwrap_or(field.to_owned());
+    let mut grouped: HashMap<String, Vec<Value>> = HashMap::new();
+    for chunk in keys.chunks(RELATION_BATCH_SIZE) {
+        let mut qs = QueryCondition::default();
+        qs.and.push(ConditionItem {
+            field: field.to_owned(),
+            op: "in".to_string(),
+            value: Value::Array(chunk.to_vec()),
+            ..Default::default()
+        });
+        for row in dso.query(rb.clone(), jwt, &qs).await? {
+            let key = relation_key_text(row.get(&prop).unwrap_or(&Value::Null));
+            grouped.entry(key).or_default().push(row);
+        }
+    }
+    Ok(grouped)
+}
+
+/**
+ * 查询中间表，返回当前对象关联字段的值所对应的关联对象关联字段的值
+ */
+async fn load_relation_middle(
+    rb: Arc<dyn Executor>,
+    middle: &(String, String, String),
+    keys: &[Value],
+) -> Result<HashMap<String, Vec<Value>>, anyhow::Error> {
+    let (table, left, right) = middle;
+    let driver = get_executor_driver_name(rb.as_ref());
+    let mut mapping: HashMap<String, Vec<Value>> = HashMap::new();
+    for chunk in keys.chunks(RELATION_BATCH_SIZE) {
+        let qs = QueryCondition {
+            and: vec![ConditionItem {
+                field: left.clone(),
+                op: "in".to_string(),
+                value: Value::Array(chunk.to_vec()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let (cond, args) = qs.to_query(driver, true)?;
+        if !is_safe_identifier(table) || !is_safe_identifier(right) {
+            return Err(anyhow::anyhow!("Invalid relation middle table {table}"));
+        }
+        let sql = format!("select {left} as _left_, {right} as _right_ from {table} where {cond}");
+        let rs = rb.query(&sql, args.into_iter().map(|v| rbs::to_value!(v)).collect()).await?;
+        for row in rbatis::decode::<Vec<Value>>(rs)? {
+            let key = relation_key_text(row.get("_left_").unwrap_or(&Value::Null));
+            mapping.entry(key).or_default().push(row.get("_right_").cloned().unwrap_or(Value::Null));
+        }
+    }
+    Ok(mapping)
+}
+
+/**
+ * 批量加载记录中的关联对象，调用前rows中关联字段的属性保存的是关联字段的原始值
+ * 对整个结果集中的每个关联字段只执行一次IN查询（N..N关系先查询中间表），然后在内存中回填到每条记录中
+ */
+pub fn attach_relations<'a>(
+    rb: Arc<dyn Executor>,
+    jwt: &'a JwtUserClaims,
+    stconf: &'a StoreServiceConfig,
+    rows: &'a mut [Value],
+    cols: Vec<Column>,
+    ns: &'a str,
+) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>> {
+    Box::pin(async move {
+        for col in cols.iter().filter(|c| c.col_type.clone().unwrap_or_default().to_lowercase() == "relation") {
+            let prop = col.prop_name.clone().unwrap_or(col.field_name.clone());
+            let mut keys = vec![];
+            let mut seen = std::collections::HashSet::new();
+            for row in rows.iter() {
+                if let Some(v) = row.get(&prop).filter(|v| !v.is_null()) {
+                    if seen.insert(relation_key_text(v)) {
+                        keys.push(v.clone());
+                    }
+                }
+            }
+            if keys.is_empty() {
+                continue;
+            }
+
+            let sto = col.relation_object.clone().and_then(|f| stconf.get_object(&f));
+            let (sto, field) = match (sto, col.relation_field.clone()) {
+                (Some(sto), Some(field)) => (sto, field),
+                (None, _) => {
+                    log::warn!("Column was defined as relation but the relative object was not found in current namespace {ns}.");
+                    set_relation_null(rows, &prop);
+                    continue;
+                }
+                (_, None) => {
+                    log::warn!("Column was defined as relation but the relation field was not specifield.");
+                    set_relation_null(rows, &prop);
+                    continue;
+                }
+            };
+            let dso = DbStoreObject(sto.to_owned(), stconf.to_owned(), AuthorizationConfig::get());
+
+            let middle = match col.relation_middle.clone().filter(|f| !f.trim().is_empty()) {
+                Some(mid) => match parse_relation_middle(&mid) {
+                    Some(mid) => Some(mid),
+                    None => {
+                        log::warn!("Column was defined as N..N relation but the middle expression {mid} is invalid.");
+                        set_relation_null(rows, &prop);
+                        continue;
+                    }
+                },
+                None => None,
+            };
+
+            let mapping = match &middle {
+                Some(mid) => match load_relation_middle(rb.clone(), mid, &keys).await {
+                    Ok(mapping) => Some(mapping),
+                    Err(err) => {
+                        log::warn!("Could not query the relation middle table {}: {err}", mid.0);
+                        set_relation_null(rows, &prop);
+                        continue;
+                    }
+                },
+                None => None,
+            };
+
+            let targets = match &mapping {
+                Some(mp) => {
+                    let mut seen = std::collections::HashSet::new();
+                    mp.values()
+                        .flatten()
+                        .filter(|v| !v.is_null() && seen.insert(relation_key_text(v)))
+                        .cloned()
+                        .collect::<Vec<Value>>()
+                }
+                None => keys.clone(),
+            };
+
+            let grouped = match load_relation_objects(rb.clone(), jwt, &dso, &field, &targets).await {
+                Ok(grouped) => grouped,
+                Err(err) => {
+                    log::warn!("Could not load the relation object {}: {err}", dso.0.name);
+                    set_relation_null(rows, &prop);
+                    continue;
+                }
+            };
+
+            for row in rows.iter_mut() {
+                let key = match row.get(&prop).filter(|v| !v.is_null()) {
+                    Some(v) => relation_key_text(v),
+                    None => continue,
+                };
+                let val = match &mapping {
+                    Some(mp) => Value::Array(
+                        mp.get(&key)
+                            .map(|rights| {
+                                rights
+                                    .iter()
+                                    .flat_map(|r| grouped.get(&relation_key_text(r)).cloned().unwrap_or_default())
+                                    .collect()
+                            })
+                            .unwrap_or_default(),
+                    ),
+                    None if col.relation_array => Value::Array(grouped.get(&key).cloned().unwrap_or_default()),
+                    None => grouped.get(&key).and_then(|f| f.first().cloned()).unwrap_or(Value::Null),
+                };
+                if let Some(mp) = row.as_object_mut() {
+                    mp.insert(prop.clone(), val);
+                }
+            }
+        }
+    })
+}
+
+fn set_relation_null(rows: &mut [Value], prop: &str) {
+    for row in rows.iter_mut() {
+        if let Some(mp) = row.as_object_mut() {
+            mp.insert(prop.to_owned(), Value::Null);
+        }
+    }
+}
+
 pub async fn decode_val_by_type(
     _rb: Arc<dyn Executor>,
     _jwt: &JwtUserClaims,
@@ -309,6 +537,22 @@ pub async fn decode_map_custom_fields_list(
     rs: rbs::Value,
     fields: &Vec<Column>,
     ns: &str,
+) -> Result<Value, anyhow::Error> {
+    let mut rows = vec![decode_map_fields_list_plain(rb.clone(), jwt, stconf, rs, fields, ns).await?];
+    attach_relations(rb, jwt, stconf, &mut rows, fields.to_owned(), ns).await;
+    Ok(rows.pop().unwrap_or(Value::Null))
+}
+
+/**
+ * 解码一条记录，关联字段只保存其原始值，不加载关联对象
+ */
+async fn decode_map_fields_list_plain(
+    rb: Arc<dyn Executor>,
+    jwt: &JwtUserClaims,
+    stconf: &StoreServiceConfig,
+    rs: rbs::Value,
+    fields: &Vec<Column>,
+    ns: &str,
 ) -> Result<Value, anyhow::Error> {
     match rs {
         rbs::Value::Map(mp) => {
@@ -328,12 +572,8 @@ pub async fn decode_map_custom_fields_list(
                         let val = if col.col_type.clone().unwrap_or_default().to_lowercase()
                             == "relation"
                         {
-                            let rb_ = rb.clone();
-                            let jwt_ = jwt.clone();
-                            let stconf_ = stconf.clone();
-                            let ns_ = ns.to_owned().clone();
-                            let col_ = col.clone();
-                            decode_relation(rb_, jwt_.to_owned(), stconf_, v, ns_, col_).await
+                            // 先保存关联字段的原始值，由attach_relations批量加载
+                            rbatis::decode::<Value>(v).unwrap_or(Value::Null)
                         } else {
                             decode_val_by_type(rb.clone(), jwt, stconf, v, ns, col).await
                         };
@@ -354,6 +594,22 @@ pub async fn decode_map_custom_fields_map(
     rs: rbs::Value,
     fields: &HashMap<String, Column>,
     ns: &str,
+) -> Result<Value, anyhow::Error> {
+    let mut rows = vec![decode_map_fields_map_plain(rb.clone(), jwt, stconf, rs, fields, ns).await?];
+    attach_relations(rb, jwt, stconf, &mut rows, fields.values().cloned().collect(), ns).await;
+    Ok(rows.pop().unwrap_or(Value::Null))
+}
+
+/**
+ * 解码一条记录，关联字段只保存其原始值，不加载关联对象
+ */
+async fn decode_map_fields_map_plain(
+    rb: Arc<dyn Executor>,
+    jwt: &JwtUserClaims,
+    stconf: &StoreServiceConfig,
+    rs: rbs::Value,
+    fields: &HashMap<String, Column>,
+    ns: &str,
 ) -> Result<Value, anyhow::Error> {
     match rs {
         rbs::Value::Map(mp) => {
@@ -366,14 +622,8 @@ pub async fn decode_map_custom_fields_map(
                         let val = decode_val_by_type(rb.clone(), jwt, stconf, v, ns, col).await;
                         obj.insert(prop_name, val);
                     } else {
-                        let rb_ = rb.clone();
-                        let jwt_ = jwt.clone();
-                        let stconf_ = stconf.clone();
-                        let ns_ = ns.to_owned().clone();
-                        let col_ = col.clone();
-                        log::info!("Decode Relation: {ns_}/{col_:?}");
-                        let val = decode_relation(rb_, jwt_, stconf_, v, ns_, col_).await;
-                        obj.insert(prop_name, val);
+                        // 先保存关联字段的原始值，由attach_relations批量加载
+                        obj.insert(prop_name, rbatis::decode::<Value>(v).unwrap_or(Value::Null));
                     }
                 } else if let Ok(val) = rbatis::decode::<Value>(v) {
                     obj.insert(key, val);
@@ -389,14 +639,7 @@ pub async fn decode_map_custom_fields_map(
                         let val = if col.col_type.clone().unwrap_or_default().to_lowercase()
                             == "relation"
                         {
-                            let rb_ = rb.clone();
-                            let jwt_ = jwt.clone();
-                            let stconf_ = stconf.clone();
-                            let ns_ = ns.to_owned().clone();
-                            let col_ = col.clone();
-                            log::info!("Decode Relation: {ns_}/{col_:?}");
-                            let mtv = v.clone();
-                            decode_relation(rb_, jwt_, stconf_, mtv, ns_, col_).await
+                            rbatis::decode::<Value>(v.clone()).unwrap_or(Value::Null)
                         } else {
                             decode_val_by_type(rb.clone(), jwt, stconf, v.to_owned(), ns, col).await
                         };
@@ -424,7 +667,7 @@ pub async fn decode_vec_custom_fields_list(
             let mut rets = vec![];
             for tp in list {
                 let tv = if let Ok(ts) =
-                    decode_map_custom_fields_list(rb.clone(), jwt, stconf, tp, fields, ns).await
+                    decode_map_fields_list_plain(rb.clone(), jwt, stconf, tp, fields, ns).await
                 {
                     ts
                 } else {
@@ -432,6 +675,37 @@ pub async fn decode_vec_custom_fields_list(
                 };
                 rets.push(tv);
             }
+            attach_relations(rb, jwt, stconf, &mut rets, fields.to_owned(), ns).await;
+            Ok(rets)
+        }
+        _ => Ok(rbatis::decode::<Vec<Value>>(rs)?),
+    }
+}
+
+/**
+ * 解码记录的原始内容，不进行脱敏，关联字段只保存其原始值，用于审计的前后镜像
+ */
+pub(crate) async fn decode_vec_raw_fields_list(
+    rb: Arc<dyn Executor>,
+    jwt: &JwtUserClaims,
+    stconf: &StoreServiceConfig,
+    rs: rbs::Value,
+    fields: &[Column],
+    ns: &str,
+) -> Result<Vec<Value>, anyhow::Error> {
+    let raw_fields = fields
+        .iter()
+        .map(|c| Column {
+            desensitize: None,
+            ..c.clone()
+        })
+        .collect::<Vec<Column>>();
+    match rs {
+        rbs::Value::Array(list) => {
+            let mut rets = vec![];
+            for tp in list {
+                rets.push(decode_map_fields_list_plain(rb.clone(), jwt, stconf, tp, &raw_fields, ns).await?);
+            }
             Ok(rets)
         }
         _ => Ok(rbatis::decode::<Vec<Value>>(rs)?),
@@ -452,7 +726,7 @@ pub async fn decode_vec_custom_fields(
             let mut rets = vec![];
             for tp in list {
                 let tv = if let Ok(ts) =
-                    decode_map_custom_fields_map(rb.clone(), jwt, stconf, tp, fields, ns).await
+                    decode_map_fields_map_plain(rb.clone(), jwt, stconf, tp, fields, ns).await
                 {
                     ts
                 } else {
@@ -460,6 +734,7 @@ pub async fn decode_vec_custom_fields(
                 };
                 rets.push(tv);
             }
+            attach_relations(rb, jwt, stconf, &mut rets, fields.values().cloned().collect(), ns).await;
             Ok(rets)
         }
         _ => Ok(rbatis::decode::<Vec<Value>>(rs)?),
@@ -531,3 +806,49 @@ pub fn refine_column_value(t: &Value, col: &Column) -> Value {
     }
 }
 
+
+/**
+ * 解析count查询的结果，不同数据库返回的列名不同，取第一行的值
+ */
+pub(crate) fn decode_count_result(rs: rbs::Value) -> u64 {
+    match rbatis::decode::<Value>(rs) {
+        Ok(Value::Array(tm)) => match tm.first() {
+            Some(Value::Object(tx)) => tx.values().next_back().and_then(|v| v.as_u64()).unwrap_or(0u64),
+            Some(t) => t.as_u64().unwrap_or(0u64),
+            None => 0u64,
+        },
+        Ok(rts) => rts.as_u64().unwrap_or(0u64),
+        Err(e) => {
+            log::info!("Error {e}");
+            0u64
+        }
+    }
+}
+
+/**
+ * 游标分页会多查询一条记录用于判断是否存在下一页
+ * 截断多余的记录，并使用本页的最后一条记录生成下一页的游标
+ */
+pub(crate) fn split_cursor_page<F>(
+    rs: rbs::Value,
+    size: u64,
+    qs: &QueryCondition,
+    prop_of: F,
+) -> (rbs::Value, Option<String>)
+where
+    F: Fn(&str) -> Option<String>,
+{
+    match rs {
+        rbs::Value::Array(mut rows) if rows.len() as u64 > size => {
+            rows.truncate(size as usize);
+            let next_cursor = rows
+                .last()
+                .cloned()
+                .and_then(|last| rbatis::decode::<Vec<Value>>(rbs::Value::Array(vec![last])).ok())
+                .and_then(|mut rts| rts.pop())
+                .and_then(|row| qs.make_cursor(&row, prop_of));
+            (rbs::Value::Array(rows), next_cursor)
+        }
+        _ => (rs, None),
+    }
+}
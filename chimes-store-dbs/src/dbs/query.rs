@@ -1,7 +1,11 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use anyhow::{anyhow, Error};
 
+use chimes_dbs_factory::get_dialect;
+use chimes_dbs_factory::get_executor_dialect;
+use chimes_dbs_factory::get_executor_driver_name;
 use chimes_store_core::config::{
     auth::{AuthorizationConfig, JwtUserClaims},
     Column, QueryCondition, QueryObject, StoreServiceConfig,
@@ -10,7 +14,7 @@ use itertools::Itertools;
 use rbatis::{executor::Executor, IPageRequest, Page};
 use serde_json::Value;
 
-use crate::dbs::decode_vec_custom_fields;
+use crate::dbs::{decode_count_result, decode_vec_custom_fields, split_cursor_page};
 
 pub struct DbQueryObject(
     pub QueryObject,
@@ -93,6 +97,55 @@ impl DbQueryObject {
         }
     }
 
+    /**
+     * 校验查询条件中的字段及操作符，并将属性名转换为字段名
+     */
+    pub fn refine_condition(&self, qs: &QueryCondition) -> Result<QueryCondition, Error> {
+        Ok(qs
+            .refine_fields(|f| self.0.resolve_field_name(f))?
+            .mark_json_fields(|f| self.0.is_json_field(f)))
+    }
+
+    /**
+     * QueryObject的查询语句是固定的，投影只作用于返回结果，未请求的relation字段不会被加载
+     */
+    fn projected_fields_map(&self, qs: &QueryCondition) -> HashMap<String, Column> {
+        self.0
+            .fields_map()
+            .into_iter()
+            .filter(|(_, c)| {
+                !qs.has_projection()
+                    || c.col_type != Some("relation".to_owned())
+                    || qs.is_projected(&c.field_name, c.prop_name.as_deref())
+            })
+            .collect()
+    }
+
+    fn apply_projection(&self, qs: &QueryCondition, rows: Vec<Value>) -> Vec<Value> {
+        if !qs.has_projection() {
+            return rows;
+        }
+        rows.into_iter()
+            .map(|row| match row {
+                Value::Object(mut mp) => {
+                    mp.retain(|k, _| {
+                        match self
+                            .0
+                            .fields
+                            .iter()
+                            .find(|c| c.prop_name.as_deref() == Some(k.as_str()) || c.field_name == *k)
+                        {
+                            Some(c) => qs.is_projected(&c.field_name, c.prop_name.as_deref()),
+                            None => qs.is_projected(k, None),
+                        }
+                    });
+                    Value::Object(mp)
+                }
+                _ => row,
+            })
+            .collect()
+    }
+
     pub fn generate_permission_sql(&self) -> Option<String> {
         if self.0.data_permission && self.2.data_permission {
             let permit_sql = format!(
@@ -146,8 +199,9 @@ impl DbQueryObject {
         fix_param: &Value,
         qs: &QueryCondition,
     ) -> Result<Vec<Value>, Error> {
+        let qs = &self.refine_condition(qs)?;
         let mut sql = self.0.query_body.clone();
-        let (cond_sql, cond_args) = qs.to_query(false)?;
+        let (cond_sql, cond_args) = qs.to_query(get_executor_driver_name(rb.as_ref()), false)?;
 
         if !qs.is_empty_condition() {
             sql.push_str(" and ");
@@ -179,12 +233,12 @@ impl DbQueryObject {
                     jwt,
                     &self.1,
                     rs,
-                    &self.0.fields_map(),
+                    &self.projected_fields_map(qs),
                     &self.1.namespace,
                 )
                 .await
                 {
-                    Ok(mp) => Ok(mp),
+                    Ok(mp) => Ok(self.apply_projection(qs, mp)),
                     Err(err) => Err(err),
                 }
             }
@@ -202,6 +256,7 @@ impl DbQueryObject {
         fix_param: &Value,
         qs: &QueryCondition,
     ) -> Result<Page<Value>, Error> {
+        let qs = &self.refine_condition(qs)?;
         let mut sql = self.0.query_body.clone();
 
         let pagereq = match qs.to_page_request() {
@@ -232,7 +287,7 @@ impl DbQueryObject {
                 self.rewrite_count_sql(&csql)
             };
 
-        let (cond_sql, cond_args) = qs.to_query(false)?;
+        let (cond_sql, cond_args) = qs.to_query(get_executor_driver_name(rb.as_ref()), false)?;
 
         if !qs.is_empty_condition() {
             sql.push_str(" and ");
@@ -244,14 +299,7 @@ impl DbQueryObject {
             count_sql.push_str(&cond_sql);
         }
 
-        sql.push_str(
-            format!(
-                " limit {} offset {} ",
-                pagereq.page_size(),
-                pagereq.offset()
-            )
-            .as_str(),
-        );
+        let sql = get_executor_dialect(rb.as_ref()).paginate(&sql, pagereq.page_size(), pagereq.offset());
 
         let (rw_sql, mut fixed_args) = self.make_fixed_params_args(&sql, jwt, fix_param);
 
@@ -317,14 +365,14 @@ impl DbQueryObject {
                     jwt,
                     &self.1,
                     rs,
-                    &self.0.fields_map(),
+                    &self.projected_fields_map(qs),
                     &self.1.namespace,
                 )
                 .await
                 {
                     Ok(mp) => Ok(
                         Page::new_total(pagereq.page_no(), pagereq.page_size(), total)
-                            .set_records(mp),
+                            .set_records(self.apply_projection(qs, mp)),
                     ),
                     Err(err) => Err(err),
                 }
@@ -335,4 +383,119 @@ impl DbQueryObject {
             }
         }
     }
+
+    /**
+     * 游标（keyset）分页查询，QueryObject的fields中定义为pkey的字段将作为游标的一部分
+     * 未定义主键时，sorts中的字段必须能够唯一确定一条记录
+     */
+    pub async fn cursor_query(
+        &self,
+        rb: Arc<dyn Executor>,
+        jwt: &JwtUserClaims,
+        fix_param: &Value,
+        qs: &QueryCondition,
+    ) -> Result<(Page<Value>, Option<String>), Error> {
+        let pkeys = self
+            .0
+            .fields
+            .iter()
+            .filter(|f| f.pkey)
+            .map(|f| f.field_name.clone())
+            .collect_vec();
+        let qs = &self.refine_condition(qs)?.with_cursor_keys(&pkeys)?;
+        let paging = qs.paging.clone().unwrap_or_default();
+        let size = paging.size.max(1);
+        let driver = get_executor_driver_name(rb.as_ref());
+        let mut sql = self.0.query_body.clone();
+
+        if jwt.superadmin {
+            sql = sql.replace("${DATA_PERMISSION_SQL}", " ");
+        } else if let Some(psql) = self.generate_permission_sql() {
+            sql = sql.replace("${DATA_PERMISSION_SQL}", &psql);
+        }
+
+        let total = if paging.skip_count {
+            0u64
+        } else {
+            let mut count_sql =
+                if self.0.count_query.is_none() || self.0.count_query == Some(String::new()) {
+                    format!("select count(1) from ({}", sql.clone())
+                } else {
+                    let mut csql = self.0.count_query.clone().unwrap_or_default();
+                    if jwt.superadmin {
+                        csql = csql.replace("${DATA_PERMISSION_SQL}", " ");
+                    } else if let Some(psql) = self.generate_permission_sql() {
+                        csql = csql.replace("${DATA_PERMISSION_SQL}", &psql);
+                    }
+                    self.rewrite_count_sql(&csql)
+                };
+            let (count_cond, count_args) = qs.to_query(driver, true)?;
+            if !qs.is_empty_condition() {
+                count_sql.push_str(" and ");
+                count_sql.push_str(&count_cond);
+            }
+            if self.0.count_query.is_none() || self.0.count_query == Some(String::new()) {
+                count_sql.push_str(") a__");
+            }
+            let (rw_count_sql, mut fixed_count_args) =
+                self.make_fixed_params_args(&count_sql, jwt, fix_param);
+            fixed_count_args.append(
+                &mut count_args
+                    .into_iter()
+                    .map(|v| rbs::to_value!(v))
+                    .collect_vec(),
+            );
+            match rb.query(&rw_count_sql, fixed_count_args).await {
+                Ok(rs) => decode_count_result(rs),
+                Err(err) => {
+                    log::info!("Error : {}", err);
+                    0u64
+                }
+            }
+        };
+
+        let (cond_sql, cond_args) = qs.to_cursor_query(driver)?;
+        sql.push_str(" and ");
+        sql.push_str(&cond_sql);
+        let sql = get_dialect(driver).paginate(&sql, size + 1, 0);
+
+        let (rw_sql, mut fixed_args) = self.make_fixed_params_args(&sql, jwt, fix_param);
+        fixed_args.append(
+            &mut cond_args
+                .into_iter()
+                .map(|v| rbs::to_value!(v))
+                .collect_vec(),
+        );
+
+        log::info!("Query: {}", rw_sql.clone());
+
+        match rb.query(&rw_sql, fixed_args).await {
+            Ok(rs) => {
+                let (rs, next_cursor) = split_cursor_page(rs, size, qs, |f| {
+                    self.0
+                        .fields
+                        .iter()
+                        .find(|c| c.field_name == f)
+                        .and_then(|c| c.prop_name.clone())
+                });
+                let mp = decode_vec_custom_fields(
+                    rb.clone(),
+                    jwt,
+                    &self.1,
+                    rs,
+                    &self.projected_fields_map(qs),
+                    &self.1.namespace,
+                )
+                .await?;
+                let mut page = Page::new_total(1, size, total)
+                    .set_records(self.apply_projection(qs, mp));
+                page.do_count = !paging.skip_count;
+                Ok((page, next_cursor))
+            }
+            Err(err) => {
+                log::info!("test error : {:?}", err);
+                Err(anyhow::Error::new(err))
+            }
+        }
+    }
 }
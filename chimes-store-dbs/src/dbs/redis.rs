@@ -2,9 +2,7 @@ use anyhow::anyhow;
 use chimes_store_core::service::invoker::InvocationContext;
 use chimes_store_core::service::sdk::Invocation;
 use chimes_store_core::service::sdk::InvokeUri;
-use chimes_store_core::utils::redis::redis_del;
-use chimes_store_core::utils::redis::redis_get;
-use chimes_store_core::utils::redis::redis_set;
+use chimes_store_core::utils::redis_cmd::{check_redis_permission, redis_invoke};
 use futures_lite::Future;
 use serde_json::Value;
 use std::pin::Pin;
@@ -14,39 +12,36 @@ use std::sync::Mutex;
 pub struct RedisInvocation();
 
 /**
- * TODO: 实现Redis的请求命令
- * 目前只实现了GET/SET/DEL三个命令，后续继续实现
+ * Redis的请求命令，key为InvokeUri的query，如redis://ns/redis?user:1#hgetall
+ * 支持的方法及参数见redis_invoke，执行前按ctx中的用户及角色检查方法的权限
  */
+fn invoke_redis(
+    uri: &InvokeUri,
+    ctx: &Arc<Mutex<InvocationContext>>,
+    args: &[Value],
+) -> Result<Value, anyhow::Error> {
+    check_redis_permission(&uri.namespace, &uri.method, &ctx.lock().unwrap())?;
+    redis_invoke(
+        &uri.namespace,
+        &uri.method,
+        &uri.query.clone().unwrap_or_default(),
+        args,
+    )
+}
 
 impl Invocation for RedisInvocation {
     fn invoke_return_option(
         &'static self,
         uri: &InvokeUri,
-        _ctx: Arc<Mutex<InvocationContext>>,
+        ctx: Arc<Mutex<InvocationContext>>,
         args: &[Value],
     ) -> Pin<Box<dyn Future<Output = Result<Option<Value>, anyhow::Error>> + Send>> {
-        let res = match uri.method.as_str() {
-            "get" => redis_get(&uri.namespace, &uri.query.clone().unwrap_or_default()),
-            "set" => {
-                let text = args[0].as_str();
-                redis_set(
-                    &uri.namespace,
-                    &uri.query.clone().unwrap_or_default(),
-                    text.unwrap_or_default(),
-                )
-            }
-            "del" => redis_del(&uri.namespace, &uri.query.clone().unwrap_or_default()),
-            _ => Err(anyhow!("Not implemented")),
-        };
+        let res = invoke_redis(uri, &ctx, args);
 
         Box::pin(async move {
-            if let Some(text) = res? {
-                match serde_json::from_str::<Value>(&text) {
-                    Ok(t) => Ok(Some(t)),
-                    Err(_) => Ok(Some(Value::String(text))),
-                }
-            } else {
-                Ok(None)
+            match res? {
+                Value::Null => Ok(None),
+                t => Ok(Some(t)),
             }
         })
     }
@@ -54,31 +49,17 @@ impl Invocation for RedisInvocation {
     fn invoke_return_vec(
         &'static self,
         uri: &InvokeUri,
-        _ctx: Arc<Mutex<InvocationContext>>,
+        ctx: Arc<Mutex<InvocationContext>>,
         args: &[Value],
     ) -> Pin<Box<dyn Future<Output = Result<Vec<Value>, anyhow::Error>> + Send>> {
-        let res = match uri.method.as_str() {
-            "get" => redis_get(&uri.namespace, &uri.query.clone().unwrap_or_default()),
-            "set" => {
-                let text = args[0].as_str();
-                redis_set(
-                    &uri.namespace,
-                    &uri.query.clone().unwrap_or_default(),
-                    text.unwrap_or_default(),
-                )
-            }
-            "del" => redis_del(&uri.namespace, &uri.query.clone().unwrap_or_default()),
-            _ => Err(anyhow!("Not implemented")),
-        };
+        let res = invoke_redis(uri, &ctx, args);
 
         Box::pin(async move {
-            if let Some(text) = res? {
-                match serde_json::from_str::<Vec<Value>>(&text) {
-                    Ok(t) => Ok(t),
-                    Err(err) => Err(anyhow!(err)),
-                }
-            } else {
-                Ok(vec![])
+            match res? {
+                Value::Null => Ok(vec![]),
+                Value::Array(t) => Ok(t),
+                Value::Object(mp) => Ok(vec![Value::Object(mp)]),
+                t => Err(anyhow!("The value {} is not a list.", t)),
             }
         })
     }
@@ -86,31 +67,19 @@ impl Invocation for RedisInvocation {
     fn invoke_return_page(
         &'static self,
         uri: &InvokeUri,
-        _ctx: Arc<Mutex<InvocationContext>>,
+        ctx: Arc<Mutex<InvocationContext>>,
         args: &[Value],
     ) -> Pin<Box<dyn Future<Output = Result<rbatis::Page<Value>, anyhow::Error>> + Send>> {
-        let res = match uri.method.as_str() {
-            "get" => redis_get(&uri.namespace, &uri.query.clone().unwrap_or_default()),
-            "set" => {
-                let text = args[0].as_str();
-                redis_set(
-                    &uri.namespace,
-                    &uri.query.clone().unwrap_or_default(),
-                    text.unwrap_or_default(),
-                )
-            }
-            "del" => redis_del(&uri.namespace, &uri.query.clone().unwrap_or_default()),
-            _ => Err(anyhow!("Not implemented")),
-        };
+        let res = invoke_redis(uri, &ctx, args);
 
         Box::pin(async move {
-            if let Some(text) = res? {
-                match serde_json::from_str::<rbatis::Page<Value>>(&text) {
-                    Ok(t) => Ok(t),
-                    Err(err) => Err(anyhow!(err)),
+            match res? {
+                Value::Null => Ok(rbatis::Page::new_total(0, 0, 0)),
+                Value::Array(t) => {
+                    let total = t.len() as u64;
+                    Ok(rbatis::Page::new(1, total, total, t))
                 }
-            } else {
-                Ok(rbatis::Page::new_total(0, 0, 0))
+                t => serde_json::from_value::<rbatis::Page<Value>>(t).map_err(|err| anyhow!(err)),
             }
         })
     }
@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use chimes_store_core::config::{Column, FieldValidationError, StoreObject};
+use chimes_store_core::service::invoker::InvocationContext;
+use chimes_store_core::service::script::ExtensionRegistry;
+use regex::Regex;
+use serde_json::{json, Value};
+
+lazy_static::lazy_static! {
+    static ref EMAIL_REGEX: Regex = Regex::new(r"^[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,}$").unwrap();
+    static ref PHONE_REGEX: Regex = Regex::new(r"^(\+?86-?)?(1[3-9]\d{9}|0\d{2,3}-?\d{7,8})$").unwrap();
+    static ref REGEX_CACHE: Mutex<HashMap<String, Regex>> = Mutex::new(HashMap::new());
+}
+
+/**
+ * 字段验证规则，由Column.validation解析而来
+ * 多个规则以;分隔，如：required;length:1,32;regex:^[a-z]+$
+ * regex和rhai会把其后的全部内容作为表达式，因此需要放在最后
+ */
+#[derive(Debug, Clone)]
+enum FieldRule {
+    Required,
+    Length(Option<usize>, Option<usize>),
+    Range(Option<f64>, Option<f64>),
+    Regex(String),
+    Enum(Vec<String>),
+    Email,
+    Phone,
+    IdCard,
+    Rhai(String),
+}
+
+fn parse_bound<T: std::str::FromStr>(text: Option<&str>) -> Option<T> {
+    text.map(|f| f.trim()).filter(|f| !f.is_empty()).and_then(|f| f.parse::<T>().ok())
+}
+
+fn parse_rules(expr: &str) -> Result<Vec<FieldRule>, String> {
+    let mut rules = vec![];
+    let mut rest = expr.trim();
+    while !rest.is_empty() {
+        let (name, _) = rest.split_once(':').unwrap_or((rest, ""));
+        let name = name.trim().to_lowercase();
+        if name == "regex" || name == "rhai" {
+            let arg = rest.split_once(':').map(|(_, t)| t.trim().to_owned()).unwrap_or_default();
+            rules.push(if name == "regex" { FieldRule::Regex(arg) } else { FieldRule::Rhai(arg) });
+            break;
+        }
+        let (seg, next) = rest.split_once(';').unwrap_or((rest, ""));
+        let (name, arg) = match seg.split_once(':') {
+            Some((n, a)) => (n.trim().to_lowercase(), a.trim()),
+            None => (seg.trim().to_lowercase(), ""),
+        };
+        let mut bounds = arg.splitn(2, ',');
+        let rule = match name.as_str() {
+            "" => None,
+            "required" => Some(FieldRule::Required),
+            "length" | "len" => Some(FieldRule::Length(parse_bound(bounds.next()), parse_bound(bounds.next()))),
+            "range" => Some(FieldRule::Range(parse_bound(bounds.next()), parse_bound(bounds.next()))),
+            "enum" => Some(FieldRule::Enum(arg.split(',').map(|f| f.trim().to_owned()).collect())),
+            "email" => Some(FieldRule::Email),
+            "phone" | "mobile" => Some(FieldRule::Phone),
+            "idcard" | "id_card" => Some(FieldRule::IdCard),
+            _ => return Err(format!("unknown validation rule {name}")),
+        };
+        if let Some(rule) = rule {
+            rules.push(rule);
+        }
+        rest = next.trim();
+    }
+    Ok(rules)
+}
+
+/**
+ * 检查18位身份证号码的校验位，15位的旧号码只检查格式
+ */
+fn is_valid_idcard(text: &str) -> bool {
+    let chars = text.chars().collect::<Vec<char>>();
+    match chars.len() {
+        15 => chars.iter().all(|c| c.is_ascii_digit()),
+        18 => {
+            if !chars[..17].iter().all(|c| c.is_ascii_digit()) {
+                return false;
+            }
+            const WEIGHTS: [u32; 17] = [7, 9, 10, 5, 8, 4, 2, 1, 6, 3, 7, 9, 10, 5, 8, 4, 2];
+            const CHECKS: [char; 11] = ['1', '0', 'X', '9', '8', '7', '6', '5', '4', '3', '2'];
+            let sum: u32 = chars[..17]
+                .iter()
+                .zip(WEIGHTS.iter())
+                .map(|(c, w)| c.to_digit(10).unwrap_or(0) * w)
+                .sum();
+            CHECKS[(sum % 11) as usize] == chars[17].to_ascii_uppercase()
+        }
+        _ => false,
+    }
+}
+
+fn value_text(val: &Value) -> String {
+    match val {
+        Value::String(s) => s.clone(),
+        _ => val.to_string(),
+    }
+}
+
+fn value_number(val: &Value) -> Option<f64> {
+    match val {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse::<f64>().ok(),
+        _ => None,
+    }
+}
+
+fn match_regex(pattern: &str, text: &str) -> Result<bool, String> {
+    let mut cache = REGEX_CACHE.lock().unwrap();
+    if !cache.contains_key(pattern) {
+        let re = Regex::new(pattern).map_err(|err| format!("invalid regex {pattern}: {err}"))?;
+        cache.insert(pattern.to_owned(), re);
+    }
+    Ok(cache[pattern].is_match(text))
+}
+
+/**
+ * 执行rhai表达式，参数为[字段值, 整个对象]
+ * 返回null或true表示通过，false表示不通过，返回字符串则作为错误描述（空字符串表示通过）
+ */
+fn eval_rhai_rule(script: &str, val: &Value, obj: &Value) -> Option<String> {
+    let func = match ExtensionRegistry::get_extension("rhai").and_then(|f| f.fn_return_option_script) {
+        Some(func) => func,
+        None => return Some("rhai script engine is not available".to_owned()),
+    };
+    let ctx = Arc::new(Mutex::new(InvocationContext::new()));
+    match func(script, ctx, &[val.clone(), obj.clone()]) {
+        Ok(None) | Ok(Some(Value::Null)) | Ok(Some(Value::Bool(true))) => None,
+        Ok(Some(Value::String(msg))) if msg.is_empty() => None,
+        Ok(Some(Value::String(msg))) => Some(msg),
+        Ok(Some(_)) => Some("is invalid".to_owned()),
+        Err(err) => Some(format!("validation script error: {err}")),
+    }
+}
+
+fn is_blank(val: Option<&Value>) -> bool {
+    match val {
+        None | Some(Value::Null) => true,
+        Some(Value::String(s)) => s.trim().is_empty(),
+        _ => false,
+    }
+}
+
+/**
+ * 按规则验证一个字段，返回该字段的全部错误
+ * 字段的值为空时只检查required规则
+ */
+fn validate_column(col: &Column, rules: &[FieldRule], val: Option<&Value>, obj: &Value) -> Vec<String> {
+    let mut errs = vec![];
+    if is_blank(val) {
+        if rules.iter().any(|r| matches!(r, FieldRule::Required)) {
+            errs.push("is required".to_owned());
+        }
+        return errs;
+    }
+    let val = val.unwrap();
+    let text = value_text(val);
+    for rule in rules {
+        let err = match rule {
+            FieldRule::Required => None,
+            FieldRule::Length(min, max) => {
+                let len = text.chars().count();
+                if min.map(|m| len < m).unwrap_or(false) || max.map(|m| len > m).unwrap_or(false) {
+                    Some(format!(
+                        "length should be between {} and {}",
+                        min.map(|m| m.to_string()).unwrap_or("0".to_owned()),
+                        max.map(|m| m.to_string()).unwrap_or("*".to_owned())
+                    ))
+                } else {
+                    None
+                }
+            }
+            FieldRule::Range(min, max) => match value_number(val) {
+                Some(n) if min.map(|m| n < m).unwrap_or(false) || max.map(|m| n > m).unwrap_or(false) => Some(format!(
+                    "should be between {} and {}",
+                    min.map(|m| m.to_string()).unwrap_or("*".to_owned()),
+                    max.map(|m| m.to_string()).unwrap_or("*".to_owned())
+                )),
+                Some(_) => None,
+                None => Some("should be a number".to_owned()),
+            },
+            FieldRule::Regex(pattern) => match match_regex(pattern, &text) {
+                Ok(true) => None,
+                Ok(false) => Some("does not match the pattern".to_owned()),
+                Err(err) => Some(err),
+            },
+            FieldRule::Enum(items) => {
+                if items.contains(&text) {
+                    None
+                } else {
+                    Some(format!("should be one of {}", items.join(",")))
+                }
+            }
+            FieldRule::Email => (!EMAIL_REGEX.is_match(&text)).then(|| "is not a valid email".to_owned()),
+            FieldRule::Phone => (!PHONE_REGEX.is_match(&text)).then(|| "is not a valid phone number".to_owned()),
+            FieldRule::IdCard => (!is_valid_idcard(&text)).then(|| "is not a valid id card number".to_owned()),
+            FieldRule::Rhai(script) => eval_rhai_rule(script, val, obj),
+        };
+        if let Some(err) = err {
+            log::debug!("validate {} failed: {}", col.field_name, err);
+            errs.push(err);
+        }
+    }
+    errs
+}
+
+/**
+ * 根据Column.validation验证写入的对象，StoreObject.validation为false时不验证
+ * parti_valid为true时只验证对象中提供了的字段，否则所有配置了规则的字段都会被验证
+ * 验证失败返回FieldValidationError，包含每个属性的错误描述
+ */
+pub fn validate_fields(val: &Value, st: &StoreObject) -> Result<(), FieldValidationError> {
+    if !st.validation {
+        return Ok(());
+    }
+    let mut verr = FieldValidationError::new(&st.name);
+    let empty = json!({});
+    let obj = if val.is_object() { val } else { &empty };
+    for col in st.fields.iter() {
+        let expr = match col.validation.clone().filter(|f| !f.trim().is_empty()) {
+            Some(expr) => expr,
+            None => continue,
+        };
+        let prop = col.prop_name.clone().unwrap_or(col.field_name.clone());
+        let present = obj.get(&prop);
+        if st.parti_valid && present.is_none() {
+            continue;
+        }
+        match parse_rules(&expr) {
+            Ok(rules) => {
+                for err in validate_column(col, &rules, present, obj) {
+                    verr.add_error(&prop, &err);
+                }
+            }
+            Err(err) => verr.add_error(&prop, &err),
+        }
+    }
+    if verr.is_empty() {
+        Ok(())
+    } else {
+        Err(verr)
+    }
+}
@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 
 use chimes_store_core::{
-    config::{QueryObject, ServerConfig, StoreObject},
+    config::{QueryObject, ServerConfig, StoreObject, AGGREGATE_FUNCTIONS, CONDITION_OPERATORS},
     service::starter::MxStoreService,
 };
 use salvo::oapi::{
@@ -44,6 +44,31 @@ fn to_api_result_schema(t: RefOr<Schema>, array: bool) -> schema::Schema {
     schema::Schema::Object(apiresult)
 }
 
+fn to_api_result_batch_schema(t: RefOr<Schema>) -> schema::Schema {
+    let mut apiresult = Object::new();
+    apiresult = apiresult.property(
+        "affect_rows",
+        Object::new().schema_type(schema::BasicType::Integer),
+    );
+    apiresult = apiresult.property("inserted", t.clone().to_array());
+    apiresult = apiresult.property("updated", t.clone().to_array());
+    let failed = Object::new()
+        .property("index", Object::new().schema_type(schema::BasicType::Integer))
+        .property("row", t)
+        .property(
+            "reason",
+            Object::new().schema_type(schema::BasicType::String),
+        )
+        .property(
+            "code",
+            Object::new()
+                .schema_type(schema::BasicType::String)
+                .description("invalid为校验失败（包括未提供版本号），stale为版本号冲突"),
+        );
+    apiresult = apiresult.property("failed", failed.to_array());
+    to_api_result_schema(RefOr::Type(schema::Schema::Object(apiresult)), false)
+}
+
 fn to_api_result_page_schema(t: RefOr<Schema>) -> schema::Schema {
     let mut apiresult = Object::new();
     apiresult = apiresult.property(
@@ -63,6 +88,12 @@ fn to_api_result_page_schema(t: RefOr<Schema>) -> schema::Schema {
         Object::new().schema_type(schema::BasicType::Integer),
     );
     apiresult = apiresult.property("records", t.to_array());
+    apiresult = apiresult.property(
+        "next_cursor",
+        Object::new()
+            .schema_type(schema::BasicType::String)
+            .description("游标分页时下一页的游标，作为paging.after传入以查询下一页，没有下一页时不返回"),
+    );
     to_api_result_schema(RefOr::Type(schema::Schema::Object(apiresult)), false)
 }
 
@@ -78,7 +109,7 @@ fn to_add_query_condition(t: Schema) -> schema::Schema {
             "op",
             Object::new()
                 .schema_type(schema::BasicType::String)
-                .description("字段名称"),
+                .description(format!("操作符，可选值：{}", CONDITION_OPERATORS.join(", "))),
         )
         .property(
             "value",
@@ -135,6 +166,50 @@ fn to_add_query_condition(t: Schema) -> schema::Schema {
             Object::new()
                 .schema_type(schema::BasicType::Integer)
                 .description("分页记录数"),
+        )
+        .property(
+            "cursor",
+            Object::new()
+                .schema_type(schema::BasicType::Boolean)
+                .description("启用游标（keyset）分页，按sorts及主键定位，不使用current/offset"),
+        )
+        .property(
+            "after",
+            Object::new()
+                .schema_type(schema::BasicType::String)
+                .description("上一页返回的next_cursor，提供该值时自动启用游标分页，不可修改sorts"),
+        )
+        .property(
+            "skip_count",
+            Object::new()
+                .schema_type(schema::BasicType::Boolean)
+                .description("游标分页时不执行count查询，total返回0"),
+        );
+
+    let ai = Object::new()
+        .property(
+            "func",
+            Object::new()
+                .schema_type(schema::BasicType::String)
+                .description(format!("聚合函数，可选值：{}", AGGREGATE_FUNCTIONS.join(", "))),
+        )
+        .property(
+            "field",
+            Object::new()
+                .schema_type(schema::BasicType::String)
+                .description("聚合的字段，count可以使用*"),
+        )
+        .property(
+            "distinct",
+            Object::new()
+                .schema_type(schema::BasicType::Boolean)
+                .description("是否去重，如count(distinct field)"),
+        )
+        .property(
+            "alias",
+            Object::new()
+                .schema_type(schema::BasicType::String)
+                .description("结果中的键名，缺省为func_field"),
         );
 
     let qc = Object::new()
@@ -142,7 +217,7 @@ fn to_add_query_condition(t: Schema) -> schema::Schema {
             "and",
             ci.clone()
                 .to_array()
-                .description("组合成AND条件字段及条件表达"),
+                .description("组合成AND条件字段及条件表达，字段可以使用关联对象的属性，如dept.name、roles.code"),
         )
         .property(
             "or",
@@ -152,7 +227,7 @@ fn to_add_query_condition(t: Schema) -> schema::Schema {
         )
         .property(
             "sorts",
-            oi.clone().to_array().description("需要进行排序的字段"),
+            oi.clone().to_array().description("需要进行排序的字段，可以使用一对一关联对象的属性，如dept.name"),
         )
         .property(
             "group_by",
@@ -160,7 +235,39 @@ fn to_add_query_condition(t: Schema) -> schema::Schema {
                 .to_array()
                 .description("分组查询中需要列出的字段"),
         )
-        .property("paging", pg.clone().description("分页"));
+        .property("paging", pg.clone().description("分页"))
+        .property(
+            "aggregates",
+            ai.clone()
+                .to_array()
+                .description("聚合表达式，仅用于aggregate"),
+        )
+        .property(
+            "having",
+            ci.clone()
+                .to_array()
+                .description("对聚合结果的过滤条件，字段为聚合的别名或分组字段，仅用于aggregate"),
+        )
+        .property(
+            "fields",
+            Object::new()
+                .schema_type(schema::BasicType::String)
+                .to_array()
+                .description("需要返回的字段（属性名或字段名），为空时返回全部字段，未列出的关联对象不会被加载"),
+        )
+        .property(
+            "exclude_fields",
+            Object::new()
+                .schema_type(schema::BasicType::String)
+                .to_array()
+                .description("不需要返回的字段（属性名或字段名）"),
+        )
+        .property(
+            "include_deleted",
+            Object::new()
+                .schema_type(schema::BasicType::Boolean)
+                .description("包含已软删除的记录，仅对超级管理员有效"),
+        );
 
     if let Schema::Object(xmp) = t {
         let mut mp = xmp.clone();
@@ -365,7 +472,7 @@ impl ToSchemaObjects for StoreObject {
                 .add_content("application/json", sch_ref.clone()),
         );
         ins_op = ins_op.summary(format!("对象{}的新增操作", self.name.clone()));
-        ins_op = ins_op.description(format!("对表{}执行数据库的insert操作，如果表中有自增ID，则该自增ID应留空，返回值中将会包含所产生的自增ID。", self.object_name.clone()));
+        ins_op = ins_op.description(format!("对表{}执行数据库的insert操作，如果表中有自增ID，则该自增ID应留空，返回值中将会包含所产生的自增ID。对象开启了字段验证时，验证失败返回400，data中为每个属性的错误描述。", self.object_name.clone()));
 
         let mut resp = Response::new("返回新增后的对象");
         resp = resp.add_content(
@@ -387,7 +494,7 @@ impl ToSchemaObjects for StoreObject {
                 .add_content("application/json", sch_ref.clone()),
         );
         ins_op = ins_op.summary(format!("对象{}的更新操作", self.name.clone()));
-        ins_op = ins_op.description(format!("对表{}执行数据库的update操作。作为该对象的主键必须提供。更新操作时，如果内容没有被修改，则不会对该字段进行更新。", self.object_name.clone()));
+        ins_op = ins_op.description(format!("对表{}执行数据库的update操作。作为该对象的主键必须提供。更新操作时，如果内容没有被修改，则不会对该字段进行更新。对象定义了version生成器的字段时，会以传入的版本号作为乐观锁条件，记录已被修改时返回409（stale record）错误。", self.object_name.clone()));
 
         let mut resp = Response::new("返回更新后的对象");
         resp = resp.add_content(
@@ -411,7 +518,7 @@ impl ToSchemaObjects for StoreObject {
         );
         ins_op = ins_op.summary(format!("对象{}的删除操作", self.name.clone()));
         ins_op = ins_op.description(format!(
-            "对表{}执行数据库的按主键删除操作。作为该对象的主键必须提供。主键必须提供。",
+            "对表{}执行数据库的按主键删除操作。作为该对象的主键必须提供。主键必须提供。启用软删除时只设置删除标记。",
             self.object_name.clone()
         ));
 
@@ -427,6 +534,58 @@ impl ToSchemaObjects for StoreObject {
             PathItem::new(salvo::oapi::PathItemType::Post, ins_op),
         );
 
+        if self.soft_delete_field.is_some() {
+            //restore
+            let mut ins_op = Operation::new();
+            ins_op = ins_op.request_body(
+                RequestBody::new()
+                    .required(salvo::oapi::Required::True)
+                    .add_content("application/json", schpk_ref.clone()),
+            );
+            ins_op = ins_op.summary(format!("对象{}的恢复操作", self.name.clone()));
+            ins_op = ins_op.description(format!(
+                "恢复表{}中已软删除的记录，同时清空deleted_at/deleted_by字段。主键必须提供。",
+                self.object_name.clone()
+            ));
+
+            let mut resp = Response::new("返回恢复的对象");
+            resp = resp.add_content(
+                "application/json",
+                Content::new(to_api_result_schema(sch_ref.clone(), false)),
+            );
+            ins_op = ins_op.add_response("200", RefOr::Type(resp));
+
+            hash.insert(
+                format!("/api/object/{}/{}/restore", ns, self.name.clone()),
+                PathItem::new(salvo::oapi::PathItemType::Post, ins_op),
+            );
+
+            //purge
+            let mut ins_op = Operation::new();
+            ins_op = ins_op.request_body(
+                RequestBody::new()
+                    .required(salvo::oapi::Required::True)
+                    .add_content("application/json", schpk_ref.clone()),
+            );
+            ins_op = ins_op.summary(format!("对象{}的彻底删除操作", self.name.clone()));
+            ins_op = ins_op.description(format!(
+                "从表{}中物理删除已软删除的记录，未被软删除的记录不会被删除。主键必须提供。",
+                self.object_name.clone()
+            ));
+
+            let mut resp = Response::new("返回删除的对象");
+            resp = resp.add_content(
+                "application/json",
+                Content::new(to_api_result_schema(sch_ref.clone(), false)),
+            );
+            ins_op = ins_op.add_response("200", RefOr::Type(resp));
+
+            hash.insert(
+                format!("/api/object/{}/{}/purge", ns, self.name.clone()),
+                PathItem::new(salvo::oapi::PathItemType::Post, ins_op),
+            );
+        }
+
         //upsert
         let mut ins_op = Operation::new();
         let upsert_req = to_add_query_condition(sch.clone());
@@ -436,7 +595,7 @@ impl ToSchemaObjects for StoreObject {
                 .add_content("application/json", RefOr::Type(upsert_req.clone())),
         );
         ins_op = ins_op.summary(format!("对象{}的更新或新增操作", self.name.clone()));
-        ins_op = ins_op.description(format!("对表{}执行数据库的update或insert操作。作为该对象的主键如果没有提供，则执行insert操作，否则，会根据主键执行查询，如果主键查询有对应的记录，则执行update，没有则执行insert操作。更新操作时，如果内容没有被修改，则不会对该字段进行更新。同时，如果传入的对象中，有包含_cond的QueryCondition对象，则会根据_cond所表示的查询条件来执行查询，进而判断是否执行相应的操作。", self.object_name.clone()));
+        ins_op = ins_op.description(format!("对表{}执行数据库的update或insert操作。作为该对象的主键如果没有提供，则执行insert操作，否则，会根据主键执行查询，如果主键查询有对应的记录，则执行update，没有则执行insert操作。更新操作时，如果内容没有被修改，则不会对该字段进行更新。同时，如果传入的对象中，有包含_cond的QueryCondition对象，则会根据_cond所表示的查询条件来执行查询，进而判断是否执行相应的操作。对象定义了version生成器的字段时，会以传入的版本号作为乐观锁条件，记录已被修改时返回409（stale record）错误。", self.object_name.clone()));
 
         let mut resp = Response::new("返回更新后或插入的对象");
         resp = resp.add_content(
@@ -465,12 +624,12 @@ impl ToSchemaObjects for StoreObject {
                 .add_content("application/json", RefOr::Type(savebatch_req.clone())),
         );
         savebatch_op = savebatch_op.summary(format!("对象{}的批量更新或新增操作", self.name.clone()));
-        savebatch_op = savebatch_op.description(format!("对表{}执行数据库的upsert操作，接口通过POST接收对象的JSON结构，且，按照upsert的机制，数组中的元素中，可以附带有_cond的属性，用于确定对象的唯一性。作为该对象的主键如果没有提供，则执行insert操作，否则，会根据主键执行查询，如果主键查询有对应的记录，则执行update，没有则执行insert操作。更新操作时，如果内容没有被修改，则不会对该字段进行更新。同时，如果传入的对象中，有包含_cond的QueryCondition对象，则会根据_cond所表示的查询条件来执行查询，进而判断是否执行相应的操作。", self.object_name.clone()));
+        savebatch_op = savebatch_op.description(format!("对表{}执行数据库的upsert操作，接口通过POST接收对象的JSON结构，且，按照upsert的机制，数组中的元素中，可以附带有_cond的属性，用于确定对象的唯一性。作为该对象的主键如果没有提供，则执行insert操作，否则，会根据主键执行查询，如果主键查询有对应的记录，则执行update，没有则执行insert操作。更新操作时，如果内容没有被修改，则不会对该字段进行更新。同时，如果传入的对象中，有包含_cond的QueryCondition对象，则会根据_cond所表示的查询条件来执行查询，进而判断是否执行相应的操作。没有_cond的记录会按字段分组后使用多行插入及数据库原生的upsert语法（mysql为on duplicate key update，postgres/sqlite为on conflict，mssql为merge）批量保存，新增或更新按主键是否已经存在来区分。校验失败或版本号冲突的记录不会保存，在failed中返回其序号、原因及code（invalid/stale），字段验证失败时errors为每个属性的错误描述。", self.object_name.clone()));
 
-        let mut resp = Response::new("返回更新后或插入的对象列表");
+        let mut resp = Response::new("返回新增、更新及失败的对象列表");
         resp = resp.add_content(
             "application/json",
-            Content::new(to_api_result_schema(sch_ref.clone(), true)),
+            Content::new(to_api_result_batch_schema(sch_ref.clone())),
         );
         savebatch_op = savebatch_op.add_response("200", RefOr::Type(resp));
 
@@ -511,7 +670,7 @@ impl ToSchemaObjects for StoreObject {
                 .add_content("application/json", RefOr::Type(upsert_req.clone())),
         );
         ins_op = ins_op.summary(format!("对象{}的按条件批量更新操作", self.name.clone()));
-        ins_op = ins_op.description(format!("对表{}执行数据库的update操作。传入的对象中基础的该对象的要更新的数据，_cond为更新时的批量条件。", self.object_name.clone()));
+        ins_op = ins_op.description(format!("对表{}执行数据库的update操作。传入的对象中基础的该对象的要更新的数据，_cond为更新时的批量条件。对象定义了version生成器的字段时，会以传入的版本号作为乐观锁条件，记录已被修改时返回409（stale record）错误。", self.object_name.clone()));
 
         let mut resp = Response::new("返回受影响的记录数");
         resp = resp.add_content(
@@ -618,7 +777,7 @@ impl ToSchemaObjects for StoreObject {
                 ),
         );
         ins_op = ins_op.summary(format!("对象{}的唯一记录查询", self.name.clone()));
-        ins_op = ins_op.description(format!("对表{}执行数据库的按SELECT操作，返回零条或一条件记录。请求体传递QueryCondition，且QueryCondition中的paging条件必须提供", self.object_name.clone()));
+        ins_op = ins_op.description(format!("对表{}执行数据库的按SELECT操作，返回零条或一条件记录。请求体传递QueryCondition，且QueryCondition中的paging条件必须提供。paging.cursor为true或提供了paging.after时使用游标分页，返回next_cursor用于查询下一页", self.object_name.clone()));
 
         let mut resp = Response::new("返回查询到的对象分页列表");
         resp = resp.add_content(
@@ -632,6 +791,69 @@ impl ToSchemaObjects for StoreObject {
             PathItem::new(salvo::oapi::PathItemType::Post, ins_op),
         );
 
+        //aggregate
+        let mut ins_op = Operation::new();
+        ins_op = ins_op.request_body(
+            RequestBody::new()
+                .required(salvo::oapi::Required::True)
+                .add_content(
+                    "application/json",
+                    RefOr::Type(to_add_query_condition(schema::Schema::Array(
+                        Object::new().to_array(),
+                    ))),
+                ),
+        );
+        ins_op = ins_op.summary(format!("对象{}的聚合查询", self.name.clone()));
+        ins_op = ins_op.description(format!(
+            "对表{}执行分组聚合查询。请求体传递QueryCondition，aggregates必须提供，group_by为分组字段，having为对聚合结果的过滤，sorts可以使用聚合的别名",
+            self.object_name.clone()
+        ));
+
+        let mut resp = Response::new("返回以分组字段及聚合别名为键的记录列表");
+        resp = resp.add_content(
+            "application/json",
+            Content::new(to_api_result_schema(
+                RefOr::Type(schema::Schema::Object(Object::new())),
+                true,
+            )),
+        );
+        ins_op = ins_op.add_response("200", RefOr::Type(resp));
+
+        hash.insert(
+            format!("/api/object/{}/{}/aggregate", ns, self.name.clone()),
+            PathItem::new(salvo::oapi::PathItemType::Post, ins_op),
+        );
+
+        if self.audit {
+            //history
+            let mut ins_op = Operation::new();
+            ins_op = ins_op.request_body(
+                RequestBody::new()
+                    .required(salvo::oapi::Required::True)
+                    .add_content("application/json", schpk_ref.clone()),
+            );
+            ins_op = ins_op.summary(format!("对象{}的变更历史", self.name.clone()));
+            ins_op = ins_op.description(format!(
+                "查询表{}中记录的变更历史（审计记录），按审计时间倒序返回。主键必须提供，需要在命名空间中配置audit_object。",
+                self.object_name.clone()
+            ));
+
+            let mut resp = Response::new("返回审计记录列表");
+            resp = resp.add_content(
+                "application/json",
+                Content::new(to_api_result_schema(
+                    RefOr::Type(schema::Schema::Object(Object::new())),
+                    true,
+                )),
+            );
+            ins_op = ins_op.add_response("200", RefOr::Type(resp));
+
+            hash.insert(
+                format!("/api/object/{}/{}/history", ns, self.name.clone()),
+                PathItem::new(salvo::oapi::PathItemType::Post, ins_op),
+            );
+        }
+
         hash
     }
 }
@@ -761,7 +983,7 @@ impl ToSchemaObjects for QueryObject {
                 ),
         );
         ins_op = ins_op.summary(format!("自定义查询{}的唯一记录查询", self.name.clone()));
-        ins_op = ins_op.description(format!("对表{}执行数据库的按SELECT操作，返回零条或一条件记录。请求体通过_cond传递QueryCondition，其它字段为该查询所必须的固定参数，且QueryCondition中的paging条件必须提供", self.object_name.clone()));
+        ins_op = ins_op.description(format!("对表{}执行数据库的按SELECT操作，返回零条或一条件记录。请求体通过_cond传递QueryCondition，其它字段为该查询所必须的固定参数，且QueryCondition中的paging条件必须提供。paging.cursor为true或提供了paging.after时使用游标分页，返回next_cursor用于查询下一页", self.object_name.clone()));
 
         let mut resp = Response::new("返回查询到的对象分页列表");
         resp = resp.add_content(
@@ -1,7 +1,7 @@
 use chimes_store_core::{
     pin_submit,
     service::{registry::SchemaRegistry, starter::MxStoreService},
-    utils::redis::init_ns_scoped_redis,
+    utils::{cache::init_ns_cache, redis::init_ns_scoped_redis},
 };
 use dbs::{
     invoker::{DbQueryServiceInvocation, DbStoreServiceInvocation},
@@ -21,6 +21,7 @@ pub fn register_objects_and_querys(ns: &str) {
         if let Some(ms) = MxStoreService::get(&nms) {
             log::info!("init redis connection for {}", ms.get_namespace());
             init_ns_scoped_redis(&ms.get_config());
+            init_ns_cache(&ms.get_config());
         }
     });
 }
@@ -2,10 +2,11 @@ use std::sync::{Arc, Mutex};
 
 use chimes_store_core::{
     service::{
+        batch::{invoke_batch, BatchStep},
         invoker::{InvocationContext, JwtFromDepot},
         starter::MxStoreService,
     },
-    utils::{ApiResult, ApiResult2},
+    utils::{get_local_timestamp, ApiResult, ApiResult2},
 };
 use rbatis::Page;
 use salvo::{
@@ -159,6 +160,49 @@ pub async fn common_invoke_page(
     }
 }
 
+/**
+ * 在同一个事务中依次执行多个步骤，请求体为步骤的列表，或者{"steps": [...]}
+ * 每个步骤为{uri, params, return}，params中可以使用${jsonpath}引用之前步骤的结果，如${$.steps[0].id}
+ * 全部成功时提交并返回各步骤的结果，任一步骤失败时回滚，返回失败步骤的序号及错误
+ */
+#[endpoint]
+pub async fn common_invoke_batch(
+    depot: &mut Depot,
+    req: &mut Request,
+) -> Json<ManageApiResult<Value>> {
+    let body = match req.parse_body::<Value>().await {
+        Ok(t) => t,
+        Err(err) => {
+            return Json(ManageApiResult::error(
+                405,
+                format!("Could not Parse body to JSON format {:?}", err).as_str(),
+            ));
+        }
+    };
+
+    let steps = body.get("steps").cloned().unwrap_or(body);
+    let steps = match serde_json::from_value::<Vec<BatchStep>>(steps) {
+        Ok(t) => t,
+        Err(err) => {
+            return Json(ManageApiResult::error(
+                405,
+                format!("Invalid batch steps {}", err).as_str(),
+            ));
+        }
+    };
+
+    if steps.is_empty() {
+        return Json(ManageApiResult::error(405, "No steps provided"));
+    }
+
+    let ret = invoke_batch(InvocationContext::from_depot(depot), &steps).await;
+    let data = serde_json::to_value(&ret).unwrap_or(Value::Null);
+    match ret.error {
+        Some(err) => Json(ManageApiResult::new(500, &err, data, get_local_timestamp())),
+        None => Json(ManageApiResult::ok(data)),
+    }
+}
+
 #[handler]
 pub async fn common_file_send(depot: &mut Depot, req: &mut Request, res: &mut Response) {
     // let params = req.parse_params::<Value>().expect("unexpect format");
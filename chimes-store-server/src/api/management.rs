@@ -1,4 +1,4 @@
-use std::{fs, path::Path, str::FromStr, time::Duration};
+use std::{collections::{HashMap, HashSet}, fs, path::Path, str::FromStr, time::Duration};
 
 use super::{AuthResponse, FunctionRegistry};
 use crate::{
@@ -10,6 +10,7 @@ use crate::{
     },
 };
 use async_std::{path::PathBuf, stream::StreamExt};
+use chimes_store_core::dbs::probe::{ColumnInfo, ForeignKeyInfo, IndexInfo};
 use chimes_store_core::{service::{invoker::JwtFromDepot, sdk::{InvokeUri, MxProbeService}, starter::save_config}, utils::global_data::{rsa_decrypt_with_private_key, rsa_encrypt_with_public_key}};
 use chimes_store_core::service::starter::MxStoreService;
 use chimes_store_core::utils::ApiResult;
@@ -354,6 +355,7 @@ pub async fn probe_table(_depot: &mut Depot, req: &mut Request) -> Json<ApiResul
  * - ES
  * - Redis
  * - MonogoDB
+ * 表没有主键时默认不设置主键，传入unique_as_key=true时使用第一个唯一索引作为主键
  */
 #[handler]
 pub async fn generate(depot: &mut Depot, req: &mut Request) -> Json<ApiResult<Value>> {
@@ -362,6 +364,7 @@ pub async fn generate(depot: &mut Depot, req: &mut Request) -> Json<ApiResult<Va
     let ns_ = req.query::<String>("ns").unwrap_or_default();
     let sch = req.query::<String>("schema").unwrap_or_default();
     let rule = req.query::<String>("rule").unwrap_or("none".to_owned());
+    let unique_as_key = req.query::<bool>("unique_as_key").unwrap_or(false);
 
     let sto = req
         .parse_body::<Vec<StoreObject>>()
@@ -372,6 +375,9 @@ pub async fn generate(depot: &mut Depot, req: &mut Request) -> Json<ApiResult<Va
 
     let newsto = if let Some(tss) = MxStoreService::get(&ns_) {
         let mut sxsto = vec![];
+        let mut generated = HashSet::new();
+        let mut fks = HashMap::new();
+        let mut uniques = HashMap::new();
         for mut s in sto {
             if s.object_type.is_empty() {
                 if let Ok(Some(tbl)) = tss.probe_one_table(&sch, &s.object_name).await {
@@ -379,6 +385,10 @@ pub async fn generate(depot: &mut Depot, req: &mut Request) -> Json<ApiResult<Va
                 }
             }
             let mut keys = s.get_key_columns().iter().map(|f| f.field_name.clone()).collect_vec();
+            let unique_keys = match tss.probe_table_indexes(&sch, &s.object_name).await {
+                Ok(idxs) => IndexInfo::unique_groups(&idxs).into_iter().map(|(_, cols)| cols).collect_vec(),
+                Err(_) => vec![],
+            };
             
             if keys.is_empty() {
                 if let Ok(kss) = tss.probe_table_keys(&sch, &s.object_name).await {
@@ -388,6 +398,12 @@ pub async fn generate(depot: &mut Depot, req: &mut Request) -> Json<ApiResult<Va
                         .collect_vec();
                 }
             }
+            if keys.is_empty() && unique_as_key {
+                // 没有主键时，按请求使用第一个唯一索引作为主键
+                if let Some(first) = unique_keys.first() {
+                    keys = first.clone();
+                }
+            }
             if s.fields.is_empty() {
                 if let Ok(vss) = tss.probe_table(&sch, &s.object_name).await {
                     s.fields = vss
@@ -400,11 +416,17 @@ pub async fn generate(depot: &mut Depot, req: &mut Request) -> Json<ApiResult<Va
                             c
                         })
                         .collect::<Vec<Column>>();
+                    generated.insert(s.name.clone());
+                    if let Ok(fkss) = tss.probe_table_foreign_keys(&sch, &s.object_name).await {
+                        fks.insert(s.name.clone(), fkss);
+                    }
+                    uniques.insert(s.name.clone(), unique_keys);
                 };
             }
 
             sxsto.push(s);
         }
+        infer_relations(&mut sxsto, &generated, &fks, &uniques, &tss.get_config().objects, &rule);
         sxsto
     } else {
         sto
@@ -421,6 +443,179 @@ pub async fn generate(depot: &mut Depot, req: &mut Request) -> Json<ApiResult<Va
     Json(ApiResult::ok(json!({"result": res})))
 }
 
+/**
+ * 根据StoreObject的字段定义生成并执行DDL，使数据库中的表结构与定义一致
+ * dry_run缺省为true，只返回将要执行的语句；body为要迁移的对象名称列表，为空时迁移命名空间中的所有对象
+ */
+#[handler]
+pub async fn migrate(depot: &mut Depot, req: &mut Request) -> Json<ApiResult<Value>> {
+    let ns_ = req.query::<String>("ns").unwrap_or_default();
+    let sch = req.query::<String>("schema").unwrap_or_default();
+    let dry_run = req.query::<bool>("dry_run").unwrap_or(true);
+    let names = req.parse_body::<Vec<String>>().await.unwrap_or_default();
+
+    let applied_by = depot
+        .jwt_auth_data::<JwtClaims>()
+        .map(|f| f.claims.sid.clone())
+        .unwrap_or_default();
+
+    match MxStoreService::get(&ns_) {
+        Some(tss) => {
+            let ret = if dry_run {
+                tss.plan_migration(&sch, &names).await
+            } else {
+                tss.apply_migration(&sch, &names, &applied_by).await
+            };
+            match ret {
+                Ok(plan) => Json(ApiResult::ok(json!(plan))),
+                Err(err) => Json(ApiResult::error(500, format!("{}", err).as_str())),
+            }
+        }
+        None => Json(ApiResult::error(404, "Service Not-Found ")),
+    }
+}
+
+/**
+ * 去除外键字段名中的id后缀，如dept_id、deptId转为dept
+ */
+fn strip_key_suffix(field: &str) -> String {
+    let lower = field.to_lowercase();
+    let len = field.chars().count();
+    let base = if lower.ends_with("_id") {
+        field.substring(0, len - 3)
+    } else if lower.ends_with("id") && len > 2 {
+        field.substring(0, len - 2)
+    } else {
+        field
+    };
+    base.trim_end_matches('_').to_owned()
+}
+
+fn relation_prop_name(name: &str, rule: &str) -> Option<String> {
+    let info = ColumnInfo {
+        column_name: Some(name.to_owned()),
+        ..Default::default()
+    };
+    naming_property(&info, rule).filter(|f| !f.is_empty())
+}
+
+fn has_prop_name(obj: &StoreObject, prop: &str) -> bool {
+    obj.fields
+        .iter()
+        .any(|c| c.field_name == prop || c.prop_name.as_deref() == Some(prop))
+}
+
+/**
+ * 根据探测到的外键为本次生成的对象补充relation字段
+ * 外键字段生成一对一的relation，被引用的对象如果也是本次生成的，则生成反向的一对多relation（外键字段上有唯一索引时为一对一）
+ * 被引用的表可以是本次生成的对象或命名空间中已有的对象，复合外键不做处理
+ */
+fn infer_relations(
+    objects: &mut [StoreObject],
+    generated: &HashSet<String>,
+    fks: &HashMap<String, Vec<ForeignKeyInfo>>,
+    uniques: &HashMap<String, Vec<Vec<String>>>,
+    existing: &[StoreObject],
+    rule: &str,
+) {
+    let tables = objects
+        .iter()
+        .chain(existing.iter())
+        .map(|o| (o.object_name.to_lowercase(), o.name.clone()))
+        .collect_vec();
+    let find_target = |table: &str| {
+        tables
+            .iter()
+            .find(|(t, _)| *t == table.to_lowercase())
+            .map(|(_, n)| n.clone())
+    };
+
+    let mut reverses = vec![];
+    for obj in objects.iter_mut().filter(|o| generated.contains(&o.name)) {
+        let groups = fks
+            .get(&obj.name)
+            .cloned()
+            .unwrap_or_default()
+            .into_iter()
+            .into_group_map_by(|f| f.constraint_name.clone().unwrap_or_default());
+        for (_, grp) in groups.into_iter().sorted_by(|a, b| a.0.cmp(&b.0)) {
+            if grp.len() != 1 {
+                continue;
+            }
+            let fk = &grp[0];
+            let (field, ref_table, ref_field) = match (
+                fk.column_name.clone(),
+                fk.referenced_table_name.clone(),
+                fk.referenced_column_name.clone(),
+            ) {
+                (Some(f), Some(t), Some(r)) => (f, t, r),
+                _ => continue,
+            };
+            let target = match find_target(&ref_table) {
+                Some(t) => t,
+                None => continue,
+            };
+
+            let prop = [strip_key_suffix(&field), target.clone()]
+                .iter()
+                .filter_map(|f| relation_prop_name(f, rule))
+                .find(|f| !has_prop_name(obj, f));
+            if let Some(prop) = prop {
+                let title = obj
+                    .fields
+                    .iter()
+                    .find(|c| c.field_name == field)
+                    .and_then(|c| c.title.clone())
+                    .filter(|t| *t != field)
+                    .unwrap_or(prop.clone());
+                obj.fields.push(Column {
+                    field_name: field.clone(),
+                    prop_name: Some(prop),
+                    title: Some(title),
+                    col_type: Some("relation".to_owned()),
+                    relation_object: Some(target.clone()),
+                    relation_field: Some(ref_field.clone()),
+                    ..Default::default()
+                });
+            }
+
+            if generated.contains(&target) {
+                let unique = uniques
+                    .get(&obj.name)
+                    .map(|u| u.iter().any(|cols| *cols == [field.clone()]))
+                    .unwrap_or(false);
+                reverses.push((
+                    target,
+                    Column {
+                        field_name: ref_field,
+                        title: Some(obj.name.clone()),
+                        col_type: Some("relation".to_owned()),
+                        relation_object: Some(obj.name.clone()),
+                        relation_field: Some(field),
+                        relation_array: !unique,
+                        ..Default::default()
+                    },
+                ));
+            }
+        }
+    }
+
+    for (target, mut col) in reverses {
+        let source = col.relation_object.clone().unwrap_or_default();
+        if let Some(obj) = objects.iter_mut().find(|o| o.name == target) {
+            let base = if col.relation_array {
+                format!("{}_list", source)
+            } else {
+                source
+            };
+            if let Some(prop) = relation_prop_name(&base, rule).filter(|f| !has_prop_name(obj, f)) {
+                col.prop_name = Some(prop);
+                obj.fields.push(col);
+            }
+        }
+    }
+}
+
 #[handler]
 pub async fn fetch_namespaces(_depot: &mut Depot, _req: &mut Request) -> Json<ApiResult<Value>> {
     let nss = MxStoreService::get_namespaces();
@@ -80,7 +80,7 @@ impl FunctionRegistry {
         FUNCTION_REGISTRY_LIST.clone().into_iter().filter(|p| {
             (p.id != "_es" && p.id != "_redis")
             || (p.id == "_es" && MxStoreService::get(ns).map(|f| !f.get_plugin_config_by_protocol("elasticsearch").is_empty()).unwrap_or(false))
-            || (p.id == "_redis" && MxStoreService::get(ns).map(|f| f.get_config().redis_url.map(|url| !url.is_empty()).unwrap_or_default()).unwrap_or(false))
+            || (p.id == "_redis" && MxStoreService::get(ns).map(|f| f.get_config().has_redis()).unwrap_or(false))
         }).collect_vec()
     }
 }
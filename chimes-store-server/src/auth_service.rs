@@ -572,6 +572,16 @@ impl Handler for AuthUserRole {
         res: &mut Response,
         ctrl: &mut FlowCtrl,
     ) {
+        // 记录请求的来源地址，用于审计
+        depot.insert("_REMOTE_ADDR", req.remote_addr().to_string());
+        // 请求头X-Read-Primary为true时查询不使用只读副本
+        if let Some(primary) = req.header::<String>("X-Read-Primary") {
+            depot.insert("_READ_PRIMARY", primary.eq_ignore_ascii_case("true") || primary == "1");
+        }
+        // 请求头X-Tenant-Scope为all时，超级管理员可以跨租户访问数据
+        if let Some(scope) = req.header::<String>("X-Tenant-Scope") {
+            depot.insert("_CROSS_TENANT", scope.eq_ignore_ascii_case("all"));
+        }
         let path = req.uri().path();
         let sppath = path.split('/').filter(|p| !p.is_empty()).collect_vec();
         let api = sppath[0];
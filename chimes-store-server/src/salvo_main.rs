@@ -301,6 +301,7 @@ pub async fn salvo_main(args: Args, config: config::Config) {
         .push(Router::with_path("/execute/option").put(api::common::common_invoke_option))
         .push(Router::with_path("/execute/list").put(api::common::common_invoke_vec))
         .push(Router::with_path("/execute/paged").put(api::common::common_invoke_page))
+        .push(Router::with_path("/execute/batch").post(api::common::common_invoke_batch))
         .push(Router::with_path("tools/jsonpath_test").post(api::tools::tool_jsonpath_test))
         .push(Router::with_path("tools/tera_test").post(api::tools::tool_tera_test))
         .push(Router::with_path("tools/rhai_test").post(api::tools::tool_rhai_test))
@@ -323,6 +324,7 @@ pub async fn salvo_main(args: Args, config: config::Config) {
         .push(Router::with_path("/execute/option").put(api::common::common_invoke_option))
         .push(Router::with_path("/execute/list").put(api::common::common_invoke_vec))
         .push(Router::with_path("/execute/paged").put(api::common::common_invoke_page))
+        .push(Router::with_path("/execute/batch").post(api::common::common_invoke_batch))
         .push(Router::with_path("/file/<ns>/get/<file_id>").get(api::common::common_file_send))
         .append(&mut get_salvo_service_router())
         .append(&mut plugin_router_install());    
@@ -414,6 +416,7 @@ pub async fn salvo_main(args: Args, config: config::Config) {
                 .push(Router::with_path("probe/schema").get(api::management::probe_schema))
                 .push(Router::with_path("probe/table").get(api::management::probe_table))
                 .push(Router::with_path("generate").post(api::management::generate))
+                .push(Router::with_path("migrate").post(api::management::migrate))
                 .push(Router::with_path("fetch/namespaces").get(api::management::fetch_namespaces))
                 .push(Router::with_path("fetch/pluginnames").get(api::management::fetch_plugin_name))
                 .push(Router::with_path("plugin/list").get(api::management::plugin_list))
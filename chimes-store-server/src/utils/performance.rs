@@ -1,3 +1,5 @@
+use chimes_store_core::service::starter::MxStoreService;
+use chimes_store_core::utils::cache::get_cache_stats;
 use chimes_store_core::utils::executor::TaskCounter;
 use chimes_store_core::utils::get_local_timestamp;
 use lazy_static::lazy_static;
@@ -24,6 +26,84 @@ pub struct ChimesPerformanceInfo {
     pub handlers: u64,           // handlers
     pub success: bool,           // success or not
     pub counter: CustomCounterInfo,
+    pub pools: Vec<ChimesPoolInfo>, // connection pools of the namespaces
+    pub caches: Vec<ChimesCacheInfo>, // result caches of the namespaces
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, ToSchema)]
+pub struct ChimesPoolInfo {
+    pub namespace: String,
+    pub role: String,            // primary or replica-N
+    pub max_open: u64,           // max open connections
+    pub connections: u64,        // opened connections
+    pub in_use: u64,             // connections in use
+    pub idle: u64,               // idle connections
+    pub waiters: u64,            // requests waiting for a connection
+    pub acquired: u64,           // total acquired
+    pub acquire_failed: u64,     // total failed or timed out
+    pub acquire_avg_ms: f64,     // average acquire latency
+    pub acquire_max_ms: f64,     // max acquire latency
+    pub expired: u64,            // connections recreated by idle timeout or max lifetime
+}
+
+pub fn get_pool_performance() -> Vec<ChimesPoolInfo> {
+    let mut pools = vec![];
+    MxStoreService::store_service_foreach(|mss| {
+        let ns = mss.get_namespace();
+        for (role, st) in mss.get_pool_stats() {
+            pools.push(ChimesPoolInfo {
+                namespace: ns.clone(),
+                role,
+                max_open: st.max_open,
+                connections: st.connections,
+                in_use: st.in_use,
+                idle: st.idle,
+                waiters: st.waits,
+                acquired: st.acquired,
+                acquire_failed: st.acquire_failed,
+                acquire_avg_ms: st.acquire_avg_micros as f64 / 1000f64,
+                acquire_max_ms: st.acquire_max_micros as f64 / 1000f64,
+                expired: st.expired,
+            });
+        }
+    });
+    pools
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, ToSchema)]
+pub struct ChimesCacheInfo {
+    pub namespace: String,
+    pub backend: String,         // memory or redis
+    pub entries: u64,            // cached entries (memory only)
+    pub bytes: u64,              // cached bytes (memory only)
+    pub max_entries: u64,        // capacity of entries (memory only)
+    pub max_bytes: u64,          // capacity of bytes (memory only)
+    pub hits: u64,               // total hits
+    pub misses: u64,             // total misses
+    pub inserts: u64,            // total inserts
+    pub evictions: u64,          // entries evicted by capacity
+    pub expirations: u64,        // entries removed by expiration
+    pub invalidations: u64,      // entries removed by tag invalidation
+}
+
+pub fn get_cache_performance() -> Vec<ChimesCacheInfo> {
+    get_cache_stats()
+        .into_iter()
+        .map(|(ns, st)| ChimesCacheInfo {
+            namespace: ns,
+            backend: st.backend,
+            entries: st.entries,
+            bytes: st.bytes,
+            max_entries: st.max_entries,
+            max_bytes: st.max_bytes,
+            hits: st.hits,
+            misses: st.misses,
+            inserts: st.inserts,
+            evictions: st.evictions,
+            expirations: st.expirations,
+            invalidations: st.invalidations,
+        })
+        .collect()
 }
 
 #[derive(Debug, Clone, Default, Deserialize, Serialize, ToSchema)]
@@ -334,6 +414,8 @@ impl ChimesPerformanceInfo {
             handlers: hc as u64,
             success: true,
             counter: get_custom_performance_counter().to_counter(),
+            pools: get_pool_performance(),
+            caches: get_cache_performance(),
         };
 
         Ok(newitem)
@@ -369,6 +451,8 @@ impl ChimesPerformanceInfo {
             handlers: hc as u64,
             success: true,
             counter: get_custom_performance_counter().to_counter(),
+            pools: get_pool_performance(),
+            caches: get_cache_performance(),
         };
         Ok(newitem)
     }
@@ -5,7 +5,7 @@ use rbatis::Page;
 use reqwest::RhaiHttpClient;
 use resolver::{
     require_create, InvocationContextGetterSetter, RhaiStoreObject, RhaiStorePlugin,
-    RhaiStoreQuery, RhaiStoreServiceResolver, ValueGetterSetter,
+    RhaiStoreQuery, RhaiStoreRedis, RhaiStoreServiceResolver, ValueGetterSetter,
 };
 use rhai::Dynamic;
 use std::{
@@ -234,6 +234,18 @@ impl EvalEngine {
                     RhaiStoreObject::invoke(caller, "delete", ctx, arg)
                 },
             )
+            .register_fn(
+                "restore",
+                |caller: &mut RhaiStoreObject, ctx: Arc<Mutex<InvocationContext>>, arg: Value| {
+                    RhaiStoreObject::invoke(caller, "restore", ctx, arg)
+                },
+            )
+            .register_fn(
+                "purge",
+                |caller: &mut RhaiStoreObject, ctx: Arc<Mutex<InvocationContext>>, arg: Value| {
+                    RhaiStoreObject::invoke(caller, "purge", ctx, arg)
+                },
+            )
             .register_fn("query", RhaiStoreObject::query)
             .register_fn(
                 "query",
@@ -247,6 +259,19 @@ impl EvalEngine {
                 |caller: &mut RhaiStoreObject, ctx: Arc<Mutex<InvocationContext>>, arg: Value| {
                     RhaiStoreObject::paged_query(caller, ctx, vec![arg])
                 },
+            )
+            .register_fn("aggregate", RhaiStoreObject::aggregate)
+            .register_fn(
+                "aggregate",
+                |caller: &mut RhaiStoreObject, ctx: Arc<Mutex<InvocationContext>>, arg: Value| {
+                    RhaiStoreObject::aggregate(caller, ctx, vec![arg])
+                },
+            )
+            .register_fn(
+                "history",
+                |caller: &mut RhaiStoreObject, ctx: Arc<Mutex<InvocationContext>>, arg: Value| {
+                    RhaiStoreObject::history(caller, ctx, vec![arg])
+                },
             );
 
         engin
@@ -280,6 +305,7 @@ impl EvalEngine {
             .register_fn("timestamp_millis", RhaiDateTime::timestamp_millis);
 
         RhaiStorePlugin::register(&mut engin);
+        RhaiStoreRedis::register(&mut engin);
 
         engin
             .register_type_with_name::<Value>("JSON")
@@ -2,6 +2,7 @@ use chimes_store_core::config::{ConditionItem, IPaging, OrdianlItem, QueryCondit
 use chimes_store_core::pin_blockon_async;
 use chimes_store_core::service::sdk::InvokeUri;
 use chimes_store_core::service::{invoker::InvocationContext, starter::MxStoreService};
+use chimes_store_core::utils::redis_cmd::{check_redis_permission, REDIS_METHODS};
 use rbatis::{IPageRequest, Page};
 use rhai::{
     Array, CustomType, Dynamic, Engine, EvalAltResult, ImmutableString, Module, ModuleResolver,
@@ -328,6 +329,64 @@ impl RhaiStoreObject {
         ))))
     }
 
+    pub fn aggregate(
+        &mut self,
+        ctx: Arc<Mutex<InvocationContext>>,
+        args: Vec<Value>,
+    ) -> Result<Vec<Value>, Box<EvalAltResult>> {
+        let call_uri = format!("{}#aggregate", self.uri);
+        let call_uri2 = call_uri.clone();
+        pin_blockon_async!(async move {
+            let ret = match MxStoreService::invoke_return_vec(call_uri.clone(), ctx, args).await {
+                Ok(t) => {
+                    log::info!("OK: {:?}", t);
+                    Ok(t)
+                }
+                Err(err) => {
+                    log::info!("Err: {:?}", err);
+                    Err(Box::new(EvalAltResult::ErrorRuntime(
+                        Dynamic::from(err.to_string()),
+                        Position::new(1, 1),
+                    )))
+                }
+            };
+            Box::new(ret) as Box<dyn Any + Send + Sync>
+        })
+        .unwrap_or(Err(Box::new(EvalAltResult::ErrorRuntime(
+            Dynamic::from(call_uri2),
+            Position::new(1, 1),
+        ))))
+    }
+
+    pub fn history(
+        &mut self,
+        ctx: Arc<Mutex<InvocationContext>>,
+        args: Vec<Value>,
+    ) -> Result<Vec<Value>, Box<EvalAltResult>> {
+        let call_uri = format!("{}#history", self.uri);
+        let call_uri2 = call_uri.clone();
+        pin_blockon_async!(async move {
+            let ret = match MxStoreService::invoke_return_vec(call_uri.clone(), ctx, args).await {
+                Ok(t) => {
+                    log::info!("OK: {:?}", t);
+                    Ok(t)
+                }
+                Err(err) => {
+                    log::info!("Err: {:?}", err);
+                    Err(Box::new(EvalAltResult::ErrorRuntime(
+                        Dynamic::from(err.to_string()),
+                        Position::new(1, 1),
+                    )))
+                }
+            };
+            Box::new(ret) as Box<dyn Any + Send + Sync>
+        })
+        .unwrap_or(Err(Box::new(EvalAltResult::ErrorRuntime(
+            Dynamic::from(call_uri2),
+            Position::new(1, 1),
+        ))))
+    }
+
     pub fn paged_query(
         &mut self,
         ctx: Arc<Mutex<InvocationContext>>,
@@ -429,6 +488,97 @@ impl RhaiStoreQuery {
     }
 }
 
+/**
+ * 在脚本中调用redis://的方法，如：
+ * let redis = new_redis_object("com.siline");
+ * redis.hset(ctx, "user:1", #{ name: "test" });
+ * let user = redis.hgetall(ctx, "user:1");
+ * 参数为数组时作为参数列表，其它值作为第一个参数
+ */
+#[derive(Clone, CustomType)]
+pub struct RhaiStoreRedis {
+    ns: String,
+}
+
+impl RhaiStoreRedis {
+    pub fn new(ns: &str) -> Self {
+        Self { ns: ns.to_owned() }
+    }
+
+    pub fn register(engine: &mut Engine) {
+        engine
+            .register_type_with_name::<RhaiStoreRedis>("RedisObject")
+            .register_fn("new_redis_object", RhaiStoreRedis::new);
+
+        for &(name, _) in REDIS_METHODS.iter() {
+            engine.register_fn(
+                name,
+                |callctx: NativeCallContext,
+                 obj: &mut RhaiStoreRedis,
+                 ctx: Arc<Mutex<InvocationContext>>,
+                 key: &str| { obj.invoke(callctx.fn_name(), ctx, key, vec![]) },
+            );
+            engine.register_fn(
+                name,
+                |callctx: NativeCallContext,
+                 obj: &mut RhaiStoreRedis,
+                 ctx: Arc<Mutex<InvocationContext>>,
+                 key: &str,
+                 args: Array| { obj.invoke(callctx.fn_name(), ctx, key, args) },
+            );
+            engine.register_fn(
+                name,
+                |callctx: NativeCallContext,
+                 obj: &mut RhaiStoreRedis,
+                 ctx: Arc<Mutex<InvocationContext>>,
+                 key: &str,
+                 arg: Dynamic| { obj.invoke(callctx.fn_name(), ctx, key, vec![arg]) },
+            );
+        }
+    }
+
+    pub fn invoke(
+        &mut self,
+        method: &str,
+        ctx: Arc<Mutex<InvocationContext>>,
+        key: &str,
+        args: Array,
+    ) -> Result<Value, Box<EvalAltResult>> {
+        if let Err(err) = check_redis_permission(&self.ns, method, &ctx.lock().unwrap()) {
+            return Err(Box::new(EvalAltResult::ErrorRuntime(
+                Dynamic::from(err.to_string()),
+                Position::new(1, 1),
+            )));
+        }
+        let call_uri = format!("redis://{}/redis?{}#{}", self.ns, key, method);
+        let call_uri2 = call_uri.clone();
+        let vec_args = args
+            .into_iter()
+            .map(|d| {
+                if d.is::<Value>() {
+                    Ok(d.cast::<Value>())
+                } else {
+                    rhai::serde::from_dynamic::<Value>(&d)
+                }
+            })
+            .collect::<Result<Vec<Value>, Box<EvalAltResult>>>()?;
+        pin_blockon_async!(async move {
+            let ret = match MxStoreService::invoke_return_one(call_uri.clone(), ctx, vec_args).await {
+                Ok(t) => Ok(t.unwrap_or(Value::Null)),
+                Err(err) => Err(Box::new(EvalAltResult::ErrorRuntime(
+                    Dynamic::from(err.to_string()),
+                    Position::new(1, 1),
+                ))),
+            };
+            Box::new(ret) as Box<dyn Any + Send + Sync>
+        })
+        .unwrap_or(Err(Box::new(EvalAltResult::ErrorRuntime(
+            Dynamic::from(call_uri2),
+            Position::new(1, 1),
+        ))))
+    }
+}
+
 #[derive(Clone, CustomType)]
 pub struct RhaiStorePlugin {
     uri: String,
@@ -1227,6 +1377,7 @@ impl ValueGetterSetter {
             group_by: vec![g_oi],
             sorts: vec![ord_oi],
             paging,
+            ..Default::default()
         })
     }
 
@@ -1237,7 +1388,8 @@ impl ValueGetterSetter {
     pub fn create_ordianl_item_args(field: &str, asc: bool) -> Value {
         json!(OrdianlItem {
             field: field.to_owned(),
-            sort_asc: asc
+            sort_asc: asc,
+            ..Default::default()
         })
     }
 